@@ -0,0 +1,57 @@
+// Times reading counters through the real binary, which needs pdh.dll, so it
+// only runs on Windows. Set PERFLOGTOOL_BENCH_LOG to a big .blg to measure
+// something closer to a customer log than the sample CSV, and
+// PERFLOGTOOL_BENCH_COUNTERS to two or more comma-separated counters in it to
+// compare the single-counter path with the general one.
+//
+//     cargo bench --bench read
+
+#[cfg(windows)]
+mod read {
+    use std::process::Command;
+
+    use criterion::{criterion_group, Criterion};
+
+    const SAMPLE_CSV: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/sample.csv");
+
+    const SAMPLE_COUNTERS: &str = concat!(
+        "\\\\TESTSERVER\\Processor(_Total)\\% Processor Time,",
+        "\\\\TESTSERVER\\Processor(0)\\% Processor Time"
+    );
+
+    fn export(log: &str, counters: &[&str]) {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_perflogtool"));
+        command.args(["export", log]);
+        for counter in counters {
+            command.args(["--counter", counter]);
+        }
+
+        let output = command.output().expect("Failed to run perflogtool");
+        assert!(output.status.success(), "perflogtool export failed");
+    }
+
+    fn bench_export(c: &mut Criterion) {
+        let log = std::env::var("PERFLOGTOOL_BENCH_LOG").unwrap_or(SAMPLE_CSV.to_string());
+        let counters =
+            std::env::var("PERFLOGTOOL_BENCH_COUNTERS").unwrap_or(SAMPLE_COUNTERS.to_string());
+        let counters = counters.split(',').collect::<Vec<&str>>();
+
+        // The first run lists the counters into the summary cache, which
+        // would otherwise be timed in the first sample.
+        export(&log, &counters[..1]);
+
+        let mut group = c.benchmark_group("export");
+        group.sample_size(10);
+        group.bench_function("one counter", |b| b.iter(|| export(&log, &counters[..1])));
+        group.bench_function("all counters", |b| b.iter(|| export(&log, &counters)));
+        group.finish();
+    }
+
+    criterion_group!(benches, bench_export);
+}
+
+#[cfg(windows)]
+criterion::criterion_main!(read::benches);
+
+#[cfg(not(windows))]
+fn main() {}
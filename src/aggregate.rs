@@ -0,0 +1,152 @@
+use std::collections::{BTreeSet, HashMap};
+
+use clap::ValueEnum;
+use time::OffsetDateTime;
+
+use crate::{
+    cli::AggregateArgs,
+    counter_path::CounterPath,
+    pdh_helper::CounterValueWithTime,
+    resample::Aggregate,
+    series::{Series, SeriesBuilder},
+    stats::sort_values,
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Across {
+    /// The same counter on every machine, like the requests/sec of each
+    /// server in a web farm
+    Machines,
+    /// Every instance of a counter, like the working set of each process.
+    /// _Total is left as it is rather than counted twice.
+    Instances,
+}
+
+// The path the counters combined into one series have in common, or None
+// for counters that aren't combined: paths that don't parse, and with
+// instances, counters without any and _Total.
+fn group(across: &[Across], counter: &str) -> Option<String> {
+    let mut path = CounterPath::parse(counter)?;
+    for across in across {
+        match across {
+            Across::Machines => path.machine.clear(),
+            Across::Instances => match &path.instance {
+                Some(instance) if instance != "_Total" => path.instance = Some("*".to_string()),
+                _ => return None,
+            },
+        }
+    }
+    Some(path.to_string())
+}
+
+// Replaces the counters that differ only by what --across names with one
+// series per group, named like \Web Service(_Total)\Total Method
+// Requests/sec:sum or \\WEB01\Process(*)\Working Set:sum, or adds those
+// series after the counters with --keep-members. Without --aggregate the
+// counters are returned unchanged.
+pub fn aggregate_across(
+    counters: Vec<String>,
+    series: Vec<Series>,
+    args: &AggregateArgs,
+) -> (Vec<String>, Vec<Series>) {
+    let aggregate = match args.aggregate {
+        Some(aggregate) => aggregate,
+        None => return (counters, series),
+    };
+
+    // Groups in the order their first counter appears.
+    let mut groups = Vec::<(String, Vec<usize>)>::new();
+    let mut group_index = HashMap::<String, usize>::new();
+    let mut ungrouped = Vec::new();
+
+    for (index, counter) in counters.iter().enumerate() {
+        let group = match group(&args.across, counter) {
+            Some(group) => group,
+            None => {
+                ungrouped.push(index);
+                continue;
+            }
+        };
+        let at = *group_index.entry(group.clone()).or_insert_with(|| {
+            groups.push((group, Vec::new()));
+            groups.len() - 1
+        });
+        groups[at].1.push(index);
+    }
+
+    let combined = groups
+        .iter()
+        .map(|(group, members)| {
+            let members = members
+                .iter()
+                .map(|i| &series[*i])
+                .collect::<Vec<&Series>>();
+            (
+                format!("{}:{}", group, aggregate.name()),
+                combine(&members, aggregate),
+            )
+        })
+        .collect::<Vec<(String, Series)>>();
+
+    let kept = if args.keep_members {
+        (0..counters.len()).collect()
+    } else {
+        ungrouped
+    };
+
+    let mut names = Vec::new();
+    let mut columns = Vec::new();
+    let mut series = series
+        .into_iter()
+        .map(Some)
+        .collect::<Vec<Option<Series>>>();
+    for index in kept {
+        names.push(counters[index].clone());
+        columns.push(series[index].take().unwrap());
+    }
+    for (name, samples) in combined {
+        names.push(name);
+        columns.push(samples);
+    }
+
+    (names, columns)
+}
+
+// Combines the series at every time any of them has a sample. Collections on
+// different machines rarely line up, so each series counts with its latest
+// value from its first sample to its last.
+fn combine(members: &[&Series], aggregate: Aggregate) -> Series {
+    let times = members
+        .iter()
+        .flat_map(|s| s.iter().map(|sample| sample.time()))
+        .collect::<BTreeSet<OffsetDateTime>>();
+
+    // The index of the next sample of each member.
+    let mut next = vec![0; members.len()];
+    let mut builder = SeriesBuilder::new(1, times.len());
+    let mut values = Vec::with_capacity(members.len());
+
+    for time in times {
+        values.clear();
+        for (member, next) in members.iter().zip(next.iter_mut()) {
+            while *next < member.len() && member.time(*next) <= time {
+                *next += 1;
+            }
+            if *next == 0 || (*next == member.len() && member.time(*next - 1) < time) {
+                continue;
+            }
+            values.push(member.value(*next - 1));
+        }
+
+        if values.is_empty() {
+            continue;
+        }
+        sort_values(&mut values);
+        builder.push(
+            0,
+            CounterValueWithTime::Double(time, aggregate.compute(&values)),
+        );
+    }
+
+    builder.finish().pop().unwrap()
+}
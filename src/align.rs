@@ -0,0 +1,116 @@
+use clap::ValueEnum;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    pdh_helper::CounterValueWithTime,
+    resample::bucket_start,
+    series::{Series, SeriesBuilder},
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Interpolation {
+    /// The value of the closer sample
+    Nearest,
+    /// A straight line between the samples before and after
+    Linear,
+}
+
+// A sample more than this many of a counter's usual intervals from the next
+// is a gap in the log, like between two collector runs, and nothing is made
+// up across it.
+const MAX_GAP_INTERVALS: i32 = 3;
+
+// Puts every series onto one grid of times, interval apart and lined up like
+// resample buckets, so counters sampled at different offsets or from
+// different machines have a value in the same rows. Grid times before a
+// counter's first sample, after its last, or inside a gap in it are left
+// without a value.
+pub fn align(series: &[Series], interval: Duration, method: Interpolation) -> Vec<Series> {
+    let start = series
+        .iter()
+        .filter_map(|s| s.first())
+        .map(|s| s.time())
+        .min();
+    let end = series
+        .iter()
+        .filter_map(|s| s.last())
+        .map(|s| s.time())
+        .max();
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return series.to_vec(),
+    };
+
+    let max_gaps = series
+        .iter()
+        .map(|s| usual_interval(s).map(|i| i * MAX_GAP_INTERVALS))
+        .collect::<Vec<Option<Duration>>>();
+
+    let capacity = ((end - start).whole_nanoseconds() / interval.whole_nanoseconds().max(1)) + 1;
+    let mut builder = SeriesBuilder::new(series.len(), capacity as usize);
+
+    // The index of the first sample at or after the grid time, per series.
+    let mut next = vec![0; series.len()];
+
+    let mut time = bucket_start(start, interval);
+    while time <= end {
+        for (index, samples) in series.iter().enumerate() {
+            while next[index] < samples.len() && samples.time(next[index]) < time {
+                next[index] += 1;
+            }
+
+            if let Some(value) = value_at(samples, next[index], time, max_gaps[index], method) {
+                builder.push(index, CounterValueWithTime::Double(time, value));
+            }
+        }
+
+        time += interval;
+    }
+
+    builder.finish()
+}
+
+fn value_at(
+    samples: &Series,
+    next: usize,
+    time: OffsetDateTime,
+    max_gap: Option<Duration>,
+    method: Interpolation,
+) -> Option<f64> {
+    if next == samples.len() {
+        return None;
+    }
+    if samples.time(next) == time {
+        return Some(samples.value(next));
+    }
+    if next == 0 {
+        return None;
+    }
+
+    let (before, after) = (samples.time(next - 1), samples.time(next));
+    if max_gap.is_some_and(|max_gap| after - before > max_gap) {
+        return None;
+    }
+
+    let (v0, v1) = (samples.value(next - 1), samples.value(next));
+    Some(match method {
+        Interpolation::Nearest if time - before <= after - time => v0,
+        Interpolation::Nearest => v1,
+        Interpolation::Linear => v0 + (v1 - v0) * ((time - before) / (after - before)),
+    })
+}
+
+// The median time between samples, which a few missed collections don't
+// throw off.
+fn usual_interval(samples: &Series) -> Option<Duration> {
+    let mut intervals = (1..samples.len())
+        .map(|i| samples.time(i) - samples.time(i - 1))
+        .collect::<Vec<Duration>>();
+
+    if intervals.is_empty() {
+        return None;
+    }
+
+    intervals.sort();
+    Some(intervals[intervals.len() / 2])
+}
@@ -0,0 +1,410 @@
+pub mod charts;
+pub mod cpu;
+pub mod custom;
+pub mod disk;
+pub mod domain_controller;
+pub mod hyperv;
+pub mod iis;
+pub mod interrupts;
+pub mod memory;
+pub mod memory_pressure;
+pub mod network;
+pub mod ratio;
+pub mod storage;
+pub mod tcp_smb;
+pub mod threshold;
+
+use clap::ValueEnum;
+use time::{Duration, OffsetDateTime, UtcOffset};
+
+use crate::{
+    cli::AnalyzeArgs,
+    export::format_time,
+    pdh_helper::CounterValueWithTime,
+    reader::read_counters,
+    reader::CounterData,
+    selection::CounterSelection,
+    series::Series,
+    status::{record, Outcome},
+    timespec::{display_offset, zone_label},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "INFO"),
+            Severity::Warning => write!(f, "WARNING"),
+            Severity::Critical => write!(f, "CRITICAL"),
+        }
+    }
+}
+
+pub struct Finding {
+    pub analyzer: &'static str,
+    pub severity: Severity,
+    pub counter: String,
+    pub start: OffsetDateTime,
+    pub end: OffsetDateTime,
+    pub message: String,
+}
+
+// A set of analyzers for one area, and a summary of the counters behind
+// them printed after the findings. Parsed with ValueEnum for the names and
+// help, since --profile also takes the names of profile files.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum AnalyzeProfile {
+    /// Disk latency, queues, and free space, with a health summary per disk
+    Disk,
+    /// Available memory, paging, pools, commit, and working set growth, with
+    /// when the machine was short of memory
+    Memory,
+    /// Adapter utilization against link bandwidth, output queues, and TCP
+    /// retransmits, with a status per adapter
+    Network,
+    /// ASP.NET queues and execution times, with requests, worker processes,
+    /// and restarts per application pool and application
+    Iis,
+    /// Host CPU contention, virtual processor dispatch wait, and dynamic
+    /// memory, with each VM's share of the host. For captures on the host.
+    Hyperv,
+    /// Single cores saturated behind a low _Total, and DPC and interrupt
+    /// storms, with how busy each NUMA node and the busiest cores were
+    Cpu,
+}
+
+impl AnalyzeProfile {
+    fn analyzers(&self) -> &'static [&'static str] {
+        match self {
+            AnalyzeProfile::Disk => &["storage", "disk"],
+            AnalyzeProfile::Memory => &["memory-pressure", "memory"],
+            AnalyzeProfile::Network => &["tcp-smb", "network"],
+            AnalyzeProfile::Iis => &["iis", "ratios"],
+            AnalyzeProfile::Hyperv => &["hyperv"],
+            AnalyzeProfile::Cpu => &["cpu", "interrupts"],
+        }
+    }
+
+    fn summary_counters(&self) -> Vec<String> {
+        match self {
+            AnalyzeProfile::Disk => disk::summary_counters(),
+            AnalyzeProfile::Memory => memory::summary_counters(),
+            AnalyzeProfile::Network => network::summary_counters(),
+            AnalyzeProfile::Iis => iis::summary_counters(),
+            AnalyzeProfile::Hyperv => hyperv::summary_counters(),
+            AnalyzeProfile::Cpu => cpu::summary_counters(),
+        }
+    }
+
+    fn print_summary(&self, data: &CounterData) {
+        match self {
+            AnalyzeProfile::Disk => disk::print_summary(data),
+            AnalyzeProfile::Memory => memory::print_summary(data),
+            AnalyzeProfile::Network => network::print_summary(data),
+            AnalyzeProfile::Iis => iis::print_summary(data),
+            AnalyzeProfile::Hyperv => hyperv::print_summary(data),
+            AnalyzeProfile::Cpu => cpu::print_summary(data),
+        }
+    }
+}
+
+// A profile picked with --profile, built in or loaded from a file.
+#[derive(Clone, Copy)]
+enum Profile {
+    BuiltIn(AnalyzeProfile),
+    File(&'static threshold::ThresholdProfile),
+}
+
+impl Profile {
+    fn find(name: &str) -> Option<Profile> {
+        if let Ok(profile) = AnalyzeProfile::from_str(name, true) {
+            return Some(Profile::BuiltIn(profile));
+        }
+        custom::file_profiles()
+            .iter()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+            .map(Profile::File)
+    }
+
+    fn analyzers(&self) -> &'static [&'static str] {
+        match self {
+            Profile::BuiltIn(profile) => profile.analyzers(),
+            Profile::File(profile) => std::slice::from_ref(&profile.name),
+        }
+    }
+
+    fn summary_counters(&self) -> Vec<String> {
+        match self {
+            Profile::BuiltIn(profile) => profile.summary_counters(),
+            Profile::File(_) => Vec::new(),
+        }
+    }
+
+    fn print_summary(&self, data: &CounterData) {
+        match self {
+            Profile::BuiltIn(profile) => profile.print_summary(data),
+            Profile::File(profile) => custom::print_summary(profile, data),
+        }
+    }
+}
+
+fn print_profiles() {
+    for profile in AnalyzeProfile::value_variants() {
+        let value = profile.to_possible_value().unwrap();
+        eprintln!(
+            "  {:<12} {}",
+            value.get_name(),
+            value.get_help().map(|h| h.to_string()).unwrap_or_default()
+        );
+    }
+    for profile in custom::file_profiles() {
+        eprintln!("  {:<12} {}", profile.name, profile.description);
+    }
+}
+
+pub trait Analyzer {
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    // Counter patterns to read, in the form accepted by --counter.
+    fn counters(&self) -> Vec<&'static str>;
+
+    fn analyze(&self, data: &CounterData) -> Vec<Finding>;
+}
+
+// The built-in analyzers and the profiles loaded from files.
+pub fn all_analyzers() -> Vec<Box<dyn Analyzer>> {
+    let mut analyzers = all_builtin_analyzers();
+    for profile in custom::file_profiles() {
+        analyzers.push(Box::new(profile.clone()));
+    }
+    analyzers
+}
+
+pub fn all_builtin_analyzers() -> Vec<Box<dyn Analyzer>> {
+    vec![
+        Box::new(interrupts::InterruptAnalyzer),
+        Box::new(cpu::CpuAnalyzer),
+        Box::new(memory_pressure::MemoryPressureAnalyzer),
+        Box::new(storage::StorageAnalyzer),
+        Box::new(disk::profile()),
+        Box::new(memory::profile()),
+        Box::new(network::NetworkAnalyzer),
+        Box::new(iis::profile()),
+        Box::new(hyperv::profile()),
+        Box::new(tcp_smb::profile()),
+        Box::new(domain_controller::profile()),
+        Box::new(ratio::profile()),
+    ]
+}
+
+pub fn analyze(args: &AnalyzeArgs) {
+    let profile = match &args.profile {
+        Some(name) => match Profile::find(name) {
+            Some(profile) => Some(profile),
+            None => {
+                eprintln!("There's no profile named {}. Available profiles:", name);
+                print_profiles();
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let analyzers = all_analyzers()
+        .into_iter()
+        .filter(|a| match profile {
+            Some(profile) => profile.analyzers().contains(&a.name()),
+            None => {
+                args.analyzer.is_empty()
+                    || args
+                        .analyzer
+                        .iter()
+                        .any(|name| name.eq_ignore_ascii_case(a.name()))
+            }
+        })
+        .collect::<Vec<Box<dyn Analyzer>>>();
+
+    if analyzers.is_empty() {
+        eprintln!("No analyzers matched. Available analyzers:");
+        for analyzer in all_analyzers() {
+            eprintln!("  {:<12} {}", analyzer.name(), analyzer.description());
+        }
+        return;
+    }
+
+    let mut patterns = analyzers
+        .iter()
+        .flat_map(|a| a.counters())
+        .map(String::from)
+        .collect::<Vec<String>>();
+    if let Some(profile) = profile {
+        patterns.extend(profile.summary_counters());
+    }
+
+    let mut counter_data = match read_counters(&args.source, &CounterSelection::new(&patterns)) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    let time_filter = args.time_filter.time_filter();
+    for samples in counter_data.samples.values_mut() {
+        *samples = time_filter.apply(std::mem::take(samples));
+    }
+
+    let mut findings = analyzers
+        .iter()
+        .flat_map(|a| a.analyze(&counter_data))
+        .collect::<Vec<Finding>>();
+
+    findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.start.cmp(&b.start)));
+
+    let charts = match &args.charts {
+        Some(dir) => charts::write_finding_charts(
+            dir,
+            &findings,
+            &analyzers,
+            &counter_data,
+            time_filter.offset,
+        ),
+        None => findings.iter().map(|_| None).collect(),
+    };
+
+    print_findings(&findings, &charts);
+    record_outcome(&findings);
+
+    if let Some(profile) = profile {
+        profile.print_summary(&counter_data);
+    }
+}
+
+// Findings set the exit code, so scheduled checks can act on them.
+pub fn record_outcome(findings: &[Finding]) {
+    match findings.iter().map(|f| f.severity).max() {
+        Some(Severity::Critical) => record(Outcome::Critical),
+        Some(Severity::Warning) => record(Outcome::Warning),
+        _ => {}
+    }
+}
+
+pub fn print_findings(findings: &[Finding], charts: &[Option<String>]) {
+    let offset = display_offset();
+    if offset == UtcOffset::UTC {
+        println!("{} findings.", findings.len());
+    } else {
+        println!(
+            "{} findings. Times are {}.",
+            findings.len(),
+            zone_label(offset)
+        );
+    }
+
+    for (finding, chart) in findings.iter().zip(charts) {
+        println!();
+        println!(
+            "[{}] {} {} - {}",
+            finding.severity,
+            finding.analyzer,
+            format_time(finding.start),
+            format_time(finding.end)
+        );
+        println!("  {}", finding.counter);
+        println!("  {}", finding.message);
+        if let Some(chart) = chart {
+            println!("  Chart: {}", chart);
+        }
+    }
+}
+
+pub struct Violation {
+    pub start: OffsetDateTime,
+    pub end: OffsetDateTime,
+    pub peak: f64,
+    pub average: f64,
+}
+
+impl Violation {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+// Finds runs of consecutive samples above the threshold that last at least
+// min_duration, so single-sample blips don't become findings.
+pub fn sustained_above(
+    samples: &[CounterValueWithTime],
+    threshold: f64,
+    min_duration: Duration,
+) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut run_start = None;
+
+    for (i, sample) in samples.iter().enumerate() {
+        if sample.value() > threshold {
+            run_start.get_or_insert(i);
+            continue;
+        }
+
+        if let Some(start) = run_start.take() {
+            push_violation(&samples[start..i], min_duration, &mut violations);
+        }
+    }
+
+    if let Some(start) = run_start {
+        push_violation(&samples[start..], min_duration, &mut violations);
+    }
+
+    violations
+}
+
+fn push_violation(
+    run: &[CounterValueWithTime],
+    min_duration: Duration,
+    violations: &mut Vec<Violation>,
+) {
+    let (first, last) = (&run[0], &run[run.len() - 1]);
+
+    if last.time() - first.time() < min_duration {
+        return;
+    }
+
+    violations.push(Violation {
+        start: first.time(),
+        end: last.time(),
+        peak: run.iter().map(|s| s.value()).fold(f64::MIN, f64::max),
+        average: run.iter().map(|s| s.value()).sum::<f64>() / run.len() as f64,
+    });
+}
+
+pub fn average(samples: &[CounterValueWithTime]) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    Some(samples.iter().map(|s| s.value()).sum::<f64>() / samples.len() as f64)
+}
+
+pub fn average_between(
+    samples: &Series,
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+) -> Option<f64> {
+    let values = samples
+        .iter()
+        .filter(|s| s.time() >= start && s.time() <= end)
+        .map(|s| s.value())
+        .collect::<Vec<f64>>();
+
+    if values.is_empty() {
+        return None;
+    }
+
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
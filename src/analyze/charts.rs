@@ -0,0 +1,110 @@
+use std::path::Path;
+
+use time::{Duration, UtcOffset};
+
+use crate::{
+    analyze::{Analyzer, Finding},
+    chart::write_panels,
+    counter_path::CounterPath,
+    plot::Series,
+    reader::CounterData,
+    selection::counter_matches,
+};
+
+// How much of the log to show on either side of a finding.
+const CONTEXT: Duration = Duration::minutes(30);
+
+const MAX_PANELS: usize = 6;
+
+const PANEL_HEIGHT: u32 = 240;
+
+const WIDTH: u32 = 1280;
+
+// Writes a chart for each finding and returns the path of each chart, or None
+// when there was nothing to draw.
+pub fn write_finding_charts(
+    dir: &str,
+    findings: &[Finding],
+    analyzers: &[Box<dyn Analyzer>],
+    data: &CounterData,
+    offset: UtcOffset,
+) -> Vec<Option<String>> {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        eprintln!("Failed to create {}: {}", dir, e);
+        return findings.iter().map(|_| None).collect();
+    }
+
+    findings
+        .iter()
+        .enumerate()
+        .map(|(index, finding)| {
+            let (start, end) = (finding.start - CONTEXT, finding.end + CONTEXT);
+
+            let series = chart_counters(finding, analyzers, data)
+                .into_iter()
+                .take(MAX_PANELS)
+                .map(|c| Series {
+                    name: c.clone(),
+                    points: data.samples[c]
+                        .iter()
+                        .filter(|s| s.time() >= start && s.time() <= end)
+                        .map(|s| (s.time(), s.value()))
+                        .collect(),
+                })
+                .filter(|s| !s.points.is_empty())
+                .collect::<Vec<Series>>();
+
+            if series.is_empty() {
+                return None;
+            }
+
+            let file_name = format!("{:03}-{}.png", index + 1, finding.analyzer);
+            let path = Path::new(dir).join(file_name).display().to_string();
+            let size = (WIDTH, PANEL_HEIGHT * series.len() as u32);
+
+            match write_panels(&path, &series, (finding.start, finding.end), size, offset) {
+                Ok(()) => Some(path),
+                Err(e) => {
+                    eprintln!("Failed to write {}: {}", path, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// The finding's own counter when it's a real one. Otherwise the finding names
+// something like a disk or a machine-wide score, so chart the analyzer's
+// counters under that name, or on that machine.
+fn chart_counters<'a>(
+    finding: &Finding,
+    analyzers: &[Box<dyn Analyzer>],
+    data: &'a CounterData,
+) -> Vec<&'a String> {
+    if let Some((counter, _)) = data.samples.get_key_value(&finding.counter) {
+        return vec![counter];
+    }
+
+    let prefix = format!("{}\\", finding.counter);
+    let under = data
+        .counters
+        .iter()
+        .filter(|c| c.starts_with(&prefix))
+        .collect::<Vec<&String>>();
+    if !under.is_empty() {
+        return under;
+    }
+
+    let machine = CounterPath::parse(&finding.counter).map(|p| p.machine);
+    let patterns = analyzers
+        .iter()
+        .filter(|a| a.name() == finding.analyzer)
+        .flat_map(|a| a.counters())
+        .collect::<Vec<&str>>();
+
+    data.counters
+        .iter()
+        .filter(|c| CounterPath::parse(c).map(|p| p.machine) == machine)
+        .filter(|c| patterns.iter().any(|p| counter_matches(p, c)))
+        .collect()
+}
@@ -0,0 +1,352 @@
+use std::collections::BTreeMap;
+
+use time::Duration;
+
+use crate::{
+    analyze::{average_between, sustained_above, Analyzer, Finding, Severity},
+    counter_path::CounterPath,
+    reader::CounterData,
+    series::Series,
+    timespec::format_duration,
+};
+
+const OBJECT: &str = "Processor Information";
+
+const PROCESSOR_TIME: &str = "% Processor Time";
+const DPC_TIME: &str = "% DPC Time";
+const INTERRUPT_TIME: &str = "% Interrupt Time";
+
+// A core this busy can't take more work, and one thread, or one core's
+// interrupts, is as fast as it gets.
+const SATURATED_PERCENT: f64 = 90.0;
+const PEGGED_PERCENT: f64 = 98.0;
+
+// Below this, _Total looks like there's plenty of CPU left, which is what
+// hides a saturated core.
+const TOTAL_LOW_PERCENT: f64 = 50.0;
+
+// When DPCs and interrupts are this much of a saturated core's time, a
+// driver is keeping it busy rather than a thread.
+const DRIVER_SHARE: f64 = 0.3;
+
+const MIN_DURATION: Duration = Duration::minutes(2);
+
+// How many of the busiest cores the summary lists for each machine.
+const TOP_CORES: usize = 8;
+
+const THREAD_ADVICE: &str = "Work that runs on one thread is limited by this core no matter how \
+     idle the others are; look for a single-threaded process, or a lock that serializes work, \
+     busy over the same time.";
+
+const DRIVER_ADVICE: &str = "Much of it was DPCs and interrupts, so a driver is keeping the core \
+     busy; check that RSS spreads the NIC's interrupts across cores, and that storage and NIC \
+     drivers are current.";
+
+pub struct CpuAnalyzer;
+
+impl Analyzer for CpuAnalyzer {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn description(&self) -> &'static str {
+        "Single cores saturated while _Total looks low, by NUMA node"
+    }
+
+    fn counters(&self) -> Vec<&'static str> {
+        vec![
+            "\\Processor Information(*)\\% Processor Time",
+            "\\Processor Information(*)\\% DPC Time",
+            "\\Processor Information(*)\\% Interrupt Time",
+        ]
+    }
+
+    fn analyze(&self, data: &CounterData) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for (counter, samples) in data.matching(&format!("\\{}(*)\\{}", OBJECT, PROCESSOR_TIME)) {
+            let path = match CounterPath::parse(counter) {
+                Some(path) => path,
+                None => continue,
+            };
+            let (node, core) = match path.instance.as_deref().and_then(node_and_core) {
+                Some((node, Some(core))) => (node, core),
+                _ => continue,
+            };
+
+            let total = sibling(data, &path, "_Total", PROCESSOR_TIME);
+            let node_total = sibling(data, &path, &format!("{},_Total", node), PROCESSOR_TIME);
+            let instance = path.instance.as_deref().unwrap_or_default();
+            let dpc = sibling(data, &path, instance, DPC_TIME);
+            let interrupt = sibling(data, &path, instance, INTERRUPT_TIME);
+
+            for v in sustained_above(&samples.to_vec(), SATURATED_PERCENT, MIN_DURATION) {
+                // A busy _Total is the high CPU other analyzers and charts
+                // already make plain.
+                let total_average = match total.and_then(|t| average_between(t, v.start, v.end)) {
+                    Some(total_average) if total_average < TOTAL_LOW_PERCENT => total_average,
+                    _ => continue,
+                };
+
+                let severity = if v.average >= PEGGED_PERCENT {
+                    Severity::Critical
+                } else {
+                    Severity::Warning
+                };
+
+                let mut message = format!(
+                    "Core {} on NUMA node {} averaged {:.1}% (peak {:.1}%) for {} while _Total averaged {:.1}%",
+                    core,
+                    node,
+                    v.average,
+                    v.peak,
+                    format_duration(v.duration()),
+                    total_average
+                );
+                if let Some(node_average) =
+                    node_total.and_then(|n| average_between(n, v.start, v.end))
+                {
+                    message.push_str(&format!(" and node {} {:.1}%", node, node_average));
+                }
+                message.push_str(". ");
+
+                let driver_time = [dpc, interrupt]
+                    .iter()
+                    .filter_map(|s| s.and_then(|s| average_between(s, v.start, v.end)))
+                    .sum::<f64>();
+                if driver_time >= v.average * DRIVER_SHARE {
+                    message.push_str(DRIVER_ADVICE);
+                } else {
+                    message.push_str(THREAD_ADVICE);
+                }
+
+                findings.push(Finding {
+                    analyzer: self.name(),
+                    severity,
+                    counter: counter.clone(),
+                    start: v.start,
+                    end: v.end,
+                    message,
+                });
+            }
+        }
+
+        findings
+    }
+}
+
+// Processor Information instances are named node,core, like 0,3, with
+// 0,_Total for a node and _Total for the machine. Returns the node, and the
+// core unless it's a node's total.
+fn node_and_core(instance: &str) -> Option<(&str, Option<&str>)> {
+    let (node, core) = instance.split_once(',')?;
+    match core {
+        "_Total" => Some((node, None)),
+        _ => Some((node, Some(core))),
+    }
+}
+
+// Another counter of the same machine's Processor Information.
+fn sibling<'a>(
+    data: &'a CounterData,
+    path: &CounterPath,
+    instance: &str,
+    counter: &str,
+) -> Option<&'a Series> {
+    let sibling = CounterPath {
+        machine: path.machine.clone(),
+        object: path.object.clone(),
+        instance: Some(instance.to_string()),
+        counter: counter.to_string(),
+    };
+    data.samples.get(&sibling.to_string())
+}
+
+// The counters the summary reads.
+pub fn summary_counters() -> Vec<String> {
+    [PROCESSOR_TIME, DPC_TIME, INTERRUPT_TIME]
+        .iter()
+        .map(|counter| format!("\\{}(*)\\{}", OBJECT, counter))
+        .collect()
+}
+
+#[derive(Default)]
+struct Core<'a> {
+    processor: Option<&'a Series>,
+    dpc: Option<&'a Series>,
+    interrupt: Option<&'a Series>,
+}
+
+#[derive(Default)]
+struct Machine<'a> {
+    total: Option<&'a Series>,
+    // By node, then core, both sorted by number rather than text so node 10
+    // comes after node 9.
+    nodes: BTreeMap<u32, Node<'a>>,
+}
+
+#[derive(Default)]
+struct Node<'a> {
+    total: Option<&'a Series>,
+    cores: BTreeMap<u32, Core<'a>>,
+}
+
+// How busy each machine's NUMA nodes were, with their DPC and interrupt
+// time, and the busiest cores.
+pub fn print_summary(data: &CounterData) {
+    let mut machines = BTreeMap::<String, Machine>::new();
+
+    for counter in &data.counters {
+        let path = match CounterPath::parse(counter) {
+            Some(path) if path.object.eq_ignore_ascii_case(OBJECT) => path,
+            _ => continue,
+        };
+        let instance = match &path.instance {
+            Some(instance) => instance,
+            None => continue,
+        };
+        let samples = &data.samples[counter];
+        let machine = machines.entry(path.machine.clone()).or_default();
+        let is_processor_time = path.counter.eq_ignore_ascii_case(PROCESSOR_TIME);
+
+        if instance == "_Total" {
+            if is_processor_time {
+                machine.total = Some(samples);
+            }
+            continue;
+        }
+
+        let (node, core) = match node_and_core(instance) {
+            Some((node, core)) => match node.parse::<u32>() {
+                Ok(node) => (node, core),
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        let node = machine.nodes.entry(node).or_default();
+
+        let core = match core.map(|c| c.parse::<u32>()) {
+            Some(Ok(core)) => node.cores.entry(core).or_default(),
+            Some(Err(_)) => continue,
+            None => {
+                if is_processor_time {
+                    node.total = Some(samples);
+                }
+                continue;
+            }
+        };
+        if is_processor_time {
+            core.processor = Some(samples);
+        } else if path.counter.eq_ignore_ascii_case(DPC_TIME) {
+            core.dpc = Some(samples);
+        } else if path.counter.eq_ignore_ascii_case(INTERRUPT_TIME) {
+            core.interrupt = Some(samples);
+        }
+    }
+
+    machines.retain(|_, machine| !machine.nodes.is_empty());
+
+    for (name, machine) in &machines {
+        println!();
+        println!("CPU topology of \\\\{}:", name);
+        print_machine(machine);
+        print_nodes(machine);
+        print_busiest_cores(machine);
+    }
+}
+
+fn average(samples: Option<&Series>) -> Option<f64> {
+    let samples = samples.filter(|s| !s.is_empty())?;
+    Some(samples.values().sum::<f64>() / samples.len() as f64)
+}
+
+fn peak(samples: Option<&Series>) -> Option<f64> {
+    samples.and_then(|s| s.values().reduce(f64::max))
+}
+
+// The average of the cores' averages, for a counter of each core.
+fn cores_average<'a>(
+    cores: impl Iterator<Item = &'a Core<'a>>,
+    counter: impl Fn(&Core<'a>) -> Option<&'a Series>,
+) -> Option<f64> {
+    let averages = cores
+        .filter_map(|c| average(counter(c)))
+        .collect::<Vec<f64>>();
+    (!averages.is_empty()).then(|| averages.iter().sum::<f64>() / averages.len() as f64)
+}
+
+fn format(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{:.1}%", v))
+}
+
+fn print_machine(machine: &Machine) {
+    let cores = machine.nodes.values().map(|n| n.cores.len()).sum::<usize>();
+    println!(
+        "  {} NUMA node{}, {} logical processors, {} average and {} at the peak",
+        machine.nodes.len(),
+        if machine.nodes.len() == 1 { "" } else { "s" },
+        cores,
+        format(average(machine.total)),
+        format(peak(machine.total))
+    );
+}
+
+fn print_nodes(machine: &Machine) {
+    println!(
+        "  {:>4}  {:>5}  {:>7}  {:>7}  {:>7}  {:>9}  Busiest core",
+        "Node", "Cores", "Avg", "Peak", "DPC", "Interrupt"
+    );
+    for (number, node) in &machine.nodes {
+        let busiest = node
+            .cores
+            .iter()
+            .filter_map(|(n, c)| average(c.processor).map(|a| (n, a)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(n, a)| format!("{} at {:.1}%", n, a))
+            .unwrap_or_else(|| "-".to_string());
+
+        println!(
+            "  {:>4}  {:>5}  {:>7}  {:>7}  {:>7}  {:>9}  {}",
+            number,
+            node.cores.len(),
+            format(average(node.total)),
+            format(peak(node.total)),
+            format(cores_average(node.cores.values(), |c| c.dpc)),
+            format(cores_average(node.cores.values(), |c| c.interrupt)),
+            busiest
+        );
+    }
+}
+
+fn print_busiest_cores(machine: &Machine) {
+    let mut cores = machine
+        .nodes
+        .iter()
+        .flat_map(|(node, n)| n.cores.iter().map(move |(core, c)| (*node, *core, c)))
+        .filter_map(|(node, core, c)| average(c.processor).map(|a| (node, core, c, a)))
+        .collect::<Vec<(u32, u32, &Core, f64)>>();
+    if cores.is_empty() {
+        return;
+    }
+    cores.sort_by(|a, b| b.3.total_cmp(&a.3));
+
+    println!(
+        "  Busiest {} of {} cores:",
+        TOP_CORES.min(cores.len()),
+        cores.len()
+    );
+    println!(
+        "  {:>9}  {:>7}  {:>7}  {:>7}  {:>9}",
+        "Node,Core", "Avg", "Peak", "DPC", "Interrupt"
+    );
+    for (node, core, c, avg) in cores.iter().take(TOP_CORES) {
+        println!(
+            "  {:>9}  {:>7}  {:>7}  {:>7}  {:>9}",
+            format!("{},{}", node, core),
+            format(Some(*avg)),
+            format(peak(c.processor)),
+            format(average(c.dpc)),
+            format(average(c.interrupt))
+        );
+    }
+}
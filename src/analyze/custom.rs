@@ -0,0 +1,275 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    analyze::{
+        all_builtin_analyzers,
+        threshold::{per_second, Direction, ThresholdProfile, ThresholdRule},
+    },
+    counter_path::CounterPath,
+    pdh_helper::CounterValueWithTime,
+    reader::CounterData,
+    timespec::parse_duration,
+};
+
+static PROFILES_DIR: OnceLock<Option<String>> = OnceLock::new();
+static PROFILES: OnceLock<Vec<ThresholdProfile>> = OnceLock::new();
+
+// Set once at startup from --profiles-dir.
+pub fn set_profiles_dir(dir: Option<String>) {
+    let _ = PROFILES_DIR.set(dir);
+}
+
+// A profile file is TOML, like:
+//
+//   name = "sql"
+//   description = "SQL Server buffer pool and lock waits"
+//
+//   [[rule]]
+//   counter = '\SQLServer:Buffer Manager\Page life expectancy'
+//   direction = "below"
+//   warning = 300
+//   critical = 100
+//   duration = "5m"
+//   unit = " s"
+//   advice = "Pages leave the buffer pool quickly; SQL Server needs more memory."
+//
+// duration defaults to 1m, scale to 1, and unit and advice to nothing. Set
+// cumulative = true for counters that are running totals.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(rename = "rule")]
+    rules: Vec<RuleFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    counter: String,
+    direction: Direction,
+    warning: f64,
+    critical: f64,
+    #[serde(default = "default_duration")]
+    duration: String,
+    #[serde(default = "default_scale")]
+    scale: f64,
+    #[serde(default)]
+    unit: String,
+    #[serde(default)]
+    cumulative: bool,
+    #[serde(default)]
+    advice: String,
+}
+
+fn default_duration() -> String {
+    "1m".to_string()
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+// The profiles in the profiles directory, read the first time they're asked
+// for. Files that can't be read are reported and skipped.
+pub fn file_profiles() -> &'static [ThresholdProfile] {
+    PROFILES.get_or_init(|| {
+        let dir = match profiles_dir() {
+            Some(dir) => dir,
+            None => return Vec::new(),
+        };
+        load_profiles(&dir)
+    })
+}
+
+// --profiles-dir, or perflogtool\profiles under the user's roaming app data
+// when it exists.
+fn profiles_dir() -> Option<PathBuf> {
+    if let Some(dir) = PROFILES_DIR.get().cloned().flatten() {
+        return Some(PathBuf::from(dir));
+    }
+
+    let dir = PathBuf::from(std::env::var_os("APPDATA")?)
+        .join("perflogtool")
+        .join("profiles");
+    dir.is_dir().then_some(dir)
+}
+
+fn load_profiles(dir: &Path) -> Vec<ThresholdProfile> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to read the profiles in {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let mut paths = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("toml"))
+        })
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+
+    let builtin = all_builtin_analyzers()
+        .iter()
+        .map(|a| a.name())
+        .collect::<Vec<&str>>();
+
+    let mut profiles = Vec::<ThresholdProfile>::new();
+    for path in paths {
+        let profile = match load_profile(&path) {
+            Ok(profile) => profile,
+            Err(e) => {
+                eprintln!("Skipping the profile {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let taken = builtin
+            .iter()
+            .copied()
+            .chain(profiles.iter().map(|p| p.name))
+            .any(|name| name.eq_ignore_ascii_case(profile.name));
+        if taken {
+            eprintln!(
+                "Skipping the profile {}: there's already an analyzer named {}.",
+                path.display(),
+                profile.name
+            );
+            continue;
+        }
+
+        profiles.push(profile);
+    }
+
+    profiles
+}
+
+fn load_profile(path: &Path) -> Result<ThresholdProfile, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file = toml::from_str::<ProfileFile>(&text).map_err(|e| e.to_string())?;
+
+    if file.name.trim().is_empty() {
+        return Err("the profile has no name".to_string());
+    }
+    if file.rules.is_empty() {
+        return Err("the profile has no rules".to_string());
+    }
+
+    let mut rules = Vec::new();
+    for rule in file.rules {
+        let min_duration = parse_duration(&rule.duration)
+            .map_err(|e| format!("bad duration for {}: {}", rule.counter, e))?;
+        rules.push(ThresholdRule {
+            counter: leak(rule.counter),
+            direction: rule.direction,
+            warning: rule.warning,
+            critical: rule.critical,
+            min_duration,
+            scale: rule.scale,
+            unit: leak(rule.unit),
+            cumulative: rule.cumulative,
+            advice: leak(rule.advice),
+        });
+    }
+
+    Ok(ThresholdProfile {
+        name: leak(file.name),
+        description: leak(file.description),
+        rules,
+    })
+}
+
+// Analyzers are named and described by static strings, and profiles are
+// loaded once and kept for the rest of the run, so their text is leaked
+// rather than changing every analyzer to own it.
+fn leak(text: String) -> &'static str {
+    Box::leak(text.into_boxed_str())
+}
+
+// A row per counter a rule of the profile matched, with its average and
+// worst value, and how much of the capture it was past the warning
+// threshold.
+pub fn print_summary(profile: &ThresholdProfile, data: &CounterData) {
+    let mut rows = Vec::new();
+
+    for rule in &profile.rules {
+        for (counter, samples) in data.matching(rule.counter) {
+            let is_total = CounterPath::parse(counter)
+                .and_then(|p| p.instance)
+                .is_some_and(|i| i == "_Total");
+            if is_total && !rule.counter.contains("_Total") {
+                continue;
+            }
+
+            let samples = samples.to_vec();
+            let samples = if rule.cumulative {
+                per_second(&samples)
+            } else {
+                samples
+            };
+            let values = samples
+                .iter()
+                .map(CounterValueWithTime::value)
+                .map(|v| v * rule.scale)
+                .collect::<Vec<f64>>();
+            if values.is_empty() {
+                continue;
+            }
+
+            let past = |v: f64, threshold: f64| match rule.direction {
+                Direction::Above => v > threshold,
+                Direction::Below => v < threshold,
+            };
+            let average = values.iter().sum::<f64>() / values.len() as f64;
+            let worst = match rule.direction {
+                Direction::Above => values.iter().copied().fold(f64::MIN, f64::max),
+                Direction::Below => values.iter().copied().fold(f64::MAX, f64::min),
+            };
+            let warning = values.iter().filter(|v| past(**v, rule.warning)).count() as f64
+                / values.len() as f64
+                * 100.0;
+
+            rows.push((
+                format!("{:.2}{}", average, rule.unit),
+                format!("{:.2}{}", worst, rule.unit),
+                warning,
+                counter.clone(),
+            ));
+        }
+    }
+
+    if rows.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("{}:", profile.description_or_name());
+    println!("{:>12}  {:>12}  {:>7}  Counter", "Avg", "Worst", "Warning");
+    for (average, worst, warning, counter) in rows {
+        println!(
+            "{:>12}  {:>12}  {:>6.0}%  {}",
+            average, worst, warning, counter
+        );
+    }
+}
+
+impl ThresholdProfile {
+    fn description_or_name(&self) -> &'static str {
+        if self.description.is_empty() {
+            self.name
+        } else {
+            self.description
+        }
+    }
+}
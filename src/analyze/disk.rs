@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+
+use time::Duration;
+
+use crate::{
+    analyze::threshold::{Direction, ThresholdProfile, ThresholdRule},
+    counter_path::CounterPath,
+    reader::CounterData,
+    units::Unit,
+};
+
+// Microsoft's guidance for disk latency: under 10 ms is good, and over 25 ms
+// is slow enough for users to notice.
+const SLOW_MS: f64 = 25.0;
+const CRITICAL_MS: f64 = 50.0;
+
+const LATENCY_ADVICE: &str = "Requests are waiting on the disk; compare with the queue length \
+     and Disk Transfers/sec to tell a busy disk from slow storage behind it.";
+
+const FREE_SPACE_ADVICE: &str = "The volume is nearly full; free up space or extend it before \
+     writes start failing.";
+
+const LATENCY: &str = "Avg. Disk sec/Transfer";
+const READ_LATENCY: &str = "Avg. Disk sec/Read";
+const WRITE_LATENCY: &str = "Avg. Disk sec/Write";
+const QUEUE: &str = "Avg. Disk Queue Length";
+const TRANSFERS: &str = "Disk Transfers/sec";
+const BYTES: &str = "Disk Bytes/sec";
+const IDLE: &str = "% Idle Time";
+
+const SUMMARY_COUNTERS: [&str; 7] = [
+    LATENCY,
+    READ_LATENCY,
+    WRITE_LATENCY,
+    QUEUE,
+    TRANSFERS,
+    BYTES,
+    IDLE,
+];
+
+// Read and write latency of volumes and disks. The storage analyzer already
+// looks at PhysicalDisk sec/Transfer against the queue.
+pub fn profile() -> ThresholdProfile {
+    let mut rules = Vec::new();
+
+    for counter in [
+        "\\LogicalDisk(*)\\Avg. Disk sec/Transfer",
+        "\\LogicalDisk(*)\\Avg. Disk sec/Read",
+        "\\LogicalDisk(*)\\Avg. Disk sec/Write",
+        "\\PhysicalDisk(*)\\Avg. Disk sec/Read",
+        "\\PhysicalDisk(*)\\Avg. Disk sec/Write",
+    ] {
+        rules.push(ThresholdRule {
+            counter,
+            direction: Direction::Above,
+            warning: SLOW_MS,
+            critical: CRITICAL_MS,
+            min_duration: Duration::minutes(2),
+            scale: 1000.0,
+            unit: " ms",
+            cumulative: false,
+            advice: LATENCY_ADVICE,
+        });
+    }
+
+    rules.push(ThresholdRule {
+        counter: "\\LogicalDisk(*)\\% Free Space",
+        direction: Direction::Below,
+        warning: 10.0,
+        critical: 5.0,
+        min_duration: Duration::minutes(1),
+        scale: 1.0,
+        unit: "%",
+        cumulative: false,
+        advice: FREE_SPACE_ADVICE,
+    });
+
+    ThresholdProfile {
+        name: "disk",
+        description: "Volume and disk read and write latency, and volumes running out of space",
+        rules,
+    }
+}
+
+// The counters the summary table reads for every disk.
+pub fn summary_counters() -> Vec<String> {
+    ["LogicalDisk", "PhysicalDisk"]
+        .iter()
+        .flat_map(|object| SUMMARY_COUNTERS.map(|counter| format!("\\{}(*)\\{}", object, counter)))
+        .collect()
+}
+
+// The values of one disk's counters over the capture, by counter name.
+type DiskCounters = BTreeMap<&'static str, Vec<f64>>;
+
+// A row per volume and disk with its average latency, how much of the capture
+// it was slow for, and the load it was under.
+pub fn print_summary(data: &CounterData) {
+    let mut disks = BTreeMap::<String, DiskCounters>::new();
+
+    for counter in &data.counters {
+        let path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+        let name = match SUMMARY_COUNTERS
+            .into_iter()
+            .find(|name| path.counter.eq_ignore_ascii_case(name))
+        {
+            Some(name) => name,
+            None => continue,
+        };
+        let instance = match &path.instance {
+            Some(instance) if instance != "_Total" => instance,
+            _ => continue,
+        };
+        if path.object != "LogicalDisk" && path.object != "PhysicalDisk" {
+            continue;
+        }
+
+        let disk = format!("\\\\{}\\{}({})", path.machine, path.object, instance);
+        let values = data.samples[counter].iter().map(|s| s.value()).collect();
+        disks.entry(disk).or_default().insert(name, values);
+    }
+
+    disks.retain(|_, counters| counters.contains_key(LATENCY));
+    if disks.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("Disk health (latency in ms):");
+    println!(
+        "{:>8}  {:>8}  {:>8}  {:>6}  {:>6}  {:>8}  {:>12}  {:>5}  {:<8}  Disk",
+        "Avg", "Read", "Write", "Slow", "Queue", "IOPS", "Throughput", "Idle", "Health"
+    );
+
+    for (disk, counters) in &disks {
+        let average = |name: &str| {
+            counters
+                .get(name)
+                .filter(|v| !v.is_empty())
+                .map(|v| v.iter().sum::<f64>() / v.len() as f64)
+        };
+        let format =
+            |value: Option<f64>, f: &dyn Fn(f64) -> String| value.map_or("-".to_string(), f);
+
+        let latency = &counters[LATENCY];
+        let slow = latency.iter().filter(|v| **v * 1000.0 > SLOW_MS).count() as f64
+            / latency.len().max(1) as f64;
+        let average_ms = average(LATENCY).unwrap_or(0.0) * 1000.0;
+
+        println!(
+            "{:>8.1}  {:>8}  {:>8}  {:>5.0}%  {:>6}  {:>8}  {:>12}  {:>5}  {:<8}  {}",
+            average_ms,
+            format(average(READ_LATENCY), &|v| format!("{:.1}", v * 1000.0)),
+            format(average(WRITE_LATENCY), &|v| format!("{:.1}", v * 1000.0)),
+            slow * 100.0,
+            format(average(QUEUE), &|v| format!("{:.1}", v)),
+            format(average(TRANSFERS), &|v| format!("{:.0}", v)),
+            format(average(BYTES), &|v| format!(
+                "{}/s",
+                Unit::Bytes(1.0).format(v)
+            )),
+            format(average(IDLE), &|v| format!("{:.0}%", v.min(100.0))),
+            health(average_ms, slow),
+            disk
+        );
+    }
+}
+
+// Critical when latency is bad on average or for a quarter of the capture,
+// slow when it is for more than a few percent of it.
+fn health(average_ms: f64, slow: f64) -> &'static str {
+    if average_ms >= CRITICAL_MS || slow >= 0.25 {
+        "Critical"
+    } else if average_ms >= SLOW_MS || slow >= 0.05 {
+        "Slow"
+    } else {
+        "OK"
+    }
+}
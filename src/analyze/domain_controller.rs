@@ -0,0 +1,117 @@
+use time::Duration;
+
+use crate::analyze::threshold::{Direction, ThresholdProfile, ThresholdRule};
+
+const ATQ_ADVICE: &str = "Requests are waiting for an LDAP worker thread, so the DC is \
+     overloaded; look for expensive or inefficient LDAP queries and at LSASS CPU.";
+
+const LDAP_ADVICE: &str = "Slow LDAP responses hold up every client of this DC; check LSASS \
+     CPU, disk latency on the NTDS database volume, and expensive queries.";
+
+const LSASS_ADVICE: &str = "LSASS is busy handling directory and authentication requests; \
+     a Data Collector Set with the Active Directory Diagnostics template shows which clients \
+     and queries are responsible.";
+
+const NETLOGON_ADVICE: &str = "NTLM authentications are queuing for Netlogon; consider raising \
+     MaxConcurrentApi or moving clients to Kerberos.";
+
+const ADACCESS_ADVICE: &str = "Exchange is seeing slow responses from this domain controller; \
+     check the DC side of the same time range.";
+
+pub fn profile() -> ThresholdProfile {
+    ThresholdProfile {
+        name: "dc",
+        description: "Domain controller health from NTDS, LSASS, Netlogon, and Exchange ADAccess",
+        rules: vec![
+            ThresholdRule {
+                counter: "\\NTDS\\ATQ Estimated Queue Delay",
+                direction: Direction::Above,
+                warning: 1.0,
+                critical: 100.0,
+                min_duration: Duration::minutes(2),
+                scale: 1.0,
+                unit: " ms",
+                cumulative: false,
+                advice: ATQ_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\NTDS\\ATQ Outstanding Queued Requests",
+                direction: Direction::Above,
+                warning: 1.0,
+                critical: 20.0,
+                min_duration: Duration::minutes(2),
+                scale: 1.0,
+                unit: "",
+                cumulative: false,
+                advice: ATQ_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\NTDS\\ATQ Request Latency",
+                direction: Direction::Above,
+                warning: 100.0,
+                critical: 500.0,
+                min_duration: Duration::minutes(2),
+                scale: 1.0,
+                unit: " ms",
+                cumulative: false,
+                advice: LDAP_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\NTDS\\LDAP Bind Time",
+                direction: Direction::Above,
+                warning: 50.0,
+                critical: 100.0,
+                min_duration: Duration::minutes(2),
+                scale: 1.0,
+                unit: " ms",
+                cumulative: false,
+                advice: LDAP_ADVICE,
+            },
+            // % Processor Time for a process is relative to one core.
+            ThresholdRule {
+                counter: "\\Process(lsass)\\% Processor Time",
+                direction: Direction::Above,
+                warning: 80.0,
+                critical: 200.0,
+                min_duration: Duration::minutes(5),
+                scale: 1.0,
+                unit: "%",
+                cumulative: false,
+                advice: LSASS_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\Netlogon(*)\\Semaphore Waiters",
+                direction: Direction::Above,
+                warning: 1.0,
+                critical: 10.0,
+                min_duration: Duration::minutes(1),
+                scale: 1.0,
+                unit: "",
+                cumulative: false,
+                advice: NETLOGON_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\MSExchange ADAccess Domain Controllers(*)\\LDAP Read Time",
+                direction: Direction::Above,
+                warning: 50.0,
+                critical: 100.0,
+                min_duration: Duration::minutes(2),
+                scale: 1.0,
+                unit: " ms",
+                cumulative: false,
+                advice: ADACCESS_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\MSExchange ADAccess Domain Controllers(*)\\LDAP Search Time",
+                direction: Direction::Above,
+                warning: 50.0,
+                critical: 100.0,
+                min_duration: Duration::minutes(2),
+                scale: 1.0,
+                unit: " ms",
+                cumulative: false,
+                advice: ADACCESS_ADVICE,
+            },
+        ],
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    analyze::threshold::{Direction, ThresholdProfile, ThresholdRule},
+    counter_path::CounterPath,
+    reader::CounterData,
+    series::Series,
+};
+
+// Microsoft's guidance for % Total Run Time of the host's logical
+// processors: under 60% is healthy, and past 90% the VMs are competing for
+// them.
+const BUSY_PERCENT: f64 = 75.0;
+const CONTENDED_PERCENT: f64 = 90.0;
+
+const LOGICAL: &str = "Hyper-V Hypervisor Logical Processor";
+const VIRTUAL: &str = "Hyper-V Hypervisor Virtual Processor";
+const ROOT: &str = "Hyper-V Hypervisor Root Virtual Processor";
+const DYNAMIC_MEMORY: &str = "Hyper-V Dynamic Memory VM";
+
+const RUN_TIME: &str = "% Total Run Time";
+const WAIT_PER_DISPATCH: &str = "CPU Wait Time Per Dispatch";
+const PHYSICAL_MEMORY: &str = "Physical Memory";
+const PRESSURE: &str = "Current Pressure";
+
+const HOST_ADVICE: &str = "The host's logical processors are nearly always busy, so virtual \
+     processors wait to run; move VMs to another host or reduce their virtual processors.";
+
+const DISPATCH_ADVICE: &str = "The virtual processor waited a long time to be scheduled on a \
+     logical processor, which the VM sees as slow CPU even when its own usage looks low.";
+
+const PRESSURE_ADVICE: &str = "Dynamic memory can't give the VM as much memory as it's asking \
+     for; raise its maximum memory, or free up memory on the host.";
+
+// Thresholds for counters of a Hyper-V host. The % Total Run Time of
+// processors inside the VMs is misleading, so the host's view is what's
+// judged.
+pub fn profile() -> ThresholdProfile {
+    ThresholdProfile {
+        name: "hyperv",
+        description: "Hyper-V host CPU contention, VP dispatch wait, and dynamic memory pressure",
+        rules: vec![
+            ThresholdRule {
+                counter: "\\Hyper-V Hypervisor Logical Processor(_Total)\\% Total Run Time",
+                direction: Direction::Above,
+                warning: BUSY_PERCENT,
+                critical: CONTENDED_PERCENT,
+                min_duration: Duration::minutes(5),
+                scale: 1.0,
+                unit: "%",
+                cumulative: false,
+                advice: HOST_ADVICE,
+            },
+            // In nanoseconds, checked in microseconds.
+            ThresholdRule {
+                counter: "\\Hyper-V Hypervisor Virtual Processor(*)\\CPU Wait Time Per Dispatch",
+                direction: Direction::Above,
+                warning: 50.0,
+                critical: 100.0,
+                min_duration: Duration::minutes(2),
+                scale: 0.001,
+                unit: " us",
+                cumulative: false,
+                advice: DISPATCH_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\Hyper-V Dynamic Memory VM(*)\\Current Pressure",
+                direction: Direction::Above,
+                warning: 100.0,
+                critical: 120.0,
+                min_duration: Duration::minutes(2),
+                scale: 1.0,
+                unit: "%",
+                cumulative: false,
+                advice: PRESSURE_ADVICE,
+            },
+        ],
+    }
+}
+
+// The counters the summary reads.
+pub fn summary_counters() -> Vec<String> {
+    vec![
+        format!("\\{}(*)\\{}", LOGICAL, RUN_TIME),
+        format!("\\{}(*)\\{}", ROOT, RUN_TIME),
+        format!("\\{}(*)\\{}", VIRTUAL, RUN_TIME),
+        format!("\\{}(*)\\{}", VIRTUAL, WAIT_PER_DISPATCH),
+        format!("\\{}(*)\\{}", DYNAMIC_MEMORY, PHYSICAL_MEMORY),
+        format!("\\{}(*)\\{}", DYNAMIC_MEMORY, PRESSURE),
+    ]
+}
+
+#[derive(Default)]
+struct Host<'a> {
+    logical_processors: usize,
+    total_run_time: Option<&'a Series>,
+    // % Total Run Time summed across the root's or a VM's virtual
+    // processors at each collection.
+    root: BTreeMap<OffsetDateTime, f64>,
+    vms: BTreeMap<String, Vm<'a>>,
+}
+
+#[derive(Default)]
+struct Vm<'a> {
+    virtual_processors: usize,
+    run_time: BTreeMap<OffsetDateTime, f64>,
+    waits: Vec<&'a Series>,
+    memory: Option<&'a Series>,
+    pressure: Option<&'a Series>,
+}
+
+// How busy each host was, and a row per VM with its share of the host's
+// processors and its dynamic memory.
+pub fn print_summary(data: &CounterData) {
+    let mut hosts = BTreeMap::<String, Host>::new();
+
+    for counter in &data.counters {
+        let path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+        let instance = match &path.instance {
+            Some(instance) => instance,
+            None => continue,
+        };
+        let samples = &data.samples[counter];
+        let host = hosts.entry(path.machine.clone()).or_default();
+        let is_run_time = path.counter.eq_ignore_ascii_case(RUN_TIME);
+
+        match path.object.as_str() {
+            LOGICAL if is_run_time => {
+                if instance == "_Total" {
+                    host.total_run_time = Some(samples);
+                } else {
+                    host.logical_processors += 1;
+                }
+            }
+            ROOT if is_run_time && instance != "_Total" => add(&mut host.root, samples),
+            VIRTUAL if instance != "_Total" => {
+                // Virtual processors are named like VM01:Hv VP 0.
+                let name = match instance.rsplit_once(':') {
+                    Some((name, _)) => name.to_string(),
+                    None => continue,
+                };
+                let vm = host.vms.entry(name).or_default();
+                if is_run_time {
+                    vm.virtual_processors += 1;
+                    add(&mut vm.run_time, samples);
+                } else if path.counter.eq_ignore_ascii_case(WAIT_PER_DISPATCH) {
+                    vm.waits.push(samples);
+                }
+            }
+            DYNAMIC_MEMORY if instance != "_Total" => {
+                let vm = host.vms.entry(instance.clone()).or_default();
+                if path.counter.eq_ignore_ascii_case(PHYSICAL_MEMORY) {
+                    vm.memory = Some(samples);
+                } else if path.counter.eq_ignore_ascii_case(PRESSURE) {
+                    vm.pressure = Some(samples);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    hosts.retain(|_, host| host.total_run_time.is_some() || !host.vms.is_empty());
+
+    for (machine, host) in &hosts {
+        println!();
+        println!("Hyper-V host \\\\{}:", machine);
+        print_host(host);
+        print_vms(host);
+    }
+}
+
+fn add(target: &mut BTreeMap<OffsetDateTime, f64>, samples: &Series) {
+    for sample in samples.iter() {
+        *target.entry(sample.time()).or_default() += sample.value();
+    }
+}
+
+fn average(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+fn format(value: Option<f64>, f: &dyn Fn(f64) -> String) -> String {
+    value.map_or("-".to_string(), f)
+}
+
+fn print_host(host: &Host) {
+    if let Some(run_time) = host.total_run_time.filter(|s| !s.is_empty()) {
+        let busy = run_time.values().filter(|v| *v > CONTENDED_PERCENT).count() as f64
+            / run_time.len() as f64
+            * 100.0;
+        println!(
+            "  Logical processors: {}, {:.1}% average run time, {:.1}% at the peak, above {}% for {:.0}% of the capture",
+            host.logical_processors,
+            average(run_time.values()).unwrap_or(0.0),
+            run_time.values().fold(0.0, f64::max),
+            CONTENDED_PERCENT,
+            busy
+        );
+
+        let verdict = if busy >= 25.0 {
+            "the VMs were contending for the host's processors"
+        } else if busy > 0.0 || average(run_time.values()).unwrap_or(0.0) > BUSY_PERCENT {
+            "the host's processors were busy at times"
+        } else {
+            "no CPU contention on the host"
+        };
+        println!("  Verdict: {}.", verdict);
+    }
+
+    if host.logical_processors > 0 {
+        if let Some(root) = average(host.root.values().copied()) {
+            println!(
+                "  Root partition: {:.1}% of the host's processors",
+                root / host.logical_processors as f64
+            );
+        }
+    }
+}
+
+fn print_vms(host: &Host) {
+    if host.vms.is_empty() {
+        return;
+    }
+
+    // Each VM's share of the host is the run time of its virtual processors
+    // over the total the host's logical processors could run.
+    let capacity = host.logical_processors as f64;
+
+    let mut vms = host.vms.iter().collect::<Vec<(&String, &Vm)>>();
+    vms.sort_by(|a, b| {
+        let share = |vm: &Vm| average(vm.run_time.values().copied()).unwrap_or(0.0);
+        share(b.1).total_cmp(&share(a.1))
+    });
+
+    println!(
+        "  {:>3}  {:>6}  {:>7}  {:>6}  {:>9}  {:>9}  {:>8}  {:>8}  VM",
+        "VPs", "Avg VP", "Peak VP", "Host", "Wait", "Memory", "Pressure", "Peak"
+    );
+    for (name, vm) in vms {
+        let vps = vm.virtual_processors.max(1) as f64;
+        let per_vp = average(vm.run_time.values().map(|v| v / vps));
+        let peak = vm.run_time.values().map(|v| v / vps).reduce(f64::max);
+        let share = (capacity > 0.0)
+            .then(|| average(vm.run_time.values().copied()).map(|v| v / capacity))
+            .flatten();
+        let wait = average(vm.waits.iter().flat_map(|s| s.values()));
+        let memory = vm.memory.and_then(|s| average(s.values()));
+        let pressure = vm.pressure.and_then(|s| average(s.values()));
+        let peak_pressure = vm.pressure.and_then(|s| s.values().reduce(f64::max));
+
+        println!(
+            "  {:>3}  {:>6}  {:>7}  {:>6}  {:>9}  {:>9}  {:>8}  {:>8}  {}",
+            format(
+                (vm.virtual_processors > 0).then_some(vm.virtual_processors as f64),
+                &|v| format!("{:.0}", v)
+            ),
+            format(per_vp, &|v| format!("{:.1}%", v)),
+            format(peak, &|v| format!("{:.1}%", v)),
+            format(share, &|v| format!("{:.1}%", v)),
+            format(wait, &|v| format!("{:.1} us", v / 1000.0)),
+            format(memory, &|v| format!("{:.0} MB", v)),
+            format(pressure, &|v| format!("{:.0}%", v)),
+            format(peak_pressure, &|v| format!("{:.0}%", v)),
+            name
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    analyze::threshold::{Direction, ThresholdProfile, ThresholdRule},
+    counter_path::CounterPath,
+    reader::CounterData,
+    series::Series,
+    units::Unit,
+};
+
+const QUEUE_ADVICE: &str = "Requests are waiting for a thread; look for slow calls the \
+     application is blocked on, or thread pool starvation in the worker process.";
+
+const EXECUTION_ADVICE: &str = "Requests are slow to execute; compare with the worker \
+     process CPU and with the time spent in calls to databases and other services.";
+
+const WAIT_ADVICE: &str = "Requests wait in the queue before they run, so users see the wait \
+     on top of the execution time; the application can't keep up with the load.";
+
+const APP_POOL_QUEUE_ADVICE: &str = "HTTP.sys is holding requests for the application pool \
+     because its worker processes aren't picking them up fast enough.";
+
+const REQUESTS: &str = "Requests / Sec";
+const ACTIVE: &str = "Active Requests";
+const APP_POOL_QUEUE: &str = "CurrentQueueSize";
+const ID_PROCESS: &str = "ID Process";
+const CPU: &str = "% Processor Time";
+const PRIVATE_BYTES: &str = "Private Bytes";
+
+const APP_REQUESTS: &str = "Requests/Sec";
+const APP_EXECUTING: &str = "Requests Executing";
+const APP_EXECUTION_TIME: &str = "Request Execution Time";
+
+const APP_RESTARTS: &str = "Application Restarts";
+const WORKER_RESTARTS: &str = "Worker Process Restarts";
+
+pub fn profile() -> ThresholdProfile {
+    ThresholdProfile {
+        name: "iis",
+        description: "ASP.NET request queues, execution and wait times, and app pool queues",
+        rules: vec![
+            ThresholdRule {
+                counter: "\\ASP.NET\\Requests Queued",
+                direction: Direction::Above,
+                warning: 10.0,
+                critical: 50.0,
+                min_duration: Duration::minutes(1),
+                scale: 1.0,
+                unit: "",
+                cumulative: false,
+                advice: QUEUE_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\HTTP Service Request Queues(*)\\CurrentQueueSize",
+                direction: Direction::Above,
+                warning: 10.0,
+                critical: 100.0,
+                min_duration: Duration::minutes(1),
+                scale: 1.0,
+                unit: "",
+                cumulative: false,
+                advice: APP_POOL_QUEUE_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\ASP.NET\\Request Execution Time",
+                direction: Direction::Above,
+                warning: 1000.0,
+                critical: 5000.0,
+                min_duration: Duration::minutes(2),
+                scale: 1.0,
+                unit: " ms",
+                cumulative: false,
+                advice: EXECUTION_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\ASP.NET\\Request Wait Time",
+                direction: Direction::Above,
+                warning: 1000.0,
+                critical: 5000.0,
+                min_duration: Duration::minutes(2),
+                scale: 1.0,
+                unit: " ms",
+                cumulative: false,
+                advice: WAIT_ADVICE,
+            },
+        ],
+    }
+}
+
+// The counters the summary reads. Worker processes are matched to their
+// application pool through the PID that W3SVC_W3WP puts in its instance
+// names, like 4120_DefaultAppPool.
+pub fn summary_counters() -> Vec<String> {
+    [
+        format!("\\W3SVC_W3WP(*)\\{}", REQUESTS),
+        format!("\\W3SVC_W3WP(*)\\{}", ACTIVE),
+        format!("\\HTTP Service Request Queues(*)\\{}", APP_POOL_QUEUE),
+        format!("\\Process(w3wp*)\\{}", ID_PROCESS),
+        format!("\\Process(w3wp*)\\{}", CPU),
+        format!("\\Process(w3wp*)\\{}", PRIVATE_BYTES),
+        format!("\\ASP.NET Applications(*)\\{}", APP_REQUESTS),
+        format!("\\ASP.NET Applications(*)\\{}", APP_EXECUTING),
+        format!("\\ASP.NET Applications(*)\\{}", APP_EXECUTION_TIME),
+        format!("\\ASP.NET\\{}", APP_RESTARTS),
+        format!("\\ASP.NET\\{}", WORKER_RESTARTS),
+    ]
+    .to_vec()
+}
+
+// What one application pool did over the capture, from the counters of all
+// of its worker processes.
+#[derive(Default)]
+struct AppPool {
+    pids: BTreeSet<u32>,
+    // Summed across the worker processes at each collection.
+    requests: BTreeMap<OffsetDateTime, f64>,
+    active: BTreeMap<OffsetDateTime, f64>,
+    cpu: BTreeMap<OffsetDateTime, f64>,
+    private_bytes: BTreeMap<OffsetDateTime, f64>,
+    queue: Option<f64>,
+}
+
+// Per application pool and per ASP.NET application tables, and how many
+// times ASP.NET restarted applications and worker processes.
+pub fn print_summary(data: &CounterData) {
+    let mut pools = BTreeMap::<(String, String), AppPool>::new();
+    // The pool each worker process PID belongs to.
+    let mut pool_of = HashMap::<(String, u32), String>::new();
+    let mut processes = HashMap::<(String, String), BTreeMap<&str, &Series>>::new();
+    let mut applications = BTreeMap::<(String, String), BTreeMap<&str, &Series>>::new();
+    let mut restarts = BTreeMap::<String, (f64, f64)>::new();
+
+    for counter in &data.counters {
+        let path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+        let samples = &data.samples[counter];
+        let instance = path.instance.clone().unwrap_or_default();
+        if instance == "_Total" || instance.eq_ignore_ascii_case("__Total__") {
+            continue;
+        }
+
+        match path.object.as_str() {
+            "W3SVC_W3WP" => {
+                let (pid, pool) = match instance.split_once('_') {
+                    Some((pid, pool)) => match pid.parse::<u32>() {
+                        Ok(pid) => (pid, pool.to_string()),
+                        Err(_) => continue,
+                    },
+                    None => continue,
+                };
+                pool_of.insert((path.machine.clone(), pid), pool.clone());
+                let entry = pools.entry((path.machine, pool)).or_default();
+                entry.pids.insert(pid);
+                let target = if path.counter.eq_ignore_ascii_case(REQUESTS) {
+                    &mut entry.requests
+                } else if path.counter.eq_ignore_ascii_case(ACTIVE) {
+                    &mut entry.active
+                } else {
+                    continue;
+                };
+                add(target, samples);
+            }
+            "HTTP Service Request Queues" if path.counter.eq_ignore_ascii_case(APP_POOL_QUEUE) => {
+                pools.entry((path.machine, instance)).or_default().queue =
+                    samples.values().reduce(f64::max);
+            }
+            "Process" => {
+                for name in [ID_PROCESS, CPU, PRIVATE_BYTES] {
+                    if path.counter.eq_ignore_ascii_case(name) {
+                        processes
+                            .entry((path.machine.clone(), instance.clone()))
+                            .or_default()
+                            .insert(name, samples);
+                    }
+                }
+            }
+            "ASP.NET Applications" => {
+                for name in [APP_REQUESTS, APP_EXECUTING, APP_EXECUTION_TIME] {
+                    if path.counter.eq_ignore_ascii_case(name) {
+                        applications
+                            .entry((path.machine.clone(), instance.clone()))
+                            .or_default()
+                            .insert(name, samples);
+                    }
+                }
+            }
+            "ASP.NET" => {
+                // The restart counters count since ASP.NET started, so the
+                // restarts during the capture are the difference.
+                let restarted = match (samples.first(), samples.last()) {
+                    (Some(first), Some(last)) => (last.value() - first.value()).max(0.0),
+                    _ => continue,
+                };
+                let entry = restarts.entry(path.machine).or_default();
+                if path.counter.eq_ignore_ascii_case(APP_RESTARTS) {
+                    entry.0 += restarted;
+                } else if path.counter.eq_ignore_ascii_case(WORKER_RESTARTS) {
+                    entry.1 += restarted;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Process instance names shift as worker processes come and go, so each
+    // sample is matched to a pool by the PID at that collection.
+    for ((machine, _), counters) in &processes {
+        let pids = match counters.get(ID_PROCESS) {
+            Some(pids) => pids
+                .iter()
+                .map(|s| (s.time(), s.value() as u32))
+                .collect::<HashMap<OffsetDateTime, u32>>(),
+            None => continue,
+        };
+        for (name, samples) in counters {
+            for sample in samples.iter() {
+                let pool = match pids
+                    .get(&sample.time())
+                    .and_then(|pid| pool_of.get(&(machine.clone(), *pid)))
+                {
+                    Some(pool) => pool,
+                    None => continue,
+                };
+                let pool = pools.get_mut(&(machine.clone(), pool.clone())).unwrap();
+                let target = match *name {
+                    CPU => &mut pool.cpu,
+                    PRIVATE_BYTES => &mut pool.private_bytes,
+                    _ => continue,
+                };
+                *target.entry(sample.time()).or_default() += sample.value();
+            }
+        }
+    }
+
+    print_pools(&pools);
+    print_applications(&applications);
+
+    restarts.retain(|_, (applications, workers)| *applications > 0.0 || *workers > 0.0);
+    if !restarts.is_empty() {
+        println!();
+        println!("ASP.NET restarts during the capture:");
+        for (machine, (applications, workers)) in restarts {
+            println!(
+                "  {:.0} application restarts, {:.0} worker process restarts  \\\\{}",
+                applications, workers, machine
+            );
+        }
+    }
+}
+
+fn add(target: &mut BTreeMap<OffsetDateTime, f64>, samples: &Series) {
+    for sample in samples.iter() {
+        *target.entry(sample.time()).or_default() += sample.value();
+    }
+}
+
+fn average(values: &BTreeMap<OffsetDateTime, f64>) -> Option<f64> {
+    (!values.is_empty()).then(|| values.values().sum::<f64>() / values.len() as f64)
+}
+
+fn peak(values: &BTreeMap<OffsetDateTime, f64>) -> Option<f64> {
+    values.values().copied().reduce(f64::max)
+}
+
+fn format(value: Option<f64>, f: &dyn Fn(f64) -> String) -> String {
+    value.map_or("-".to_string(), f)
+}
+
+fn print_pools(pools: &BTreeMap<(String, String), AppPool>) {
+    if pools.is_empty() {
+        return;
+    }
+
+    let machines = pools
+        .keys()
+        .map(|(machine, _)| machine)
+        .collect::<BTreeSet<&String>>();
+
+    println!();
+    println!("Application pools:");
+    println!(
+        "{:>8}  {:>8}  {:>6}  {:>6}  {:>7}  {:>6}  {:>10}  Pool",
+        "Req/s", "Peak", "Active", "Queue", "Workers", "CPU", "Private"
+    );
+    for ((machine, name), pool) in pools {
+        let name = if machines.len() > 1 {
+            format!("\\\\{} {}", machine, name)
+        } else {
+            name.clone()
+        };
+        println!(
+            "{:>8}  {:>8}  {:>6}  {:>6}  {:>7}  {:>6}  {:>10}  {}",
+            format(average(&pool.requests), &|v| format!("{:.1}", v)),
+            format(peak(&pool.requests), &|v| format!("{:.1}", v)),
+            format(peak(&pool.active), &|v| format!("{:.0}", v)),
+            format(pool.queue, &|v| format!("{:.0}", v)),
+            pool.pids.len(),
+            format(average(&pool.cpu), &|v| format!("{:.0}%", v)),
+            format(peak(&pool.private_bytes), &|v| Unit::Bytes(1.0).format(v)),
+            name
+        );
+    }
+}
+
+fn print_applications(applications: &BTreeMap<(String, String), BTreeMap<&str, &Series>>) {
+    if applications.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("ASP.NET applications (execution time in ms):");
+    println!(
+        "{:>8}  {:>9}  {:>8}  {:>8}  Application",
+        "Req/s", "Executing", "Avg time", "Max time"
+    );
+    for ((machine, name), counters) in applications {
+        let series = |counter: &str| {
+            counters
+                .get(counter)
+                .map(|s| s.iter().map(|s| (s.time(), s.value())).collect())
+                .unwrap_or_default()
+        };
+        let (requests, executing, time) = (
+            series(APP_REQUESTS),
+            series(APP_EXECUTING),
+            series(APP_EXECUTION_TIME),
+        );
+        println!(
+            "{:>8}  {:>9}  {:>8}  {:>8}  \\\\{}\\{}",
+            format(average(&requests), &|v| format!("{:.1}", v)),
+            format(peak(&executing), &|v| format!("{:.0}", v)),
+            format(average(&time), &|v| format!("{:.0}", v)),
+            format(peak(&time), &|v| format!("{:.0}", v)),
+            machine,
+            name
+        );
+    }
+}
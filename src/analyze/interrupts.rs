@@ -0,0 +1,179 @@
+use time::Duration;
+
+use crate::{
+    analyze::{average_between, sustained_above, Analyzer, Finding, Severity},
+    counter_path::CounterPath,
+    reader::CounterData,
+    timespec::format_duration,
+};
+
+const CORE_TIME_WARNING: f64 = 15.0;
+const CORE_TIME_CRITICAL: f64 = 30.0;
+const CONTEXT_SWITCHES_PER_CORE_WARNING: f64 = 5000.0;
+const CONTEXT_SWITCHES_PER_CORE_CRITICAL: f64 = 15000.0;
+
+// A core running this many times hotter than _Total is doing work that isn't
+// being spread across processors, which is the signature of a driver storm.
+const CONCENTRATION_FACTOR: f64 = 3.0;
+
+const MIN_DURATION: Duration = Duration::minutes(1);
+
+pub struct InterruptAnalyzer;
+
+impl Analyzer for InterruptAnalyzer {
+    fn name(&self) -> &'static str {
+        "interrupts"
+    }
+
+    fn description(&self) -> &'static str {
+        "Context switch, DPC, and interrupt storms per core"
+    }
+
+    fn counters(&self) -> Vec<&'static str> {
+        vec![
+            "\\System\\Context Switches/sec",
+            "\\Processor(*)\\% DPC Time",
+            "\\Processor(*)\\% Interrupt Time",
+            "\\Processor(*)\\DPCs Queued/sec",
+        ]
+    }
+
+    fn analyze(&self, data: &CounterData) -> Vec<Finding> {
+        let mut findings = context_switch_findings(self.name(), data);
+        findings.extend(core_time_findings(self.name(), data, "% DPC Time"));
+        findings.extend(core_time_findings(self.name(), data, "% Interrupt Time"));
+        findings
+    }
+}
+
+fn core_count(data: &CounterData, machine: &str) -> usize {
+    let mut cores = data
+        .counters
+        .iter()
+        .filter_map(|c| CounterPath::parse(c))
+        .filter(|p| p.machine == machine && p.object == "Processor")
+        .filter_map(|p| p.instance)
+        .filter(|i| i != "_Total")
+        .collect::<Vec<String>>();
+
+    cores.sort();
+    cores.dedup();
+    cores.len().max(1)
+}
+
+fn context_switch_findings(analyzer: &'static str, data: &CounterData) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for (counter, samples) in data.matching("\\System\\Context Switches/sec") {
+        let machine = match CounterPath::parse(counter) {
+            Some(path) => path.machine,
+            None => continue,
+        };
+        let cores = core_count(data, &machine) as f64;
+
+        for v in sustained_above(
+            &samples.to_vec(),
+            CONTEXT_SWITCHES_PER_CORE_WARNING * cores,
+            MIN_DURATION,
+        ) {
+            let severity = if v.average / cores >= CONTEXT_SWITCHES_PER_CORE_CRITICAL {
+                Severity::Critical
+            } else {
+                Severity::Warning
+            };
+
+            findings.push(Finding {
+                analyzer,
+                severity,
+                counter: counter.clone(),
+                start: v.start,
+                end: v.end,
+                message: format!(
+                    "Context switches averaged {:.0}/sec ({:.0} per core across {} cores) for {}, \
+                     peaking at {:.0}/sec. Check DPC and interrupt time for a driver cause.",
+                    v.average,
+                    v.average / cores,
+                    cores,
+                    format_duration(v.duration()),
+                    v.peak
+                ),
+            });
+        }
+    }
+
+    findings
+}
+
+fn core_time_findings(
+    analyzer: &'static str,
+    data: &CounterData,
+    counter_name: &str,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for (counter, samples) in data.matching(&format!("\\Processor(*)\\{}", counter_name)) {
+        let path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+        let core = match &path.instance {
+            Some(core) if core != "_Total" => core,
+            _ => continue,
+        };
+
+        let total = data.samples.get(&format!(
+            "\\\\{}\\Processor(_Total)\\{}",
+            path.machine, counter_name
+        ));
+        let dpcs_queued = data.samples.get(&format!(
+            "\\\\{}\\Processor({})\\DPCs Queued/sec",
+            path.machine, core
+        ));
+
+        for v in sustained_above(&samples.to_vec(), CORE_TIME_WARNING, MIN_DURATION) {
+            let severity = if v.average >= CORE_TIME_CRITICAL {
+                Severity::Critical
+            } else {
+                Severity::Warning
+            };
+
+            let mut message = format!(
+                "{} on core {} averaged {:.1}% (peak {:.1}%) for {}",
+                counter_name,
+                core,
+                v.average,
+                v.peak,
+                format_duration(v.duration())
+            );
+
+            let total_average = total.and_then(|t| average_between(t, v.start, v.end));
+            if let Some(total_average) = total_average {
+                message.push_str(&format!(" while _Total averaged {:.1}%", total_average));
+            }
+
+            if let Some(dpcs) = dpcs_queued.and_then(|d| average_between(d, v.start, v.end)) {
+                message.push_str(&format!(", with {:.0} DPCs queued/sec", dpcs));
+            }
+
+            message.push('.');
+
+            if total_average.is_some_and(|t| v.average > t * CONCENTRATION_FACTOR) {
+                message.push_str(
+                    " The load is concentrated on this core, which is typical of a driver \
+                     storm or a NIC that isn't spreading interrupts with RSS.",
+                );
+            }
+
+            findings.push(Finding {
+                analyzer,
+                severity,
+                counter: counter.clone(),
+                start: v.start,
+                end: v.end,
+                message,
+            });
+        }
+    }
+
+    findings
+}
@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    analyze::{
+        sustained_above,
+        threshold::{Direction, ThresholdProfile, ThresholdRule},
+    },
+    counter_path::CounterPath,
+    export::format_time,
+    pdh_helper::CounterValueWithTime,
+    reader::CounterData,
+    series::Series,
+    timespec::format_duration,
+    units::Unit,
+};
+
+// The machine counts as memory constrained while it has less than this
+// available, or has committed more than this much of the commit limit.
+const AVAILABLE_LOW_MB: f64 = 500.0;
+const COMMIT_HIGH_PERCENT: f64 = 90.0;
+
+const MIN_DURATION: Duration = Duration::minutes(2);
+
+// How many of the processes whose working sets grew the most to list.
+const TOP_PROCESSES: usize = 5;
+
+const AVAILABLE_ADVICE: &str = "Little memory is left for new allocations and the file cache; \
+     look at which working sets grew over the same time.";
+
+const PAGING_ADVICE: &str = "Heavy hard paging; if Available MBytes is also low, the machine is \
+     paging because it's short on memory rather than reading mapped files.";
+
+const COMMIT_ADVICE: &str =
+    "Commit is close to the limit, and allocations fail when it's reached; \
+     look for a process leaking memory, or make the paging file bigger.";
+
+const AVAILABLE: &str = "Available MBytes";
+const PAGES: &str = "Pages/sec";
+const NONPAGED: &str = "Pool Nonpaged Bytes";
+const PAGED: &str = "Pool Paged Bytes";
+const COMMITTED: &str = "Committed Bytes";
+const COMMIT_LIMIT: &str = "Commit Limit";
+const WORKING_SET: &str = "Working Set";
+
+const MEMORY_COUNTERS: [&str; 6] = [AVAILABLE, PAGES, NONPAGED, PAGED, COMMITTED, COMMIT_LIMIT];
+
+// Thresholds from Microsoft's guidance for the Memory object. The
+// memory-pressure analyzer weighs most of these together; these flag each
+// one on its own.
+pub fn profile() -> ThresholdProfile {
+    ThresholdProfile {
+        name: "memory",
+        description: "Low available memory, hard paging, and commit near the limit",
+        rules: vec![
+            ThresholdRule {
+                counter: "\\Memory\\Available MBytes",
+                direction: Direction::Below,
+                warning: AVAILABLE_LOW_MB,
+                critical: 100.0,
+                min_duration: Duration::minutes(5),
+                scale: 1.0,
+                unit: " MB",
+                cumulative: false,
+                advice: AVAILABLE_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\Memory\\Pages/sec",
+                direction: Direction::Above,
+                warning: 1000.0,
+                critical: 5000.0,
+                min_duration: Duration::minutes(5),
+                scale: 1.0,
+                unit: "/sec",
+                cumulative: false,
+                advice: PAGING_ADVICE,
+            },
+            ThresholdRule {
+                counter: "\\Memory\\% Committed Bytes In Use",
+                direction: Direction::Above,
+                warning: 80.0,
+                critical: COMMIT_HIGH_PERCENT,
+                min_duration: MIN_DURATION,
+                scale: 1.0,
+                unit: "%",
+                cumulative: false,
+                advice: COMMIT_ADVICE,
+            },
+        ],
+    }
+}
+
+// The counters the summary reads for every machine.
+pub fn summary_counters() -> Vec<String> {
+    MEMORY_COUNTERS
+        .iter()
+        .map(|counter| format!("\\Memory\\{}", counter))
+        .chain([format!("\\Process(*)\\{}", WORKING_SET)])
+        .collect()
+}
+
+#[derive(Default)]
+struct MachineMemory<'a> {
+    memory: BTreeMap<&'static str, &'a Series>,
+    working_sets: Vec<(String, &'a Series)>,
+}
+
+// For each machine, where its memory went over the capture and whether, and
+// when, it was short of it.
+pub fn print_summary(data: &CounterData) {
+    let mut machines = BTreeMap::<String, MachineMemory>::new();
+
+    for counter in &data.counters {
+        let path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+        let samples = &data.samples[counter];
+        if samples.is_empty() {
+            continue;
+        }
+
+        if path.object == "Memory" {
+            if let Some(name) = MEMORY_COUNTERS
+                .into_iter()
+                .find(|name| path.counter.eq_ignore_ascii_case(name))
+            {
+                machines
+                    .entry(path.machine)
+                    .or_default()
+                    .memory
+                    .insert(name, samples);
+            }
+        } else if path.object == "Process" && path.counter.eq_ignore_ascii_case(WORKING_SET) {
+            match path.instance {
+                Some(instance) if instance != "_Total" => machines
+                    .entry(path.machine)
+                    .or_default()
+                    .working_sets
+                    .push((instance, samples)),
+                _ => continue,
+            }
+        }
+    }
+
+    machines.retain(|_, machine| !machine.memory.is_empty());
+
+    for (machine, memory) in &machines {
+        println!();
+        println!("Memory on \\\\{}:", machine);
+        print_machine(memory);
+    }
+}
+
+fn print_machine(machine: &MachineMemory) {
+    let bytes = Unit::Bytes(1.0);
+
+    if let Some(available) = machine.memory.get(AVAILABLE) {
+        let (average, _, low) = stats(available);
+        println!(
+            "  Available:       {} average, {} at the lowest",
+            bytes.format(average * 1024.0 * 1024.0),
+            bytes.format(low * 1024.0 * 1024.0)
+        );
+    }
+
+    if let Some(pages) = machine.memory.get(PAGES) {
+        let (average, peak, _) = stats(pages);
+        println!(
+            "  Pages/sec:       {:.0} average, {:.0} at the peak",
+            average, peak
+        );
+    }
+
+    for (name, label) in [(NONPAGED, "Nonpaged pool"), (PAGED, "Paged pool")] {
+        if let Some(pool) = machine.memory.get(name) {
+            let (_, peak, _) = stats(pool);
+            println!(
+                "  {:<16} {} to {}, {} at the peak",
+                format!("{}:", label),
+                bytes.format(pool.value(0)),
+                bytes.format(pool.value(pool.len() - 1)),
+                bytes.format(peak)
+            );
+        }
+    }
+
+    let commit = commit_percent(machine);
+    if let (Some(committed), Some(limit)) = (
+        machine.memory.get(COMMITTED),
+        machine.memory.get(COMMIT_LIMIT),
+    ) {
+        let (_, peak, _) = stats(committed);
+        let (_, _, lowest_limit) = stats(limit);
+        let peak_percent = commit.iter().map(|s| s.value()).fold(0.0, f64::max);
+        println!(
+            "  Committed:       {} at the peak, of a {} limit ({:.0}% at the peak)",
+            bytes.format(peak),
+            bytes.format(lowest_limit),
+            peak_percent
+        );
+    }
+
+    print_working_sets(&machine.working_sets);
+
+    let constrained = constrained_periods(machine, &commit);
+    let first = machine
+        .memory
+        .values()
+        .filter_map(|s| s.first())
+        .min_by_key(|s| s.time());
+    let last = machine
+        .memory
+        .values()
+        .filter_map(|s| s.last())
+        .max_by_key(|s| s.time());
+    let capture = match (first, last) {
+        (Some(first), Some(last)) => last.time() - first.time(),
+        _ => Duration::ZERO,
+    };
+
+    if constrained.is_empty() {
+        println!(
+            "  Verdict: not memory constrained. Available memory stayed above {} MB and commit below {}% of the limit.",
+            AVAILABLE_LOW_MB, COMMIT_HIGH_PERCENT
+        );
+        return;
+    }
+
+    let total = constrained
+        .iter()
+        .map(|(start, end)| *end - *start)
+        .fold(Duration::ZERO, |a, b| a + b);
+    println!(
+        "  Verdict: memory constrained for {} of {}:",
+        format_duration(total),
+        format_duration(capture)
+    );
+    for (start, end) in constrained {
+        let mut evidence = Vec::new();
+        if let Some(available) = machine.memory.get(AVAILABLE) {
+            if let Some(low) = lowest_between(available, start, end) {
+                evidence.push(format!("{:.0} MB available at the lowest", low));
+            }
+        }
+        if let Some(peak) = commit
+            .iter()
+            .filter(|s| s.time() >= start && s.time() <= end)
+            .map(|s| s.value())
+            .reduce(f64::max)
+        {
+            evidence.push(format!("{:.0}% of the commit limit at the peak", peak));
+        }
+        println!(
+            "    {} - {}  {}",
+            format_time(start),
+            format_time(end),
+            evidence.join(", ")
+        );
+    }
+}
+
+// The average, highest, and lowest value of a series that isn't empty.
+fn stats(samples: &Series) -> (f64, f64, f64) {
+    let (mut sum, mut high, mut low) = (0.0, f64::MIN, f64::MAX);
+    for value in samples.values() {
+        sum += value;
+        high = high.max(value);
+        low = low.min(value);
+    }
+    (sum / samples.len() as f64, high, low)
+}
+
+fn lowest_between(samples: &Series, start: OffsetDateTime, end: OffsetDateTime) -> Option<f64> {
+    samples
+        .iter()
+        .filter(|s| s.time() >= start && s.time() <= end)
+        .map(|s| s.value())
+        .reduce(f64::min)
+}
+
+// Committed Bytes as a percentage of the Commit Limit collected with it.
+fn commit_percent(machine: &MachineMemory) -> Vec<CounterValueWithTime> {
+    let (committed, limit) = match (
+        machine.memory.get(COMMITTED),
+        machine.memory.get(COMMIT_LIMIT),
+    ) {
+        (Some(committed), Some(limit)) => (committed, limit),
+        _ => return Vec::new(),
+    };
+
+    let limits = limit
+        .iter()
+        .map(|s| (s.time(), s.value()))
+        .collect::<BTreeMap<OffsetDateTime, f64>>();
+
+    committed
+        .iter()
+        .filter_map(|s| {
+            let limit = *limits.get(&s.time())?;
+            (limit > 0.0).then(|| CounterValueWithTime::Double(s.time(), s.value() / limit * 100.0))
+        })
+        .collect()
+}
+
+// The runs of collections where the machine was low on available memory or
+// commit, lasting long enough to matter.
+fn constrained_periods(
+    machine: &MachineMemory,
+    commit: &[CounterValueWithTime],
+) -> Vec<(OffsetDateTime, OffsetDateTime)> {
+    let mut constrained = BTreeMap::<OffsetDateTime, bool>::new();
+
+    if let Some(available) = machine.memory.get(AVAILABLE) {
+        for sample in available.iter() {
+            *constrained.entry(sample.time()).or_default() |= sample.value() < AVAILABLE_LOW_MB;
+        }
+    }
+    for sample in commit {
+        *constrained.entry(sample.time()).or_default() |= sample.value() > COMMIT_HIGH_PERCENT;
+    }
+
+    let flags = constrained
+        .into_iter()
+        .map(|(time, low)| CounterValueWithTime::Double(time, if low { 1.0 } else { 0.0 }))
+        .collect::<Vec<CounterValueWithTime>>();
+
+    sustained_above(&flags, 0.5, MIN_DURATION)
+        .into_iter()
+        .map(|v| (v.start, v.end))
+        .collect()
+}
+
+// The processes whose working sets grew the most between the first and last
+// collection they were in, which is where a leak shows up.
+fn print_working_sets(working_sets: &[(String, &Series)]) {
+    let mut growth = working_sets
+        .iter()
+        .filter(|(_, samples)| samples.len() > 1)
+        .map(|(name, samples)| {
+            let (first, last) = (samples.value(0), samples.value(samples.len() - 1));
+            (last - first, first, last, name)
+        })
+        .filter(|(grew, _, _, _)| *grew > 0.0)
+        .collect::<Vec<(f64, f64, f64, &String)>>();
+
+    if growth.is_empty() {
+        return;
+    }
+
+    growth.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let bytes = Unit::Bytes(1.0);
+    println!("  Working sets that grew the most:");
+    for (grew, first, last, name) in growth.into_iter().take(TOP_PROCESSES) {
+        println!(
+            "    {:>10}  {} to {}  {}",
+            format!("+{}", bytes.format(grew)),
+            bytes.format(first),
+            bytes.format(last),
+            name
+        );
+    }
+}
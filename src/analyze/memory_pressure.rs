@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashMap};
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    analyze::{average_between, sustained_above, Analyzer, Finding, Severity},
+    counter_path::CounterPath,
+    pdh_helper::CounterValueWithTime,
+    reader::CounterData,
+    series::Series,
+    timespec::format_duration,
+};
+
+const SCORE_WARNING: f64 = 50.0;
+const SCORE_CRITICAL: f64 = 75.0;
+
+const MIN_DURATION: Duration = Duration::minutes(2);
+
+// Each component adds up to its weight to the 0-100 score, scaled linearly
+// between the value where it starts to matter and the value where it's bad.
+struct Component {
+    object: &'static str,
+    counter: &'static str,
+    healthy: f64,
+    bad: f64,
+    weight: f64,
+    unit: &'static str,
+}
+
+const COMPONENTS: [Component; 5] = [
+    Component {
+        object: "Memory",
+        counter: "Available MBytes",
+        healthy: 1024.0,
+        bad: 100.0,
+        weight: 35.0,
+        unit: " MB",
+    },
+    Component {
+        object: "Memory",
+        counter: "Pages Input/sec",
+        healthy: 10.0,
+        bad: 500.0,
+        weight: 20.0,
+        unit: "/sec",
+    },
+    Component {
+        object: "Memory",
+        counter: "Page Reads/sec",
+        healthy: 5.0,
+        bad: 200.0,
+        weight: 20.0,
+        unit: "/sec",
+    },
+    Component {
+        object: "Paging File",
+        counter: "% Usage",
+        healthy: 50.0,
+        bad: 90.0,
+        weight: 15.0,
+        unit: "%",
+    },
+    Component {
+        object: "Memory",
+        counter: "Pool Nonpaged Bytes",
+        healthy: 256.0 * 1024.0 * 1024.0,
+        bad: 2048.0 * 1024.0 * 1024.0,
+        weight: 10.0,
+        unit: " bytes",
+    },
+];
+
+impl Component {
+    fn score(&self, value: f64) -> f64 {
+        let fraction = (value - self.healthy) / (self.bad - self.healthy);
+        fraction.clamp(0.0, 1.0) * self.weight
+    }
+
+    fn matches(&self, path: &CounterPath) -> bool {
+        path.object == self.object
+            && path.counter == self.counter
+            && path.instance.as_deref().unwrap_or("_Total") == "_Total"
+    }
+}
+
+pub struct MemoryPressureAnalyzer;
+
+impl Analyzer for MemoryPressureAnalyzer {
+    fn name(&self) -> &'static str {
+        "memory-pressure"
+    }
+
+    fn description(&self) -> &'static str {
+        "Composite memory pressure score from availability, paging, and pool usage"
+    }
+
+    fn counters(&self) -> Vec<&'static str> {
+        vec![
+            "\\Memory\\Available MBytes",
+            "\\Memory\\Pages Input/sec",
+            "\\Memory\\Page Reads/sec",
+            "\\Paging File(_Total)\\% Usage",
+            "\\Memory\\Pool Nonpaged Bytes",
+        ]
+    }
+
+    fn analyze(&self, data: &CounterData) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for (machine, components) in components_by_machine(data) {
+            let score = score_series(&components);
+
+            for v in sustained_above(&score, SCORE_WARNING, MIN_DURATION) {
+                let severity = if v.average >= SCORE_CRITICAL {
+                    Severity::Critical
+                } else {
+                    Severity::Warning
+                };
+
+                let mut evidence = components
+                    .iter()
+                    .filter_map(|(component, samples)| {
+                        let average = average_between(samples, v.start, v.end)?;
+                        Some((component.score(average), component, average))
+                    })
+                    .filter(|(score, _, _)| *score > 0.0)
+                    .collect::<Vec<(f64, &&Component, f64)>>();
+                evidence.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+                let evidence = evidence
+                    .iter()
+                    .map(|(score, component, average)| {
+                        format!(
+                            "{} averaged {:.1}{} (+{:.0})",
+                            component.counter, average, component.unit, score
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join("; ");
+
+                findings.push(Finding {
+                    analyzer: self.name(),
+                    severity,
+                    counter: format!("\\\\{}\\Memory pressure score", machine),
+                    start: v.start,
+                    end: v.end,
+                    message: format!(
+                        "Memory pressure score averaged {:.0} (peak {:.0}) for {}. Evidence: {}.",
+                        v.average,
+                        v.peak,
+                        format_duration(v.duration()),
+                        evidence
+                    ),
+                });
+            }
+        }
+
+        findings
+    }
+}
+
+fn components_by_machine(
+    data: &CounterData,
+) -> BTreeMap<String, Vec<(&'static Component, &Series)>> {
+    let mut machines = BTreeMap::<String, Vec<(&'static Component, &Series)>>::new();
+
+    for counter in &data.counters {
+        let path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        if let Some(component) = COMPONENTS.iter().find(|c| c.matches(&path)) {
+            machines
+                .entry(path.machine)
+                .or_default()
+                .push((component, &data.samples[counter]));
+        }
+    }
+
+    machines
+}
+
+// Scores every timestamp where at least one component has a sample, carrying
+// each component's last value forward so the series stay aligned.
+fn score_series(components: &[(&'static Component, &Series)]) -> Vec<CounterValueWithTime> {
+    let mut updates = BTreeMap::<OffsetDateTime, Vec<(usize, f64)>>::new();
+    for (index, (_, samples)) in components.iter().enumerate() {
+        for sample in samples.iter() {
+            updates
+                .entry(sample.time())
+                .or_default()
+                .push((index, sample.value()));
+        }
+    }
+
+    let mut latest = HashMap::<usize, f64>::new();
+    let mut score = Vec::with_capacity(updates.len());
+
+    for (time, values) in updates {
+        latest.extend(values);
+
+        let total = latest
+            .iter()
+            .map(|(index, value)| components[*index].0.score(*value))
+            .sum();
+
+        score.push(CounterValueWithTime::Double(time, total));
+    }
+
+    score
+}
@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    analyze::{sustained_above, Analyzer, Finding, Severity},
+    counter_path::CounterPath,
+    pdh_helper::CounterValueWithTime,
+    reader::CounterData,
+    series::Series,
+    timespec::format_duration,
+    units::Unit,
+};
+
+// Utilization of the link, in percent. Past about 70% a link starts queueing
+// packets, and bursts no longer fit.
+const BUSY_PERCENT: f64 = 70.0;
+const CONGESTED_PERCENT: f64 = 90.0;
+
+// Microsoft's guidance is that an output queue longer than 2 packets means
+// the adapter can't keep up.
+const QUEUE_LONG: f64 = 2.0;
+
+const MIN_DURATION: Duration = Duration::minutes(2);
+
+const TOTAL: &str = "Bytes Total/sec";
+const SENT: &str = "Bytes Sent/sec";
+const RECEIVED: &str = "Bytes Received/sec";
+const BANDWIDTH: &str = "Current Bandwidth";
+const QUEUE: &str = "Output Queue Length";
+
+const NIC_COUNTERS: [&str; 5] = [TOTAL, SENT, RECEIVED, BANDWIDTH, QUEUE];
+
+const RETRANSMIT_HIGH_PERCENT: f64 = 1.0;
+
+const SEGMENTS_SENT: &str = "Segments Sent/sec";
+const SEGMENTS_RETRANSMITTED: &str = "Segments Retransmitted/sec";
+
+const UTILIZATION_ADVICE: &str = "The link is close to full, so packets queue and latency \
+     grows; look at what is sending the traffic, or move to a faster link or NIC team.";
+
+const QUEUE_ADVICE: &str = "Packets are waiting to be sent; the adapter or the link can't keep \
+     up, or the driver is stalling.";
+
+// The values of one adapter's counters, by counter name.
+type NicCounters<'a> = BTreeMap<&'static str, &'a Series>;
+
+pub struct NetworkAnalyzer;
+
+impl Analyzer for NetworkAnalyzer {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn description(&self) -> &'static str {
+        "Network adapter utilization against link bandwidth, and output queues"
+    }
+
+    fn counters(&self) -> Vec<&'static str> {
+        vec![
+            "\\Network Interface(*)\\Bytes Total/sec",
+            "\\Network Interface(*)\\Current Bandwidth",
+            "\\Network Interface(*)\\Output Queue Length",
+        ]
+    }
+
+    fn analyze(&self, data: &CounterData) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for (nic, counters) in adapters(data) {
+            let utilization = utilization(&counters);
+            for v in sustained_above(&utilization, BUSY_PERCENT, MIN_DURATION) {
+                findings.push(Finding {
+                    analyzer: self.name(),
+                    severity: if v.average >= CONGESTED_PERCENT {
+                        Severity::Critical
+                    } else {
+                        Severity::Warning
+                    },
+                    counter: format!("{}\\% Utilization", nic),
+                    start: v.start,
+                    end: v.end,
+                    message: format!(
+                        "Stayed above {}% of the link bandwidth for {}, averaging {:.1}% (worst {:.1}%). {}",
+                        BUSY_PERCENT,
+                        format_duration(v.duration()),
+                        v.average,
+                        v.peak,
+                        UTILIZATION_ADVICE
+                    ),
+                });
+            }
+
+            let queue = match counters.get(QUEUE) {
+                Some(queue) => queue.to_vec(),
+                None => continue,
+            };
+            for v in sustained_above(&queue, QUEUE_LONG, MIN_DURATION) {
+                findings.push(Finding {
+                    analyzer: self.name(),
+                    severity: Severity::Warning,
+                    counter: format!("{}\\{}", nic, QUEUE),
+                    start: v.start,
+                    end: v.end,
+                    message: format!(
+                        "Stayed above {} packets for {}, averaging {:.1} (worst {:.0}). {}",
+                        QUEUE_LONG,
+                        format_duration(v.duration()),
+                        v.average,
+                        v.peak,
+                        QUEUE_ADVICE
+                    ),
+                });
+            }
+        }
+
+        findings
+    }
+}
+
+// The counters the summary reads for every adapter, and the TCP counters for
+// the retransmit rate.
+pub fn summary_counters() -> Vec<String> {
+    NIC_COUNTERS
+        .iter()
+        .map(|counter| format!("\\Network Interface(*)\\{}", counter))
+        .chain(["TCPv4", "TCPv6"].iter().flat_map(|object| {
+            [SEGMENTS_SENT, SEGMENTS_RETRANSMITTED]
+                .map(|counter| format!("\\{}\\{}", object, counter))
+        }))
+        .collect()
+}
+
+// Network adapters by their path, like \\MACHINE\Network Interface(name).
+fn adapters(data: &CounterData) -> BTreeMap<String, NicCounters<'_>> {
+    let mut adapters = BTreeMap::<String, NicCounters>::new();
+
+    for counter in &data.counters {
+        let path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+        if path.object != "Network Interface" {
+            continue;
+        }
+        let name = match NIC_COUNTERS
+            .into_iter()
+            .find(|name| path.counter.eq_ignore_ascii_case(name))
+        {
+            Some(name) => name,
+            None => continue,
+        };
+        let instance = match &path.instance {
+            Some(instance) => instance,
+            None => continue,
+        };
+
+        let nic = format!("\\\\{}\\Network Interface({})", path.machine, instance);
+        adapters
+            .entry(nic)
+            .or_default()
+            .insert(name, &data.samples[counter]);
+    }
+
+    adapters
+}
+
+// Bytes Total/sec as a percentage of Current Bandwidth, which is in bits per
+// second, at each collection that has both. Adapters that report no
+// bandwidth, like some virtual ones, have no utilization.
+fn utilization(counters: &NicCounters) -> Vec<CounterValueWithTime> {
+    let (total, bandwidth) = match (counters.get(TOTAL), counters.get(BANDWIDTH)) {
+        (Some(total), Some(bandwidth)) => (total, bandwidth),
+        _ => return Vec::new(),
+    };
+
+    let bandwidth = bandwidth
+        .iter()
+        .map(|s| (s.time(), s.value()))
+        .collect::<BTreeMap<OffsetDateTime, f64>>();
+
+    total
+        .iter()
+        .filter_map(|s| {
+            let bits = *bandwidth.get(&s.time())?;
+            (bits > 0.0)
+                .then(|| CounterValueWithTime::Double(s.time(), s.value() * 8.0 / bits * 100.0))
+        })
+        .collect()
+}
+
+// A row per adapter with how full its link was, and the TCP retransmit rate
+// of each machine after.
+pub fn print_summary(data: &CounterData) {
+    let adapters = adapters(data)
+        .into_iter()
+        .filter(|(_, counters)| counters.contains_key(TOTAL))
+        .collect::<BTreeMap<String, NicCounters>>();
+
+    if !adapters.is_empty() {
+        println!();
+        println!("Network adapters:");
+        println!(
+            "{:>6}  {:>6}  {:>5}  {:>12}  {:>12}  {:>5}  {:>10}  {:<9}  Adapter",
+            "Avg", "Peak", "Busy", "Sent", "Received", "Queue", "Link", "Status"
+        );
+    }
+
+    for (nic, counters) in &adapters {
+        let average = |name: &str| {
+            counters
+                .get(name)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.values().sum::<f64>() / s.len() as f64)
+        };
+        let format =
+            |value: Option<f64>, f: &dyn Fn(f64) -> String| value.map_or("-".to_string(), f);
+        let rate = |v: f64| format!("{}/s", Unit::Bytes(1.0).format(v));
+
+        let utilization = utilization(counters);
+        let values = utilization.iter().map(|s| s.value()).collect::<Vec<f64>>();
+        let (average_percent, peak_percent, busy) = if values.is_empty() {
+            (None, None, None)
+        } else {
+            (
+                Some(values.iter().sum::<f64>() / values.len() as f64),
+                Some(values.iter().copied().fold(0.0, f64::max)),
+                Some(
+                    values.iter().filter(|v| **v > BUSY_PERCENT).count() as f64
+                        / values.len() as f64
+                        * 100.0,
+                ),
+            )
+        };
+
+        let queue_peak = counters
+            .get(QUEUE)
+            .and_then(|s| s.values().reduce(f64::max));
+        let status = status(&utilization, queue_peak);
+
+        println!(
+            "{:>6}  {:>6}  {:>5}  {:>12}  {:>12}  {:>5}  {:>10}  {:<9}  {}",
+            format(average_percent, &|v| format!("{:.1}%", v)),
+            format(peak_percent, &|v| format!("{:.1}%", v)),
+            format(busy, &|v| format!("{:.0}%", v)),
+            format(average(SENT), &rate),
+            format(average(RECEIVED), &rate),
+            format(average(QUEUE), &|v| format!("{:.1}", v)),
+            format(average(BANDWIDTH), &format_bandwidth),
+            status,
+            nic
+        );
+    }
+
+    print_retransmits(data);
+}
+
+// Congested when the link stayed nearly full or packets stayed queued, busy
+// when it was above the busy line for a while.
+fn status(utilization: &[CounterValueWithTime], queue_peak: Option<f64>) -> &'static str {
+    if utilization.is_empty() {
+        return "No link";
+    }
+    if !sustained_above(utilization, CONGESTED_PERCENT, MIN_DURATION).is_empty()
+        || queue_peak.is_some_and(|q| q > QUEUE_LONG)
+    {
+        "Congested"
+    } else if !sustained_above(utilization, BUSY_PERCENT, MIN_DURATION).is_empty() {
+        "Busy"
+    } else {
+        "OK"
+    }
+}
+
+// Link speeds are in bits per second, and named in powers of ten.
+fn format_bandwidth(bits: f64) -> String {
+    if bits >= 1e9 {
+        format!("{:.0} Gbps", bits / 1e9)
+    } else if bits >= 1e6 {
+        format!("{:.0} Mbps", bits / 1e6)
+    } else {
+        format!("{:.0} bps", bits)
+    }
+}
+
+// Retransmitted segments as a percentage of the segments sent, over the
+// whole capture. More than about 1% usually means loss on the path.
+fn print_retransmits(data: &CounterData) {
+    let mut machines = BTreeMap::<(String, String), (f64, f64)>::new();
+
+    for counter in &data.counters {
+        let path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+        if path.object != "TCPv4" && path.object != "TCPv6" {
+            continue;
+        }
+        let sum = data.samples[counter].values().sum::<f64>();
+        let totals = machines.entry((path.machine, path.object)).or_default();
+        if path.counter.eq_ignore_ascii_case(SEGMENTS_SENT) {
+            totals.0 += sum;
+        } else if path.counter.eq_ignore_ascii_case(SEGMENTS_RETRANSMITTED) {
+            totals.1 += sum;
+        }
+    }
+
+    machines.retain(|_, (sent, _)| *sent > 0.0);
+    if machines.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("TCP retransmits:");
+    for ((machine, object), (sent, retransmitted)) in machines {
+        let percent = retransmitted / sent * 100.0;
+        println!(
+            "  {:>6.2}%  {:<4}  \\\\{}\\{}",
+            percent,
+            if percent > RETRANSMIT_HIGH_PERCENT {
+                "High"
+            } else {
+                "OK"
+            },
+            machine,
+            object
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    analyze::{sustained_above, threshold::per_second, Analyzer, Finding, Severity},
+    counter_path::CounterPath,
+    pdh_helper::CounterValueWithTime,
+    reader::CounterData,
+    series::Series,
+    timespec::format_duration,
+};
+
+const SUCCESS_ADVICE: &str = "Requests are failing; look for errors in the application's \
+     event log and IIS logs for the same time range.";
+
+const CACHE_ADVICE: &str = "Most requests are missing the cache, so they're served the slow \
+     way; check for cache flushes, memory limits on the cache, or uncacheable content.";
+
+pub enum Denominator {
+    // The denominator counts everything, including what the numerator counts,
+    // like Requests Total for Requests Succeeded.
+    Total(&'static str),
+    // The denominator counts the rest, like misses for hits, so the total is
+    // the sum of the two.
+    Rest(&'static str),
+}
+
+// One "X per Y" health indicator, like cache hits over lookups, checked as a
+// percentage that shouldn't drop below the thresholds. The two counters are
+// paired by machine and instance.
+pub struct RatioRule {
+    pub name: &'static str,
+    pub numerator: &'static str,
+    pub denominator: Denominator,
+    pub warning: f64,
+    pub critical: f64,
+    pub min_duration: Duration,
+    // Intervals where the total is below this rate are skipped, since a
+    // handful of requests makes for a meaningless percentage.
+    pub min_total: f64,
+    // The counters are running totals, so they're compared as per-second rates.
+    pub cumulative: bool,
+    pub advice: &'static str,
+}
+
+// An analyzer made of ratio rules.
+pub struct RatioProfile {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub rules: Vec<RatioRule>,
+}
+
+pub fn profile() -> RatioProfile {
+    RatioProfile {
+        name: "ratios",
+        description: "Success rates and cache hit ratios from pairs of counters",
+        rules: vec![
+            RatioRule {
+                name: "ASP.NET request success rate",
+                numerator: "\\ASP.NET Applications(*)\\Requests Succeeded",
+                denominator: Denominator::Total("\\ASP.NET Applications(*)\\Requests Total"),
+                warning: 99.0,
+                critical: 95.0,
+                min_duration: Duration::minutes(5),
+                min_total: 1.0,
+                cumulative: true,
+                advice: SUCCESS_ADVICE,
+            },
+            RatioRule {
+                name: "ASP.NET output cache hit ratio",
+                numerator: "\\ASP.NET Applications(*)\\Output Cache Hits",
+                denominator: Denominator::Rest("\\ASP.NET Applications(*)\\Output Cache Misses"),
+                warning: 50.0,
+                critical: 20.0,
+                min_duration: Duration::minutes(10),
+                min_total: 1.0,
+                cumulative: true,
+                advice: CACHE_ADVICE,
+            },
+            RatioRule {
+                name: "IIS kernel URI cache hit ratio",
+                numerator: "\\Web Service Cache\\Kernel: URI Cache Hits",
+                denominator: Denominator::Rest("\\Web Service Cache\\Kernel: URI Cache Misses"),
+                warning: 50.0,
+                critical: 20.0,
+                min_duration: Duration::minutes(10),
+                min_total: 1.0,
+                cumulative: true,
+                advice: CACHE_ADVICE,
+            },
+            RatioRule {
+                name: "IIS file cache hit ratio",
+                numerator: "\\Web Service Cache\\File Cache Hits",
+                denominator: Denominator::Rest("\\Web Service Cache\\File Cache Misses"),
+                warning: 50.0,
+                critical: 20.0,
+                min_duration: Duration::minutes(10),
+                min_total: 1.0,
+                cumulative: true,
+                advice: CACHE_ADVICE,
+            },
+        ],
+    }
+}
+
+impl Analyzer for RatioProfile {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn counters(&self) -> Vec<&'static str> {
+        self.rules
+            .iter()
+            .flat_map(|r| [r.numerator, r.denominator.pattern()])
+            .collect()
+    }
+
+    fn analyze(&self, data: &CounterData) -> Vec<Finding> {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.check(self.name, data))
+            .collect()
+    }
+}
+
+impl Denominator {
+    fn pattern(&self) -> &'static str {
+        match self {
+            Denominator::Total(pattern) | Denominator::Rest(pattern) => pattern,
+        }
+    }
+}
+
+impl RatioRule {
+    fn check(&self, analyzer: &'static str, data: &CounterData) -> Vec<Finding> {
+        let denominators = data
+            .matching(self.denominator.pattern())
+            .into_iter()
+            .filter_map(|(counter, samples)| Some((pair_key(counter)?, samples)))
+            .collect::<HashMap<(String, Option<String>), &Series>>();
+
+        let mut findings = Vec::new();
+
+        for (counter, numerator) in data.matching(self.numerator) {
+            let denominator = match pair_key(counter).and_then(|key| denominators.get(&key)) {
+                Some(denominator) => denominator,
+                None => continue,
+            };
+
+            // The percentage is negated so a drop below the threshold is a
+            // run above it.
+            let ratios = self
+                .ratios(&numerator.to_vec(), &denominator.to_vec())
+                .into_iter()
+                .map(|(time, ratio)| CounterValueWithTime::Double(time, -ratio))
+                .collect::<Vec<CounterValueWithTime>>();
+
+            for v in sustained_above(&ratios, -self.warning, self.min_duration) {
+                let (average, worst) = (-v.average, -v.peak);
+                let severity = if average <= self.critical {
+                    Severity::Critical
+                } else {
+                    Severity::Warning
+                };
+
+                findings.push(Finding {
+                    analyzer,
+                    severity,
+                    counter: counter.to_string(),
+                    start: v.start,
+                    end: v.end,
+                    message: format!(
+                        "{} stayed below {}% for {}, averaging {:.1}% (worst {:.1}%). {}",
+                        self.name,
+                        self.warning,
+                        format_duration(v.duration()),
+                        average,
+                        worst,
+                        self.advice
+                    ),
+                });
+            }
+        }
+
+        findings
+    }
+
+    // The percentage at each time both counters have a sample and the total
+    // is busy enough to mean something.
+    fn ratios(
+        &self,
+        numerator: &[CounterValueWithTime],
+        denominator: &[CounterValueWithTime],
+    ) -> Vec<(OffsetDateTime, f64)> {
+        let (numerator, denominator) = if self.cumulative {
+            (per_second(numerator), per_second(denominator))
+        } else {
+            (numerator.to_vec(), denominator.to_vec())
+        };
+
+        let denominator = denominator
+            .iter()
+            .map(|s| (s.time(), s.value()))
+            .collect::<HashMap<OffsetDateTime, f64>>();
+
+        numerator
+            .iter()
+            .filter_map(|s| {
+                let part = s.value();
+                let total = match self.denominator {
+                    Denominator::Total(_) => *denominator.get(&s.time())?,
+                    Denominator::Rest(_) => part + denominator.get(&s.time())?,
+                };
+                if total < self.min_total || total <= 0.0 {
+                    return None;
+                }
+                Some((s.time(), (100.0 * part / total).min(100.0)))
+            })
+            .collect()
+    }
+}
+
+// Counters of a pair share their machine and instance.
+fn pair_key(counter: &str) -> Option<(String, Option<String>)> {
+    let path = CounterPath::parse(counter)?;
+    Some((path.machine.to_lowercase(), path.instance))
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    analyze::{Analyzer, Finding, Severity},
+    counter_path::CounterPath,
+    reader::CounterData,
+    timespec::format_duration,
+};
+
+// A queue this deep means requests are waiting on the disk.
+const QUEUE_BUSY: f64 = 2.0;
+
+// Avg. Disk sec/Transfer is in seconds.
+const LATENCY_SLOW: f64 = 0.020;
+const LATENCY_CRITICAL: f64 = 0.050;
+
+const MIN_DURATION: Duration = Duration::minutes(1);
+
+// Problems on the same disk closer together than this are reported as one
+// finding rather than a burst of short ones.
+const COALESCE_GAP: Duration = Duration::minutes(10);
+
+const AVG_QUEUE: &str = "Avg. Disk Queue Length";
+const CURRENT_QUEUE: &str = "Current Disk Queue Length";
+const LATENCY: &str = "Avg. Disk sec/Transfer";
+
+#[derive(Clone, Copy, PartialEq)]
+enum DiskState {
+    Idle,
+    BusyHealthy,
+    Saturated,
+    SlowUnderLightLoad,
+}
+
+struct DiskSample {
+    time: OffsetDateTime,
+    avg_queue: f64,
+    current_queue: Option<f64>,
+    latency: f64,
+}
+
+impl DiskSample {
+    fn state(&self) -> DiskState {
+        match (self.avg_queue >= QUEUE_BUSY, self.latency >= LATENCY_SLOW) {
+            (true, true) => DiskState::Saturated,
+            (false, true) => DiskState::SlowUnderLightLoad,
+            (true, false) => DiskState::BusyHealthy,
+            (false, false) => DiskState::Idle,
+        }
+    }
+
+    fn is_problem(&self) -> bool {
+        matches!(
+            self.state(),
+            DiskState::Saturated | DiskState::SlowUnderLightLoad
+        )
+    }
+}
+
+pub struct StorageAnalyzer;
+
+impl Analyzer for StorageAnalyzer {
+    fn name(&self) -> &'static str {
+        "storage"
+    }
+
+    fn description(&self) -> &'static str {
+        "Disk queue length against latency, separating busy disks from saturated ones"
+    }
+
+    fn counters(&self) -> Vec<&'static str> {
+        vec![
+            "\\PhysicalDisk(*)\\Avg. Disk Queue Length",
+            "\\PhysicalDisk(*)\\Current Disk Queue Length",
+            "\\PhysicalDisk(*)\\Avg. Disk sec/Transfer",
+        ]
+    }
+
+    fn analyze(&self, data: &CounterData) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for ((machine, disk), samples) in samples_by_disk(data) {
+            let counter = format!("\\\\{}\\PhysicalDisk({})", machine, disk);
+
+            for episode in episodes(&samples, DiskSample::is_problem) {
+                findings.push(problem_finding(self.name(), &counter, &samples[episode]));
+            }
+
+            let busy = episodes(&samples, |s| s.state() == DiskState::BusyHealthy);
+            if let Some(finding) = busy_finding(self.name(), &counter, &samples, &busy) {
+                findings.push(finding);
+            }
+        }
+
+        findings
+    }
+}
+
+// Lines up the three counters for every disk, carrying each counter's last
+// value forward so a missing sample doesn't drop the timestamp.
+fn samples_by_disk(data: &CounterData) -> BTreeMap<(String, String), Vec<DiskSample>> {
+    let mut updates =
+        BTreeMap::<(String, String), BTreeMap<OffsetDateTime, Vec<(&str, f64)>>>::new();
+
+    for counter in &data.counters {
+        let path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let name = match path.counter.as_str() {
+            AVG_QUEUE => AVG_QUEUE,
+            CURRENT_QUEUE => CURRENT_QUEUE,
+            LATENCY => LATENCY,
+            _ => continue,
+        };
+
+        let disk = match path.instance {
+            Some(instance) if path.object == "PhysicalDisk" && instance != "_Total" => instance,
+            _ => continue,
+        };
+
+        let timeline = updates.entry((path.machine, disk)).or_default();
+        for sample in data.samples[counter].iter() {
+            timeline
+                .entry(sample.time())
+                .or_default()
+                .push((name, sample.value()));
+        }
+    }
+
+    updates
+        .into_iter()
+        .map(|(disk, timeline)| {
+            let (mut avg_queue, mut current_queue, mut latency) = (None, None, None);
+            let mut samples = Vec::new();
+
+            for (time, values) in timeline {
+                for (name, value) in values {
+                    match name {
+                        AVG_QUEUE => avg_queue = Some(value),
+                        CURRENT_QUEUE => current_queue = Some(value),
+                        _ => latency = Some(value),
+                    }
+                }
+
+                if let (Some(avg_queue), Some(latency)) = (avg_queue, latency) {
+                    samples.push(DiskSample {
+                        time,
+                        avg_queue,
+                        current_queue,
+                        latency,
+                    });
+                }
+            }
+
+            (disk, samples)
+        })
+        .collect()
+}
+
+// Index ranges covering samples that match, merging matches less than
+// COALESCE_GAP apart and dropping ranges shorter than MIN_DURATION.
+fn episodes(
+    samples: &[DiskSample],
+    matches: impl Fn(&DiskSample) -> bool,
+) -> Vec<std::ops::Range<usize>> {
+    let mut ranges = Vec::<std::ops::Range<usize>>::new();
+
+    for (i, sample) in samples.iter().enumerate() {
+        if !matches(sample) {
+            continue;
+        }
+
+        match ranges.last_mut() {
+            Some(range) if sample.time - samples[range.end - 1].time <= COALESCE_GAP => {
+                range.end = i + 1
+            }
+            _ => ranges.push(i..i + 1),
+        }
+    }
+
+    ranges.retain(|r| samples[r.end - 1].time - samples[r.start].time >= MIN_DURATION);
+    ranges
+}
+
+fn problem_finding(analyzer: &'static str, counter: &str, episode: &[DiskSample]) -> Finding {
+    let problems = episode
+        .iter()
+        .filter(|s| s.is_problem())
+        .collect::<Vec<&DiskSample>>();
+    let saturated = problems
+        .iter()
+        .filter(|s| s.state() == DiskState::Saturated)
+        .count();
+
+    let n = problems.len() as f64;
+    let average_latency = problems.iter().map(|s| s.latency).sum::<f64>() / n;
+    let peak_latency = problems.iter().map(|s| s.latency).fold(f64::MIN, f64::max);
+    let average_queue = problems.iter().map(|s| s.avg_queue).sum::<f64>() / n;
+    let peak_current_queue = problems
+        .iter()
+        .filter_map(|s| s.current_queue)
+        .reduce(f64::max);
+
+    let severity = if average_latency >= LATENCY_CRITICAL {
+        Severity::Critical
+    } else {
+        Severity::Warning
+    };
+
+    let (start, end) = (episode[0].time, episode[episode.len() - 1].time);
+
+    let pattern = if saturated * 2 >= problems.len() {
+        "Saturated: the queue is deep and requests are slow, so the disk can't keep up with \
+         the load"
+    } else {
+        "Slow under light load: requests are slow without a queue behind them, which points \
+         at the storage beyond the disk (array, host, or driver) rather than demand"
+    };
+
+    let current_queue = match peak_current_queue {
+        Some(peak) => format!(", current queue peaked at {:.0}", peak),
+        None => String::new(),
+    };
+
+    Finding {
+        analyzer,
+        severity,
+        counter: counter.to_string(),
+        start,
+        end,
+        message: format!(
+            "{}. Latency averaged {:.1} ms (peak {:.1} ms) with an average queue of {:.1}{} \
+             over {}; {} of {} slow samples were saturated.",
+            pattern,
+            average_latency * 1000.0,
+            peak_latency * 1000.0,
+            average_queue,
+            current_queue,
+            format_duration(end - start),
+            saturated,
+            problems.len()
+        ),
+    }
+}
+
+// One informational finding per disk covering every busy-but-healthy period,
+// so a disk that's working hard without trouble doesn't look like a problem.
+fn busy_finding(
+    analyzer: &'static str,
+    counter: &str,
+    samples: &[DiskSample],
+    busy: &[std::ops::Range<usize>],
+) -> Option<Finding> {
+    let (first, last) = (busy.first()?, busy.last()?);
+
+    let busy_samples = busy
+        .iter()
+        .flat_map(|r| &samples[r.clone()])
+        .filter(|s| s.state() == DiskState::BusyHealthy)
+        .collect::<Vec<&DiskSample>>();
+
+    let n = busy_samples.len() as f64;
+    let average_queue = busy_samples.iter().map(|s| s.avg_queue).sum::<f64>() / n;
+    let average_latency = busy_samples.iter().map(|s| s.latency).sum::<f64>() / n;
+    let busy_time = busy
+        .iter()
+        .map(|r| samples[r.end - 1].time - samples[r.start].time)
+        .sum::<Duration>();
+
+    Some(Finding {
+        analyzer,
+        severity: Severity::Info,
+        counter: counter.to_string(),
+        start: samples[first.start].time,
+        end: samples[last.end - 1].time,
+        message: format!(
+            "Busy but healthy for {} across {} periods: the queue averaged {:.1} while latency \
+             stayed at {:.1} ms.",
+            format_duration(busy_time),
+            busy.len(),
+            average_queue,
+            average_latency * 1000.0
+        ),
+    })
+}
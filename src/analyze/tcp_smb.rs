@@ -0,0 +1,108 @@
+use time::Duration;
+
+use crate::analyze::threshold::{Direction, ThresholdProfile, ThresholdRule};
+
+const RETRANSMIT_ADVICE: &str = "Retransmits mean segments are being lost or delayed on the \
+     path; check NIC errors, switch ports, and firewalls between the tiers.";
+
+const FAILURE_ADVICE: &str = "Connections are being refused or timing out; check that the \
+     remote service is listening and that nothing in between is dropping SYNs.";
+
+const RESET_ADVICE: &str = "Established connections are being reset; look for firewalls or \
+     load balancers with idle timeouts, or services restarting.";
+
+const SMB_ADVICE: &str = "Slow SMB responses can be the network or the file server; compare \
+     with disk latency on the server before blaming the network.";
+
+pub fn profile() -> ThresholdProfile {
+    let mut rules = Vec::new();
+
+    rules.extend(tcp_rules(
+        "\\TCPv4\\Segments Retransmitted/sec",
+        "\\TCPv4\\Connection Failures",
+        "\\TCPv4\\Connections Reset",
+    ));
+    rules.extend(tcp_rules(
+        "\\TCPv6\\Segments Retransmitted/sec",
+        "\\TCPv6\\Connection Failures",
+        "\\TCPv6\\Connections Reset",
+    ));
+
+    for counter in [
+        "\\SMB Client Shares(*)\\Avg. sec/Data Request",
+        "\\SMB Client Shares(*)\\Avg. sec/Read",
+        "\\SMB Client Shares(*)\\Avg. sec/Write",
+    ] {
+        rules.push(ThresholdRule {
+            counter,
+            direction: Direction::Above,
+            warning: 25.0,
+            critical: 50.0,
+            min_duration: Duration::minutes(2),
+            scale: 1000.0,
+            unit: " ms",
+            cumulative: false,
+            advice: SMB_ADVICE,
+        });
+    }
+
+    rules.push(ThresholdRule {
+        counter: "\\SMB Client Shares(*)\\Avg. Data Queue Length",
+        direction: Direction::Above,
+        warning: 2.0,
+        critical: 10.0,
+        min_duration: Duration::minutes(2),
+        scale: 1.0,
+        unit: "",
+        cumulative: false,
+        advice: SMB_ADVICE,
+    });
+
+    ThresholdProfile {
+        name: "tcp-smb",
+        description: "TCP retransmits, connection failures and resets, and SMB client latency",
+        rules,
+    }
+}
+
+fn tcp_rules(
+    retransmits: &'static str,
+    failures: &'static str,
+    resets: &'static str,
+) -> Vec<ThresholdRule> {
+    vec![
+        ThresholdRule {
+            counter: retransmits,
+            direction: Direction::Above,
+            warning: 10.0,
+            critical: 50.0,
+            min_duration: Duration::minutes(2),
+            scale: 1.0,
+            unit: "/sec",
+            cumulative: false,
+            advice: RETRANSMIT_ADVICE,
+        },
+        ThresholdRule {
+            counter: failures,
+            direction: Direction::Above,
+            warning: 1.0,
+            critical: 10.0,
+            min_duration: Duration::minutes(2),
+            scale: 1.0,
+            unit: "/sec",
+            cumulative: true,
+            advice: FAILURE_ADVICE,
+        },
+        ThresholdRule {
+            counter: resets,
+            direction: Direction::Above,
+            warning: 1.0,
+            critical: 10.0,
+            min_duration: Duration::minutes(2),
+            scale: 1.0,
+            unit: "/sec",
+            cumulative: true,
+            advice: RESET_ADVICE,
+        },
+    ]
+}
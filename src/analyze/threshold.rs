@@ -0,0 +1,163 @@
+use serde::Deserialize;
+use time::Duration;
+
+use crate::{
+    analyze::{sustained_above, Analyzer, Finding, Severity},
+    counter_path::CounterPath,
+    pdh_helper::CounterValueWithTime,
+    reader::CounterData,
+    timespec::format_duration,
+};
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Above,
+    Below,
+}
+
+// One counter checked against fixed warning and critical thresholds. Values
+// are multiplied by scale before comparing and printing, so thresholds can be
+// written in the unit shown, like milliseconds for a counter in seconds.
+#[derive(Clone)]
+pub struct ThresholdRule {
+    pub counter: &'static str,
+    pub direction: Direction,
+    pub warning: f64,
+    pub critical: f64,
+    pub min_duration: Duration,
+    pub scale: f64,
+    pub unit: &'static str,
+    // The counter is a running total, so it's checked as a per-second rate.
+    pub cumulative: bool,
+    pub advice: &'static str,
+}
+
+// An analyzer made of threshold rules, for counters that can be judged on
+// their own.
+#[derive(Clone)]
+pub struct ThresholdProfile {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub rules: Vec<ThresholdRule>,
+}
+
+impl Analyzer for ThresholdProfile {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn counters(&self) -> Vec<&'static str> {
+        self.rules.iter().map(|r| r.counter).collect()
+    }
+
+    fn analyze(&self, data: &CounterData) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for rule in &self.rules {
+            for (counter, samples) in data.matching(rule.counter) {
+                // _Total repeats what the instances already report.
+                let is_total = CounterPath::parse(counter)
+                    .and_then(|p| p.instance)
+                    .is_some_and(|i| i == "_Total");
+                if is_total && !rule.counter.contains("_Total") {
+                    continue;
+                }
+
+                findings.extend(rule.check(self.name, counter, &samples.to_vec()));
+            }
+        }
+
+        findings
+    }
+}
+
+impl ThresholdRule {
+    fn check(
+        &self,
+        analyzer: &'static str,
+        counter: &str,
+        samples: &[CounterValueWithTime],
+    ) -> Vec<Finding> {
+        // Thresholds below are checked by flipping the sign, so the same run
+        // detection works in both directions.
+        let sign = match self.direction {
+            Direction::Above => 1.0,
+            Direction::Below => -1.0,
+        };
+
+        let values = if self.cumulative {
+            per_second(samples)
+        } else {
+            samples
+                .iter()
+                .map(|s| CounterValueWithTime::Double(s.time(), s.value()))
+                .collect()
+        };
+
+        let values = values
+            .iter()
+            .map(|s| CounterValueWithTime::Double(s.time(), sign * s.value() * self.scale))
+            .collect::<Vec<CounterValueWithTime>>();
+
+        let comparison = match self.direction {
+            Direction::Above => "above",
+            Direction::Below => "below",
+        };
+
+        sustained_above(&values, sign * self.warning, self.min_duration)
+            .into_iter()
+            .map(|v| {
+                let (average, peak) = (sign * v.average, sign * v.peak);
+                let severity = if sign * average >= sign * self.critical {
+                    Severity::Critical
+                } else {
+                    Severity::Warning
+                };
+
+                Finding {
+                    analyzer,
+                    severity,
+                    counter: counter.to_string(),
+                    start: v.start,
+                    end: v.end,
+                    message: format!(
+                        "Stayed {} {}{} for {}, averaging {:.2}{} (worst {:.2}{}). {}",
+                        comparison,
+                        self.warning,
+                        self.unit,
+                        format_duration(v.duration()),
+                        average,
+                        self.unit,
+                        peak,
+                        self.unit,
+                        self.advice
+                    ),
+                }
+            })
+            .collect()
+    }
+}
+
+// Turns a running total into the rate between consecutive samples. A drop
+// means the counter was reset, so that interval is skipped.
+pub fn per_second(samples: &[CounterValueWithTime]) -> Vec<CounterValueWithTime> {
+    samples
+        .windows(2)
+        .filter_map(|pair| {
+            let seconds = (pair[1].time() - pair[0].time()).as_seconds_f64();
+            let delta = pair[1].value() - pair[0].value();
+            if seconds <= 0.0 || delta < 0.0 {
+                return None;
+            }
+            Some(CounterValueWithTime::Double(
+                pair[1].time(),
+                delta / seconds,
+            ))
+        })
+        .collect()
+}
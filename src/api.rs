@@ -0,0 +1,275 @@
+use std::{
+    collections::BTreeMap,
+    net::{TcpListener, TcpStream},
+    panic::AssertUnwindSafe,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use time::{serde::rfc3339, OffsetDateTime};
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    cache::cached_summary,
+    cli::ApiArgs,
+    http::{read_request, write_response},
+    log_files::find_log_files,
+    pdh_helper::{bind_input_logfiles, status_name, PerfLogSummary},
+    reader::read_selected_counters,
+    selection::CounterSelection,
+    series_set::SeriesSet,
+    status::progress,
+};
+
+// What GET /api/v1 answers with, for whoever is writing a client.
+const USAGE: &str = r#"perflogtool API
+
+POST /api/v1/summary
+  {"logs": "D:\\perflogs\\*.blg"}
+  The files matched, and the machines, objects, counters, instances, and
+  time range of the logs, like summary prints.
+
+POST /api/v1/read
+  {"logs": "D:\\perflogs\\*.blg",
+   "counters": ["\\Processor(*)\\% Processor Time", "Available MBytes"],
+   "start": "2024-06-12T08:00:00Z", "end": "2024-06-12T09:00:00Z"}
+  The samples of each counter matching a pattern, with its object,
+  instance, unit, and counter type, and how many samples had no value, by
+  reason. Patterns are text or wildcards,
+  like --counter takes. start and end are optional RFC 3339 times.
+
+logs is a glob pattern on the machine running the API, matching .blg,
+.csv, .tsv, or .etl logs, or .zip or .gz files of them. Errors are
+{"error": "..."} with a 4xx or 5xx status.
+"#;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SummaryRequest {
+    logs: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReadRequest {
+    logs: String,
+    counters: Vec<String>,
+    #[serde(default, with = "rfc3339::option")]
+    start: Option<OffsetDateTime>,
+    #[serde(default, with = "rfc3339::option")]
+    end: Option<OffsetDateTime>,
+}
+
+#[derive(Serialize)]
+struct SummaryResponse {
+    files: Vec<String>,
+    #[serde(flatten)]
+    summary: PerfLogSummary,
+}
+
+#[derive(Serialize)]
+struct ReadResponse {
+    files: Vec<String>,
+    counters: Vec<CounterResponse>,
+}
+
+#[derive(Serialize)]
+struct CounterResponse {
+    counter: String,
+    object: Option<String>,
+    instance: Option<String>,
+    unit: &'static str,
+    counter_type: Option<u32>,
+    samples: Vec<Sample>,
+    // How many samples had no value, by the reason PDH gave.
+    errors: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+struct Sample {
+    #[serde(with = "rfc3339")]
+    time: OffsetDateTime,
+    value: f64,
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+}
+
+// Answers summary and read requests from other tools until interrupted.
+// Each request binds its own logs, so requests for different logs run at
+// the same time on the workers, and PDH's calls, which block, only hold up
+// the worker they're on.
+pub fn api(args: &ApiArgs) {
+    let listener = match TcpListener::bind(&args.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", args.listen, e);
+            return;
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel::<TcpStream>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..args.workers {
+        let receiver = Arc::clone(&receiver);
+        thread::spawn(move || loop {
+            let stream = match receiver.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            if let Err(e) = handle(stream) {
+                eprintln!("Failed to answer a request: {}", e);
+            }
+        });
+    }
+
+    progress!(
+        "Answering requests at http://{}/api/v1 on {} workers until interrupted.",
+        args.listen,
+        args.workers
+    );
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let _ = sender.send(stream);
+            }
+            Err(e) => eprintln!("Failed to accept a connection: {}", e),
+        }
+    }
+}
+
+fn handle(mut stream: TcpStream) -> std::io::Result<()> {
+    let request = read_request(&stream)?;
+
+    let response = match (request.method.as_str(), request.target.as_str()) {
+        ("GET", "/api/v1" | "/api/v1/") => {
+            return write_response(&mut stream, 200, "text/plain", USAGE.as_bytes());
+        }
+        ("POST", "/api/v1/summary") => answer(&request.body, summary),
+        ("POST", "/api/v1/read") => answer(&request.body, read),
+        (_, "/api/v1/summary" | "/api/v1/read") => Response::error(405, "Use POST."),
+        _ => Response::error(404, "Not found. GET /api/v1 lists the requests."),
+    };
+
+    write_response(
+        &mut stream,
+        response.status,
+        "application/json",
+        response.body.as_bytes(),
+    )
+}
+
+// Parses the body, runs the request, and turns what it returns into JSON.
+// PDH failures panic, like they do for the commands, so a panic is caught
+// and answered as a 500 rather than taking the worker down.
+fn answer<T: for<'de> Deserialize<'de>, R: Serialize>(
+    body: &[u8],
+    run: fn(T) -> Result<R, Response>,
+) -> Response {
+    let request = match serde_json::from_slice::<T>(body) {
+        Ok(request) => request,
+        Err(e) => return Response::error(400, &format!("Bad request: {}", e)),
+    };
+
+    match std::panic::catch_unwind(AssertUnwindSafe(|| run(request))) {
+        Ok(Ok(result)) => match serde_json::to_string(&result) {
+            Ok(body) => Response { status: 200, body },
+            Err(e) => Response::error(500, &e.to_string()),
+        },
+        Ok(Err(response)) => response,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| panic.downcast_ref::<&str>().copied())
+                .unwrap_or("the request failed");
+            Response::error(500, message)
+        }
+    }
+}
+
+// A bound data source, closed when dropped, so a request that fails with a
+// panic doesn't leave its logs open in a server that runs until stopped.
+struct DataSource(isize);
+
+impl Drop for DataSource {
+    fn drop(&mut self) {
+        unsafe { PdhCloseLog(self.0, 0) };
+    }
+}
+
+// Binds the logs and returns the files with the data source.
+fn bind(logs: &str) -> Result<(Vec<String>, DataSource), Response> {
+    let files = find_log_files(logs);
+    if files.is_empty() {
+        return Err(Response::error(404, &format!("No files matched {}.", logs)));
+    }
+    let source = DataSource(bind_input_logfiles(files.clone()));
+    Ok((files, source))
+}
+
+fn summary(request: SummaryRequest) -> Result<SummaryResponse, Response> {
+    let (files, source) = bind(&request.logs)?;
+    let summary = cached_summary(&files, source.0);
+    drop(source);
+
+    Ok(SummaryResponse { files, summary })
+}
+
+fn read(request: ReadRequest) -> Result<ReadResponse, Response> {
+    if request.counters.is_empty() {
+        return Err(Response::error(400, "counters is empty."));
+    }
+
+    let (files, source) = bind(&request.logs)?;
+    let summary = cached_summary(&files, source.0);
+    let counter_data = read_selected_counters(
+        source.0,
+        &summary,
+        &CounterSelection::new(&request.counters),
+        None,
+    );
+    drop(source);
+
+    let in_range = |time: OffsetDateTime| {
+        request.start.is_none_or(|start| time >= start) && request.end.is_none_or(|end| time <= end)
+    };
+
+    let counters = SeriesSet::from(counter_data)
+        .into_iter()
+        .map(|s| CounterResponse {
+            object: s.object().map(str::to_string),
+            instance: s.instance().map(str::to_string),
+            unit: s.unit.name(),
+            counter_type: s.counter_type,
+            samples: s
+                .times()
+                .zip(s.values())
+                .filter(|(time, _)| in_range(*time))
+                .map(|(time, value)| Sample { time, value })
+                .collect(),
+            errors: s
+                .errors
+                .iter()
+                .flat_map(|e| &e.statuses)
+                .map(|(status, count)| (status_name(*status), *count))
+                .collect(),
+            counter: s.counter,
+        })
+        .collect();
+
+    Ok(ReadResponse { files, counters })
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use flate2::read::GzDecoder;
+use windows::Win32::{
+    Foundation::{CloseHandle, BOOL, ERROR_ACCESS_DENIED, FALSE, STILL_ACTIVE, TRUE},
+    System::{
+        Console::SetConsoleCtrlHandler,
+        Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+    },
+};
+use zip::ZipArchive;
+
+// The logs in an archive that are extracted. Anything else, like the
+// collector's config or text notes, is left alone.
+const LOG_EXTENSIONS: [&str; 3] = ["blg", "csv", "tsv"];
+
+static NEXT_ARCHIVE: AtomicUsize = AtomicUsize::new(0);
+
+// The logs extracted from each archive this run, by its path, so scanning
+// the files again, like export --follow does on every poll or the API does
+// for every request, doesn't extract them again.
+static EXTRACTED: Mutex<BTreeMap<String, Extraction>> = Mutex::new(BTreeMap::new());
+
+struct Extraction {
+    // The archive's size and modified time when it was extracted.
+    version: String,
+    dir: PathBuf,
+    logs: Vec<String>,
+}
+
+impl Extraction {
+    fn is_current(&self, version: &str) -> bool {
+        self.version == version && self.logs.iter().all(|log| Path::new(log).exists())
+    }
+}
+
+pub fn is_archive(path: &str) -> bool {
+    matches!(extension(Path::new(path)).as_deref(), Some("zip" | "gz"))
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().map(|e| e.to_string_lossy().to_lowercase())
+}
+
+// The logs are extracted under the temp directory, in a directory for this
+// run that's removed when it ends.
+fn extract_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("perflogtool-{}", std::process::id()))
+}
+
+// Removes the extracted logs when dropped, so they're cleaned up at the end
+// of the run even when a command panics. Commands that run until
+// interrupted, like export --follow and api, end without it being dropped,
+// so Ctrl+C removes them too.
+pub struct ExtractedLogs;
+
+impl ExtractedLogs {
+    pub fn start() -> ExtractedLogs {
+        remove_abandoned();
+        unsafe { SetConsoleCtrlHandler(Some(console_handler), TRUE) };
+        ExtractedLogs
+    }
+}
+
+impl Drop for ExtractedLogs {
+    fn drop(&mut self) {
+        let dir = extract_dir();
+        if dir.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                eprintln!("Failed to remove {}: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+// Removes the extracted logs and lets the default handler end the process.
+// Commands that stop on Ctrl+C themselves, like collect, install their own
+// handler, which runs first, and end with ExtractedLogs dropped.
+unsafe extern "system" fn console_handler(_ctrl_type: u32) -> BOOL {
+    let _ = std::fs::remove_dir_all(extract_dir());
+    FALSE
+}
+
+// Removes the logs extracted by runs that ended without removing them, like
+// ones that were killed.
+fn remove_abandoned() {
+    let entries = match std::fs::read_dir(std::env::temp_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let pid = entry
+            .file_name()
+            .to_string_lossy()
+            .strip_prefix("perflogtool-")
+            .and_then(|pid| pid.parse::<u32>().ok());
+        match pid {
+            Some(pid) if pid != std::process::id() && !is_running(pid) => {
+                let _ = std::fs::remove_dir_all(entry.path());
+            }
+            _ => {}
+        }
+    }
+}
+
+// A process that can't be opened for lack of access, like an elevated one,
+// is taken to be running.
+fn is_running(pid: u32) -> bool {
+    let handle = match unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) } {
+        Ok(handle) => handle,
+        Err(e) => return e.code() == ERROR_ACCESS_DENIED.to_hresult(),
+    };
+
+    let mut exit_code = 0;
+    let running = unsafe { GetExitCodeProcess(handle, &mut exit_code) }.as_bool()
+        && exit_code == STILL_ACTIVE.0 as u32;
+    unsafe { CloseHandle(handle) };
+    running
+}
+
+// Extracts the logs in a .zip, or the one log in a .gz, and returns their
+// paths. A .gz is named for the log it holds, like perf.blg.gz. An archive
+// already extracted this run, and not changed since, isn't extracted again.
+pub fn extract_logs(path: &str) -> Result<Vec<String>, String> {
+    let metadata = std::fs::metadata(path).map_err(|e| e.to_string())?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let version = format!("{}|{}", metadata.len(), modified);
+
+    if let Some(done) = EXTRACTED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(path)
+        .filter(|e| e.is_current(&version))
+    {
+        return Ok(done.logs.clone());
+    }
+
+    let dir = extract_dir().join(NEXT_ARCHIVE.fetch_add(1, Ordering::Relaxed).to_string());
+    let logs = match extract(path, &dir) {
+        Ok(logs) => logs,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&dir);
+            return Err(e);
+        }
+    };
+
+    let mut extracted = EXTRACTED.lock().unwrap_or_else(|e| e.into_inner());
+    // Another thread, like another API request, may have extracted the same
+    // archive in the meantime. Only one of the two is kept.
+    if let Some(done) = extracted.get(path).filter(|e| e.is_current(&version)) {
+        let logs = done.logs.clone();
+        drop(extracted);
+        let _ = std::fs::remove_dir_all(&dir);
+        return Ok(logs);
+    }
+    let replaced = extracted.insert(
+        path.to_string(),
+        Extraction {
+            version,
+            dir,
+            logs: logs.clone(),
+        },
+    );
+    drop(extracted);
+
+    // The logs of the archive before it changed are removed now rather than
+    // when the run ends, for the API, which runs until it's stopped. Any
+    // still being read are left for the end of the run.
+    if let Some(replaced) = replaced {
+        let _ = std::fs::remove_dir_all(&replaced.dir);
+    }
+
+    Ok(logs)
+}
+
+fn extract(path: &str, dir: &Path) -> Result<Vec<String>, String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let extracted = match extension(Path::new(path)).as_deref() {
+        Some("gz") => extract_gzip(path, file, dir)?,
+        _ => extract_zip(file, dir)?,
+    };
+
+    if extracted.is_empty() {
+        return Err("there are no .blg, .csv, or .tsv logs in it".to_string());
+    }
+
+    Ok(extracted)
+}
+
+fn extract_gzip(path: &str, file: File, dir: &Path) -> Result<Vec<String>, String> {
+    let name = Path::new(path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if !is_log(&name) {
+        return Ok(Vec::new());
+    }
+
+    let output = dir.join(&name);
+    let mut reader = GzDecoder::new(BufReader::new(file));
+    let mut writer = File::create(&output).map_err(|e| e.to_string())?;
+    std::io::copy(&mut reader, &mut writer).map_err(|e| e.to_string())?;
+
+    Ok(vec![output.display().to_string()])
+}
+
+// Members in folders are extracted side by side, so ones with the same name
+// get a number in front.
+fn extract_zip(file: File, dir: &Path) -> Result<Vec<String>, String> {
+    let mut archive = ZipArchive::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+
+    let mut extracted = Vec::new();
+    for index in 0..archive.len() {
+        let mut member = archive.by_index(index).map_err(|e| e.to_string())?;
+        let name = match member
+            .enclosed_name()
+            .and_then(|p| p.file_name().map(|n| n.to_owned()))
+        {
+            Some(name) if member.is_file() && is_log(&name.to_string_lossy()) => name,
+            _ => continue,
+        };
+
+        let mut output = dir.join(&name);
+        if output.exists() {
+            output = dir.join(format!("{}-{}", index, name.to_string_lossy()));
+        }
+
+        let mut writer = File::create(&output).map_err(|e| e.to_string())?;
+        std::io::copy(&mut member, &mut writer)
+            .map_err(|e| format!("failed to extract {}: {}", member.name(), e))?;
+        extracted.push(output.display().to_string());
+    }
+
+    Ok(extracted)
+}
+
+fn is_log(name: &str) -> bool {
+    extension(Path::new(name)).is_some_and(|e| LOG_EXTENSIONS.contains(&e.as_str()))
+}
@@ -0,0 +1,382 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+use time::OffsetDateTime;
+
+use crate::{
+    pdh_helper::{
+        detail_level, format_flags, get_perflog_summary, machine_patterns, CounterInfo,
+        CounterValueWithTime, MachineSummary, ObjectSummary, PerfLogSummary, TimeRange,
+    },
+    reader::CounterData,
+    timespec::extend_log_range,
+};
+
+const MAGIC: &[u8] = b"PERFLOGTOOL-CACHE 2\n";
+const SUMMARY_MAGIC: &[u8] = b"PERFLOGTOOL-SUMMARY 3\n";
+
+static SUMMARY_CACHE: OnceLock<bool> = OnceLock::new();
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+// Set once at startup from --no-cache.
+pub fn set_summary_cache(enabled: bool) {
+    let _ = SUMMARY_CACHE.set(enabled);
+}
+
+// Everything the expensive read phase of triage produces, saved so a rerun
+// over the same logs can skip it.
+pub struct CachedRead {
+    pub start: OffsetDateTime,
+    pub end: OffsetDateTime,
+    pub machines: Vec<String>,
+    pub data: CounterData,
+}
+
+// Identifies the logs a cache was built from by path, size, and modified
+// time, so a cache is never reused after the logs change.
+pub fn fingerprint(files: &[String], separate: bool) -> String {
+    // The detail level and --machine decide which counters are listed, and
+    // the format flags what the values are, so a cache made with others
+    // doesn't fit.
+    let mut fingerprint = format!(
+        "separate={}\ndetail={}\nmachines={}\nformat={:#x}\n",
+        separate,
+        detail_level().0,
+        machine_patterns().join("|"),
+        format_flags()
+    );
+    for file in files {
+        let metadata = std::fs::metadata(file).ok();
+        let size = metadata.as_ref().map(|m| m.len()).unwrap_or(0);
+        let modified = metadata
+            .and_then(|m| m.modified().ok())
+            .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        fingerprint.push_str(&format!("{}|{}|{}\n", file, size, modified));
+    }
+    fingerprint
+}
+
+pub fn write_cache(path: &Path, fingerprint: &str, read: &CachedRead) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writer.write_all(MAGIC)?;
+    write_string(&mut writer, fingerprint)?;
+    write_time(&mut writer, read.start)?;
+    write_time(&mut writer, read.end)?;
+
+    writer.write_all(&(read.machines.len() as u32).to_le_bytes())?;
+    for machine in &read.machines {
+        write_string(&mut writer, machine)?;
+    }
+
+    writer.write_all(&(read.data.counters.len() as u32).to_le_bytes())?;
+    for counter in &read.data.counters {
+        let samples = read.data.samples.get(counter).cloned().unwrap_or_default();
+
+        write_string(&mut writer, counter)?;
+
+        match read.data.info.get(counter) {
+            Some(info) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&info.counter_type.to_le_bytes())?;
+                writer.write_all(&info.default_scale.to_le_bytes())?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+
+        writer.write_all(&(samples.len() as u64).to_le_bytes())?;
+
+        for sample in samples.iter() {
+            let (tag, bits) = match sample {
+                CounterValueWithTime::Long(_, v) => (0u8, v as i64 as u64),
+                CounterValueWithTime::Double(_, v) => (1u8, v.to_bits()),
+                CounterValueWithTime::Large(_, v) => (2u8, v as u64),
+            };
+            writer.write_all(&[tag])?;
+            write_time(&mut writer, sample.time())?;
+            writer.write_all(&bits.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()
+}
+
+// Enumerating the machines, objects, and counters of a big set of logs is
+// slow, so the result is kept under the user's local app data, keyed by the
+// fingerprint of the files. With --no-cache the logs are enumerated again and
+// the cached copy replaced.
+pub fn cached_summary(files: &[String], hdatasource: isize) -> PerfLogSummary {
+    let fingerprint = fingerprint(files, false);
+    let path = summary_cache_path(&fingerprint);
+
+    if *SUMMARY_CACHE.get().unwrap_or(&true) {
+        if let Some(summary) = path.as_deref().and_then(|p| read_summary(p, &fingerprint)) {
+            extend_log_range(summary.start_time, summary.end_time);
+            return summary;
+        }
+    }
+
+    let summary = get_perflog_summary(hdatasource);
+
+    if let Some(path) = path {
+        if let Err(e) = write_summary(&path, &fingerprint, &summary) {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    summary
+}
+
+fn summary_cache_path(fingerprint: &str) -> Option<PathBuf> {
+    // The fingerprint is checked again when reading, so a hash that changes
+    // between builds only costs a cache miss.
+    cache_file("summaries", fingerprint, "cache")
+}
+
+// A file under the user's local app data named by a hash of the key, in a
+// directory for each kind of cached file.
+pub fn cache_file(kind: &str, key: &str, extension: &str) -> Option<PathBuf> {
+    let base = std::env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let dir = base.join("perflogtool").join(kind);
+    std::fs::create_dir_all(&dir).ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    Some(dir.join(format!("{:016x}.{}", hasher.finish(), extension)))
+}
+
+// Written to a file of its own and renamed into place, so api requests
+// reading the same logs at once never see a half-written summary.
+fn write_summary(path: &Path, fingerprint: &str, summary: &PerfLogSummary) -> std::io::Result<()> {
+    let temp = path.with_extension(format!(
+        "{}-{}.tmp",
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = File::create(&temp)
+        .and_then(|file| write_summary_to(&mut BufWriter::new(file), fingerprint, summary))
+        .and_then(|_| std::fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written
+}
+
+fn write_summary_to(
+    writer: &mut impl Write,
+    fingerprint: &str,
+    summary: &PerfLogSummary,
+) -> std::io::Result<()> {
+    writer.write_all(SUMMARY_MAGIC)?;
+    write_string(writer, fingerprint)?;
+    write_time(writer, summary.start_time)?;
+    write_time(writer, summary.end_time)?;
+    writer.write_all(&summary.sample_count.to_le_bytes())?;
+
+    writer.write_all(&(summary.ranges.len() as u32).to_le_bytes())?;
+    for range in &summary.ranges {
+        write_time(writer, range.start_time)?;
+        write_time(writer, range.end_time)?;
+        writer.write_all(&range.sample_count.to_le_bytes())?;
+    }
+
+    writer.write_all(&(summary.machines.len() as u32).to_le_bytes())?;
+    for machine in &summary.machines {
+        write_string(writer, &machine.name)?;
+        writer.write_all(&(machine.objects.len() as u32).to_le_bytes())?;
+        for object in &machine.objects {
+            write_string(writer, &object.name)?;
+            write_strings(writer, &object.counters)?;
+            write_strings(writer, &object.instances)?;
+        }
+    }
+
+    writer.flush()
+}
+
+fn read_summary(path: &Path, fingerprint: &str) -> Option<PerfLogSummary> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+
+    let mut magic = vec![0u8; SUMMARY_MAGIC.len()];
+    reader.read_exact(&mut magic).ok()?;
+    if magic != SUMMARY_MAGIC || read_string(&mut reader).ok()? != fingerprint {
+        return None;
+    }
+
+    read_summary_contents(&mut reader).ok()
+}
+
+fn read_summary_contents(reader: &mut impl Read) -> std::io::Result<PerfLogSummary> {
+    let start_time = read_time(reader)?;
+    let end_time = read_time(reader)?;
+    let sample_count = read_u32(reader)?;
+
+    let mut ranges = Vec::new();
+    for _ in 0..read_u32(reader)? {
+        ranges.push(TimeRange {
+            start_time: read_time(reader)?,
+            end_time: read_time(reader)?,
+            sample_count: read_u32(reader)?,
+        });
+    }
+
+    let mut machines = Vec::new();
+    for _ in 0..read_u32(reader)? {
+        let name = read_string(reader)?;
+        let mut objects = Vec::new();
+        for _ in 0..read_u32(reader)? {
+            objects.push(ObjectSummary {
+                name: read_string(reader)?,
+                counters: read_strings(reader)?,
+                instances: read_strings(reader)?,
+                counter_info: HashMap::new(),
+            });
+        }
+        machines.push(MachineSummary { name, objects });
+    }
+
+    Ok(PerfLogSummary {
+        machines,
+        start_time,
+        end_time,
+        sample_count,
+        ranges,
+    })
+}
+
+// Returns None when there's no cache, it's unreadable, or it was built from
+// different logs.
+pub fn read_cache(path: &Path, fingerprint: &str) -> Option<CachedRead> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+
+    let mut magic = vec![0u8; MAGIC.len()];
+    reader.read_exact(&mut magic).ok()?;
+    if magic != MAGIC || read_string(&mut reader).ok()? != fingerprint {
+        return None;
+    }
+
+    read_contents(&mut reader).ok()
+}
+
+fn read_contents(reader: &mut impl Read) -> std::io::Result<CachedRead> {
+    let start = read_time(reader)?;
+    let end = read_time(reader)?;
+
+    let machines = (0..read_u32(reader)?)
+        .map(|_| read_string(reader))
+        .collect::<std::io::Result<Vec<String>>>()?;
+
+    let mut data = CounterData {
+        counters: Vec::new(),
+        samples: Default::default(),
+        info: Default::default(),
+        errors: Default::default(),
+    };
+
+    for _ in 0..read_u32(reader)? {
+        let counter = read_string(reader)?;
+
+        let mut has_info = [0u8];
+        reader.read_exact(&mut has_info)?;
+        if has_info[0] == 1 {
+            let counter_type = read_u32(reader)?;
+            let default_scale = read_u32(reader)? as i32;
+            data.info.insert(
+                counter.clone(),
+                CounterInfo {
+                    counter_type,
+                    default_scale,
+                },
+            );
+        }
+
+        let count = read_u64(reader)?;
+
+        let mut samples = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut tag = [0u8];
+            reader.read_exact(&mut tag)?;
+            let time = read_time(reader)?;
+            let bits = read_u64(reader)?;
+
+            samples.push(match tag[0] {
+                0 => CounterValueWithTime::Long(time, bits as i64 as i32),
+                1 => CounterValueWithTime::Double(time, f64::from_bits(bits)),
+                2 => CounterValueWithTime::Large(time, bits as i64),
+                _ => return Err(std::io::ErrorKind::InvalidData.into()),
+            });
+        }
+
+        data.counters.push(counter.clone());
+        data.samples.insert(counter, samples.into_iter().collect());
+    }
+
+    Ok(CachedRead {
+        start,
+        end,
+        machines,
+        data,
+    })
+}
+
+fn write_string(writer: &mut impl Write, s: &str) -> std::io::Result<()> {
+    writer.write_all(&(s.len() as u32).to_le_bytes())?;
+    writer.write_all(s.as_bytes())
+}
+
+fn write_strings(writer: &mut impl Write, strings: &[String]) -> std::io::Result<()> {
+    writer.write_all(&(strings.len() as u32).to_le_bytes())?;
+    for s in strings {
+        write_string(writer, s)?;
+    }
+    Ok(())
+}
+
+fn write_time(writer: &mut impl Write, time: OffsetDateTime) -> std::io::Result<()> {
+    writer.write_all(&time.unix_timestamp_nanos().to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_string(reader: &mut impl Read) -> std::io::Result<String> {
+    let mut bytes = vec![0u8; read_u32(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| std::io::ErrorKind::InvalidData.into())
+}
+
+fn read_strings(reader: &mut impl Read) -> std::io::Result<Vec<String>> {
+    (0..read_u32(reader)?)
+        .map(|_| read_string(reader))
+        .collect()
+}
+
+fn read_time(reader: &mut impl Read) -> std::io::Result<OffsetDateTime> {
+    let mut bytes = [0u8; 16];
+    reader.read_exact(&mut bytes)?;
+    OffsetDateTime::from_unix_timestamp_nanos(i128::from_le_bytes(bytes))
+        .map_err(|_| std::io::ErrorKind::InvalidData.into())
+}
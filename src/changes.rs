@@ -0,0 +1,99 @@
+use time::OffsetDateTime;
+
+use crate::{
+    cli::ChangesArgs, export::format_time, pdh_helper::CounterValueWithTime, reader::read_counters,
+};
+
+pub struct Change {
+    pub time: OffsetDateTime,
+    pub value: f64,
+}
+
+pub fn changes(args: &ChangesArgs) {
+    let selection = match args.counters.selection() {
+        Some(selection) => selection,
+        None => return,
+    };
+
+    let counter_data = match read_counters(&args.source, &selection) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    if counter_data.counters.is_empty() {
+        eprintln!("No counters matched.");
+        return;
+    }
+
+    let time_filter = args.time_filter.time_filter();
+
+    let mut printed = 0;
+    let mut skipped = 0;
+    for counter in &counter_data.counters {
+        let samples = counter_data.samples[counter]
+            .iter()
+            .filter(|s| time_filter.matches(s.time()))
+            .collect::<Vec<CounterValueWithTime>>();
+
+        if samples.is_empty() {
+            continue;
+        }
+
+        let changes = find_changes(&samples);
+
+        // The first entry is the starting value, not a change.
+        if changes.len() - 1 > args.max_changes {
+            skipped += 1;
+            continue;
+        }
+
+        if args.changed_only && changes.len() == 1 {
+            continue;
+        }
+
+        printed += 1;
+
+        let unit = counter_data.unit(counter);
+        println!("{}", counter);
+        for change in &changes {
+            println!(
+                "  {}  {}",
+                format_time(change.time),
+                unit.format(change.value)
+            );
+        }
+        println!(
+            "  {}  (last sample)",
+            format_time(samples[samples.len() - 1].time())
+        );
+    }
+
+    if printed == 0 {
+        eprintln!("No counters matched.");
+    }
+
+    if skipped > 0 {
+        eprintln!(
+            "Skipped {} counters that changed more than {} times.",
+            skipped, args.max_changes
+        );
+    }
+}
+
+// The first sample and every sample whose value differs from the one before.
+pub fn find_changes(samples: &[CounterValueWithTime]) -> Vec<Change> {
+    let mut changes = Vec::<Change>::new();
+
+    for sample in samples {
+        let value = sample.value();
+        if changes.last().is_some_and(|c| c.value == value) {
+            continue;
+        }
+        changes.push(Change {
+            time: sample.time(),
+            value,
+        });
+    }
+
+    changes
+}
@@ -0,0 +1,354 @@
+use std::path::Path;
+
+use plotters::{coord::Shift, prelude::*};
+use time::{Duration, OffsetDateTime, UtcOffset};
+
+use crate::{
+    heatmap::{heat_color, heat_fraction, heatmap_cells},
+    plot::{format_axis_value, time_label_format, time_range, value_range, Series},
+    timespec::zone_label,
+};
+
+// Pixels per column of a heatmap image.
+const HEATMAP_CELL_WIDTH: u32 = 4;
+
+// Draws the series to a PNG or SVG file, picked by the file extension.
+pub fn write_chart(
+    path: &str,
+    series: &[Series],
+    thresholds: &[f64],
+    size: (u32, u32),
+    offset: UtcOffset,
+) -> Result<(), String> {
+    match image_format(path)? {
+        ImageFormat::Png => draw(
+            BitMapBackend::new(path, size).into_drawing_area(),
+            series,
+            thresholds,
+            offset,
+        ),
+        ImageFormat::Svg => draw(
+            SVGBackend::new(path, size).into_drawing_area(),
+            series,
+            thresholds,
+            offset,
+        ),
+    }
+}
+
+// Draws each series in its own panel, stacked on a shared time axis, so
+// counters with different units can be read together. The highlighted range
+// is shaded in every panel.
+pub fn write_panels(
+    path: &str,
+    series: &[Series],
+    highlight: (OffsetDateTime, OffsetDateTime),
+    size: (u32, u32),
+    offset: UtcOffset,
+) -> Result<(), String> {
+    match image_format(path)? {
+        ImageFormat::Png => draw_panels(
+            BitMapBackend::new(path, size).into_drawing_area(),
+            series,
+            highlight,
+            offset,
+        ),
+        ImageFormat::Svg => draw_panels(
+            SVGBackend::new(path, size).into_drawing_area(),
+            series,
+            highlight,
+            offset,
+        ),
+    }
+}
+
+// Draws a heatmap with a row per series, the first at the top, colored from
+// the low to the high end of scale.
+pub fn write_heatmap(
+    path: &str,
+    rows: &[Series],
+    scale: (f64, f64),
+    size: (u32, u32),
+    offset: UtcOffset,
+) -> Result<(), String> {
+    match image_format(path)? {
+        ImageFormat::Png => draw_heatmap(
+            BitMapBackend::new(path, size).into_drawing_area(),
+            rows,
+            scale,
+            offset,
+        ),
+        ImageFormat::Svg => draw_heatmap(
+            SVGBackend::new(path, size).into_drawing_area(),
+            rows,
+            scale,
+            offset,
+        ),
+    }
+}
+
+enum ImageFormat {
+    Png,
+    Svg,
+}
+
+fn image_format(path: &str) -> Result<ImageFormat, String> {
+    let extension = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("png") => Ok(ImageFormat::Png),
+        Some("svg") => Ok(ImageFormat::Svg),
+        _ => Err("the file name must end in .png or .svg".to_string()),
+    }
+}
+
+fn draw<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    series: &[Series],
+    thresholds: &[f64],
+    offset: UtcOffset,
+) -> Result<(), String> {
+    let (start, end) = time_range(series);
+    let (min, max) = value_range(series, thresholds);
+    let span = (end - start).as_seconds_f64().max(1.0);
+    let format = time_label_format(start, end, offset);
+
+    let x_label = |x: &f64| {
+        (start + Duration::seconds_f64(*x))
+            .to_offset(offset)
+            .format(format)
+            .unwrap()
+    };
+    let y_label = |y: &f64| format_axis_value(*y);
+
+    root.fill(&WHITE).map_err(|e| e.to_string())?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(80)
+        .build_cartesian_2d(0.0..span, min..max)
+        .map_err(|e| e.to_string())?;
+
+    let x_desc = format!("Time ({})", zone_label(offset));
+
+    chart
+        .configure_mesh()
+        .x_labels(8)
+        .x_label_formatter(&x_label)
+        .y_label_formatter(&y_label)
+        .x_desc(x_desc)
+        .draw()
+        .map_err(|e| e.to_string())?;
+
+    for (index, s) in series.iter().enumerate() {
+        let color = Palette99::pick(index).to_rgba();
+        let points = s
+            .points
+            .iter()
+            .map(|(time, value)| ((*time - start).as_seconds_f64(), *value));
+
+        chart
+            .draw_series(LineSeries::new(points, color.stroke_width(2)))
+            .map_err(|e| e.to_string())?
+            .label(&s.name)
+            .legend(move |(x, y)| {
+                PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2))
+            });
+    }
+
+    for threshold in thresholds {
+        chart
+            .draw_series(DashedLineSeries::new(
+                [(0.0, *threshold), (span, *threshold)],
+                10,
+                6,
+                BLACK.mix(0.6).stroke_width(1),
+            ))
+            .map_err(|e| e.to_string())?
+            .label(format!("Threshold {}", threshold))
+            .legend(|(x, y)| {
+                PathElement::new(vec![(x, y), (x + 20, y)], BLACK.mix(0.6).stroke_width(1))
+            });
+    }
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.85))
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()
+        .map_err(|e| e.to_string())?;
+
+    root.present().map_err(|e| e.to_string())
+}
+
+fn draw_panels<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    series: &[Series],
+    highlight: (OffsetDateTime, OffsetDateTime),
+    offset: UtcOffset,
+) -> Result<(), String> {
+    let (start, end) = time_range(series);
+    let span = (end - start).as_seconds_f64().max(1.0);
+    let format = time_label_format(start, end, offset);
+
+    let x_label = |x: &f64| {
+        (start + Duration::seconds_f64(*x))
+            .to_offset(offset)
+            .format(format)
+            .unwrap()
+    };
+    let y_label = |y: &f64| format_axis_value(*y);
+
+    let (highlight_start, highlight_end) = (
+        (highlight.0 - start).as_seconds_f64().clamp(0.0, span),
+        (highlight.1 - start).as_seconds_f64().clamp(0.0, span),
+    );
+
+    root.fill(&WHITE).map_err(|e| e.to_string())?;
+
+    for (index, (s, area)) in series
+        .iter()
+        .zip(root.split_evenly((series.len(), 1)))
+        .enumerate()
+    {
+        let (min, max) = value_range(std::slice::from_ref(s), &[]);
+
+        let mut chart = ChartBuilder::on(&area)
+            .margin(10)
+            .caption(&s.name, ("sans-serif", 16))
+            .x_label_area_size(30)
+            .y_label_area_size(80)
+            .build_cartesian_2d(0.0..span, min..max)
+            .map_err(|e| e.to_string())?;
+
+        chart
+            .configure_mesh()
+            .x_labels(8)
+            .x_label_formatter(&x_label)
+            .y_label_formatter(&y_label)
+            .draw()
+            .map_err(|e| e.to_string())?;
+
+        chart
+            .draw_series(std::iter::once(Rectangle::new(
+                [(highlight_start, min), (highlight_end, max)],
+                RED.mix(0.12).filled(),
+            )))
+            .map_err(|e| e.to_string())?;
+
+        let color = Palette99::pick(index).to_rgba();
+        let points = s
+            .points
+            .iter()
+            .map(|(time, value)| ((*time - start).as_seconds_f64(), *value));
+
+        chart
+            .draw_series(LineSeries::new(points, color.stroke_width(2)))
+            .map_err(|e| e.to_string())?;
+    }
+
+    root.present().map_err(|e| e.to_string())
+}
+
+fn draw_heatmap<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    rows: &[Series],
+    scale: (f64, f64),
+    offset: UtcOffset,
+) -> Result<(), String> {
+    let (start, end) = time_range(rows);
+    let span = (end - start).as_seconds_f64().max(1.0);
+    let format = time_label_format(start, end, offset);
+    let columns = (root.dim_in_pixel().0 / HEATMAP_CELL_WIDTH).max(10) as usize;
+    let cells = heatmap_cells(rows, columns);
+
+    let x_label = |x: &f64| {
+        (start + Duration::seconds_f64(*x))
+            .to_offset(offset)
+            .format(format)
+            .unwrap()
+    };
+    // Rows are centered on whole numbers, with the first at the top, so the
+    // labels land on them.
+    let y_label = |y: &f64| {
+        let row = rows.len() as f64 - 1.0 - y;
+        if row.fract() == 0.0 && row >= 0.0 && row < rows.len() as f64 {
+            rows[row as usize].name.clone()
+        } else {
+            String::new()
+        }
+    };
+
+    root.fill(&WHITE).map_err(|e| e.to_string())?;
+
+    let (chart_area, legend_area) = root.split_horizontally(root.dim_in_pixel().0 as i32 - 120);
+
+    let mut chart = ChartBuilder::on(&chart_area)
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(200)
+        .build_cartesian_2d(0.0..span, -0.5..rows.len() as f64 - 0.5)
+        .map_err(|e| e.to_string())?;
+
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_labels(8)
+        .y_labels(rows.len())
+        .x_label_formatter(&x_label)
+        .y_label_formatter(&y_label)
+        .x_desc(format!("Time ({})", zone_label(offset)))
+        .draw()
+        .map_err(|e| e.to_string())?;
+
+    let column_span = span / columns as f64;
+    let rectangles = cells.iter().enumerate().flat_map(|(index, row)| {
+        let y = (rows.len() - 1 - index) as f64;
+        row.iter().enumerate().filter_map(move |(column, value)| {
+            let (r, g, b) = heat_color(heat_fraction((*value)?, scale));
+            Some(Rectangle::new(
+                [
+                    (column as f64 * column_span, y - 0.5),
+                    ((column + 1) as f64 * column_span, y + 0.5),
+                ],
+                RGBColor(r, g, b).filled(),
+            ))
+        })
+    });
+    chart.draw_series(rectangles).map_err(|e| e.to_string())?;
+
+    // A color bar from the low end of the scale at the bottom to the high
+    // end at the top.
+    let mut legend = ChartBuilder::on(&legend_area)
+        .margin_top(20)
+        .margin_bottom(60)
+        .margin_right(20)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0.0..1.0, scale.0..scale.1.max(scale.0 + f64::EPSILON))
+        .map_err(|e| e.to_string())?;
+
+    legend
+        .configure_mesh()
+        .disable_mesh()
+        .disable_x_axis()
+        .y_label_style(("sans-serif", 12))
+        .y_label_formatter(&|y: &f64| format_axis_value(*y))
+        .draw()
+        .map_err(|e| e.to_string())?;
+
+    const STEPS: usize = 100;
+    let step = (scale.1 - scale.0) / STEPS as f64;
+    legend
+        .draw_series((0..STEPS).map(|i| {
+            let (r, g, b) = heat_color(i as f64 / (STEPS - 1) as f64);
+            let low = scale.0 + step * i as f64;
+            Rectangle::new([(0.0, low), (1.0, low + step)], RGBColor(r, g, b).filled())
+        }))
+        .map_err(|e| e.to_string())?;
+
+    root.present().map_err(|e| e.to_string())
+}
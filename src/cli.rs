@@ -0,0 +1,1302 @@
+use std::sync::OnceLock;
+
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+
+use time::{Duration, UtcOffset};
+
+use crate::{
+    aggregate::Across,
+    align::Interpolation,
+    completions::Shell,
+    counter_path::{map_machine, MachineMap},
+    derive::Derivation,
+    filter::{SamplePredicate, TimeFilter},
+    log_files::FileOrder,
+    monitor::AlertRule,
+    number_format::NumberFormat,
+    profile::ProfileBy,
+    rename::Rename,
+    resample::Aggregate,
+    selection::{read_counter_list, CounterSelection},
+    series::Duplicates,
+    stats::{GroupBy, StatsSort},
+    timespec::{
+        display_offset, parse_duration, parse_interval, parse_time_spec, parse_utc_offset, DaySet,
+        HoursRange, TimeSpec,
+    },
+};
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Summarize and extract data from perfmon logs",
+    after_help = "Exit codes:
+  0  Success
+  1  Failed
+  2  Bad arguments
+  3  No files matched the pattern
+  4  The logs couldn't be bound
+  5  Some files were skipped, or a log stopped reading partway through
+  6  analyze or triage found a warning
+  7  analyze or triage found a critical problem"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Time zone for printed and exported times, and for --start, --end,
+    /// --hours, and --days: UTC, local, or an offset like +02:00
+    #[arg(long, global = true, default_value = "UTC", value_parser = parse_utc_offset)]
+    pub timezone: UtcOffset,
+
+    /// List the counters in the logs again instead of reusing the list from
+    /// an earlier run over the same files
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
+    /// List each counter's samples that had no value, by the status PDH gave
+    /// instead, rather than just how many there were
+    #[arg(long, global = true)]
+    pub show_errors: bool,
+
+    /// Keep reading past damaged samples in a log that was cut off, like by
+    /// a crash, instead of stopping at the first one
+    #[arg(long, global = true)]
+    pub salvage: bool,
+
+    /// Only read every Nth sample of the logs, to thin out a long capture
+    /// quickly. Rates are still over the interval the logs were collected at.
+    #[arg(long, global = true, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub every: u64,
+
+    /// Directory of analysis profiles in .toml files, run by analyze and
+    /// triage along with the built-in analyzers. Defaults to
+    /// perflogtool\profiles under the user's app data.
+    #[arg(long, global = true)]
+    pub profiles_dir: Option<String>,
+
+    /// Only list counters meant for users at this level or below
+    #[arg(long, global = true, value_enum, default_value = "wizard")]
+    pub detail: DetailLevel,
+
+    /// Only read the counters of machines matching this name or wildcard
+    /// pattern, like SQL01 or WEB*, in logs collected from several machines.
+    /// The others are skipped without listing their counters (repeatable)
+    #[arg(long, global = true, value_name = "NAME")]
+    pub machine: Vec<String>,
+
+    /// Don't cap percentages at 100, so totals of several processors, like
+    /// \Process(_Total)\% Processor Time, aren't cut off
+    #[arg(long, global = true)]
+    pub no_cap: bool,
+
+    /// Multiply every value by 1000
+    #[arg(long, global = true)]
+    pub times_1000: bool,
+
+    /// Don't apply counters' scale factors to their values
+    #[arg(long, global = true)]
+    pub no_scale: bool,
+
+    /// Order to read the matching files in
+    #[arg(long, global = true, value_enum, default_value = "modified")]
+    pub order: FileOrder,
+
+    /// Which sample to keep when logs that overlap have more than one for a
+    /// counter at the same time
+    #[arg(long, global = true, value_enum, default_value = "first")]
+    pub duplicates: Duplicates,
+
+    /// Leave out progress messages, like the files found, and only write
+    /// results, warnings, and errors
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DetailLevel {
+    /// Counters most users need
+    Novice,
+    Advanced,
+    Expert,
+    /// Every counter
+    Wizard,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print the machines, objects, counters, and time range of the logs
+    Summary(SummaryArgs),
+    /// Split the logs into separate files by time
+    Split(SplitArgs),
+    /// Export counter samples as CSV
+    Export(Box<ExportArgs>),
+    /// Print the counter paths matching a regular expression
+    Find(FindArgs),
+    /// Run a SQL-like query over the samples and print the result as CSV,
+    /// like "SELECT counter, avg(value) FROM samples WHERE counter LIKE
+    /// '%Disk%' GROUP BY counter"
+    Query(QueryArgs),
+    /// Print the machines in the logs
+    ListMachines(ListMachinesArgs),
+    /// Print the objects in the logs
+    ListObjects(ListObjectsArgs),
+    /// Print the instances of an object, like the processes that were running
+    ListInstances(ListInstancesArgs),
+    /// Check that counter paths are in the logs before a long export
+    Validate(ValidateArgs),
+    /// Look for known performance problems
+    Analyze(AnalyzeArgs),
+    /// Print the min, average, estimated 95th percentile, and max of each counter
+    Stats(StatsArgs),
+    /// Draw the instances of a wildcard counter as rows of a heatmap
+    Heatmap(HeatmapArgs),
+    /// Print the statistics of each counter by hour of the day and day of the
+    /// week, to find the busy hours in a log of several days
+    Profile(ProfileArgs),
+    /// Rank the instances of a wildcard counter
+    Top(TopArgs),
+    /// Compare counters between a baseline and an incident log
+    Compare(CompareArgs),
+    /// List the objects, counters, and instances in one set of logs but not
+    /// the other, like to check a recapture has the counters asked for
+    InventoryDiff(InventoryDiffArgs),
+    /// Find samples that stand out from a rolling baseline
+    Spikes(SpikesArgs),
+    /// Print the values of configuration counters and when they changed
+    Changes(ChangesArgs),
+    /// Print when processes started and exited, from the Process counters,
+    /// to line restarts up with slowdowns
+    Processes(ProcessesArgs),
+    /// Draw counter samples as a chart in the terminal
+    Plot(PlotArgs),
+    /// Run everything and write a report, data, and charts to a directory
+    Triage(TriageArgs),
+    /// Write a Grafana dashboard of the counters, with their samples to load
+    /// into the InfluxDB it reads from
+    Grafana(GrafanaArgs),
+    /// Chart the logs in a browser, from a web server on this machine with a
+    /// JSON API for the counters and their samples
+    Serve(ServeArgs),
+    /// Answer summary and read requests from other tools over HTTP, as JSON.
+    /// GET /api/v1 lists the requests.
+    Api(ApiArgs),
+    /// Watch this machine's counters and raise alerts when rules are broken
+    Monitor(MonitorArgs),
+    /// Collect this machine's counters into logs that start anew by size or
+    /// time, like logman, in the foreground or as a Windows service
+    Collect(CollectArgs),
+    /// Play the logs' samples back as live counters on this machine, at the
+    /// pace they were collected or faster, to test dashboards and alerts
+    /// against a past incident
+    Replay(ReplayArgs),
+    /// Print a script that adds tab completion to bash or PowerShell
+    Completions(CompletionsArgs),
+    /// Print the ways a partly typed counter path could go on, for the
+    /// completion scripts
+    CompleteCounter(CompleteCounterArgs),
+}
+
+#[derive(Args)]
+pub struct SummaryArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+
+    /// How much to list: 1 for machines, 2 for objects with their counter
+    /// and instance counts, 3 for every counter and instance
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..=3))]
+    pub depth: u8,
+
+    /// List the counters and instances of objects matching this name, which
+    /// can have wildcards (repeatable)
+    #[arg(long, value_name = "OBJECT")]
+    pub expand: Vec<String>,
+
+    /// Summarize each capture session on its own. A file starts a new
+    /// session when it begins well after the one before ended, or has a
+    /// different set of counters.
+    #[arg(long)]
+    pub sessions: bool,
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("chunk").required(true).args(["hours", "daily"])))]
+pub struct SplitArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+
+    /// Length of each chunk in hours
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub hours: Option<u32>,
+
+    /// Write one chunk per calendar day
+    #[arg(long)]
+    pub daily: bool,
+
+    /// Format of the output files
+    #[arg(long, value_enum, default_value_t = SplitFormat::Blg)]
+    pub format: SplitFormat,
+
+    /// Directory the chunks are written to
+    #[arg(long, default_value = ".")]
+    pub out: String,
+
+    /// File name prefix for the chunks
+    #[arg(long, default_value = "perflog")]
+    pub prefix: String,
+
+    #[command(flatten)]
+    pub counters: CounterArgs,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub counters: CounterArgs,
+
+    #[command(flatten)]
+    pub aggregate: AggregateArgs,
+
+    /// Only keep samples matching a filter like "value > 90" or
+    /// "value > 90 for 5m" (repeatable)
+    #[arg(long = "where")]
+    pub filters: Vec<SamplePredicate>,
+
+    /// Name the column of the counter matching OLD as NEW, like
+    /// "Processor(_Total)\% Processor Time=cpu_total" (repeatable)
+    #[arg(long, value_name = "OLD=NEW")]
+    pub rename: Vec<Rename>,
+
+    /// File of OLD=NEW renames, one per line, applied after --rename
+    #[arg(long)]
+    pub rename_file: Option<String>,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+
+    /// Resample into buckets of this length, like 5m or 1h
+    #[arg(long, value_parser = parse_interval)]
+    pub resample: Option<Duration>,
+
+    /// Aggregates to write for each counter when resampling. Each one gets
+    /// its own column named <counter>:<aggregate>.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "avg",
+        requires = "resample"
+    )]
+    pub stat: Vec<Aggregate>,
+
+    /// Replace each sample with an aggregate of the samples in the window of
+    /// this length ending at it, like 5m, to smooth noisy counters
+    #[arg(long, value_parser = parse_interval, conflicts_with = "follow")]
+    pub rolling: Option<Duration>,
+
+    /// Aggregate to take over each --rolling window
+    #[arg(long, value_enum, default_value = "avg", requires = "rolling")]
+    pub rolling_stat: Aggregate,
+
+    /// Add a column calculated from other counters at each sample, like
+    /// 'commit_pct = "\Memory\Committed Bytes" / "\Memory\Commit Limit" * 100'
+    /// or 'io = sum("\Process(*)\IO Data Bytes/sec")'. Functions are avg,
+    /// min, max, p95, count, and sum (repeatable)
+    #[arg(long, value_name = "NAME=EXPRESSION", conflicts_with = "follow")]
+    pub derive: Vec<Derivation>,
+
+    /// Interpolate every counter onto a common grid of times this far apart,
+    /// like 15s, so counters sampled at different offsets share rows
+    #[arg(long, value_parser = parse_interval, conflicts_with_all = ["resample", "follow"])]
+    pub align: Option<Duration>,
+
+    /// How --align fills in a grid time between two samples
+    #[arg(long, value_enum, default_value = "linear", requires = "align")]
+    pub interpolate: Interpolation,
+
+    /// Write the raw first value, second value, and timestamp of each sample
+    /// instead of the formatted value
+    #[arg(long, conflicts_with_all = ["filters", "resample", "rolling", "align", "derive", "separate", "aggregate"])]
+    pub raw: bool,
+
+    /// With --raw, calculate the value over this many samples. 1 matches
+    /// perfmon, 0 skips the calculation.
+    #[arg(long, default_value_t = 1, requires = "raw")]
+    pub rate_window: usize,
+
+    /// Output format [default: csv, or tsv with --clipboard]
+    #[arg(long, value_enum)]
+    pub format: Option<ExportFormat>,
+
+    /// Keep the samples PDH gave no value for, as NaN in csv and tsv and as
+    /// null with the reason in jsonl, instead of leaving them out
+    #[arg(long, conflicts_with_all = ["raw", "resample", "rolling", "align"])]
+    pub keep_invalid: bool,
+
+    /// Column layout of csv and tsv output
+    #[arg(long, value_enum, default_value = "wide", conflicts_with_all = ["raw", "follow"])]
+    pub layout: ExportLayout,
+
+    #[command(flatten)]
+    pub number_format: NumberFormatArgs,
+
+    /// File to write instead of stdout
+    #[arg(long, conflicts_with = "clipboard")]
+    pub output: Option<String>,
+
+    /// Compress the output as it's written. Name the file to match, like
+    /// perf.csv.gz or perf.csv.zst.
+    #[arg(long, value_enum, conflicts_with_all = ["clipboard", "follow", "max_columns", "influx_url"])]
+    pub compress: Option<Compression>,
+
+    /// Copy the output to the clipboard for pasting into Excel
+    #[arg(long)]
+    pub clipboard: bool,
+
+    /// Split wide csv and tsv output into files of at most this many counter
+    /// columns each, named like perf.1.csv and perf.2.csv after --output,
+    /// with perf.manifest.csv listing which file each counter is in
+    #[arg(long, value_name = "N", requires = "output", conflicts_with_all = ["follow", "clipboard"], value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_columns: Option<u64>,
+
+    /// With --format influx, post the lines to this InfluxDB write URL, like
+    /// http://localhost:8086/api/v2/write?org=myorg&bucket=perf
+    #[arg(long, conflicts_with_all = ["output", "clipboard"])]
+    pub influx_url: Option<String>,
+
+    /// API token for --influx-url
+    #[arg(long, requires = "influx_url")]
+    pub influx_token: Option<String>,
+
+    /// Keep reading logs that a collector is still writing, and write new
+    /// samples as they appear until interrupted
+    #[arg(
+        long,
+        conflicts_with_all = ["raw", "resample", "rolling", "filters", "clipboard", "separate", "aggregate"]
+    )]
+    pub follow: bool,
+
+    /// How often to check for new samples with --follow, like 5s or 1m
+    #[arg(long, default_value = "5s", value_parser = parse_interval, requires = "follow")]
+    pub poll: Duration,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Tsv,
+    /// InfluxDB line protocol, with the object as the measurement, the
+    /// machine and instance as tags, and the counter as the field
+    Influx,
+    /// One JSON object per sample, for piping into ConvertFrom-Json
+    Jsonl,
+    /// An Excel workbook with the samples, the statistics of each counter,
+    /// and charts of the counters that vary the most. Needs --output.
+    Xlsx,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportLayout {
+    /// A row per time and a column per counter, for spreadsheets
+    Wide,
+    /// A row per sample with its time, counter, and value, for loading into
+    /// a database
+    Long,
+}
+
+#[derive(Args)]
+pub struct AnalyzeArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Only run this analyzer (repeatable). Runs every analyzer by default.
+    #[arg(long)]
+    pub analyzer: Vec<String>,
+
+    /// Run the analyzers for one area, and summarize its counters after the
+    /// findings: cpu, disk, memory, network, iis, hyperv, or the name of a
+    /// profile in the profiles directory
+    #[arg(long, conflicts_with = "analyzer")]
+    pub profile: Option<String>,
+
+    /// Draw a chart of each finding into this directory
+    #[arg(long)]
+    pub charts: Option<String>,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub counters: CounterArgs,
+
+    /// Print plain numbers instead of applying each counter's unit
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Sort every value for an exact 95th percentile instead of estimating
+    /// it in one pass, which takes memory for all the values of a counter
+    #[arg(long)]
+    pub exact: bool,
+
+    /// Print a table per counter with a row for each of its instances
+    #[arg(long, value_enum)]
+    pub group_by: Option<GroupBy>,
+
+    /// Sort the rows by this column, highest first [default with --group-by:
+    /// avg]
+    #[arg(long, value_enum)]
+    pub sort_by: Option<StatsSort>,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct ProfileArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub counters: CounterArgs,
+
+    /// Print plain numbers instead of applying each counter's unit
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Only print these tables [default: hour,day]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub by: Vec<ProfileBy>,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct TopArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Wildcard counter to rank, like "\Process(*)\% Processor Time"
+    pub counter: String,
+
+    /// Aggregate to rank the instances by
+    #[arg(long, value_enum, default_value = "avg")]
+    pub by: Aggregate,
+
+    /// Number of instances to print
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub count: usize,
+
+    /// Include the _Total and Idle pseudo-instances
+    #[arg(long)]
+    pub include_total: bool,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct InventoryDiffArgs {
+    /// Glob pattern matching the logs with the counters that should be there
+    pub expected: String,
+
+    /// Glob pattern matching the logs to check
+    pub actual: String,
+
+    /// Compare each machine's counters with the same machine's, instead of
+    /// everything in one set of logs with everything in the other
+    #[arg(long)]
+    pub by_machine: bool,
+
+    /// Leave out the instances, which differ between captures anyway when
+    /// they're processes
+    #[arg(long)]
+    pub ignore_instances: bool,
+}
+
+#[derive(Args)]
+pub struct CompareArgs {
+    /// Glob pattern matching the logs from when things were normal
+    pub baseline: String,
+
+    /// Glob pattern matching the logs from the incident
+    pub incident: String,
+
+    #[command(flatten)]
+    pub counters: CounterArgs,
+
+    /// Aggregate to compare
+    #[arg(long, value_enum, default_value = "avg")]
+    pub by: Aggregate,
+
+    /// Number of counters to print
+    #[arg(short = 'n', long, default_value_t = 20)]
+    pub count: usize,
+
+    /// Print plain numbers instead of applying each counter's unit
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Bind and read each file on its own, then stitch the series together
+    #[arg(long)]
+    pub separate: bool,
+
+    /// Remove the machine name from counter paths, so logs from different
+    /// machines can be compared
+    #[arg(long, conflicts_with = "map_machine")]
+    pub strip_machine: bool,
+
+    /// Rename machine OLD to NEW in counter paths (repeatable)
+    #[arg(long, value_name = "OLD=NEW")]
+    pub map_machine: Vec<MachineMap>,
+}
+
+impl CompareArgs {
+    pub fn source(&self, glob_pattern: &str) -> SourceArgs {
+        SourceArgs {
+            glob_pattern: glob_pattern.to_string(),
+            separate: self.separate,
+            parallel: false,
+            strip_machine: self.strip_machine,
+            map_machine: self.map_machine.clone(),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct SpikesArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Counters to check, as text or a wildcard pattern (repeatable)
+    #[arg(long, required = true)]
+    pub counter: Vec<String>,
+
+    /// Length of the rolling baseline before each sample, like 30m
+    #[arg(long, default_value = "30m", value_parser = parse_interval)]
+    pub window: Duration,
+
+    /// How many deviations from the baseline make a spike
+    #[arg(long, default_value_t = 3.0)]
+    pub threshold: f64,
+
+    /// How the baseline and deviation are measured
+    #[arg(long, value_enum, default_value = "stddev")]
+    pub method: SpikeMethod,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct ChangesArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub counters: CounterArgs,
+
+    /// Skip counters that changed more often than this, since they hold
+    /// measurements rather than configuration
+    #[arg(long, default_value_t = 10)]
+    pub max_changes: usize,
+
+    /// Only print counters that changed at least once
+    #[arg(long)]
+    pub changed_only: bool,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct ProcessesArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Only include processes with this name, with wildcards, like w3wp or
+    /// sql* (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub name: Vec<String>,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SpikeMethod {
+    /// Mean and standard deviation
+    Stddev,
+    /// Median and median absolute deviation, which ignores earlier spikes
+    Mad,
+}
+
+#[derive(Args)]
+pub struct PlotArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Counters to plot, as text or a wildcard pattern (repeatable)
+    #[arg(long, required_unless_present = "derive")]
+    pub counter: Vec<String>,
+
+    /// Chart width in characters, including the axis labels [default: terminal width]
+    #[arg(long)]
+    pub width: Option<usize>,
+
+    /// Chart height in lines
+    #[arg(long, default_value_t = 20)]
+    pub height: usize,
+
+    /// Draw a horizontal line at this value (repeatable)
+    #[arg(long)]
+    pub threshold: Vec<f64>,
+
+    /// Write the chart to a .png or .svg file instead of the terminal
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Image size in pixels, like 1600x900
+    #[arg(long, default_value = "1280x720", value_parser = parse_image_size, requires = "output")]
+    pub size: (u32, u32),
+
+    #[command(flatten)]
+    pub aggregate: AggregateArgs,
+
+    /// Plot an aggregate of the samples in the window of this length ending
+    /// at each one, like 5m, to smooth noisy counters
+    #[arg(long, value_parser = parse_interval)]
+    pub rolling: Option<Duration>,
+
+    /// Aggregate to take over each --rolling window
+    #[arg(long, value_enum, default_value = "avg", requires = "rolling")]
+    pub rolling_stat: Aggregate,
+
+    /// Plot a series calculated from other counters at each sample, like
+    /// 'total = sum("\Process(*)\IO Data Bytes/sec")' (repeatable)
+    #[arg(long, value_name = "NAME=EXPRESSION")]
+    pub derive: Vec<Derivation>,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct HeatmapArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Wildcard counter to draw, like "\Process(*)\% Processor Time"
+    pub counter: String,
+
+    /// Only draw this many instances, the highest on average
+    #[arg(short = 'n', long)]
+    pub top: Option<usize>,
+
+    /// Include the _Total and Idle pseudo-instances
+    #[arg(long)]
+    pub include_total: bool,
+
+    /// Value at the low end of the color scale [default: the lowest value]
+    #[arg(long)]
+    pub min: Option<f64>,
+
+    /// Value at the high end of the color scale, like 100 for a percentage
+    /// [default: the highest value]
+    #[arg(long)]
+    pub max: Option<f64>,
+
+    /// Heatmap width in characters, including the instance names [default:
+    /// terminal width]
+    #[arg(long)]
+    pub width: Option<usize>,
+
+    /// Write the heatmap to a .png or .svg file instead of the terminal
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Image size in pixels, like 1600x900
+    #[arg(long, default_value = "1280x720", value_parser = parse_image_size, requires = "output")]
+    pub size: (u32, u32),
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct TriageArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Directory to write the report, data, and charts to
+    #[arg(long)]
+    pub out: String,
+
+    /// Read the logs again even if an earlier run into the same directory
+    /// cached them
+    #[arg(long)]
+    pub fresh: bool,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct GrafanaArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub counters: CounterArgs,
+
+    /// Directory to write dashboard.json and the samples to, as perf.lp in
+    /// InfluxDB line protocol
+    #[arg(long)]
+    pub out: String,
+
+    /// InfluxDB bucket the dashboard reads from. It can be changed in
+    /// Grafana afterwards.
+    #[arg(long, default_value = "perf")]
+    pub bucket: String,
+
+    /// Title of the dashboard [default: perflogtool and the glob pattern]
+    #[arg(long)]
+    pub title: Option<String>,
+
+    /// Post the samples to this InfluxDB write URL instead of writing
+    /// perf.lp, like
+    /// http://localhost:8086/api/v2/write?org=myorg&bucket=perf
+    #[arg(long)]
+    pub influx_url: Option<String>,
+
+    /// API token for --influx-url
+    #[arg(long, requires = "influx_url")]
+    pub influx_token: Option<String>,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+
+    /// Port to listen on. Only this machine can connect.
+    #[arg(long, default_value_t = 8470)]
+    pub port: u16,
+}
+
+#[derive(Args)]
+pub struct ApiArgs {
+    /// Address to listen on. Use 0.0.0.0:8471 to take requests from other
+    /// machines; there's no authentication, so only on a trusted network.
+    #[arg(long, default_value = "127.0.0.1:8471")]
+    pub listen: String,
+
+    /// Requests answered at the same time. Each reads its own logs, so more
+    /// take more memory.
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..))]
+    pub workers: u64,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub counters: CounterArgs,
+
+    /// How much faster than real time to play the samples, like 60 to play
+    /// an hour in a minute
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    pub speed: f64,
+
+    /// Start over from the first sample after the last one, until
+    /// interrupted
+    #[arg(long = "loop")]
+    pub repeat: bool,
+
+    /// Register the replay counter set with lodctr first, which needs an
+    /// elevated prompt. Only needed once; unlodctr /m: with the manifest it
+    /// prints removes it.
+    #[arg(long)]
+    pub register: bool,
+
+    #[command(flatten)]
+    pub time_filter: TimeFilterArgs,
+}
+
+#[derive(Args)]
+pub struct MonitorArgs {
+    /// Alert rule like "\Processor(_Total)\% Processor Time > 90"; the
+    /// counter can have wildcards (repeatable)
+    #[arg(long, required_unless_present = "ring", value_name = "RULE")]
+    pub alert: Vec<AlertRule>,
+
+    /// How often to collect the counters, like 5s or 1m
+    #[arg(long, default_value = "5s", value_parser = parse_interval)]
+    pub interval: Duration,
+
+    /// Intervals in a row a rule must be broken before it alerts
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    pub trigger: u64,
+
+    /// Intervals in a row a rule must hold before its alert clears
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    pub clear: u64,
+
+    /// Post each alert as JSON to this http:// URL
+    #[arg(long)]
+    pub webhook: Option<String>,
+
+    /// Write each alert to the Application event log
+    #[arg(long)]
+    pub event_log: bool,
+
+    /// Keep this much of the latest samples in memory, like 30m, and save
+    /// them to a log when Enter is pressed
+    #[arg(long, value_parser = parse_interval)]
+    pub ring: Option<Duration>,
+
+    /// Counters to keep with --ring, with wildcards (repeatable) [default:
+    /// the counters of the alert rules]
+    #[arg(long, value_name = "PATTERN", requires = "ring")]
+    pub record: Vec<String>,
+
+    /// Directory the --ring logs are saved to
+    #[arg(long, default_value = ".", requires = "ring")]
+    pub dump_dir: String,
+
+    /// Format of the --ring logs
+    #[arg(long, value_enum, default_value_t = SplitFormat::Blg, requires = "ring")]
+    pub dump_format: SplitFormat,
+
+    /// When a rule alerts, keep collecting this much longer, like 5m, then
+    /// save the ring with what led up to the alert and what came after
+    #[arg(long, value_parser = parse_duration, requires_all = ["ring", "alert"])]
+    pub capture: Option<Duration>,
+
+    /// Command to start when a rule alerts, like "procdump -ma w3wp". It gets
+    /// the counter and value in PERFLOGTOOL_COUNTER and PERFLOGTOOL_VALUE
+    #[arg(long, value_name = "COMMAND", requires = "alert")]
+    pub on_alert: Option<String>,
+}
+
+#[derive(Args, Clone)]
+pub struct CollectArgs {
+    /// Counter to collect, with wildcards, like "\Process(*)\% Processor
+    /// Time" (repeatable)
+    #[arg(
+        long,
+        value_name = "PATH",
+        required_unless_present_any = ["counters_from", "uninstall_service"]
+    )]
+    pub counter: Vec<String>,
+
+    /// File of counters to collect, one path per line, like the counter
+    /// files logman takes with -cf
+    #[arg(long, value_name = "FILE")]
+    pub counters_from: Option<String>,
+
+    /// How often to collect the counters, like 15s or 1m
+    #[arg(long, default_value = "15s", value_parser = parse_interval)]
+    pub interval: Duration,
+
+    /// Directory to write the logs to
+    #[arg(long, default_value = ".")]
+    pub out: String,
+
+    /// Start of the log file names, which go on with the time each log was
+    /// started
+    #[arg(long, default_value = "perflogtool")]
+    pub prefix: String,
+
+    /// Format of the logs
+    #[arg(long, value_enum, default_value_t = SplitFormat::Blg)]
+    pub format: SplitFormat,
+
+    /// Start a new log when the current one reaches this size, like 100MB or
+    /// 1GB
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Start a new log when the current one has been written this long, like
+    /// 1h or 1d
+    #[arg(long, value_name = "DURATION", value_parser = parse_interval)]
+    pub rotate_every: Option<Duration>,
+
+    /// Delete the oldest logs so there are no more than this many
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u64).range(1..))]
+    pub keep: Option<u64>,
+
+    /// Stop after collecting this long, like 8h [default: until stopped]
+    #[arg(long, value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// Install a Windows service with this name that starts with Windows and
+    /// runs this collection, instead of running it now
+    #[arg(long, value_name = "NAME", conflicts_with = "uninstall_service")]
+    pub install_service: Option<String>,
+
+    /// Remove a service installed with --install-service
+    #[arg(long, value_name = "NAME")]
+    pub uninstall_service: Option<String>,
+
+    /// Run as the service from this directory, for the command line
+    /// --install-service registers
+    #[arg(long, hide = true, value_name = "DIR", conflicts_with_all = ["install_service", "uninstall_service"])]
+    pub service: Option<String>,
+}
+
+#[derive(Args)]
+pub struct CounterArgs {
+    /// Only include counters containing this text, or matching a wildcard
+    /// pattern like \Process(*)\% Processor Time (repeatable)
+    #[arg(long)]
+    pub counter: Vec<String>,
+
+    /// File of counters to include, one path or wildcard pattern per line,
+    /// like the counter files relog and logman take with -cf. Use - to read
+    /// them from stdin, like the output of find.
+    #[arg(long, value_name = "FILE")]
+    pub counters_from: Option<String>,
+
+    /// Include every counter and instance of this object, like Processor or
+    /// Memory (repeatable)
+    #[arg(long, value_name = "NAME")]
+    pub object: Vec<String>,
+
+    /// Leave out counters containing this text, or matching a wildcard
+    /// pattern (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+}
+
+impl CounterArgs {
+    pub fn selection(&self) -> Option<CounterSelection> {
+        let mut include = self.counter.clone();
+        if let Some(path) = &self.counters_from {
+            match read_counter_list(path) {
+                Ok(from_file) if from_file.is_empty() && path == "-" => {
+                    eprintln!("No counters were piped in.");
+                    return None;
+                }
+                Ok(from_file) if from_file.is_empty() => {
+                    eprintln!("{} has no counters in it.", path);
+                    return None;
+                }
+                Ok(from_file) => include.extend(from_file),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return None;
+                }
+            }
+        }
+
+        Some(CounterSelection {
+            include,
+            objects: self.object.clone(),
+            exclude: self.exclude.clone(),
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct SourceArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+
+    /// Bind and read each file on its own, then stitch the series together.
+    /// Use this when the files have different counter sets.
+    #[arg(long)]
+    pub separate: bool,
+
+    /// Read the files in parallel with --separate
+    #[arg(long, requires = "separate")]
+    pub parallel: bool,
+
+    /// Remove the machine name from counter paths, so logs from different
+    /// machines line up as one
+    #[arg(long, conflicts_with = "map_machine")]
+    pub strip_machine: bool,
+
+    /// Rename machine OLD to NEW in counter paths, like SQLNODE1=SQLCLUSTER
+    /// (repeatable)
+    #[arg(long, value_name = "OLD=NEW")]
+    pub map_machine: Vec<MachineMap>,
+}
+
+impl SourceArgs {
+    pub fn maps_machines(&self) -> bool {
+        self.strip_machine || !self.map_machine.is_empty()
+    }
+
+    pub fn machine_path(&self, path: &str) -> String {
+        map_machine(path, self.strip_machine, &self.map_machine)
+    }
+}
+
+#[derive(Args)]
+pub struct NumberFormatArgs {
+    /// Write values with this many digits after the decimal point, instead
+    /// of as many as it takes to read them back exactly
+    #[arg(long)]
+    pub precision: Option<usize>,
+
+    /// Write values this large or larger in scientific notation, like 1.5e9
+    #[arg(long, value_name = "VALUE")]
+    pub scientific_above: Option<f64>,
+
+    /// Write values smaller than this, other than zero, in scientific
+    /// notation, like 2.5e-7
+    #[arg(long, value_name = "VALUE")]
+    pub scientific_below: Option<f64>,
+}
+
+impl NumberFormatArgs {
+    pub fn number_format(&self) -> NumberFormat {
+        NumberFormat {
+            precision: self.precision,
+            scientific_above: self.scientific_above,
+            scientific_below: self.scientific_below,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct AggregateArgs {
+    /// Combine the counters that differ only by --across into one series
+    /// each, like the total requests/sec of a web farm
+    #[arg(long, value_enum)]
+    pub aggregate: Option<Aggregate>,
+
+    /// What the counters --aggregate combines differ by. Both, like
+    /// machines,instances, combines every instance on every machine.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "machines",
+        requires = "aggregate"
+    )]
+    pub across: Vec<Across>,
+
+    /// Keep the series --aggregate combined, along with the combined ones
+    #[arg(long, requires = "aggregate")]
+    pub keep_members: bool,
+}
+
+#[derive(Args)]
+pub struct TimeFilterArgs {
+    /// Only keep samples at or after this time, like "2023-06-12 08:00", or
+    /// a time relative to the start or end of the logs, like "end-30m" or
+    /// "start+1h"
+    #[arg(long, value_parser = parse_time_spec, conflicts_with = "last")]
+    pub start: Option<TimeSpec>,
+
+    /// Only keep samples at or before this time, like "2023-06-12 18:30:00"
+    /// or "end-5m"
+    #[arg(long, value_parser = parse_time_spec)]
+    pub end: Option<TimeSpec>,
+
+    /// Only keep the samples in this much time before the end of the logs,
+    /// like 2h. The same as --start end-2h.
+    #[arg(long, value_parser = parse_duration)]
+    pub last: Option<Duration>,
+
+    /// Only keep samples within this time of day, like 08:00-18:00
+    #[arg(long)]
+    pub hours: Option<HoursRange>,
+
+    /// Only keep samples on these days, like Mon-Fri or Sat,Sun
+    #[arg(long)]
+    pub days: Option<DaySet>,
+}
+
+impl TimeFilterArgs {
+    pub fn time_filter(&self) -> TimeFilter {
+        let offset = display_offset();
+
+        TimeFilter {
+            start: self
+                .start
+                .or(self.last.map(|last| TimeSpec::FromEnd(-last))),
+            end: self.end,
+            hours: self.hours,
+            days: self.days.clone(),
+            offset,
+            range: OnceLock::new(),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct FindArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+
+    /// Regular expression matched against the full counter path
+    pub regex: String,
+
+    /// Match without regard to case
+    #[arg(short, long)]
+    pub ignore_case: bool,
+}
+
+#[derive(Args)]
+pub struct QueryArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// SELECT columns FROM samples [WHERE condition] [GROUP BY columns]
+    /// [ORDER BY columns [DESC]] [LIMIT n]. There's a row per sample, with
+    /// the columns time, counter, machine, object, instance, name, and
+    /// value. Columns can be count, avg, min, max, or sum of value, and
+    /// conditions compare columns with =, <>, <, <=, >, >=, or LIKE, joined
+    /// by AND, OR, and NOT. Times are compared with text like
+    /// '2024-06-12 08:00' or 'end-30m'.
+    pub query: String,
+}
+
+#[derive(Args)]
+pub struct ListMachinesArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+
+    /// Counter paths to check, like "\Processor(_Total)\% Processor Time".
+    /// Paths without a machine are checked on every machine in the logs
+    pub paths: Vec<String>,
+
+    /// Also check the paths in this file, one per line, or - for stdin
+    #[arg(long)]
+    pub counters_from: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ListObjectsArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+}
+
+#[derive(Args)]
+pub struct ListInstancesArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+
+    /// Object to list the instances of, like Process
+    pub object: String,
+}
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    /// Shell to complete in. For PowerShell, add
+    /// `perflogtool completions powershell | Out-String | Invoke-Expression`
+    /// to your profile.
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+#[derive(Args)]
+pub struct CompleteCounterArgs {
+    /// Glob pattern matching the .blg, .csv, .tsv, or .etl logs to read, or
+    /// .zip or .gz files of them
+    pub glob_pattern: String,
+
+    /// What has been typed of the path so far, like \Process(sv
+    #[arg(default_value = "")]
+    pub partial: String,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SplitFormat {
+    Blg,
+    Csv,
+    Tsv,
+}
+
+impl SplitFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SplitFormat::Blg => "blg",
+            SplitFormat::Csv => "csv",
+            SplitFormat::Tsv => "tsv",
+        }
+    }
+}
+
+// Sizes like 100MB or 1.5GB. A bare number is bytes.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let text = s.trim();
+    let (number, unit) = text.split_at(
+        text.find(|c: char| c.is_ascii_alphabetic())
+            .unwrap_or(text.len()),
+    );
+
+    let number = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("Expected a size like 100MB: {}", s))?;
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "KB" => 1 << 10,
+        "MB" => 1 << 20,
+        "GB" => 1 << 30,
+        _ => return Err(format!("Expected a size like 100MB: {}", s)),
+    };
+
+    if number <= 0.0 {
+        return Err(format!("The size must be more than 0: {}", s));
+    }
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.trim().trim_end_matches(['x', 'X']).parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("Expected a speed above 0, like 1 or 60: {}", s)),
+    }
+}
+
+fn parse_image_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("Expected a size like 1280x720: {}", s))?;
+
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("Invalid image size: {}", s)),
+    }
+}
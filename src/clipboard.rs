@@ -0,0 +1,53 @@
+use windows::Win32::{
+    Foundation::{HANDLE, HWND},
+    System::{
+        DataExchange::{CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData},
+        Memory::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE},
+    },
+};
+
+// Lives in Win32_System_Ole, which is a lot to pull in for one constant.
+const CF_UNICODETEXT: u32 = 13;
+
+pub fn set_clipboard_text(text: &str) -> Result<(), String> {
+    let mut wide: Vec<u16> = text.encode_utf16().collect();
+    wide.push(0);
+
+    unsafe {
+        if !OpenClipboard(HWND(0)).as_bool() {
+            return Err("Failed to open the clipboard".to_string());
+        }
+
+        let result = copy_to_clipboard(&wide);
+
+        CloseClipboard();
+
+        result
+    }
+}
+
+unsafe fn copy_to_clipboard(wide: &[u16]) -> Result<(), String> {
+    if !EmptyClipboard().as_bool() {
+        return Err("Failed to empty the clipboard".to_string());
+    }
+
+    let hglobal = GlobalAlloc(GMEM_MOVEABLE, std::mem::size_of_val(wide))
+        .map_err(|e| format!("Failed to allocate clipboard memory: {}", e))?;
+
+    let buffer = GlobalLock(hglobal) as *mut u16;
+    if buffer.is_null() {
+        let _ = GlobalFree(hglobal);
+        return Err("Failed to lock clipboard memory".to_string());
+    }
+
+    std::ptr::copy_nonoverlapping(wide.as_ptr(), buffer, wide.len());
+    GlobalUnlock(hglobal);
+
+    // The clipboard owns the memory once SetClipboardData succeeds.
+    if let Err(e) = SetClipboardData(CF_UNICODETEXT, HANDLE(hglobal.0)) {
+        let _ = GlobalFree(hglobal);
+        return Err(format!("Failed to set clipboard data: {}", e));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,428 @@
+use std::{
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use time::{macros::format_description, OffsetDateTime};
+use windows::{
+    core::{HSTRING, PWSTR},
+    Win32::{
+        Foundation::{BOOL, TRUE},
+        System::{
+            Console::SetConsoleCtrlHandler,
+            Performance::{PDH_LOG_TYPE_BINARY, PDH_LOG_TYPE_CSV, PDH_LOG_TYPE_TSV},
+            Services::{
+                RegisterServiceCtrlHandlerW, SetServiceStatus, StartServiceCtrlDispatcherW,
+                SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_CONTROL_SHUTDOWN,
+                SERVICE_CONTROL_STOP, SERVICE_RUNNING, SERVICE_STATUS,
+                SERVICE_STATUS_CURRENT_STATE, SERVICE_STATUS_HANDLE, SERVICE_STOPPED,
+                SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+            },
+        },
+    },
+};
+
+use crate::{
+    cli::{CollectArgs, SplitFormat},
+    pdh_helper::{status_name, CounterQuery, LogWriter},
+    selection::read_counter_list,
+    timespec::{display_offset, format_duration},
+};
+
+// Stopping waits at most this long for the next collection.
+const STOP_CHECK: Duration = Duration::from_millis(250);
+
+// The service's exit code is its own, not a Windows error.
+const ERROR_SERVICE_SPECIFIC_ERROR: u32 = 1066;
+
+// Set by Ctrl+C or the service control manager to stop collecting.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+// The service control manager calls service_main without the command line,
+// so the arguments are kept here for it.
+static SERVICE_ARGS: OnceLock<CollectArgs> = OnceLock::new();
+static SERVICE_HANDLE: OnceLock<SERVICE_STATUS_HANDLE> = OnceLock::new();
+
+// Writes the counters to a log every interval, and starts a new log when the
+// current one gets too big or too old, deleting the oldest ones past --keep.
+// Unlike a logman data collector set, the whole setup is one command line
+// that can be kept in a script.
+pub fn collect(args: &CollectArgs) {
+    if let Some(name) = &args.install_service {
+        install_service(name, args);
+        return;
+    }
+    if let Some(name) = &args.uninstall_service {
+        uninstall_service(name);
+        return;
+    }
+    if let Some(dir) = &args.service {
+        run_service(args, dir);
+        return;
+    }
+
+    unsafe { SetConsoleCtrlHandler(Some(console_handler), TRUE) };
+
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+    }
+}
+
+fn run(args: &CollectArgs) -> Result<(), String> {
+    let mut paths = args.counter.clone();
+    if let Some(path) = &args.counters_from {
+        paths.extend(read_counter_list(path)?);
+    }
+
+    let mut query = CounterQuery::open(0);
+    for path in &paths {
+        if let Err(pdhstatus) = query.add(path) {
+            eprintln!("Failed to add {}: {}", path, status_name(pdhstatus));
+        }
+    }
+
+    if query.counters().count() == 0 {
+        return Err("No counters to collect.".to_string());
+    }
+
+    std::fs::create_dir_all(&args.out)
+        .map_err(|e| format!("Failed to create {}: {}", args.out, e))?;
+
+    eprintln!(
+        "Collecting {} counters every {} to {}. Press Ctrl+C to stop.",
+        query.counters().count(),
+        format_duration(args.interval),
+        args.out
+    );
+
+    let started = Instant::now();
+    let mut log: Option<OpenLog> = None;
+    let mut next = Instant::now();
+
+    while !STOP.load(Ordering::Relaxed)
+        && args
+            .duration
+            .is_none_or(|duration| started.elapsed() < duration.unsigned_abs())
+    {
+        if log.as_ref().is_none_or(|log| log.is_full(args)) {
+            if let Some(log) = log.take() {
+                log.close();
+            }
+            let opened = OpenLog::create(args, &query)?;
+            prune(args);
+            log = Some(opened);
+        }
+
+        if let Some(log) = &mut log {
+            match log.writer.update() {
+                Ok(()) => log.samples += 1,
+                Err(pdhstatus) => {
+                    eprintln!("Failed to collect the counters: {}", status_name(pdhstatus))
+                }
+            }
+        }
+
+        next += args.interval.unsigned_abs();
+        while !STOP.load(Ordering::Relaxed) && Instant::now() < next {
+            std::thread::sleep(
+                next.saturating_duration_since(Instant::now())
+                    .min(STOP_CHECK),
+            );
+        }
+    }
+
+    if let Some(log) = log {
+        log.close();
+    }
+
+    Ok(())
+}
+
+struct OpenLog {
+    writer: LogWriter,
+    path: String,
+    opened: Instant,
+    samples: u64,
+}
+
+impl OpenLog {
+    // Logs are named by the time they were started, so they sort in order.
+    fn create(args: &CollectArgs, query: &CounterQuery) -> Result<OpenLog, String> {
+        let time = OffsetDateTime::now_utc()
+            .to_offset(display_offset())
+            .format(format_description!(
+                "[year][month][day]-[hour][minute][second]"
+            ))
+            .unwrap();
+        let path = Path::new(&args.out)
+            .join(format!(
+                "{}_{}.{}",
+                args.prefix,
+                time,
+                args.format.extension()
+            ))
+            .display()
+            .to_string();
+
+        let log_type = match args.format {
+            SplitFormat::Blg => PDH_LOG_TYPE_BINARY,
+            SplitFormat::Csv => PDH_LOG_TYPE_CSV,
+            SplitFormat::Tsv => PDH_LOG_TYPE_TSV,
+        };
+
+        let writer = LogWriter::create(&path, log_type, query).map_err(|pdhstatus| {
+            format!("Failed to create {}: {}", path, status_name(pdhstatus))
+        })?;
+
+        Ok(OpenLog {
+            writer,
+            path,
+            opened: Instant::now(),
+            samples: 0,
+        })
+    }
+
+    fn is_full(&self, args: &CollectArgs) -> bool {
+        let too_big = args.max_size.is_some_and(|max| {
+            std::fs::metadata(&self.path).is_ok_and(|metadata| metadata.len() >= max)
+        });
+        let too_old = args
+            .rotate_every
+            .is_some_and(|every| self.opened.elapsed() >= every.unsigned_abs());
+
+        too_big || too_old
+    }
+
+    fn close(self) {
+        drop(self.writer);
+        eprintln!("Wrote {} samples to {}", self.samples, self.path);
+    }
+}
+
+// Deletes the oldest logs with the prefix past --keep, counting the one just
+// started.
+fn prune(args: &CollectArgs) {
+    let keep = match args.keep {
+        Some(keep) => keep as usize,
+        None => return,
+    };
+
+    let pattern = Path::new(&glob::Pattern::escape(&args.out))
+        .join(format!(
+            "{}_*.{}",
+            glob::Pattern::escape(&args.prefix),
+            args.format.extension()
+        ))
+        .display()
+        .to_string();
+
+    let mut logs = match glob::glob(&pattern) {
+        Ok(paths) => paths.filter_map(Result::ok).collect::<Vec<_>>(),
+        Err(_) => return,
+    };
+    logs.sort();
+
+    for old in &logs[..logs.len().saturating_sub(keep)] {
+        match std::fs::remove_file(old) {
+            Ok(()) => eprintln!("Deleted {}", old.display()),
+            Err(e) => eprintln!("Failed to delete {}: {}", old.display(), e),
+        }
+    }
+}
+
+unsafe extern "system" fn console_handler(_ctrl_type: u32) -> BOOL {
+    STOP.store(true, Ordering::Relaxed);
+    TRUE
+}
+
+// Registers a service that runs this command line without --install-service,
+// from the current directory so relative paths still point to the same
+// place. sc.exe does the registering, so the error messages are the ones
+// administrators already know.
+fn install_service(name: &str, args: &CollectArgs) {
+    if args.counters_from.as_deref() == Some("-") {
+        eprintln!("A service can't read its counters from stdin. Use a file with --counters-from.");
+        return;
+    }
+
+    let (exe, dir) = match (std::env::current_exe(), std::env::current_dir()) {
+        (Ok(exe), Ok(dir)) => (exe, dir),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to find the command line for the service: {}", e);
+            return;
+        }
+    };
+
+    let mut command_line = vec![quote_argument(&exe.display().to_string())];
+    let mut skip = false;
+    for arg in std::env::args().skip(1) {
+        if std::mem::take(&mut skip) || arg.starts_with("--install-service=") {
+            continue;
+        }
+        if arg == "--install-service" {
+            skip = true;
+            continue;
+        }
+        command_line.push(quote_argument(&arg));
+    }
+    command_line.push("--service".to_string());
+    command_line.push(quote_argument(&dir.display().to_string()));
+
+    let display_name = format!("perflogtool collect ({})", name);
+    let status = process::Command::new("sc.exe")
+        .args([
+            "create",
+            name,
+            "start=",
+            "auto",
+            "DisplayName=",
+            &display_name,
+        ])
+        .args(["binPath=", &command_line.join(" ")])
+        .status();
+
+    match status {
+        Ok(status) if status.success() => {
+            eprintln!("Installed {}. Start it with: sc.exe start {}", name, name)
+        }
+        Ok(status) => eprintln!("sc.exe create failed: {}", status),
+        Err(e) => eprintln!("Failed to run sc.exe: {}", e),
+    }
+}
+
+fn uninstall_service(name: &str) {
+    let status = process::Command::new("sc.exe")
+        .args(["delete", name])
+        .status();
+
+    match status {
+        Ok(status) if status.success() => eprintln!("Removed {}.", name),
+        Ok(status) => eprintln!("sc.exe delete failed: {}", status),
+        Err(e) => eprintln!("Failed to run sc.exe: {}", e),
+    }
+}
+
+// Quotes an argument so CommandLineToArgvW splits it back out unchanged.
+fn quote_argument(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        // Backslashes are only escapes in front of a quote.
+        let count = if c == '"' {
+            backslashes * 2 + 1
+        } else {
+            backslashes
+        };
+        quoted.push_str(&"\\".repeat(count));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+fn run_service(args: &CollectArgs, dir: &str) {
+    // Services start in System32.
+    if let Err(e) = std::env::set_current_dir(dir) {
+        eprintln!("Failed to change to {}: {}", dir, e);
+        return;
+    }
+
+    let _ = SERVICE_ARGS.set(args.clone());
+
+    // The name is ignored for a service that has its own process.
+    let mut name = "perflogtool\0".encode_utf16().collect::<Vec<u16>>();
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: PWSTR(name.as_mut_ptr()),
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW::default(),
+    ];
+
+    if !unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) }.as_bool() {
+        eprintln!("--service is for the service control manager. Use --install-service instead.");
+    }
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+    let handle = match RegisterServiceCtrlHandlerW(&HSTRING::from(""), Some(service_handler)) {
+        Ok(handle) => handle,
+        Err(_) => return,
+    };
+    let _ = SERVICE_HANDLE.set(handle);
+
+    set_service_state(SERVICE_RUNNING, 0);
+
+    let exit_code = match run(SERVICE_ARGS.get().unwrap()) {
+        Ok(()) => 0,
+        Err(e) => {
+            report_error(&e);
+            1
+        }
+    };
+
+    set_service_state(SERVICE_STOPPED, exit_code);
+}
+
+unsafe extern "system" fn service_handler(control: u32) {
+    if control == SERVICE_CONTROL_STOP || control == SERVICE_CONTROL_SHUTDOWN {
+        STOP.store(true, Ordering::Relaxed);
+        set_service_state(SERVICE_STOP_PENDING, 0);
+    }
+}
+
+fn set_service_state(state: SERVICE_STATUS_CURRENT_STATE, exit_code: u32) {
+    let handle = match SERVICE_HANDLE.get() {
+        Some(handle) => *handle,
+        None => return,
+    };
+
+    let status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: if state == SERVICE_RUNNING {
+            SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+        } else {
+            0
+        },
+        dwWin32ExitCode: if exit_code == 0 {
+            0
+        } else {
+            ERROR_SERVICE_SPECIFIC_ERROR
+        },
+        dwServiceSpecificExitCode: exit_code,
+        dwCheckPoint: 0,
+        dwWaitHint: if state == SERVICE_STOP_PENDING {
+            STOP_CHECK.as_millis() as u32 * 4
+        } else {
+            0
+        },
+    };
+
+    unsafe { SetServiceStatus(handle, &status) };
+}
+
+// A service has no console, so why it stopped goes to the Application event
+// log instead.
+fn report_error(message: &str) {
+    let _ = process::Command::new("eventcreate")
+        .args(["/T", "ERROR", "/ID", "101", "/L", "APPLICATION"])
+        .args(["/SO", "perflogtool", "/D", message])
+        .stdout(process::Stdio::null())
+        .status();
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use crate::{
+    cli::CompareArgs,
+    reader::{read_counters, CounterData},
+    stats::sort_values,
+    status::progress,
+    units::Unit,
+};
+
+pub struct Comparison {
+    pub counter: String,
+    pub baseline: f64,
+    pub incident: f64,
+    pub unit: Unit,
+}
+
+impl Comparison {
+    pub fn difference(&self) -> f64 {
+        self.incident - self.baseline
+    }
+
+    // Percent change from the baseline. Infinite when the baseline is zero
+    // and the incident isn't, which sorts those counters first.
+    pub fn percent_change(&self) -> f64 {
+        if self.baseline == 0.0 {
+            if self.incident == 0.0 {
+                0.0
+            } else {
+                f64::INFINITY.copysign(self.incident)
+            }
+        } else {
+            self.difference() / self.baseline.abs() * 100.0
+        }
+    }
+}
+
+pub fn compare(args: &CompareArgs) {
+    let selection = match args.counters.selection() {
+        Some(selection) => selection,
+        None => return,
+    };
+
+    progress!("Reading the baseline...");
+    let baseline = match read_counters(&args.source(&args.baseline), &selection) {
+        Some(baseline) => baseline,
+        None => return,
+    };
+
+    progress!("Reading the incident...");
+    let incident = match read_counters(&args.source(&args.incident), &selection) {
+        Some(incident) => incident,
+        None => return,
+    };
+
+    let baseline_values = aggregate(&baseline, args);
+    let incident_values = aggregate(&incident, args)
+        .into_iter()
+        .map(|(counter, value)| (counter.to_lowercase(), (counter, value)))
+        .collect::<HashMap<String, (String, f64)>>();
+
+    let mut comparisons = Vec::new();
+    let mut only_baseline = 0;
+    for (counter, value) in &baseline_values {
+        match incident_values.get(&counter.to_lowercase()) {
+            Some((_, incident_value)) => comparisons.push(Comparison {
+                counter: counter.clone(),
+                baseline: *value,
+                incident: *incident_value,
+                unit: if args.raw {
+                    Unit::Count
+                } else {
+                    baseline.unit(counter)
+                },
+            }),
+            None => only_baseline += 1,
+        }
+    }
+    let only_incident = incident_values.len() - (baseline_values.len() - only_baseline);
+
+    if comparisons.is_empty() {
+        eprintln!("No counters are in both logs.");
+        return;
+    }
+
+    comparisons.sort_by(|a, b| {
+        b.percent_change()
+            .abs()
+            .total_cmp(&a.percent_change().abs())
+            .then(b.difference().abs().total_cmp(&a.difference().abs()))
+    });
+
+    println!(
+        "Biggest changes in {} of {} counters, baseline to incident",
+        args.by.name(),
+        comparisons.len()
+    );
+    println!(
+        "{:>14}  {:>14}  {:>10}  Counter",
+        "Baseline", "Incident", "Change"
+    );
+    for c in comparisons.iter().take(args.count) {
+        let change = c.percent_change();
+        let change = if change.is_infinite() {
+            "new".to_string()
+        } else {
+            format!("{:+.1}%", change)
+        };
+        println!(
+            "{:>14}  {:>14}  {:>10}  {}",
+            c.unit.format(c.baseline),
+            c.unit.format(c.incident),
+            change,
+            c.counter
+        );
+    }
+
+    if only_baseline > 0 || only_incident > 0 {
+        eprintln!(
+            "{} counters are only in the baseline and {} only in the incident. \
+             Use --strip-machine or --map-machine if the logs are from different machines.",
+            only_baseline, only_incident
+        );
+    }
+}
+
+fn aggregate(data: &CounterData, args: &CompareArgs) -> Vec<(String, f64)> {
+    data.counters
+        .iter()
+        .filter_map(|counter| {
+            let mut values = data.samples[counter]
+                .iter()
+                .map(|s| s.value())
+                .collect::<Vec<f64>>();
+
+            if values.is_empty() {
+                return None;
+            }
+
+            sort_values(&mut values);
+            Some((counter.clone(), args.by.compute(&values)))
+        })
+        .collect()
+}
@@ -0,0 +1,345 @@
+use std::collections::BTreeSet;
+
+use clap::{Command, CommandFactory, ValueEnum};
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    cli::{Cli, CompleteCounterArgs, CompletionsArgs},
+    log_files::glob_log_files,
+    pdh_helper::{
+        bind_input_logfiles, detail_level, enum_machines, enum_object_items, enum_objects,
+    },
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Powershell,
+}
+
+// Options whose values are counter paths, which the scripts complete by
+// calling complete-counter against the logs named on the command line.
+const COUNTER_OPTIONS: &[&str] = &["--counter", "--exclude", "--record"];
+
+// The subcommands with their options, and the values of options that take
+// one of a fixed set, taken from the clap definitions so the scripts can't
+// drift from them.
+struct Words {
+    subcommands: Vec<(String, Vec<String>)>,
+    global: Vec<String>,
+    values: Vec<(String, Vec<String>)>,
+}
+
+pub fn completions(args: &CompletionsArgs) {
+    let words = words(&Cli::command());
+
+    match args.shell {
+        Shell::Bash => print!("{}", bash(&words)),
+        Shell::Powershell => print!("{}", powershell(&words)),
+    }
+}
+
+fn words(cli: &Command) -> Words {
+    let mut values = Vec::new();
+
+    let mut global = vec!["--help".to_string()];
+    for arg in cli.get_arguments().filter(|a| a.is_global_set()) {
+        if let Some(long) = arg.get_long() {
+            global.push(format!("--{}", long));
+            push_values(&mut values, &format!("--{}", long), arg);
+        }
+    }
+
+    let mut subcommands = Vec::new();
+    for subcommand in cli.get_subcommands().filter(|s| !s.is_hide_set()) {
+        let mut options = vec!["--help".to_string()];
+        for arg in subcommand.get_arguments() {
+            if let Some(long) = arg.get_long() {
+                options.push(format!("--{}", long));
+                push_values(
+                    &mut values,
+                    &format!("{} --{}", subcommand.get_name(), long),
+                    arg,
+                );
+            }
+            if let Some(short) = arg.get_short() {
+                options.push(format!("-{}", short));
+            }
+        }
+        subcommands.push((subcommand.get_name().to_string(), options));
+    }
+
+    Words {
+        subcommands,
+        global,
+        values,
+    }
+}
+
+fn push_values(values: &mut Vec<(String, Vec<String>)>, key: &str, arg: &clap::Arg) {
+    // Flags report true and false as their values.
+    if !arg.get_action().takes_values() {
+        return;
+    }
+
+    let possible = arg
+        .get_possible_values()
+        .iter()
+        .filter(|v| !v.is_hide_set())
+        .map(|v| v.get_name().to_string())
+        .collect::<Vec<String>>();
+
+    if !possible.is_empty() {
+        values.push((key.to_string(), possible));
+    }
+}
+
+fn bash(words: &Words) -> String {
+    let mut script = String::new();
+    script.push_str("_perflogtool() {\n");
+    script.push_str(
+        "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n",
+    );
+    script.push_str("    local sub=\"${COMP_WORDS[1]}\" opts i glob\n\n");
+
+    script.push_str("    if [ \"$COMP_CWORD\" -eq 1 ]; then\n");
+    script.push_str(&format!(
+        "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n",
+        words
+            .subcommands
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    ));
+    script.push_str("        return\n    fi\n\n");
+
+    // The logs are the first word after the subcommand that isn't an option.
+    script.push_str("    case \"$prev\" in\n");
+    script.push_str(&format!("    {})\n", COUNTER_OPTIONS.join("|")));
+    script.push_str("        for ((i = 2; i < COMP_CWORD; i++)); do\n");
+    script.push_str("            case \"${COMP_WORDS[i]}\" in -*) ;; *) glob=\"${COMP_WORDS[i]}\"; break ;; esac\n");
+    script.push_str("        done\n");
+    script.push_str("        local IFS=$'\\n'\n");
+    script.push_str(
+        "        COMPREPLY=($(perflogtool complete-counter \"$glob\" \"$cur\" 2>/dev/null))\n",
+    );
+    script.push_str("        return\n        ;;\n    esac\n\n");
+
+    script.push_str("    case \"$sub $prev\" in\n");
+    for (key, values) in &words.values {
+        let key = match key.strip_prefix("--") {
+            // Global options can follow any subcommand.
+            Some(_) => format!("*\" {}\"", key),
+            None => format!("\"{}\"", key),
+        };
+        script.push_str(&format!(
+            "    {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+            key,
+            values.join(" ")
+        ));
+    }
+    script.push_str("    esac\n\n");
+
+    script.push_str("    case \"$sub\" in\n");
+    for (name, options) in &words.subcommands {
+        script.push_str(&format!(
+            "    {}) opts=\"{}\" ;;\n",
+            name,
+            options.join(" ")
+        ));
+    }
+    script.push_str("    esac\n");
+    script.push_str(&format!(
+        "    COMPREPLY=($(compgen -W \"$opts {}\" -- \"$cur\"))\n",
+        words.global.join(" ")
+    ));
+    script.push_str("}\n\ncomplete -o default -F _perflogtool perflogtool perflogtool.exe\n");
+    script
+}
+
+fn powershell(words: &Words) -> String {
+    let list = |items: &[String]| {
+        items
+            .iter()
+            .map(|i| format!("'{}'", i.replace('\'', "''")))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    let mut script = String::new();
+    script.push_str(
+        "Register-ArgumentCompleter -Native -CommandName perflogtool, perflogtool.exe -ScriptBlock {\n",
+    );
+    script.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n\n");
+
+    script.push_str("    $subcommands = @{\n");
+    for (name, options) in &words.subcommands {
+        script.push_str(&format!("        '{}' = @({})\n", name, list(options)));
+    }
+    script.push_str("    }\n");
+    script.push_str("    $values = @{\n");
+    for (key, values) in &words.values {
+        script.push_str(&format!("        '{}' = @({})\n", key, list(values)));
+    }
+    script.push_str("    }\n");
+    script.push_str(&format!("    $global = @({})\n", list(&words.global)));
+    script.push_str(&format!(
+        "    $counterOptions = @({})\n\n",
+        COUNTER_OPTIONS
+            .iter()
+            .map(|o| format!("'{}'", o))
+            .collect::<Vec<String>>()
+            .join(", ")
+    ));
+
+    script.push_str(
+        "    $words = @($commandAst.CommandElements |
+        Where-Object { $_.Extent.EndOffset -lt $cursorPosition } |
+        ForEach-Object { $_.ToString() })
+    $word = $wordToComplete.Trim('''', '\"')
+
+    if ($words.Count -le 1) {
+        $candidates = $subcommands.Keys | Sort-Object
+    } else {
+        $sub = $words[1]
+        $prev = $words[-1]
+        if ($counterOptions -contains $prev) {
+            # The logs are the first word after the subcommand that isn't an option.
+            $glob = $words | Select-Object -Skip 2 | Where-Object { -not $_.StartsWith('-') } | Select-Object -First 1
+            # These already match what was typed, which they may add a \\ to.
+            $candidates = @(& perflogtool complete-counter $glob $word 2>$null)
+            $word = ''
+        } elseif ($values.ContainsKey(\"$sub $prev\")) {
+            $candidates = $values[\"$sub $prev\"]
+        } elseif ($values.ContainsKey($prev)) {
+            $candidates = $values[$prev]
+        } else {
+            $candidates = @($subcommands[$sub]) + $global
+        }
+    }
+
+    $candidates | Where-Object { $_ -like \"$word*\" } | ForEach-Object {
+        $text = if ($_ -match '[\\s''()]') { \"'\" + $_.Replace(\"'\", \"''\") + \"'\" } else { $_ }
+        [System.Management.Automation.CompletionResult]::new($text, $_, 'ParameterValue', $_)
+    }
+}
+",
+    );
+    script
+}
+
+pub fn complete_counter(args: &CompleteCounterArgs) {
+    let files = glob_log_files(&args.glob_pattern);
+    if files.is_empty() {
+        return;
+    }
+
+    let hdatasource = bind_input_logfiles(files);
+    for suggestion in suggest(hdatasource, &args.partial) {
+        println!("{}", suggestion);
+    }
+    unsafe { PdhCloseLog(hdatasource, 0) };
+}
+
+// Suggests the next part of a counter path: the machine, then the object,
+// then the instance, then the counter. Each suggestion is the whole path so
+// far, so it can replace what was typed.
+fn suggest(hdatasource: isize, partial: &str) -> BTreeSet<String> {
+    let mut suggestions = BTreeSet::new();
+    let machines = enum_machines(hdatasource);
+
+    // \\MACHINE\rest, or just \rest for every machine.
+    let (machine, rest) = match partial.strip_prefix("\\\\") {
+        Some(after) => match after.find('\\') {
+            Some(end) => (Some(&partial[..end + 2]), &after[end..]),
+            None => {
+                for machine in &machines {
+                    if starts_with(machine, partial) {
+                        suggestions.insert(format!("{}\\", machine));
+                    }
+                }
+                return suggestions;
+            }
+        },
+        None => (None, partial),
+    };
+
+    let prefix = machine.unwrap_or("");
+    let rest = rest.trim_start_matches('\\');
+    let machines = machines
+        .into_iter()
+        .filter(|m| machine.is_none_or(|machine| m.eq_ignore_ascii_case(machine)))
+        .collect::<Vec<String>>();
+
+    let objects = |machine: &String| enum_objects(machine, hdatasource, detail_level());
+    let items = |machine: &String, object: &str| {
+        let object = objects(machine)
+            .into_iter()
+            .find(|o| o.eq_ignore_ascii_case(object))?;
+        let items = enum_object_items(machine, &object, hdatasource, detail_level())?;
+        Some((object, items))
+    };
+
+    match (rest.find('('), rest.find('\\')) {
+        // \Object(instance
+        (Some(open), None) => {
+            let (object, instance) = (&rest[..open], &rest[open + 1..]);
+            for machine in &machines {
+                if let Some((object, (_, instances))) = items(machine, object) {
+                    suggestions.insert(format!("{}\\{}(*)\\", prefix, object));
+                    for i in instances.iter().filter(|i| starts_with(i, instance)) {
+                        suggestions.insert(format!("{}\\{}({})\\", prefix, object, i));
+                    }
+                }
+            }
+        }
+
+        // \Object(instance)\counter or \Object\counter
+        (_, Some(slash)) => {
+            let (object_part, counter) = (&rest[..slash], &rest[slash + 1..]);
+            let object = object_part.split('(').next().unwrap_or(object_part);
+            for machine in &machines {
+                if let Some((_, (counters, _))) = items(machine, object) {
+                    for c in counters.iter().filter(|c| starts_with(c, counter)) {
+                        suggestions.insert(format!("{}\\{}\\{}", prefix, object_part, c));
+                    }
+                }
+            }
+        }
+
+        // \Object
+        (None, None) => {
+            let matching = machines
+                .iter()
+                .flat_map(|machine| objects(machine).into_iter().map(move |o| (machine, o)))
+                .filter(|(_, o)| starts_with(o, rest))
+                .collect::<Vec<(&String, String)>>();
+
+            // Listing an object's items is slow, so only the one object left
+            // gets the ( or \ that comes next, depending on whether it has
+            // instances.
+            let single = matching
+                .iter()
+                .map(|(_, o)| o.to_lowercase())
+                .collect::<BTreeSet<String>>()
+                .len()
+                == 1;
+
+            for (machine, object) in &matching {
+                let next = match items(machine, object) {
+                    Some((_, (_, instances))) if single && !instances.is_empty() => "(",
+                    Some(_) if single => "\\",
+                    _ => "",
+                };
+                suggestions.insert(format!("{}\\{}{}", prefix, object, next));
+            }
+        }
+    }
+
+    suggestions
+}
+
+fn starts_with(text: &str, prefix: &str) -> bool {
+    text.to_lowercase().starts_with(&prefix.to_lowercase())
+}
@@ -0,0 +1,188 @@
+use std::str::FromStr;
+
+use windows::Win32::System::Performance::PDH_INVALID_ARGUMENT;
+
+use crate::pdh_helper::make_counter_path;
+
+// The pieces of a full counter path like \\MACHINE\Object(instance)\Counter.
+#[derive(PartialEq)]
+pub struct CounterPath {
+    pub machine: String,
+    pub object: String,
+    pub instance: Option<String>,
+    pub counter: String,
+}
+
+impl CounterPath {
+    pub fn parse(path: &str) -> Option<CounterPath> {
+        let (machine, rest) = match path.strip_prefix("\\\\") {
+            Some(rest) => {
+                let index = rest.find('\\')?;
+                (rest[..index].to_string(), &rest[index..])
+            }
+            None => (String::new(), path),
+        };
+
+        let rest = rest.strip_prefix('\\')?;
+        let (object_part, counter) = rest.rsplit_once('\\')?;
+
+        // Instance names can contain parentheses themselves, so take
+        // everything between the first '(' and the last ')'.
+        let (object, instance) = match (object_part.find('('), object_part.rfind(')')) {
+            (Some(open), Some(close)) if close > open => (
+                object_part[..open].to_string(),
+                Some(object_part[open + 1..close].to_string()),
+            ),
+            _ => (object_part.to_string(), None),
+        };
+
+        Some(CounterPath {
+            machine,
+            object,
+            instance,
+            counter: counter.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for CounterPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if !self.machine.is_empty() {
+            write!(f, "\\\\{}", self.machine)?;
+        }
+        match &self.instance {
+            Some(instance) => write!(f, "\\{}({})\\{}", self.object, instance, self.counter),
+            None => write!(f, "\\{}\\{}", self.object, self.counter),
+        }
+    }
+}
+
+// Builds a counter path from its pieces, like
+//
+//   CounterPathBuilder::new().machine("SQL01").object("Process").instance("w3wp").counter("ID Process")
+//
+// PDH puts the path together, so callers don't need to know where the
+// backslashes and parentheses go, or how parent instances and instance
+// indexes are written.
+#[derive(Clone, Debug, Default)]
+pub struct CounterPathBuilder {
+    machine: Option<String>,
+    object: String,
+    instance: Option<String>,
+    parent: Option<String>,
+    index: u32,
+    counter: String,
+}
+
+impl CounterPathBuilder {
+    pub fn new() -> CounterPathBuilder {
+        CounterPathBuilder::default()
+    }
+
+    // With or without the leading backslashes.
+    pub fn machine(mut self, machine: &str) -> CounterPathBuilder {
+        let machine = machine.trim_start_matches('\\');
+        self.machine = (!machine.is_empty()).then(|| format!("\\\\{}", machine));
+        self
+    }
+
+    pub fn object(mut self, object: &str) -> CounterPathBuilder {
+        self.object = object.to_string();
+        self
+    }
+
+    pub fn instance(mut self, instance: &str) -> CounterPathBuilder {
+        self.instance = Some(instance.to_string());
+        self
+    }
+
+    // The parent of the instance, like the process of a thread.
+    pub fn parent(mut self, parent: &str) -> CounterPathBuilder {
+        self.parent = Some(parent.to_string());
+        self
+    }
+
+    // Tells apart instances with the same name, written as name#index.
+    pub fn index(mut self, index: u32) -> CounterPathBuilder {
+        self.index = index;
+        self
+    }
+
+    pub fn counter(mut self, counter: &str) -> CounterPathBuilder {
+        self.counter = counter.to_string();
+        self
+    }
+
+    // Returns the PDH status when the pieces don't make a path.
+    pub fn build(&self) -> Result<String, u32> {
+        if self.object.is_empty() || self.counter.is_empty() {
+            return Err(PDH_INVALID_ARGUMENT);
+        }
+
+        make_counter_path(
+            self.machine.as_deref(),
+            &self.object,
+            self.instance.as_deref(),
+            self.parent.as_deref(),
+            self.index,
+            &self.counter,
+        )
+    }
+
+    // For paths made from names PDH listed itself, which always fit.
+    pub fn build_or_panic(&self) -> String {
+        self.build()
+            .unwrap_or_else(|status| panic!("Failed to make a counter path: {:#x}", status))
+    }
+}
+
+// Renames the machine in counter paths, like SQLNODE1=SQLCLUSTER, so logs
+// captured under different names line up. Machine names ignore case.
+#[derive(Clone, Debug)]
+pub struct MachineMap {
+    pub from: String,
+    pub to: String,
+}
+
+impl FromStr for MachineMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trim = |name: &str| name.trim().trim_start_matches('\\').to_string();
+
+        match s.split_once('=') {
+            Some((from, to)) if !trim(from).is_empty() && !trim(to).is_empty() => Ok(MachineMap {
+                from: trim(from),
+                to: trim(to),
+            }),
+            _ => Err(format!("Expected a machine mapping like OLD=NEW: {}", s)),
+        }
+    }
+}
+
+// Returns the path with its machine mapped, or removed with strip. Paths that
+// don't parse are returned unchanged.
+pub fn map_machine(path: &str, strip: bool, maps: &[MachineMap]) -> String {
+    let mut parsed = match CounterPath::parse(path) {
+        Some(parsed) => parsed,
+        None => return path.to_string(),
+    };
+
+    if strip {
+        parsed.machine.clear();
+    } else {
+        let machine = map_machine_name(&parsed.machine, maps);
+        if machine == parsed.machine {
+            return path.to_string();
+        }
+        parsed.machine = machine;
+    }
+
+    parsed.to_string()
+}
+
+pub fn map_machine_name(machine: &str, maps: &[MachineMap]) -> String {
+    maps.iter()
+        .find(|m| m.from.eq_ignore_ascii_case(machine))
+        .map_or_else(|| machine.to_string(), |m| m.to.clone())
+}
@@ -0,0 +1,396 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use clap::ValueEnum;
+use time::OffsetDateTime;
+
+use crate::{
+    pdh_helper::CounterValueWithTime, reader::CounterData, resample::Aggregate,
+    selection::CounterSelection, series::Series, stats::sort_values,
+};
+
+// A series calculated from counters at each timestamp, like
+// ratio = "\Memory\Committed Bytes" / "\Memory\Commit Limit" * 100 or
+// total = sum("\Process(*)\IO Data Bytes/sec"). Counters are quoted and
+// matched like --counter. A counter on its own has to match just one
+// counter, while the aggregate functions take every counter a pattern
+// matches.
+#[derive(Clone)]
+pub struct Derivation {
+    pub name: String,
+    expr: Expr,
+}
+
+#[derive(Clone)]
+enum Expr {
+    Number(f64),
+    Counter(String),
+    Function(Aggregate, String),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+}
+
+impl FromStr for Derivation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, expression) = match s.split_once('=') {
+            Some((name, expression)) if !name.trim().is_empty() && !name.contains('"') => {
+                (name.trim(), expression)
+            }
+            _ => {
+                return Err(format!(
+                    "Expected a derivation like name = expression: {}",
+                    s
+                ))
+            }
+        };
+
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+        };
+        let expr = parser.expression()?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(format!("Unexpected {} in {}", token, expression.trim()));
+        }
+
+        Ok(Derivation {
+            name: name.to_string(),
+            expr,
+        })
+    }
+}
+
+impl Derivation {
+    // The counter patterns to read for this derivation.
+    pub fn patterns(&self) -> Vec<String> {
+        let mut patterns = Vec::new();
+        self.expr.patterns(&mut patterns);
+        patterns
+    }
+
+    // Samples where a counter is missing, or the result isn't a number, like
+    // when dividing by zero, are left out.
+    pub fn evaluate(&self, data: &CounterData) -> Result<Series, String> {
+        let values = match self.expr.evaluate(data)? {
+            Value::Series(values) => values,
+            Value::Constant(_) => {
+                return Err(format!("{} doesn't use any counters.", self.name));
+            }
+        };
+
+        Ok(values
+            .into_iter()
+            .filter(|(_, value)| value.is_finite())
+            .map(|(time, value)| CounterValueWithTime::Double(time, value))
+            .collect())
+    }
+}
+
+// The counters to read: the selected ones plus those the derivations use.
+// With derivations and no --counter, only the derived series are wanted.
+pub fn read_selection(
+    selection: &CounterSelection,
+    derivations: &[Derivation],
+) -> CounterSelection {
+    if derivations.is_empty() {
+        return selection.clone();
+    }
+
+    let mut include = selection.include.clone();
+    include.extend(derivations.iter().flat_map(|d| d.patterns()));
+    CounterSelection {
+        include,
+        objects: selection.objects.clone(),
+        exclude: Vec::new(),
+    }
+}
+
+// Whether a counter read for read_selection was selected for itself.
+pub fn is_selected(
+    selection: &CounterSelection,
+    derivations: &[Derivation],
+    counter: &str,
+) -> bool {
+    (derivations.is_empty() || !selection.selects_all()) && selection.matches(counter)
+}
+
+pub fn evaluate_all(data: &CounterData, derivations: &[Derivation]) -> Option<Vec<Series>> {
+    derivations
+        .iter()
+        .map(|d| match d.evaluate(data) {
+            Ok(series) => Some(series),
+            Err(e) => {
+                eprintln!("Failed to calculate {}: {}", d.name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+enum Value {
+    Constant(f64),
+    Series(BTreeMap<OffsetDateTime, f64>),
+}
+
+impl Expr {
+    fn patterns(&self, patterns: &mut Vec<String>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Counter(pattern) | Expr::Function(_, pattern) => patterns.push(pattern.clone()),
+            Expr::Negate(expr) => expr.patterns(patterns),
+            Expr::Binary(left, _, right) => {
+                left.patterns(patterns);
+                right.patterns(patterns);
+            }
+        }
+    }
+
+    fn evaluate(&self, data: &CounterData) -> Result<Value, String> {
+        match self {
+            Expr::Number(value) => Ok(Value::Constant(*value)),
+
+            Expr::Counter(pattern) => {
+                let matching = data.matching(pattern);
+                match matching.as_slice() {
+                    [(_, samples)] => Ok(Value::Series(
+                        samples.iter().map(|s| (s.time(), s.value())).collect(),
+                    )),
+                    [] => Err(format!("No counters match \"{}\".", pattern)),
+                    _ => Err(format!(
+                        "\"{}\" matches {} counters. Use a function like sum or avg to combine them.",
+                        pattern,
+                        matching.len()
+                    )),
+                }
+            }
+
+            // Each timestamp's value is the aggregate of the counters that
+            // have a sample then.
+            Expr::Function(aggregate, pattern) => {
+                let matching = data.matching(pattern);
+                if matching.is_empty() {
+                    return Err(format!("No counters match \"{}\".", pattern));
+                }
+
+                let mut values = BTreeMap::<OffsetDateTime, Vec<f64>>::new();
+                for (_, samples) in matching {
+                    for sample in samples.iter() {
+                        values
+                            .entry(sample.time())
+                            .or_default()
+                            .push(sample.value());
+                    }
+                }
+
+                Ok(Value::Series(
+                    values
+                        .into_iter()
+                        .map(|(time, mut values)| {
+                            sort_values(&mut values);
+                            (time, aggregate.compute(&values))
+                        })
+                        .collect(),
+                ))
+            }
+
+            Expr::Negate(expr) => Ok(apply(Value::Constant(0.0), '-', expr.evaluate(data)?)),
+
+            Expr::Binary(left, op, right) => {
+                Ok(apply(left.evaluate(data)?, *op, right.evaluate(data)?))
+            }
+        }
+    }
+}
+
+// Two series are combined where both have a sample.
+fn apply(left: Value, op: char, right: Value) -> Value {
+    let calculate = |a: f64, b: f64| match op {
+        '+' => a + b,
+        '-' => a - b,
+        '*' => a * b,
+        _ => a / b,
+    };
+
+    match (left, right) {
+        (Value::Constant(a), Value::Constant(b)) => Value::Constant(calculate(a, b)),
+        (Value::Series(a), Value::Constant(b)) => {
+            Value::Series(a.into_iter().map(|(t, a)| (t, calculate(a, b))).collect())
+        }
+        (Value::Constant(a), Value::Series(b)) => {
+            Value::Series(b.into_iter().map(|(t, b)| (t, calculate(a, b))).collect())
+        }
+        (Value::Series(a), Value::Series(b)) => Value::Series(
+            a.into_iter()
+                .filter_map(|(t, a)| b.get(&t).map(|b| (t, calculate(a, *b))))
+                .collect(),
+        ),
+    }
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Counter(String),
+    Name(String),
+    Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Counter(pattern) => write!(f, "\"{}\"", pattern),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Symbol(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+
+            '+' | '-' | '*' | '/' | '(' | ')' => {
+                tokens.push(Token::Symbol(c));
+                chars.next();
+            }
+
+            // Counter paths, with "" for a quote inside one.
+            '"' => {
+                chars.next();
+                let mut pattern = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            pattern.push('"');
+                            chars.next();
+                        }
+                        Some('"') => break,
+                        Some(c) => pattern.push(c),
+                        None => return Err(format!("Unclosed quote in {}", text.trim())),
+                    }
+                }
+                tokens.push(Token::Counter(pattern));
+            }
+
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek() {
+                    let exponent_sign = (c == '+' || c == '-') && number.ends_with(['e', 'E']);
+                    if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign) {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                let value = number
+                    .parse()
+                    .map_err(|_| format!("Invalid number {} in {}", number, text.trim()))?;
+                tokens.push(Token::Number(value));
+            }
+
+            c if c.is_alphabetic() => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_alphanumeric() {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Name(name));
+            }
+
+            _ => return Err(format!("Unexpected {} in {}", c, text.trim())),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// expression = term (("+" | "-") term)*
+// term       = factor (("*" | "/") factor)*
+// factor     = number | counter | function "(" counter ")" | "(" expression ")"
+//            | "-" factor
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_symbol(&self, symbols: &[char]) -> Option<char> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(c)) if symbols.contains(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(c)) if c == symbol => Ok(()),
+            Some(token) => Err(format!("Expected {} but found {}", symbol, token)),
+            None => Err(format!("Expected {} at the end", symbol)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        let mut expr = self.term()?;
+        while let Some(op) = self.peek_symbol(&['+', '-']) {
+            self.position += 1;
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.factor()?;
+        while let Some(op) = self.peek_symbol(&['*', '/']) {
+            self.position += 1;
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.factor()?));
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Counter(pattern)) => Ok(Expr::Counter(pattern)),
+            Some(Token::Symbol('-')) => Ok(Expr::Negate(Box::new(self.factor()?))),
+            Some(Token::Symbol('(')) => {
+                let expr = self.expression()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(Token::Name(name)) => {
+                let aggregate = Aggregate::from_str(&name, true).map_err(|_| {
+                    format!(
+                        "Unknown function {}. Use avg, min, max, p95, count, or sum.",
+                        name
+                    )
+                })?;
+                self.expect('(')?;
+                let pattern = match self.next() {
+                    Some(Token::Counter(pattern)) => pattern,
+                    _ => return Err(format!("{} takes a quoted counter pattern", name)),
+                };
+                self.expect(')')?;
+                Ok(Expr::Function(aggregate, pattern))
+            }
+            Some(token) => Err(format!("Unexpected {}", token)),
+            None => Err("Expected a counter, number, or function at the end".to_string()),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    fs::File,
+    path::Path,
+    process::Command,
+    time::{Duration, SystemTime},
+};
+
+use crate::{cache::cache_file, status::progress};
+
+// Relogged traces not used for this long are removed, and the oldest are
+// removed while they take more than this much space.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const MAX_BYTES: u64 = 8 * 1024 * 1024 * 1024;
+
+// PDH can't read the counters perfmon writes into an .etl trace, but relog
+// can turn them into a .blg that it can. The .blg is kept under the user's
+// local app data, one for each trace, with the size and modified time of
+// the trace it was relogged from, so a trace is only relogged again when it
+// changes, like one still being written. Returns the path of the .blg.
+pub fn relog_etl(path: &str) -> Result<String, String> {
+    let metadata = std::fs::metadata(path).map_err(|e| e.to_string())?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let version = format!("{}|{}", metadata.len(), modified);
+
+    let output = cache_file("etl", path, "blg")
+        .ok_or("failed to create the directory for relogged traces")?;
+    let version_file = output.with_extension("version");
+    if output.exists() && std::fs::read_to_string(&version_file).is_ok_and(|v| v == version) {
+        // Marked as used, so it's the last to be removed.
+        let _ = File::options()
+            .write(true)
+            .open(&output)
+            .and_then(|f| f.set_modified(SystemTime::now()));
+        return Ok(output.display().to_string());
+    }
+
+    // Relog to another name first, so a relog that's interrupted doesn't
+    // leave a partial .blg to be reused.
+    let partial = output.with_extension("partial.blg");
+
+    progress!("Relogging {} to BLG...", path);
+    let result = Command::new("relog.exe")
+        .arg(path)
+        .args(["-f", "BIN", "-y", "-o"])
+        .arg(&partial)
+        .output()
+        .map_err(|e| format!("failed to run relog: {}", e))?;
+
+    if !result.status.success() || !partial.exists() {
+        // relog writes its errors to stdout.
+        let stdout = String::from_utf8_lossy(&result.stdout);
+        let message = stdout
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty())
+            .unwrap_or("no counters in the trace");
+        let _ = std::fs::remove_file(&partial);
+        return Err(format!("relog failed: {}", message));
+    }
+
+    // The .blg relogged from the trace before it changed is replaced.
+    let _ = std::fs::remove_file(&version_file);
+    std::fs::rename(&partial, &output).map_err(|e| e.to_string())?;
+    std::fs::write(&version_file, &version).map_err(|e| e.to_string())?;
+
+    if let Some(dir) = output.parent() {
+        prune(dir, &output);
+    }
+    Ok(output.display().to_string())
+}
+
+// Removes the relogged traces past MAX_AGE, then the least recently used
+// while they're over MAX_BYTES, except the one just relogged.
+fn prune(dir: &Path, keep: &Path) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let mut logs = entries
+        .flatten()
+        .map(|entry| entry.path())
+        // Relogs still running write to .partial.blg files, which are left
+        // alone.
+        .filter(|path| {
+            path.extension().is_some_and(|e| e == "blg")
+                && !path.to_string_lossy().ends_with(".partial.blg")
+                && path != keep
+        })
+        .filter_map(|path| {
+            let metadata = std::fs::metadata(&path).ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), path))
+        })
+        .collect::<Vec<(SystemTime, u64, std::path::PathBuf)>>();
+    logs.sort();
+
+    let now = SystemTime::now();
+    let mut total = std::fs::metadata(keep).map_or(0, |m| m.len())
+        + logs.iter().map(|(_, size, _)| size).sum::<u64>();
+    for (modified, size, path) in logs {
+        let expired = now.duration_since(modified).is_ok_and(|age| age > MAX_AGE);
+        if !expired && total <= MAX_BYTES {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            let _ = std::fs::remove_file(path.with_extension("version"));
+            total -= size;
+        }
+    }
+}
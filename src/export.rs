@@ -0,0 +1,755 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use flate2::write::GzEncoder;
+use time::{macros::format_description, Duration, OffsetDateTime, UtcOffset};
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    aggregate::aggregate_across,
+    align::align,
+    cli::{Compression, ExportArgs, ExportFormat, ExportLayout},
+    clipboard::set_clipboard_text,
+    derive::{evaluate_all, is_selected, read_selection},
+    filter::filter_samples,
+    influx::{post_influx, write_influx},
+    jsonl::write_jsonl,
+    log_files::{find_log_files, glob_log_files, open_log_files},
+    number_format::{format_number, set_number_format},
+    pdh_helper::{
+        bind_input_logfiles, get_filetime_from_raw, get_perflog_summary, invalid_status,
+        keep_invalid, read_raw_counter_values, set_keep_invalid, status_name, CounterValueWithTime,
+        RawCounterValue,
+    },
+    reader::{map_machines, read_counters, read_selected_counters, report_errors},
+    rename::{read_rename_file, rename_counters},
+    resample::{resample, rolling, Aggregate},
+    selection::{select_counters, CounterSelection},
+    series::{in_time_order, Series},
+    status::progress,
+    timespec::{display_offset, zone_label},
+    xlsx::write_xlsx,
+};
+
+// Anything bigger is better off in a file than pasted into a spreadsheet.
+const MAX_CLIPBOARD_BYTES: usize = 16 * 1024 * 1024;
+
+pub fn export(args: &ExportArgs) {
+    set_keep_invalid(args.keep_invalid);
+    set_number_format(args.number_format.number_format());
+
+    let selection = match args.counters.selection() {
+        Some(selection) => selection,
+        None => return,
+    };
+
+    let format = match args.format {
+        Some(format) => format,
+        None if args.clipboard => ExportFormat::Tsv,
+        None => ExportFormat::Csv,
+    };
+
+    // JSON lines have no separator.
+    let separator = match format {
+        ExportFormat::Csv => Some(','),
+        ExportFormat::Tsv => Some('\t'),
+        ExportFormat::Jsonl => None,
+        ExportFormat::Influx => return export_influx(args, &selection),
+        ExportFormat::Xlsx => return export_xlsx(args, &selection),
+    };
+
+    if args.influx_url.is_some() {
+        eprintln!("--influx-url needs --format influx.");
+        return;
+    }
+
+    if separator.is_none() && args.layout == ExportLayout::Long {
+        eprintln!(
+            "--layout long is for --format csv and tsv. JSON lines already have a line per sample."
+        );
+        return;
+    }
+
+    if separator.is_none() && (args.follow || args.clipboard) {
+        eprintln!("--format jsonl can't be used with --follow or --clipboard.");
+        return;
+    }
+
+    if separator.is_none() && args.max_columns.is_some() {
+        eprintln!("--max-columns is for --format csv and tsv.");
+        return;
+    }
+
+    if let (true, Some(separator)) = (args.follow, separator) {
+        follow(args, &selection, &mut create_writer(args), separator);
+        return;
+    }
+
+    let columns = if args.raw {
+        read_raw_columns(args, &selection)
+    } else {
+        read_columns(args, &selection)
+    };
+
+    let (columns, series) = match columns {
+        Some(columns) => columns,
+        None => return,
+    };
+
+    if columns.is_empty() {
+        eprintln!("No counters matched.");
+        return;
+    }
+
+    let separator = match separator {
+        Some(separator) => separator,
+        None => {
+            match write_jsonl(&mut create_writer(args), &columns, &series) {
+                Ok(lines) => progress!("Wrote {} lines.", lines),
+                Err(e) => eprintln!("Failed to write JSON lines: {}", e),
+            }
+            return;
+        }
+    };
+
+    if args.clipboard {
+        copy_to_clipboard(args.layout, &columns, &series, separator);
+        return;
+    }
+
+    if let (Some(max_columns), Some(output)) = (args.max_columns, &args.output) {
+        if args.layout != ExportLayout::Wide {
+            eprintln!("--max-columns is for --layout wide.");
+            return;
+        }
+        if let Err(e) =
+            write_column_chunks(output, max_columns as usize, &columns, &series, separator)
+        {
+            eprintln!("Failed to write {}: {}", output, e);
+        }
+        return;
+    }
+
+    write_layout(
+        &mut create_writer(args),
+        args.layout,
+        &columns,
+        &series,
+        separator,
+    )
+    .expect("Failed to write CSV");
+}
+
+// Line protocol needs the counter paths to split into measurements, tags,
+// and fields, so it can't be combined with the options that change columns.
+fn export_influx(args: &ExportArgs, selection: &CounterSelection) {
+    if args.raw
+        || args.resample.is_some()
+        || args.rolling.is_some()
+        || args.align.is_some()
+        || args.follow
+        || args.clipboard
+    {
+        eprintln!(
+            "--format influx can't be used with --raw, --resample, --rolling, --align, --follow, or --clipboard."
+        );
+        return;
+    }
+    if !args.derive.is_empty() || args.layout == ExportLayout::Long || args.keep_invalid {
+        eprintln!("--format influx can't be used with --derive, --layout long, or --keep-invalid.");
+        return;
+    }
+    if args.aggregate.aggregate.is_some() {
+        eprintln!("--format influx can't be used with --aggregate.");
+        return;
+    }
+    if !args.rename.is_empty() || args.rename_file.is_some() {
+        eprintln!("--format influx can't be used with --rename or --rename-file.");
+        return;
+    }
+
+    let mut counter_data = match read_counters(&args.source, selection) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    let time_filter = args.time_filter.time_filter();
+    let series = counter_data
+        .counters
+        .iter()
+        .map(|c| {
+            let samples = time_filter.apply(counter_data.samples.remove(c).unwrap_or_default());
+            filter_samples(samples, &args.filters)
+        })
+        .collect::<Vec<Series>>();
+
+    if let Some(url) = &args.influx_url {
+        if let Err(e) = post_influx(
+            url,
+            args.influx_token.as_deref(),
+            &counter_data.counters,
+            &series,
+        ) {
+            eprintln!();
+            eprintln!("{}", e);
+        }
+        return;
+    }
+
+    match write_influx(&mut create_writer(args), &counter_data.counters, &series) {
+        Ok(lines) => progress!("Wrote {} lines.", lines),
+        Err(e) => eprintln!("Failed to write line protocol: {}", e),
+    }
+}
+
+// A workbook is a zip file, which can't be streamed to stdout or compressed
+// again, and its sheets already hold the columns Excel can.
+fn export_xlsx(args: &ExportArgs, selection: &CounterSelection) {
+    let output = match &args.output {
+        Some(output) => output,
+        None => {
+            eprintln!("--format xlsx needs --output.");
+            return;
+        }
+    };
+    if args.follow || args.clipboard || args.compress.is_some() || args.max_columns.is_some() {
+        eprintln!(
+            "--format xlsx can't be used with --follow, --clipboard, --compress, or --max-columns."
+        );
+        return;
+    }
+    if args.layout == ExportLayout::Long || args.influx_url.is_some() {
+        eprintln!("--format xlsx can't be used with --layout long or --influx-url.");
+        return;
+    }
+
+    let columns = if args.raw {
+        read_raw_columns(args, selection)
+    } else {
+        read_columns(args, selection)
+    };
+
+    let (columns, series) = match columns {
+        Some(columns) => columns,
+        None => return,
+    };
+
+    if columns.is_empty() {
+        eprintln!("No counters matched.");
+        return;
+    }
+
+    match write_xlsx(Path::new(output), &columns, &series) {
+        Ok(charts) => progress!(
+            "Wrote {} counters and {} chart{} to {}.",
+            columns.len(),
+            charts,
+            if charts == 1 { "" } else { "s" },
+            output
+        ),
+        Err(e) => eprintln!("Failed to write {}: {}", output, e),
+    }
+}
+
+fn create_writer(args: &ExportArgs) -> Box<dyn Write> {
+    let writer: Box<dyn Write> = match &args.output {
+        Some(output) => Box::new(BufWriter::new(
+            File::create(output).expect("Failed to create output file"),
+        )),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    };
+
+    // Both encoders write their trailer when they're dropped.
+    match args.compress {
+        None => writer,
+        Some(Compression::Gzip) => Box::new(GzEncoder::new(writer, flate2::Compression::default())),
+        Some(Compression::Zstd) => Box::new(
+            zstd::Encoder::new(writer, 0)
+                .expect("Failed to start zstd compression")
+                .auto_finish(),
+        ),
+    }
+}
+
+// Reads the logs again every poll interval while a collector is still
+// writing them, and writes the samples newer than the last ones written, like
+// tail -f. Each read starts at the last sample written, which rate counters
+// need to calculate the first new one. The columns are the counters found on
+// the first read. Runs until interrupted.
+fn follow(
+    args: &ExportArgs,
+    selection: &CounterSelection,
+    writer: &mut dyn Write,
+    separator: char,
+) {
+    let time_filter = args.time_filter.time_filter();
+
+    let mut files = find_log_files(&args.source.glob_pattern);
+    let mut columns: Option<(Vec<String>, Vec<String>)> = None;
+    let mut last = None;
+
+    loop {
+        if !files.is_empty() {
+            // The summary cache is keyed by size and modified time, which
+            // change with every poll, so enumerate directly.
+            let hdatasource = bind_input_logfiles(files.clone());
+            let summary = get_perflog_summary(hdatasource);
+            let counter_data = map_machines(
+                read_selected_counters(hdatasource, &summary, selection, last),
+                &args.source,
+            );
+            unsafe { PdhCloseLog(hdatasource, 0) };
+
+            if columns.is_none() && !counter_data.counters.is_empty() {
+                let counters = counter_data.counters.clone();
+                let names = match column_names(args, &counters.iter().collect::<Vec<&String>>()) {
+                    Some(names) => names,
+                    None => return,
+                };
+                write_csv_header(writer, &names, separator).expect("Failed to write CSV");
+                columns = Some((counters, names));
+            }
+
+            if let Some((counters, _)) = &columns {
+                let series = counters
+                    .iter()
+                    .map(|c| {
+                        let samples = counter_data.samples.get(c).cloned().unwrap_or_default();
+                        samples.select(|i| {
+                            let time = samples.time(i);
+                            last.is_none_or(|last| time > last) && time_filter.matches(time)
+                        })
+                    })
+                    .collect::<Vec<Series>>();
+
+                if let Some(newest) = series
+                    .iter()
+                    .filter_map(|s| s.last())
+                    .map(|s| s.time())
+                    .max()
+                {
+                    last = Some(newest);
+                }
+
+                write_csv_rows(writer, counters.len(), &series, separator)
+                    .and_then(|_| writer.flush())
+                    .expect("Failed to write CSV");
+            }
+        }
+
+        std::thread::sleep(args.poll.unsigned_abs());
+        files = glob_log_files(&args.source.glob_pattern);
+    }
+}
+
+fn read_columns(
+    args: &ExportArgs,
+    selection: &CounterSelection,
+) -> Option<(Vec<String>, Vec<Series>)> {
+    let mut counter_data = read_counters(&args.source, &read_selection(selection, &args.derive))?;
+    let derived = evaluate_all(&counter_data, &args.derive)?;
+
+    let counters = counter_data
+        .counters
+        .iter()
+        .filter(|c| is_selected(selection, &args.derive, c))
+        .cloned()
+        .collect::<Vec<String>>();
+    let samples = counters
+        .iter()
+        .map(|c| counter_data.samples.remove(c).unwrap_or_default())
+        .collect::<Vec<Series>>();
+
+    // Renames apply to the combined series too.
+    let (counters, samples) = aggregate_across(counters, samples, &args.aggregate);
+
+    let mut names = column_names(args, &counters.iter().collect::<Vec<&String>>())?;
+    names.extend(args.derive.iter().map(|d| d.name.clone()));
+
+    let time_filter = args.time_filter.time_filter();
+
+    let series = samples
+        .into_iter()
+        .chain(derived)
+        .map(|mut samples| {
+            // Smooth first, so the windows at the start of the time range
+            // have the samples before it.
+            if let Some(window) = args.rolling {
+                samples = rolling(&samples, window, args.rolling_stat);
+            }
+            filter_samples(time_filter.apply(samples), &args.filters)
+        })
+        .collect::<Vec<Series>>();
+
+    match (args.resample, args.align) {
+        (Some(interval), _) => Some(resample_columns(&names, &series, interval, &args.stat)),
+        (None, Some(interval)) => Some((names, align(&series, interval, args.interpolate))),
+        (None, None) => Some((names, series)),
+    }
+}
+
+// The --rename and --rename-file aliases applied to the counter paths.
+fn column_names(args: &ExportArgs, counters: &[&String]) -> Option<Vec<String>> {
+    let mut renames = args.rename.clone();
+    if let Some(path) = &args.rename_file {
+        match read_rename_file(path) {
+            Ok(from_file) => renames.extend(from_file),
+            Err(e) => {
+                eprintln!("{}", e);
+                return None;
+            }
+        }
+    }
+
+    match rename_counters(counters, &renames) {
+        Ok(names) => Some(names),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+// Writes the raw FILETIME timestamp, first value, and second value of every
+// sample, plus the value calculated over --rate-window samples.
+fn read_raw_columns(
+    args: &ExportArgs,
+    selection: &CounterSelection,
+) -> Option<(Vec<String>, Vec<Series>)> {
+    let (hdatasource, summary) = open_log_files(&args.source.glob_pattern)?;
+
+    let counters = summary.get_all_counters();
+
+    let counters_to_read = select_counters(&counters, selection);
+
+    // Raw values are read without going through read_counters, so map the
+    // machines here. Counters mapped onto the same path can't be merged
+    // sample by sample and fail as duplicate names instead.
+    let paths = counters_to_read
+        .iter()
+        .map(|c| args.source.machine_path(c))
+        .collect::<Vec<String>>();
+
+    let names = match column_names(args, &paths.iter().collect::<Vec<&String>>()) {
+        Some(names) => names,
+        None => {
+            unsafe { PdhCloseLog(hdatasource, 0) };
+            return None;
+        }
+    };
+
+    let (mut counter_data, errors) = if counters_to_read.is_empty() {
+        (HashMap::new(), HashMap::new())
+    } else {
+        read_raw_counter_values(hdatasource, &counters_to_read, args.rate_window)
+    };
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+
+    report_errors(
+        &errors
+            .into_iter()
+            .map(|(counter, e)| (args.source.machine_path(&counter), e))
+            .collect(),
+    );
+
+    let time_filter = args.time_filter.time_filter();
+
+    let mut columns = Vec::new();
+    let mut series = Vec::new();
+
+    for (counter, name) in counters_to_read.into_iter().zip(names) {
+        let samples = counter_data
+            .remove(counter)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|s| time_filter.matches(s.time))
+            .collect::<Vec<RawCounterValue>>();
+
+        columns.push(format!("{}:timestamp", name));
+        series.push(
+            samples
+                .iter()
+                .map(|s| CounterValueWithTime::Large(s.time, get_filetime_from_raw(&s.raw)))
+                .collect(),
+        );
+
+        columns.push(format!("{}:first", name));
+        series.push(
+            samples
+                .iter()
+                .map(|s| CounterValueWithTime::Large(s.time, s.raw.FirstValue))
+                .collect(),
+        );
+
+        columns.push(format!("{}:second", name));
+        series.push(
+            samples
+                .iter()
+                .map(|s| CounterValueWithTime::Large(s.time, s.raw.SecondValue))
+                .collect(),
+        );
+
+        columns.push(format!("{}:rate", name));
+        series.push(
+            samples
+                .iter()
+                .filter_map(|s| {
+                    s.rate
+                        .map(|rate| CounterValueWithTime::Double(s.time, rate))
+                })
+                .collect(),
+        );
+    }
+
+    Some((columns, series))
+}
+
+fn resample_columns(
+    counters: &[String],
+    series: &[Series],
+    interval: Duration,
+    aggregates: &[Aggregate],
+) -> (Vec<String>, Vec<Series>) {
+    let mut columns = Vec::new();
+    let mut resampled = Vec::new();
+
+    for (counter, samples) in counters.iter().zip(series) {
+        for (aggregate, s) in aggregates
+            .iter()
+            .zip(resample(samples, interval, aggregates))
+        {
+            columns.push(format!("{}:{}", counter, aggregate.name()));
+            resampled.push(s);
+        }
+    }
+
+    (columns, resampled)
+}
+
+fn copy_to_clipboard(
+    layout: ExportLayout,
+    counters: &[String],
+    series: &[Series],
+    separator: char,
+) {
+    let mut buffer = Vec::new();
+    write_layout(&mut buffer, layout, counters, series, separator).expect("Failed to write CSV");
+
+    if buffer.len() > MAX_CLIPBOARD_BYTES {
+        eprintln!(
+            "Export is {} bytes, which is too large for the clipboard. Use --output instead.",
+            buffer.len()
+        );
+        return;
+    }
+
+    let text = String::from_utf8(buffer).expect("CSV output is not UTF-8");
+    let rows = text.lines().count().saturating_sub(1);
+
+    match set_clipboard_text(&text) {
+        Ok(()) => eprintln!("Copied {} rows to the clipboard.", rows),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// One row per timestamp, one column per counter. Counters with no sample at
+// a timestamp get an empty cell.
+pub fn write_csv(
+    writer: &mut dyn Write,
+    counters: &[String],
+    series: &[Series],
+    separator: char,
+) -> std::io::Result<()> {
+    write_csv_header(writer, counters, separator)?;
+    write_csv_rows(writer, counters.len(), series, separator)?;
+    writer.flush()
+}
+
+pub fn write_csv_header(
+    writer: &mut dyn Write,
+    counters: &[String],
+    separator: char,
+) -> std::io::Result<()> {
+    let offset = display_offset();
+    if offset == UtcOffset::UTC {
+        write!(writer, "\"Time\"")?;
+    } else {
+        write!(writer, "\"Time ({})\"", zone_label(offset))?;
+    }
+    for counter in counters {
+        write!(writer, "{}\"{}\"", separator, counter.replace('"', "\"\""))?;
+    }
+    writeln!(writer)
+}
+
+pub fn write_csv_rows(
+    writer: &mut dyn Write,
+    columns: usize,
+    series: &[Series],
+    separator: char,
+) -> std::io::Result<()> {
+    let mut rows = BTreeMap::<OffsetDateTime, Vec<Option<CounterValueWithTime>>>::new();
+    for (column, samples) in series.iter().enumerate() {
+        for sample in samples.iter() {
+            rows.entry(sample.time())
+                .or_insert_with(|| vec![None; columns])[column] = Some(sample);
+        }
+    }
+
+    for (time, values) in rows {
+        write!(writer, "{}", format_time(time))?;
+        for value in values {
+            write!(writer, "{}", separator)?;
+            if let Some(value) = value {
+                write_value(writer, &value)?;
+            }
+        }
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+// Writes the counters in files of at most max_columns columns after the
+// time, since Excel stops at 16,384 columns, and a manifest of which file
+// each counter went to. An output of perf.csv makes perf.1.csv, perf.2.csv,
+// and so on, and perf.manifest.csv.
+fn write_column_chunks(
+    output: &str,
+    max_columns: usize,
+    counters: &[String],
+    series: &[Series],
+    separator: char,
+) -> std::io::Result<()> {
+    let path = Path::new(output);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let chunk_path = |name: &str| path.with_file_name(format!("{}.{}{}", stem, name, extension));
+
+    let manifest_path = chunk_path("manifest");
+    let mut manifest = BufWriter::new(File::create(&manifest_path)?);
+    writeln!(manifest, "\"File\"{}\"Counter\"", separator)?;
+
+    let chunks = counters.chunks(max_columns).zip(series.chunks(max_columns));
+    let mut files = 0;
+    for (index, (counters, series)) in chunks.enumerate() {
+        let chunk_path = chunk_path(&(index + 1).to_string());
+        let mut writer = BufWriter::new(File::create(&chunk_path)?);
+        write_csv(&mut writer, counters, series, separator)?;
+
+        let file_name = chunk_path.file_name().unwrap_or_default().to_string_lossy();
+        for counter in counters {
+            writeln!(
+                manifest,
+                "\"{}\"{}\"{}\"",
+                file_name.replace('"', "\"\""),
+                separator,
+                counter.replace('"', "\"\"")
+            )?;
+        }
+        files += 1;
+    }
+    manifest.flush()?;
+
+    progress!(
+        "Wrote {} counters to {} files, listed in {}.",
+        counters.len(),
+        files,
+        manifest_path.display()
+    );
+    Ok(())
+}
+
+fn write_layout(
+    writer: &mut dyn Write,
+    layout: ExportLayout,
+    counters: &[String],
+    series: &[Series],
+    separator: char,
+) -> std::io::Result<()> {
+    match layout {
+        ExportLayout::Wide => write_csv(writer, counters, series, separator),
+        ExportLayout::Long => write_csv_long(writer, counters, series, separator),
+    }
+}
+
+// One row per sample with its time, counter, and value, in time order, which
+// is how databases and BI tools want to load it.
+pub fn write_csv_long(
+    writer: &mut dyn Write,
+    counters: &[String],
+    series: &[Series],
+    separator: char,
+) -> std::io::Result<()> {
+    let offset = display_offset();
+    if offset == UtcOffset::UTC {
+        write!(writer, "\"Time\"")?;
+    } else {
+        write!(writer, "\"Time ({})\"", zone_label(offset))?;
+    }
+    write!(writer, "{}\"Counter\"{}\"Value\"", separator, separator)?;
+    if keep_invalid() {
+        write!(writer, "{}\"Status\"", separator)?;
+    }
+    writeln!(writer)?;
+
+    let counters = counters
+        .iter()
+        .map(|c| format!("\"{}\"", c.replace('"', "\"\"")))
+        .collect::<Vec<String>>();
+
+    for (column, sample) in in_time_order(series) {
+        write!(
+            writer,
+            "{}{}{}{}",
+            format_time(sample.time()),
+            separator,
+            counters[column],
+            separator
+        )?;
+        write_value(writer, &sample)?;
+        if keep_invalid() {
+            let status = invalid_status(sample.value()).map(status_name);
+            write!(writer, "{}{}", separator, status.unwrap_or_default())?;
+        }
+        writeln!(writer)?;
+    }
+
+    writer.flush()
+}
+
+// Writes integers as integers so large raw values keep every digit.
+fn write_value(writer: &mut dyn Write, sample: &CounterValueWithTime) -> std::io::Result<()> {
+    match sample {
+        CounterValueWithTime::Long(_, value) => write!(writer, "{}", value),
+        CounterValueWithTime::Large(_, value) => write!(writer, "{}", value),
+        CounterValueWithTime::Double(_, value) => write!(writer, "{}", format_number(*value)),
+    }
+}
+
+// Formats in the --timezone offset without the offset itself, which is how
+// spreadsheets expect to see times.
+pub fn format_time(time: OffsetDateTime) -> String {
+    time.to_offset(display_offset())
+        .format(format_description!(
+            "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3]"
+        ))
+        .unwrap()
+}
+
+// RFC 3339 in the --timezone offset, for output read by other programs.
+pub fn format_time_with_offset(time: OffsetDateTime) -> String {
+    time.to_offset(display_offset())
+        .format(format_description!(
+            "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3][offset_hour sign:mandatory]:[offset_minute]"
+        ))
+        .unwrap()
+}
@@ -0,0 +1,209 @@
+use std::{str::FromStr, sync::OnceLock};
+
+use time::{Duration, OffsetDateTime, UtcOffset};
+
+use crate::{
+    series::Series,
+    timespec::{parse_duration, DaySet, HoursRange, TimeSpec},
+};
+
+#[derive(Clone, Copy)]
+pub enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+        }
+    }
+
+    pub fn compare(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Greater => value > threshold,
+            Comparison::GreaterOrEqual => value >= threshold,
+            Comparison::Less => value < threshold,
+            Comparison::LessOrEqual => value <= threshold,
+            Comparison::Equal => value == threshold,
+            Comparison::NotEqual => value != threshold,
+        }
+    }
+}
+
+// A filter like "value > 90" or "value > 90 for 5m". With a duration, only
+// runs of consecutive matching samples lasting at least that long are kept.
+#[derive(Clone)]
+pub struct SamplePredicate {
+    pub comparison: Comparison,
+    pub threshold: f64,
+    pub duration: Option<Duration>,
+}
+
+impl FromStr for SamplePredicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (condition, duration) = match s.split_once(" for ") {
+            Some((condition, duration)) => (condition, Some(parse_duration(duration)?)),
+            None => (s, None),
+        };
+
+        let condition = condition.trim();
+        let rest = condition
+            .strip_prefix("value")
+            .ok_or_else(|| format!("Expected a filter like \"value > 90\": {}", s))?
+            .trim_start();
+
+        let (comparison, threshold) = if let Some(t) = rest.strip_prefix(">=") {
+            (Comparison::GreaterOrEqual, t)
+        } else if let Some(t) = rest.strip_prefix("<=") {
+            (Comparison::LessOrEqual, t)
+        } else if let Some(t) = rest.strip_prefix("==") {
+            (Comparison::Equal, t)
+        } else if let Some(t) = rest.strip_prefix("!=") {
+            (Comparison::NotEqual, t)
+        } else if let Some(t) = rest.strip_prefix('>') {
+            (Comparison::Greater, t)
+        } else if let Some(t) = rest.strip_prefix('<') {
+            (Comparison::Less, t)
+        } else if let Some(t) = rest.strip_prefix('=') {
+            (Comparison::Equal, t)
+        } else {
+            return Err(format!("Unknown comparison in filter: {}", s));
+        };
+
+        let threshold = threshold
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid number in filter: {}", s))?;
+
+        Ok(SamplePredicate {
+            comparison,
+            threshold,
+            duration,
+        })
+    }
+}
+
+impl SamplePredicate {
+    fn matches(&self, samples: &Series) -> Vec<bool> {
+        let mut matches = samples
+            .values()
+            .map(|value| self.comparison.compare(value, self.threshold))
+            .collect::<Vec<bool>>();
+
+        let duration = match self.duration {
+            Some(duration) => duration,
+            None => return matches,
+        };
+
+        let mut run_start = 0;
+        while run_start < matches.len() {
+            if !matches[run_start] {
+                run_start += 1;
+                continue;
+            }
+
+            let mut run_end = run_start;
+            while run_end + 1 < matches.len() && matches[run_end + 1] {
+                run_end += 1;
+            }
+
+            if samples.time(run_end) - samples.time(run_start) < duration {
+                for m in &mut matches[run_start..=run_end] {
+                    *m = false;
+                }
+            }
+
+            run_start = run_end + 1;
+        }
+
+        matches
+    }
+}
+
+pub fn filter_samples(samples: Series, predicates: &[SamplePredicate]) -> Series {
+    if predicates.is_empty() {
+        return samples;
+    }
+
+    let mut keep = vec![true; samples.len()];
+    for predicate in predicates {
+        for (k, m) in keep.iter_mut().zip(predicate.matches(&samples)) {
+            *k = *k && m;
+        }
+    }
+
+    samples.select(|i| keep[i])
+}
+
+// Restricts samples to a time range, a time-of-day window, and a set of
+// weekdays. Sample times are UTC, so the time of day and weekday are
+// evaluated in the given offset.
+//
+// Start and end times relative to the logs are worked out the first time a
+// sample is checked, which is after the logs have been read.
+#[derive(Debug)]
+pub struct TimeFilter {
+    pub start: Option<TimeSpec>,
+    pub end: Option<TimeSpec>,
+    pub hours: Option<HoursRange>,
+    pub days: Option<DaySet>,
+    pub offset: UtcOffset,
+    pub range: OnceLock<(Option<OffsetDateTime>, Option<OffsetDateTime>)>,
+}
+
+impl TimeFilter {
+    pub fn matches(&self, time: OffsetDateTime) -> bool {
+        let (start, end) = self.range.get_or_init(|| {
+            (
+                self.start.and_then(|s| s.resolve(self.offset)),
+                self.end.and_then(|e| e.resolve(self.offset)),
+            )
+        });
+
+        if start.is_some_and(|start| time < start) {
+            return false;
+        }
+
+        if end.is_some_and(|end| time > end) {
+            return false;
+        }
+
+        let local = time.to_offset(self.offset);
+
+        if let Some(hours) = &self.hours {
+            if !hours.contains(local.time()) {
+                return false;
+            }
+        }
+
+        if let Some(days) = &self.days {
+            if !days.contains(local.weekday()) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn apply(&self, samples: Series) -> Series {
+        if self.start.is_none() && self.end.is_none() && self.hours.is_none() && self.days.is_none()
+        {
+            return samples;
+        }
+
+        samples.select(|i| self.matches(samples.time(i)))
+    }
+}
@@ -0,0 +1,34 @@
+use regex::RegexBuilder;
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{cli::FindArgs, log_files::open_log_files};
+
+pub fn find(args: &FindArgs) {
+    let regex = match RegexBuilder::new(&args.regex)
+        .case_insensitive(args.ignore_case)
+        .build()
+    {
+        Ok(regex) => regex,
+        Err(e) => {
+            eprintln!("Invalid regular expression: {}", e);
+            return;
+        }
+    };
+
+    let (hdatasource, summary) = match open_log_files(&args.glob_pattern) {
+        Some(opened) => opened,
+        None => return,
+    };
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+
+    let mut matches = 0;
+    for counter in summary.get_all_counters() {
+        if regex.is_match(&counter) {
+            println!("{}", counter);
+            matches += 1;
+        }
+    }
+
+    eprintln!("{} matching counters.", matches);
+}
@@ -0,0 +1,236 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::{
+    cli::GrafanaArgs,
+    counter_path::CounterPath,
+    export::format_time_with_offset,
+    influx::{post_influx, write_influx},
+    reader::read_counters,
+    report::json,
+    series::Series,
+    status::progress,
+    timespec::display_offset,
+};
+
+const DASHBOARD_FILE: &str = "dashboard.json";
+
+const DATA_FILE: &str = "perf.lp";
+
+// Panels are laid out two to a row.
+const PANEL_WIDTH: usize = 12;
+const PANEL_HEIGHT: usize = 8;
+
+// Writes a Grafana dashboard of the counters, reading from InfluxDB, and the
+// samples for it in line protocol, or posts them with --influx-url. Each
+// counter gets a panel with a line for each of its instances, so a wildcard
+// counter like \Process(*)\% Processor Time is one panel rather than
+// hundreds.
+pub fn grafana(args: &GrafanaArgs) {
+    let selection = match args.counters.selection() {
+        Some(selection) => selection,
+        None => return,
+    };
+
+    let mut counter_data = match read_counters(&args.source, &selection) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    let time_filter = args.time_filter.time_filter();
+    let series = counter_data
+        .counters
+        .iter()
+        .map(|c| time_filter.apply(counter_data.samples.remove(c).unwrap_or_default()))
+        .collect::<Vec<Series>>();
+
+    let times = series
+        .iter()
+        .filter(|s| !s.is_empty())
+        .map(|s| (s.time(0), s.time(s.len() - 1)))
+        .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)));
+    let (start, end) = match times {
+        Some(times) => times,
+        None => {
+            eprintln!("No samples matched.");
+            return;
+        }
+    };
+
+    let panels = panels(&counter_data.counters);
+    if panels.is_empty() {
+        eprintln!("None of the counters are full paths that can be written to InfluxDB.");
+        return;
+    }
+
+    if let Err(e) = std::fs::create_dir_all(&args.out) {
+        eprintln!("Failed to create {}: {}", args.out, e);
+        return;
+    }
+
+    let title = args
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("perflogtool: {}", args.source.glob_pattern));
+    let dashboard_path = Path::new(&args.out).join(DASHBOARD_FILE);
+    let written = File::create(&dashboard_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write_dashboard(&mut writer, &title, &args.bucket, start, end, &panels)?;
+        writer.flush()
+    });
+    if let Err(e) = written {
+        eprintln!("Failed to write {}: {}", dashboard_path.display(), e);
+        return;
+    }
+    progress!(
+        "Wrote {} panel{} to {}.",
+        panels.len(),
+        if panels.len() == 1 { "" } else { "s" },
+        dashboard_path.display()
+    );
+
+    if let Some(url) = &args.influx_url {
+        if let Err(e) = post_influx(
+            url,
+            args.influx_token.as_deref(),
+            &counter_data.counters,
+            &series,
+        ) {
+            eprintln!();
+            eprintln!("{}", e);
+        }
+        return;
+    }
+
+    let data_path = Path::new(&args.out).join(DATA_FILE);
+    let written = File::create(&data_path)
+        .and_then(|file| write_influx(&mut BufWriter::new(file), &counter_data.counters, &series));
+    match written {
+        Ok(lines) => progress!(
+            "Wrote {} lines to {}. Load them with: influx write --bucket {} --file {}",
+            lines,
+            data_path.display(),
+            args.bucket,
+            data_path.display()
+        ),
+        Err(e) => eprintln!("Failed to write {}: {}", data_path.display(), e),
+    }
+}
+
+// The counters with their instances as *, in the order they first appear,
+// which is the measurement, tags, and field their samples are written with.
+fn panels(counters: &[String]) -> Vec<CounterPath> {
+    let mut panels = Vec::<CounterPath>::new();
+    for counter in counters {
+        let mut path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => continue,
+        };
+        if path.instance.is_some() {
+            path.instance = Some("*".to_string());
+        }
+        if !panels.contains(&path) {
+            panels.push(path);
+        }
+    }
+    panels
+}
+
+// Picks the InfluxDB data source when the dashboard is imported, and reads
+// the bucket from a variable that can be changed afterwards. The time range
+// is the samples'.
+fn write_dashboard(
+    writer: &mut dyn Write,
+    title: &str,
+    bucket: &str,
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+    panels: &[CounterPath],
+) -> std::io::Result<()> {
+    let timezone = if display_offset() == UtcOffset::UTC {
+        "utc"
+    } else {
+        "browser"
+    };
+
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  \"title\": {},", json(title))?;
+    writeln!(writer, "  \"tags\": [\"perflogtool\"],")?;
+    writeln!(writer, "  \"timezone\": \"{}\",", timezone)?;
+    writeln!(writer, "  \"schemaVersion\": 38,")?;
+    writeln!(
+        writer,
+        "  \"time\": {{ \"from\": {}, \"to\": {} }},",
+        json(&format_time_with_offset(start)),
+        json(&format_time_with_offset(end))
+    )?;
+    writeln!(writer, "  \"templating\": {{ \"list\": [")?;
+    writeln!(
+        writer,
+        "    {{ \"name\": \"datasource\", \"label\": \"InfluxDB\", \"type\": \"datasource\", \"query\": \"influxdb\" }},"
+    )?;
+    writeln!(
+        writer,
+        "    {{ \"name\": \"bucket\", \"label\": \"Bucket\", \"type\": \"textbox\", \"query\": {}, \"current\": {{ \"text\": {}, \"value\": {} }} }}",
+        json(bucket),
+        json(bucket),
+        json(bucket)
+    )?;
+    writeln!(writer, "  ] }},")?;
+    writeln!(writer, "  \"panels\": [")?;
+
+    for (index, path) in panels.iter().enumerate() {
+        let x = (index % 2) * PANEL_WIDTH;
+        let y = (index / 2) * PANEL_HEIGHT;
+        writeln!(
+            writer,
+            "    {{ \"id\": {}, \"type\": \"timeseries\", \"title\": {}, \"gridPos\": {{ \"x\": {}, \"y\": {}, \"w\": {}, \"h\": {} }},",
+            index + 1,
+            json(&path.to_string()),
+            x,
+            y,
+            PANEL_WIDTH,
+            PANEL_HEIGHT
+        )?;
+        writeln!(
+            writer,
+            "      \"datasource\": {{ \"type\": \"influxdb\", \"uid\": \"${{datasource}}\" }},"
+        )?;
+        writeln!(
+            writer,
+            "      \"targets\": [{{ \"refId\": \"A\", \"query\": {} }}] }}{}",
+            json(&flux_query(path)),
+            if index + 1 < panels.len() { "," } else { "" }
+        )?;
+    }
+
+    writeln!(writer, "  ]")?;
+    writeln!(writer, "}}")
+}
+
+// The samples of a counter on the dashboard's time range, averaged down to
+// the points the panel has room for, with a line per machine and instance.
+fn flux_query(path: &CounterPath) -> String {
+    let mut filter = format!(
+        "r._measurement == {} and r._field == {}",
+        flux_string(&path.object),
+        flux_string(&path.counter)
+    );
+    if !path.machine.is_empty() {
+        filter.push_str(&format!(" and r.machine == {}", flux_string(&path.machine)));
+    }
+
+    format!(
+        "from(bucket: \"${{bucket}}\")\n  |> range(start: v.timeRangeStart, stop: v.timeRangeStop)\n  |> filter(fn: (r) => {})\n  |> aggregateWindow(every: v.windowPeriod, fn: mean, createEmpty: false)",
+        filter
+    )
+}
+
+fn flux_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,274 @@
+use std::{collections::HashSet, io::IsTerminal};
+
+use time::{Duration, OffsetDateTime, UtcOffset};
+
+use crate::{
+    chart::write_heatmap,
+    cli::HeatmapArgs,
+    counter_path::CounterPath,
+    plot::{format_axis_value, terminal_width, time_axis, time_range, value_range, Series},
+    reader::read_counters,
+    selection::CounterSelection,
+    status::progress,
+    timespec::zone_label,
+};
+
+// The most characters of an instance name to print in front of its row.
+const MAX_LABEL_WIDTH: usize = 30;
+
+// Viridis, which reads from low to high even in grayscale.
+const GRADIENT: [(u8, u8, u8); 5] = [
+    (68, 1, 84),
+    (59, 82, 139),
+    (33, 145, 140),
+    (94, 201, 98),
+    (253, 231, 37),
+];
+
+const SHADES: [char; 4] = ['░', '▒', '▓', '█'];
+
+// Samples further apart than this many of a row's usual intervals are a gap
+// in the log, which is left empty rather than filled.
+const MAX_GAP_INTERVALS: i32 = 3;
+
+// A row per instance of a wildcard counter and a column per slice of time,
+// colored by the average in the slice. A hundred processes are unreadable as
+// lines on one chart, but the few that were busy, and when, stand out here.
+pub fn heatmap(args: &HeatmapArgs) {
+    let counter_data = match read_counters(
+        &args.source,
+        &CounterSelection::new(std::slice::from_ref(&args.counter)),
+    ) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    let time_filter = args.time_filter.time_filter();
+
+    let mut rows = counter_data
+        .counters
+        .iter()
+        .filter(|c| args.include_total || !is_total(c))
+        .map(|c| Series {
+            name: c.clone(),
+            points: counter_data.samples[c]
+                .iter()
+                .filter(|s| time_filter.matches(s.time()))
+                .map(|s| (s.time(), s.value()))
+                .collect(),
+        })
+        .filter(|s| !s.points.is_empty())
+        .collect::<Vec<Series>>();
+
+    if rows.is_empty() {
+        eprintln!("No samples matched.");
+        return;
+    }
+
+    // Busiest first, so --top keeps the instances worth looking at.
+    let average = |s: &Series| s.points.iter().map(|p| p.1).sum::<f64>() / s.points.len() as f64;
+    rows.sort_by(|a, b| average(b).total_cmp(&average(a)));
+    if let Some(top) = args.top {
+        rows.truncate(top);
+    }
+    label_rows(&mut rows);
+
+    let (min, max) = value_range(&rows, &[]);
+    let scale = (args.min.unwrap_or(min), args.max.unwrap_or(max));
+
+    if let Some(output) = &args.output {
+        match write_heatmap(output, &rows, scale, args.size, time_filter.offset) {
+            Ok(()) => progress!("Wrote {}", output),
+            Err(e) => eprintln!("Failed to write {}: {}", output, e),
+        }
+        return;
+    }
+
+    let width = args.width.unwrap_or_else(terminal_width);
+    let color = std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal();
+    print!(
+        "{}",
+        render_heatmap(&rows, width, scale, time_filter.offset, color)
+    );
+}
+
+fn is_total(counter: &str) -> bool {
+    CounterPath::parse(counter)
+        .and_then(|path| path.instance)
+        .is_some_and(|instance| instance == "_Total" || instance == "Idle")
+}
+
+// Names each row by its instance, unless that leaves two rows with the same
+// name, like the same process on two machines.
+fn label_rows(rows: &mut [Series]) {
+    let labels = rows
+        .iter()
+        .map(|s| {
+            CounterPath::parse(&s.name)
+                .and_then(|path| path.instance)
+                .unwrap_or_else(|| s.name.clone())
+        })
+        .collect::<Vec<String>>();
+
+    if labels.iter().collect::<HashSet<&String>>().len() == labels.len() {
+        for (row, label) in rows.iter_mut().zip(labels) {
+            row.name = label;
+        }
+    }
+}
+
+// The average of each row in each of columns equal slices of the time range,
+// or None where a row has no samples. When there are more columns than
+// samples, a sample also fills the empty columns up to the next one, unless
+// the two are further apart than a few of the row's usual intervals.
+pub fn heatmap_cells(rows: &[Series], columns: usize) -> Vec<Vec<Option<f64>>> {
+    let (start, end) = time_range(rows);
+    let span = (end - start).as_seconds_f64();
+    let column_of = |time: OffsetDateTime| {
+        let column = if span > 0.0 {
+            ((time - start).as_seconds_f64() / span * columns as f64) as usize
+        } else {
+            0
+        };
+        column.min(columns - 1)
+    };
+
+    rows.iter()
+        .map(|row| {
+            let mut sums = vec![(0.0, 0); columns];
+            for (time, value) in &row.points {
+                let cell = &mut sums[column_of(*time)];
+                cell.0 += value;
+                cell.1 += 1;
+            }
+            let mut cells = sums
+                .into_iter()
+                .map(|(sum, count)| (count > 0).then(|| sum / count as f64))
+                .collect::<Vec<Option<f64>>>();
+
+            let mut gaps = row
+                .points
+                .windows(2)
+                .map(|w| w[1].0 - w[0].0)
+                .collect::<Vec<Duration>>();
+            gaps.sort();
+            if let Some(usual) = gaps.get(gaps.len() / 2) {
+                for pair in row.points.windows(2) {
+                    if pair[1].0 - pair[0].0 > *usual * MAX_GAP_INTERVALS {
+                        continue;
+                    }
+                    let (from, to) = (column_of(pair[0].0), column_of(pair[1].0));
+                    for column in from + 1..to {
+                        cells[column] = cells[column].or(cells[from]);
+                    }
+                }
+            }
+
+            cells
+        })
+        .collect()
+}
+
+// Where value falls between min and max, from 0 to 1.
+pub fn heat_fraction(value: f64, (min, max): (f64, f64)) -> f64 {
+    if max > min {
+        ((value - min) / (max - min)).clamp(0.0, 1.0)
+    } else {
+        0.5
+    }
+}
+
+pub fn heat_color(fraction: f64) -> (u8, u8, u8) {
+    let position = fraction.clamp(0.0, 1.0) * (GRADIENT.len() - 1) as f64;
+    let index = (position as usize).min(GRADIENT.len() - 2);
+    let t = position - index as f64;
+
+    let (from, to) = (GRADIENT[index], GRADIENT[index + 1]);
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+// Colors each cell's background, or without color shades it with a block
+// character, so the terminal needs no particular font.
+fn render_heatmap(
+    rows: &[Series],
+    width: usize,
+    scale: (f64, f64),
+    offset: UtcOffset,
+    color: bool,
+) -> String {
+    let label_width = rows
+        .iter()
+        .map(|r| r.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(MAX_LABEL_WIDTH);
+    let columns = width.saturating_sub(label_width + 1).max(10);
+    let cells = heatmap_cells(rows, columns);
+
+    let cell = |value: Option<f64>| match value {
+        None => " ".to_string(),
+        Some(value) => shade(heat_fraction(value, scale), color),
+    };
+
+    let mut output = String::new();
+    for (row, row_cells) in rows.iter().zip(&cells) {
+        output.push_str(&format!(
+            "{:>width$} ",
+            truncate(&row.name, label_width),
+            width = label_width
+        ));
+        for value in row_cells {
+            output.push_str(&cell(*value));
+        }
+        output.push('\n');
+    }
+
+    let (start, end): (OffsetDateTime, OffsetDateTime) = time_range(rows);
+    let (axis, labels) = time_axis(start, end, columns, offset);
+    output.push_str(&format!("{:>width$} {}\n", "", axis, width = label_width));
+    output.push_str(&format!("{:>width$} {}\n", "", labels, width = label_width));
+
+    if offset != UtcOffset::UTC {
+        output.push_str(&format!(
+            "{:>width$} Times are {}\n",
+            "",
+            zone_label(offset),
+            width = label_width
+        ));
+    }
+
+    let legend = (0..=10)
+        .map(|i| shade(i as f64 / 10.0, color))
+        .collect::<String>();
+    output.push_str(&format!(
+        "\n{} {} {}\n",
+        format_axis_value(scale.0),
+        legend,
+        format_axis_value(scale.1)
+    ));
+
+    output
+}
+
+fn shade(fraction: f64, color: bool) -> String {
+    if color {
+        let (r, g, b) = heat_color(fraction);
+        format!("\x1b[48;2;{};{};{}m \x1b[0m", r, g, b)
+    } else {
+        let index = (fraction * SHADES.len() as f64) as usize;
+        SHADES[index.min(SHADES.len() - 1)].to_string()
+    }
+}
+
+// Keeps the end of a long name, which is usually the part that differs.
+fn truncate(name: &str, width: usize) -> String {
+    let length = name.chars().count();
+    if length <= width {
+        return name.to_string();
+    }
+    format!(
+        "…{}",
+        name.chars().skip(length - width + 1).collect::<String>()
+    )
+}
@@ -0,0 +1,155 @@
+use std::io::Write;
+
+use crate::{
+    counter_path::CounterPath,
+    http,
+    number_format::format_number,
+    series::Series,
+    status::{progress, quiet},
+};
+
+// InfluxDB suggests batches of about 5000 lines.
+const BATCH_LINES: usize = 5000;
+
+// Writes one line per sample, with the object as the measurement, the
+// machine and instance as tags, and the counter as the field. Samples that
+// aren't finite numbers can't be written and are skipped.
+pub fn write_influx(
+    writer: &mut dyn Write,
+    counters: &[String],
+    series: &[Series],
+) -> std::io::Result<usize> {
+    let mut lines = 0;
+    for (counter, samples) in counters.iter().zip(series) {
+        let prefix = match line_prefix(counter) {
+            Some(prefix) => prefix,
+            None => continue,
+        };
+        for sample in samples.iter().filter(|s| s.value().is_finite()) {
+            writeln!(
+                writer,
+                "{}{} {}",
+                prefix,
+                format_number(sample.value()),
+                sample.time().unix_timestamp_nanos()
+            )?;
+            lines += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(lines)
+}
+
+// Posts the lines to an InfluxDB write endpoint in batches. The URL is the
+// full write URL, like http://localhost:8086/api/v2/write?org=o&bucket=b,
+// and the token, if any, is sent as an InfluxDB token.
+pub fn post_influx(
+    url: &str,
+    token: Option<&str>,
+    counters: &[String],
+    series: &[Series],
+) -> Result<usize, String> {
+    let url = if url.contains("precision=") {
+        url.to_string()
+    } else if url.contains('?') {
+        format!("{}&precision=ns", url)
+    } else {
+        format!("{}?precision=ns", url)
+    };
+
+    let mut poster = BatchPoster {
+        url,
+        headers: token
+            .map(|t| vec![("Authorization", format!("Token {}", t))])
+            .unwrap_or_default(),
+        batch: Vec::new(),
+        batch_lines: 0,
+        posted: 0,
+    };
+
+    let lines = write_influx(&mut poster, counters, series).map_err(|e| e.to_string())?;
+    progress!("\rWrote {} lines.", lines);
+
+    Ok(lines)
+}
+
+// Collects lines and posts them every BATCH_LINES lines and when flushed.
+struct BatchPoster {
+    url: String,
+    headers: Vec<(&'static str, String)>,
+    batch: Vec<u8>,
+    batch_lines: usize,
+    posted: usize,
+}
+
+impl BatchPoster {
+    // Posts the first end bytes of the batch, which must end with a line.
+    fn post(&mut self, end: usize) -> std::io::Result<()> {
+        let lines = self.batch[..end].iter().filter(|b| **b == b'\n').count();
+        http::post(
+            &self.url,
+            "text/plain; charset=utf-8",
+            &self.headers,
+            &self.batch[..end],
+        )
+        .map_err(std::io::Error::other)?;
+
+        self.posted += lines;
+        self.batch_lines -= lines;
+        self.batch.drain(..end);
+        if !quiet() {
+            eprint!("\rWrote {} lines.", self.posted);
+        }
+        Ok(())
+    }
+}
+
+impl Write for BatchPoster {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.batch.extend_from_slice(buf);
+        self.batch_lines += buf.iter().filter(|b| **b == b'\n').count();
+
+        if self.batch_lines >= BATCH_LINES {
+            if let Some(last) = self.batch.iter().rposition(|b| *b == b'\n') {
+                self.post(last + 1)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        self.post(self.batch.len())
+    }
+}
+
+// "measurement,machine=M,instance=I field=" for the counter.
+fn line_prefix(counter: &str) -> Option<String> {
+    let path = CounterPath::parse(counter)?;
+
+    let mut prefix = escape(&path.object, &[',', ' ']);
+    if !path.machine.is_empty() {
+        prefix.push_str(&format!(",machine={}", escape(&path.machine, TAG_SPECIAL)));
+    }
+    if let Some(instance) = path.instance.filter(|i| !i.is_empty()) {
+        prefix.push_str(&format!(",instance={}", escape(&instance, TAG_SPECIAL)));
+    }
+    prefix.push_str(&format!(" {}=", escape(&path.counter, TAG_SPECIAL)));
+
+    Some(prefix)
+}
+
+const TAG_SPECIAL: &[char] = &[',', '=', ' '];
+
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
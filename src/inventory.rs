@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    cli::InventoryDiffArgs, log_files::open_log_files, pdh_helper::PerfLogSummary, status::progress,
+};
+
+// The names in an object, keyed by their lowercase form since PDH doesn't
+// care about case, with the name as the first log has it.
+struct Inventory {
+    name: String,
+    counters: BTreeMap<String, String>,
+    instances: BTreeMap<String, String>,
+}
+
+// Lists the objects, counters, and instances of the expected logs that the
+// actual logs don't have, and the other way around. The counters and
+// instances of an object that's missing entirely aren't listed again.
+pub fn inventory_diff(args: &InventoryDiffArgs) {
+    progress!("Reading the expected logs...");
+    let expected = match read_inventory(&args.expected, args.by_machine) {
+        Some(expected) => expected,
+        None => return,
+    };
+
+    progress!("Reading the actual logs...");
+    let actual = match read_inventory(&args.actual, args.by_machine) {
+        Some(actual) => actual,
+        None => return,
+    };
+
+    let missing = differences(&expected, &actual, args.ignore_instances);
+    let extra = differences(&actual, &expected, args.ignore_instances);
+
+    print_section("Missing", "expected but not in the actual logs", &missing);
+    print_section("Extra", "in the actual logs but not expected", &extra);
+
+    let count = |d: &Differences| d.objects.len() + d.counters.len() + d.instances.len();
+    eprintln!("{} missing, {} extra.", count(&missing), count(&extra));
+}
+
+fn read_inventory(glob_pattern: &str, by_machine: bool) -> Option<BTreeMap<String, Inventory>> {
+    let (hdatasource, summary) = open_log_files(glob_pattern)?;
+    unsafe { PdhCloseLog(hdatasource, 0) };
+
+    Some(inventory(&summary, by_machine))
+}
+
+// Objects are named like \Processor, or \\MACHINE\Processor by machine.
+fn inventory(summary: &PerfLogSummary, by_machine: bool) -> BTreeMap<String, Inventory> {
+    let mut objects = BTreeMap::<String, Inventory>::new();
+
+    for machine in &summary.machines {
+        for object in &machine.objects {
+            let name = if by_machine {
+                format!("{}\\{}", machine.name, object.name)
+            } else {
+                format!("\\{}", object.name)
+            };
+
+            let inventory = objects.entry(name.to_lowercase()).or_insert(Inventory {
+                name,
+                counters: BTreeMap::new(),
+                instances: BTreeMap::new(),
+            });
+
+            for counter in &object.counters {
+                inventory
+                    .counters
+                    .entry(counter.to_lowercase())
+                    .or_insert(counter.clone());
+            }
+            for instance in &object.instances {
+                inventory
+                    .instances
+                    .entry(instance.to_lowercase())
+                    .or_insert(instance.clone());
+            }
+        }
+    }
+
+    objects
+}
+
+#[derive(Default)]
+struct Differences {
+    objects: Vec<String>,
+    counters: Vec<String>,
+    instances: Vec<String>,
+}
+
+// What's in from but not in to.
+fn differences(
+    from: &BTreeMap<String, Inventory>,
+    to: &BTreeMap<String, Inventory>,
+    ignore_instances: bool,
+) -> Differences {
+    let mut differences = Differences::default();
+
+    for (key, object) in from {
+        let other = match to.get(key) {
+            Some(other) => other,
+            None => {
+                differences.objects.push(object.name.clone());
+                continue;
+            }
+        };
+
+        for (key, counter) in &object.counters {
+            if !other.counters.contains_key(key) {
+                differences
+                    .counters
+                    .push(format!("{}\\{}", object.name, counter));
+            }
+        }
+
+        if ignore_instances {
+            continue;
+        }
+        for (key, instance) in &object.instances {
+            if !other.instances.contains_key(key) {
+                differences
+                    .instances
+                    .push(format!("{}({})", object.name, instance));
+            }
+        }
+    }
+
+    differences
+}
+
+fn print_section(title: &str, description: &str, differences: &Differences) {
+    let lists = [
+        ("objects", &differences.objects),
+        ("counters", &differences.counters),
+        ("instances", &differences.instances),
+    ];
+
+    for (kind, list) in lists {
+        if list.is_empty() {
+            continue;
+        }
+        println!("{} {}, {}:", title, kind, description);
+        for name in list {
+            println!("  {}", name);
+        }
+        println!();
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::Write;
+
+use crate::{
+    counter_path::CounterPath,
+    export::format_time_with_offset,
+    number_format::format_number,
+    pdh_helper::{invalid_status, status_name, CounterValueWithTime},
+    report::json,
+    series::{in_time_order, Series},
+};
+
+// Writes one JSON object per sample, in time order, for piping into
+// ConvertFrom-Json. Counters that are full paths also get their machine,
+// object, instance, and counter name as properties, and samples kept by
+// --keep-invalid get the reason they have no value. Returns the number of
+// lines written.
+pub fn write_jsonl(
+    writer: &mut dyn Write,
+    counters: &[String],
+    series: &[Series],
+) -> std::io::Result<usize> {
+    let prefixes = counters.iter().map(|c| prefix(c)).collect::<Vec<String>>();
+
+    let mut lines = 0;
+    for (column, sample) in in_time_order(series) {
+        let status = match invalid_status(sample.value()) {
+            Some(status) => format!(",\"Status\":{}", json(&status_name(status))),
+            None => String::new(),
+        };
+        writeln!(
+            writer,
+            "{{\"Time\":{},{},\"Value\":{}{}}}",
+            json(&format_time_with_offset(sample.time())),
+            prefixes[column],
+            value(&sample),
+            status
+        )?;
+        lines += 1;
+    }
+
+    writer.flush()?;
+    Ok(lines)
+}
+
+fn prefix(counter: &str) -> String {
+    let mut prefix = format!("\"Counter\":{}", json(counter));
+
+    if let Some(path) = CounterPath::parse(counter) {
+        prefix.push_str(&format!(
+            ",\"Machine\":{},\"Object\":{},\"Instance\":{},\"Name\":{}",
+            json(&path.machine),
+            json(&path.object),
+            path.instance
+                .as_deref()
+                .map(json)
+                .unwrap_or("null".to_string()),
+            json(&path.counter)
+        ));
+    }
+
+    prefix
+}
+
+// JSON has no NaN or infinity, so those are null.
+fn value(sample: &CounterValueWithTime) -> String {
+    match sample {
+        CounterValueWithTime::Long(_, value) => value.to_string(),
+        CounterValueWithTime::Large(_, value) => value.to_string(),
+        CounterValueWithTime::Double(_, value) if value.is_finite() => format_number(*value),
+        CounterValueWithTime::Double(..) => "null".to_string(),
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeSet;
+
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    cli::{ListInstancesArgs, ListMachinesArgs, ListObjectsArgs},
+    log_files::find_log_files,
+    pdh_helper::{
+        bind_input_logfiles, detail_level, enum_machines, enum_object_items, enum_objects,
+    },
+};
+
+// These make only the PDH calls needed to answer them instead of listing
+// every counter of every object like summary, which takes minutes on big
+// logs.
+pub fn list_machines(args: &ListMachinesArgs) {
+    let hdatasource = match bind(&args.glob_pattern) {
+        Some(hdatasource) => hdatasource,
+        None => return,
+    };
+
+    for machine in enum_machines(hdatasource) {
+        println!("{}", machine);
+    }
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+}
+
+pub fn list_objects(args: &ListObjectsArgs) {
+    let hdatasource = match bind(&args.glob_pattern) {
+        Some(hdatasource) => hdatasource,
+        None => return,
+    };
+
+    let objects = enum_machines(hdatasource)
+        .iter()
+        .flat_map(|machine| enum_objects(machine, hdatasource, detail_level()))
+        .collect::<BTreeSet<String>>();
+
+    for object in objects {
+        println!("{}", object);
+    }
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+}
+
+pub fn list_instances(args: &ListInstancesArgs) {
+    let hdatasource = match bind(&args.glob_pattern) {
+        Some(hdatasource) => hdatasource,
+        None => return,
+    };
+
+    let mut found = false;
+    let mut instances = BTreeSet::new();
+    for machine in enum_machines(hdatasource) {
+        // Find the name as the log has it, so the object can be given in any
+        // case.
+        let object = match enum_objects(&machine, hdatasource, detail_level())
+            .into_iter()
+            .find(|o| o.eq_ignore_ascii_case(&args.object))
+        {
+            Some(object) => object,
+            None => continue,
+        };
+
+        if let Some((_, object_instances)) =
+            enum_object_items(&machine, &object, hdatasource, detail_level())
+        {
+            found = true;
+            instances.extend(object_instances);
+        }
+    }
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+
+    if !found {
+        eprintln!("No object named {} in the logs.", args.object);
+        return;
+    }
+
+    if instances.is_empty() {
+        eprintln!("{} has no instances.", args.object);
+    }
+
+    for instance in instances {
+        println!("{}", instance);
+    }
+}
+
+fn bind(glob_pattern: &str) -> Option<isize> {
+    let files = find_log_files(glob_pattern);
+
+    if files.is_empty() {
+        return None;
+    }
+
+    Some(bind_input_logfiles(files))
+}
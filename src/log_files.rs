@@ -0,0 +1,269 @@
+use std::{fs::File, io::Read, path::Path, sync::OnceLock, time::SystemTime};
+
+use clap::ValueEnum;
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    archive::{extract_logs, is_archive},
+    cache::cached_summary,
+    etl::relog_etl,
+    pdh_helper::{
+        bind_input_logfiles, get_time_info, status_name, try_bind_input_logfiles, PerfLogSummary,
+    },
+    status::{progress, record, Outcome},
+    units::Unit,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Binary,
+    Csv,
+    Tsv,
+    Etl,
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LogFormat::Binary => write!(f, "BLG"),
+            LogFormat::Csv => write!(f, "CSV"),
+            LogFormat::Tsv => write!(f, "TSV"),
+            LogFormat::Etl => write!(f, "ETL"),
+        }
+    }
+}
+
+// Text logs written by perfmon or relog start with a "(PDH-CSV 4.0)" or
+// "(PDH-TSV 4.0)" header, so check the content before trusting the extension.
+pub fn detect_log_format(file: &str) -> LogFormat {
+    let mut header = [0u8; 64];
+    let read = File::open(file)
+        .and_then(|mut f| f.read(&mut header))
+        .unwrap_or(0);
+
+    let header = String::from_utf8_lossy(&header[..read]);
+    let header = header.trim_start_matches('\u{feff}');
+
+    if header.starts_with("\"(PDH-CSV") {
+        return LogFormat::Csv;
+    }
+
+    if header.starts_with("\"(PDH-TSV") {
+        return LogFormat::Tsv;
+    }
+
+    let extension = Path::new(file)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("csv") => LogFormat::Csv,
+        Some("tsv") => LogFormat::Tsv,
+        Some("etl") => LogFormat::Etl,
+        _ => LogFormat::Binary,
+    }
+}
+
+static FILE_ORDER: OnceLock<FileOrder> = OnceLock::new();
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum FileOrder {
+    /// When each file was last written
+    Modified,
+    /// The first sample in each file, which survives copying the files
+    Start,
+}
+
+// Set once at startup from --order.
+pub fn set_file_order(order: FileOrder) {
+    let _ = FILE_ORDER.set(order);
+}
+
+struct ScannedFile {
+    // The file PDH reads, which for an .etl trace is the .blg relogged from
+    // it.
+    path: String,
+    source: String,
+    format: LogFormat,
+    size: u64,
+    modified: SystemTime,
+}
+
+pub fn find_log_files(glob_pattern: &str) -> Vec<String> {
+    let (files, skipped) = scan_log_files(glob_pattern);
+
+    for (file, reason) in &skipped {
+        eprintln!("Skipping {}: {}", file, reason);
+    }
+    if !skipped.is_empty() {
+        record(Outcome::PartialRead);
+    }
+
+    if files.is_empty() {
+        eprintln!("No files matched {}.", glob_pattern);
+        record(Outcome::NoFiles);
+        return Vec::new();
+    }
+
+    progress!(
+        "Found {} files, {}.",
+        files.len(),
+        Unit::Bytes(1.0).format(files.iter().map(|f| f.size).sum::<u64>() as f64)
+    );
+
+    for file in &files {
+        progress!("  {} ({})", file.source, file.format);
+    }
+
+    files.into_iter().map(|f| f.path).collect()
+}
+
+// The files matching the pattern, oldest first, without printing them.
+pub fn glob_log_files(glob_pattern: &str) -> Vec<String> {
+    scan_log_files(glob_pattern)
+        .0
+        .into_iter()
+        .map(|f| f.path)
+        .collect()
+}
+
+// Stats the matching files in parallel, since that's slow on network shares,
+// and sorts them by --order. Returns the files to read, and the ones left
+// out with why.
+fn scan_log_files(glob_pattern: &str) -> (Vec<ScannedFile>, Vec<(String, String)>) {
+    let mut paths = Vec::new();
+    let mut skipped = Vec::new();
+    for entry in glob::glob(glob_pattern).expect("Failed to read glob pattern") {
+        match entry {
+            Ok(path) => paths.push(path.display().to_string()),
+            Err(e) => skipped.push((e.path().display().to_string(), e.error().to_string())),
+        }
+    }
+
+    let results = in_parallel(paths, |path| {
+        let metadata = std::fs::metadata(&path).map_err(|e| (path.clone(), e.to_string()))?;
+        if metadata.len() == 0 {
+            return Err((path, "the file is empty".to_string()));
+        }
+        let modified = metadata
+            .modified()
+            .map_err(|e| (path.clone(), e.to_string()))?;
+
+        // The logs in an archive are ordered by when the archive was
+        // written, since extracting them makes them all new.
+        if is_archive(&path) {
+            let extracted = extract_logs(&path).map_err(|e| (path.clone(), e))?;
+            return Ok(extracted
+                .into_iter()
+                .map(|log| ScannedFile {
+                    source: format!(
+                        "{} in {}",
+                        Path::new(&log)
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy(),
+                        path
+                    ),
+                    format: detect_log_format(&log),
+                    size: std::fs::metadata(&log).map_or(0, |m| m.len()),
+                    path: log,
+                    modified,
+                })
+                .collect());
+        }
+
+        let format = detect_log_format(&path);
+        let readable = match format {
+            LogFormat::Etl => relog_etl(&path).map_err(|e| (path.clone(), e))?,
+            _ => path.clone(),
+        };
+
+        Ok(vec![ScannedFile {
+            path: readable,
+            source: path,
+            format,
+            size: metadata.len(),
+            modified,
+        }])
+    });
+
+    let mut files = Vec::new();
+    for result in results {
+        match result {
+            Ok(scanned) => files.extend(scanned),
+            Err(reason) => skipped.push(reason),
+        }
+    }
+
+    match FILE_ORDER.get().unwrap_or(&FileOrder::Modified) {
+        FileOrder::Modified => files.sort_by_key(|f| f.modified),
+        FileOrder::Start => {
+            let paths = files
+                .iter()
+                .map(|f| f.path.clone())
+                .collect::<Vec<String>>();
+            // A file that won't bind is skipped, like binding them together
+            // skips it, rather than failing the scan.
+            let starts = in_parallel(paths, |path| {
+                let hdatasource = try_bind_input_logfiles(&[path])?;
+                let start = get_time_info(hdatasource).StartTime;
+                unsafe { PdhCloseLog(hdatasource, 0) };
+                Ok(start)
+            });
+
+            let mut ordered = Vec::new();
+            for (file, start) in files.into_iter().zip(starts) {
+                match start {
+                    Ok(start) => ordered.push((file, start)),
+                    Err(pdhstatus) => skipped.push((
+                        file.source,
+                        format!("it can't be bound: {}", status_name(pdhstatus)),
+                    )),
+                }
+            }
+            ordered.sort_by_key(|(f, start)| (*start, f.modified));
+            files = ordered.into_iter().map(|(f, _)| f).collect();
+        }
+    }
+
+    (files, skipped)
+}
+
+// Runs f on every item, spread over a thread per core, and returns the
+// results in the same order.
+fn in_parallel<T: Send, R: Send>(items: Vec<T>, f: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let chunk = items.len().div_ceil(threads).max(1);
+
+    let mut chunks = Vec::new();
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        chunks.push(items.by_ref().take(chunk).collect::<Vec<T>>());
+    }
+
+    std::thread::scope(|scope| {
+        let handles = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(|| chunk.into_iter().map(&f).collect::<Vec<R>>()))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("Scan thread panicked"))
+            .collect()
+    })
+}
+
+// Binds the logs and lists their counters.
+pub fn open_log_files(glob_pattern: &str) -> Option<(isize, PerfLogSummary)> {
+    let files = find_log_files(glob_pattern);
+
+    if files.is_empty() {
+        return None;
+    }
+
+    let hdatasource = bind_input_logfiles(files.clone());
+    let summary = cached_summary(&files, hdatasource);
+
+    Some((hdatasource, summary))
+}
@@ -1,67 +1,177 @@
+pub mod aggregate;
+pub mod align;
+pub mod analyze;
+pub mod api;
+pub mod archive;
+pub mod cache;
+pub mod changes;
+pub mod chart;
+pub mod cli;
+pub mod clipboard;
+pub mod collect;
+pub mod compare;
+pub mod completions;
+pub mod counter_path;
+pub mod derive;
+pub mod etl;
+pub mod export;
+pub mod filter;
+pub mod find;
+pub mod grafana;
+pub mod heatmap;
+pub mod http;
+pub mod influx;
+pub mod inventory;
+pub mod jsonl;
+pub mod list;
+pub mod log_files;
+pub mod monitor;
+pub mod number_format;
 pub mod pdh_helper;
-
-use std::{env};
-
-use windows::{
-    Win32::System::Performance::{
-        PdhCloseLog,
-    },
-};
-
-use crate::pdh_helper::{
-    bind_input_logfiles, get_perflog_summary, read_counter_values,
+pub mod plot;
+pub mod processes;
+pub mod profile;
+pub mod query;
+pub mod reader;
+pub mod rename;
+pub mod replay;
+pub mod report;
+pub mod resample;
+pub mod ring;
+pub mod selection;
+pub mod series;
+pub mod series_set;
+pub mod serve;
+pub mod sessions;
+pub mod spikes;
+pub mod split;
+pub mod stats;
+pub mod status;
+pub mod timespec;
+pub mod top;
+pub mod triage;
+pub mod units;
+pub mod validate;
+pub mod xlsx;
+
+use std::{env, panic::AssertUnwindSafe};
+
+use clap::Parser;
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    cli::{Cli, Command, SummaryArgs},
+    export::format_time,
+    log_files::open_log_files,
+    timespec::{display_offset, zone_label},
 };
 
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: {} <glob pattern>", args[0]);
-        return;
+    let cli = Cli::parse();
+
+    // Logs extracted from .zip and .gz files are removed when this is
+    // dropped at the end of the run, or on Ctrl+C. Ones left by earlier runs
+    // that were killed are removed now.
+    let extracted = archive::ExtractedLogs::start();
+
+    timespec::set_display_offset(cli.timezone);
+    cache::set_summary_cache(!cli.no_cache);
+    reader::set_show_errors(cli.show_errors);
+    reader::set_duplicates(cli.duplicates);
+    pdh_helper::set_detail_level(cli.detail);
+    pdh_helper::set_salvage(cli.salvage);
+    pdh_helper::set_every(cli.every);
+    pdh_helper::set_machines(cli.machine);
+    pdh_helper::set_format_flags(cli.no_cap, cli.times_1000, cli.no_scale);
+    log_files::set_file_order(cli.order);
+    analyze::custom::set_profiles_dir(cli.profiles_dir);
+    status::set_quiet(cli.quiet);
+
+    // The panic has already printed why, so all that's left is to clean up
+    // and exit with the code for what happened.
+    if std::panic::catch_unwind(AssertUnwindSafe(|| run(&cli.command))).is_err() {
+        status::record_panic();
     }
 
-    let glob_pattern = &args[1];
-
-    //let glob_pattern = "C:\\Users\\bill\\Downloads\\*0612*.blg";
-
-    let mut files: Vec<String> = glob::glob(glob_pattern)
-        .expect("Failed to read glob pattern")
-        .map(|x| x.unwrap().display().to_string())
-        .collect();
-
-    files.sort_by(|a, b| {
-        let a_modified = std::fs::metadata(&a).unwrap().modified().unwrap();
-        let b_modified = std::fs::metadata(&b).unwrap().modified().unwrap();
-        a_modified.cmp(&b_modified)
-    });
-
-    println!("Found {} files.", &files.len());
+    drop(extracted);
+    std::process::exit(status::exit_code());
+}
 
-    if files.len() == 0 {
-        return;
+fn run(command: &Command) {
+    match command {
+        Command::Summary(args) => summary(args),
+        Command::Split(args) => split::split(args),
+        Command::Export(args) => export::export(args),
+        Command::Find(args) => find::find(args),
+        Command::Query(args) => query::query(args),
+        Command::ListMachines(args) => list::list_machines(args),
+        Command::ListObjects(args) => list::list_objects(args),
+        Command::ListInstances(args) => list::list_instances(args),
+        Command::Validate(args) => validate::validate(args),
+        Command::Analyze(args) => analyze::analyze(args),
+        Command::Stats(args) => stats::stats(args),
+        Command::Heatmap(args) => heatmap::heatmap(args),
+        Command::Profile(args) => profile::profile(args),
+        Command::Top(args) => top::top(args),
+        Command::Compare(args) => compare::compare(args),
+        Command::InventoryDiff(args) => inventory::inventory_diff(args),
+        Command::Spikes(args) => spikes::spikes(args),
+        Command::Changes(args) => changes::changes(args),
+        Command::Processes(args) => processes::processes(args),
+        Command::Plot(args) => plot::plot(args),
+        Command::Triage(args) => triage::triage(args),
+        Command::Grafana(args) => grafana::grafana(args),
+        Command::Serve(args) => serve::serve(args),
+        Command::Api(args) => api::api(args),
+        Command::Monitor(args) => monitor::monitor(args),
+        Command::Collect(args) => collect::collect(args),
+        Command::Replay(args) => replay::replay(args),
+        Command::Completions(args) => completions::completions(args),
+        Command::CompleteCounter(args) => completions::complete_counter(args),
     }
+}
 
-    for file in &files {
-        println!("  {}", file);
+fn summary(args: &SummaryArgs) {
+    if args.sessions {
+        sessions::summarize_sessions(args);
+        return;
     }
 
-    let hdatasource = bind_input_logfiles(files);
-
-    let summary = get_perflog_summary(hdatasource);
+    let (hdatasource, mut summary) = match open_log_files(&args.glob_pattern) {
+        Some(opened) => opened,
+        None => return,
+    };
 
-    println!("Time range: {} - {}", summary.start_time, summary.end_time);
-
-    let counters = summary.get_all_counters();
-
-    let counters_to_read = &counters
-        .iter()
-        .filter(|s| s.contains("\\Processor(_Total)\\"))
-        .collect::<Vec<&String>>();
+    // Units are only shown with the counters.
+    if args.depth >= 3 || !args.expand.is_empty() {
+        summary.load_counter_info(hdatasource);
+    }
 
-    let counter_data = read_counter_values(hdatasource, counters_to_read);
+    println!(
+        "Time range: {} - {} ({})",
+        format_time(summary.start_time),
+        format_time(summary.end_time),
+        zone_label(display_offset())
+    );
+    // Logs bound together can leave gaps the overall range hides.
+    if summary.ranges.len() > 1 {
+        println!("Capture periods:");
+        for range in &summary.ranges {
+            println!(
+                "  {} - {} ({} samples)",
+                format_time(range.start_time),
+                format_time(range.end_time),
+                range.sample_count
+            );
+        }
+    }
+    summary
+        .write_samples(&mut std::io::stdout())
+        .expect("Failed to write summary");
 
-    println!("Counter data has {} entries", counter_data.len());
+    summary.print_hierarchy(args.depth, &args.expand);
 
     unsafe { PdhCloseLog(hdatasource, 0) };
 }
@@ -0,0 +1,503 @@
+use std::{
+    io::BufRead,
+    process,
+    str::FromStr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    time::{Duration, Instant},
+};
+
+use time::OffsetDateTime;
+
+use crate::{
+    cli::MonitorArgs,
+    export::format_time,
+    filter::{Comparison, SamplePredicate},
+    http,
+    pdh_helper::{expand_wildcard_path, status_name, CounterQuery},
+    report::json,
+    ring::RingBuffer,
+    timespec::format_duration,
+};
+
+// A condition on live counters, like "\Processor(_Total)\% Processor Time > 90".
+#[derive(Clone)]
+pub struct AlertRule {
+    pub pattern: String,
+    pub comparison: Comparison,
+    pub threshold: f64,
+}
+
+impl FromStr for AlertRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let is_operator = |c: char| matches!(c, '<' | '>' | '=' | '!');
+
+        // Counter paths can hold these characters too, so the comparison is
+        // the last run of them.
+        let end = s
+            .rfind(is_operator)
+            .ok_or_else(|| format!("Expected a rule like \"COUNTER > 90\": {}", s))?;
+        let start = s[..end]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_operator(*c))
+            .last()
+            .map_or(end, |(i, _)| i);
+
+        let pattern = s[..start].trim();
+        if pattern.is_empty() {
+            return Err(format!("Expected a counter before the comparison: {}", s));
+        }
+
+        let predicate = SamplePredicate::from_str(&format!("value {}", &s[start..]))?;
+        if predicate.duration.is_some() {
+            return Err(format!(
+                "Use --trigger instead of \"for\" in alert rules: {}",
+                s
+            ));
+        }
+
+        Ok(AlertRule {
+            pattern: pattern.to_string(),
+            comparison: predicate.comparison,
+            threshold: predicate.threshold,
+        })
+    }
+}
+
+impl AlertRule {
+    fn condition(&self) -> String {
+        format!("{} {}", self.comparison.symbol(), self.threshold)
+    }
+}
+
+// How often the rules' wildcards are expanded again, to watch the instances
+// that started since, like a new w3wp, and stop watching the ones that
+// ended.
+const REFRESH: Duration = Duration::from_secs(60);
+
+// Hysteresis for one counter under one rule: an alert is raised after the
+// condition holds for the trigger count in a row, and cleared after it's
+// false for the clear count in a row, so a value hovering around the
+// threshold doesn't flap.
+struct AlertState {
+    rule: usize,
+    counter: isize,
+    path: String,
+    active: bool,
+    streak: u64,
+}
+
+pub enum AlertEvent {
+    Raised,
+    Cleared,
+}
+
+impl AlertEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            AlertEvent::Raised => "ALERT",
+            AlertEvent::Cleared => "CLEAR",
+        }
+    }
+}
+
+impl AlertState {
+    fn update(&mut self, exceeded: bool, trigger: u64, clear: u64) -> Option<AlertEvent> {
+        // The streak counts intervals that disagree with the current state.
+        if exceeded != self.active {
+            self.streak += 1;
+        } else {
+            self.streak = 0;
+        }
+
+        let needed = if self.active { clear } else { trigger };
+        if self.streak < needed {
+            return None;
+        }
+
+        self.active = !self.active;
+        self.streak = 0;
+        Some(if self.active {
+            AlertEvent::Raised
+        } else {
+            AlertEvent::Cleared
+        })
+    }
+}
+
+pub fn monitor(args: &MonitorArgs) {
+    let mut query = CounterQuery::open(0);
+    let mut states = Vec::new();
+
+    for (r, rule) in args.alert.iter().enumerate() {
+        for (counter, path) in add_counters(&mut query, &rule.pattern) {
+            states.push(AlertState {
+                rule: r,
+                counter,
+                path,
+                active: false,
+                streak: 0,
+            });
+        }
+    }
+
+    // Without --record, the ring keeps the counters the rules watch.
+    let mut ring = args.ring.map(|window| {
+        let recorded = if args.record.is_empty() {
+            states.iter().map(|s| (s.counter, s.path.clone())).collect()
+        } else {
+            args.record
+                .iter()
+                .flat_map(|pattern| add_counters(&mut query, pattern))
+                .collect::<Vec<(isize, String)>>()
+        };
+        let (counters, paths): (Vec<isize>, Vec<String>) = recorded.into_iter().unzip();
+        (counters, paths.clone(), RingBuffer::new(window, paths))
+    });
+
+    if states.is_empty()
+        && ring
+            .as_ref()
+            .is_none_or(|(counters, _, _)| counters.is_empty())
+    {
+        eprintln!("No counters to monitor.");
+        return;
+    }
+
+    eprintln!(
+        "Monitoring {} counters every {}. Press Ctrl+C to stop.",
+        query.counters().count(),
+        format_duration(args.interval)
+    );
+
+    let mut dump_requests = ring.as_ref().map(|_| {
+        eprintln!(
+            "Keeping the last {} in memory. Press Enter to save it to {}.",
+            format_duration(args.ring.unwrap()),
+            args.dump_dir
+        );
+        watch_stdin()
+    });
+
+    let webhook = args.webhook.as_deref().map(start_webhook);
+
+    // Rate counters need two collections before they have a value.
+    query.collect();
+
+    // The time an automatic capture started by an alert is saved.
+    let mut capture_end: Option<OffsetDateTime> = None;
+    let mut last_refresh = Instant::now();
+
+    loop {
+        let deadline = Instant::now() + args.interval.unsigned_abs();
+        while wait_for_dump(deadline, &mut dump_requests) {
+            if let Some((_, _, ring)) = &ring {
+                save(args, ring);
+            }
+        }
+
+        if last_refresh.elapsed() >= REFRESH {
+            let recorded = ring.as_ref().map_or(&[][..], |(_, paths, _)| paths);
+            refresh(args, &mut query, &mut states, recorded, webhook.as_ref());
+            last_refresh = Instant::now();
+        }
+
+        let time = match query.collect() {
+            Some(time) => time,
+            None => {
+                eprintln!("Failed to collect counter data.");
+                continue;
+            }
+        };
+
+        if let Some((counters, _, ring)) = &mut ring {
+            let values = counters
+                .iter()
+                .map(|c| query.formatted_value(*c).ok())
+                .collect();
+            ring.push(time, values);
+
+            if capture_end.is_some_and(|end| time >= end) {
+                save(args, ring);
+                ring.release();
+                capture_end = None;
+            }
+        }
+
+        for state in &mut states {
+            // Counters whose instance went away have no value; they're
+            // treated as normal so their alerts clear.
+            let value = query.formatted_value(state.counter).ok();
+            let rule = &args.alert[state.rule];
+            let exceeded = value.is_some_and(|v| rule.comparison.compare(v, rule.threshold));
+
+            let event = match state.update(exceeded, args.trigger, args.clear) {
+                Some(event) => event,
+                None => continue,
+            };
+
+            send(
+                args,
+                webhook.as_ref(),
+                &event,
+                time,
+                &state.path,
+                rule,
+                value,
+            );
+
+            if let AlertEvent::Raised = event {
+                if let (Some(after), Some((_, _, ring)), None) =
+                    (args.capture, &mut ring, capture_end)
+                {
+                    eprintln!(
+                        "Capturing the next {} before saving.",
+                        format_duration(after)
+                    );
+                    ring.hold(time);
+                    capture_end = Some(time + after);
+                }
+
+                if let Some(command) = &args.on_alert {
+                    run_on_alert(command, &state.path, value);
+                }
+            }
+        }
+    }
+}
+
+// Posts the alerts to the webhook one after another on a thread of its own,
+// so an endpoint that's slow or down doesn't hold up collecting, counting
+// toward alerts, or the ring.
+fn start_webhook(url: &str) -> Sender<String> {
+    let (sender, receiver) = mpsc::channel::<String>();
+    let url = url.to_string();
+    std::thread::spawn(move || {
+        for body in receiver {
+            if let Err(e) = http::post(&url, "application/json", &[], body.as_bytes()) {
+                eprintln!("Failed to post to {}: {}", url, e);
+            }
+        }
+    });
+    sender
+}
+
+fn save(args: &MonitorArgs, ring: &RingBuffer) {
+    match ring.dump(&args.dump_dir, args.dump_format) {
+        Ok(file) => eprintln!("Saved {} samples to {}", ring.len(), file),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// Starts the command without waiting for it, so collection carries on while
+// something like procdump runs. The counter and value are passed in
+// environment variables.
+fn run_on_alert(command: &str, counter: &str, value: Option<f64>) {
+    let result = process::Command::new("cmd")
+        .args(["/C", command])
+        .env("PERFLOGTOOL_COUNTER", counter)
+        .env(
+            "PERFLOGTOOL_VALUE",
+            value.map_or_else(String::new, |v| v.to_string()),
+        )
+        .spawn();
+
+    if let Err(e) = result {
+        eprintln!("Failed to run {}: {}", command, e);
+    }
+}
+
+// Expands a pattern against this machine's counters and adds them to the
+// query. Returns the handle and path of each one.
+fn add_counters(query: &mut CounterQuery, pattern: &str) -> Vec<(isize, String)> {
+    let paths = match expand_wildcard_path(0, pattern) {
+        Ok(paths) => paths,
+        Err(pdhstatus) => {
+            eprintln!("Failed to expand {}: {:#x}", pattern, pdhstatus);
+            return Vec::new();
+        }
+    };
+
+    if paths.is_empty() {
+        eprintln!("No counters match {}", pattern);
+    }
+
+    let mut added = Vec::new();
+    for path in paths {
+        match query.add(&path) {
+            Ok(counter) => added.push((counter, path)),
+            Err(pdhstatus) => eprintln!("Failed to add {}: {:#x}", path, pdhstatus),
+        }
+    }
+
+    added
+}
+
+// Makes the query hold the counters the rules match now, and the ones
+// recorded in the ring, which don't change. Counters still matched keep
+// their handles, so their rates carry on and their alerts keep their state.
+fn refresh(
+    args: &MonitorArgs,
+    query: &mut CounterQuery,
+    states: &mut Vec<AlertState>,
+    recorded: &[String],
+    webhook: Option<&Sender<String>>,
+) {
+    let mut watched = Vec::new();
+    for (r, rule) in args.alert.iter().enumerate() {
+        match expand_wildcard_path(0, &rule.pattern) {
+            Ok(paths) => watched.extend(paths.into_iter().map(|path| (r, path))),
+            // Expanding can fail for a moment, like while a service restarts,
+            // so the counters it matched are kept until the next refresh.
+            Err(_) => watched.extend(
+                states
+                    .iter()
+                    .filter(|s| s.rule == r)
+                    .map(|s| (r, s.path.clone())),
+            ),
+        }
+    }
+
+    let paths = recorded
+        .iter()
+        .chain(watched.iter().map(|(_, path)| path))
+        .collect::<Vec<&String>>();
+    for (path, pdhstatus) in query.select(&paths) {
+        eprintln!("Failed to add {}: {}", path, status_name(pdhstatus));
+    }
+
+    let mut previous = std::mem::take(states);
+    let mut added = 0;
+    for (rule, path) in watched {
+        // Every counter left is in the query, so this returns its handle.
+        let counter = match query.add(&path) {
+            Ok(counter) => counter,
+            Err(_) => continue,
+        };
+        match previous
+            .iter()
+            .position(|s| s.rule == rule && s.path.eq_ignore_ascii_case(&path))
+        {
+            Some(index) => {
+                let mut state = previous.swap_remove(index);
+                state.counter = counter;
+                states.push(state);
+            }
+            None => {
+                added += 1;
+                states.push(AlertState {
+                    rule,
+                    counter,
+                    path,
+                    active: false,
+                    streak: 0,
+                });
+            }
+        }
+    }
+
+    // Alerts on counters that went away, like a process that exited, are
+    // cleared rather than left raised.
+    for state in previous.iter().filter(|s| s.active) {
+        send(
+            args,
+            webhook,
+            &AlertEvent::Cleared,
+            OffsetDateTime::now_utc(),
+            &state.path,
+            &args.alert[state.rule],
+            None,
+        );
+    }
+
+    if added > 0 || !previous.is_empty() {
+        eprintln!(
+            "Now monitoring {} counters: {} new, {} gone.",
+            query.counters().count(),
+            added,
+            previous.len()
+        );
+    }
+}
+
+// Each line typed on the console asks for a dump.
+fn watch_stdin() -> Receiver<()> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            if line.is_err() || sender.send(()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+// Waits until the deadline, returning early with true when a dump is
+// requested. Without a console to read from, it just sleeps.
+fn wait_for_dump(deadline: Instant, requests: &mut Option<Receiver<()>>) -> bool {
+    let timeout = deadline.saturating_duration_since(Instant::now());
+
+    if let Some(receiver) = requests {
+        match receiver.recv_timeout(timeout) {
+            Ok(()) => return true,
+            Err(RecvTimeoutError::Timeout) => return false,
+            Err(RecvTimeoutError::Disconnected) => *requests = None,
+        }
+    }
+
+    std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    false
+}
+
+fn send(
+    args: &MonitorArgs,
+    webhook: Option<&Sender<String>>,
+    event: &AlertEvent,
+    time: OffsetDateTime,
+    counter: &str,
+    rule: &AlertRule,
+    value: Option<f64>,
+) {
+    let value_text = value.map_or_else(|| "no value".to_string(), |v| format!("{:.2}", v));
+    let message = format!(
+        "{} {} {} ({})",
+        event.name(),
+        counter,
+        rule.condition(),
+        value_text
+    );
+
+    println!("{}  {}", format_time(time), message);
+
+    if let Some(webhook) = webhook {
+        let body = format!(
+            "{{\"event\":{},\"time\":{},\"counter\":{},\"condition\":{},\"value\":{}}}",
+            json(&event.name().to_lowercase()),
+            json(&format_time(time)),
+            json(counter),
+            json(&rule.condition()),
+            value.map_or_else(|| "null".to_string(), |v| v.to_string())
+        );
+        let _ = webhook.send(body);
+    }
+
+    if args.event_log {
+        let level = match event {
+            AlertEvent::Raised => "WARNING",
+            AlertEvent::Cleared => "INFORMATION",
+        };
+        let status = process::Command::new("eventcreate")
+            .args(["/T", level, "/ID", "100", "/L", "APPLICATION"])
+            .args(["/SO", "perflogtool", "/D", &message])
+            .stdout(process::Stdio::null())
+            .status();
+        match status {
+            Ok(status) if status.success() => (),
+            Ok(status) => eprintln!("eventcreate failed: {}", status),
+            Err(e) => eprintln!("Failed to run eventcreate: {}", e),
+        }
+    }
+}
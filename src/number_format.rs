@@ -0,0 +1,51 @@
+use std::sync::OnceLock;
+
+// How export writes values that aren't whole numbers. Rust formats numbers
+// the same way whatever the regional settings of the machine, with a dot for
+// the decimal point and no thousands separators, so a CSV from a German
+// server loads the same as one from a US server. Without any of these set,
+// values are written with as many digits as it takes to read them back
+// exactly, and never in scientific notation.
+#[derive(Clone, Copy, Default)]
+pub struct NumberFormat {
+    pub precision: Option<usize>,
+    pub scientific_above: Option<f64>,
+    pub scientific_below: Option<f64>,
+}
+
+static NUMBER_FORMAT: OnceLock<NumberFormat> = OnceLock::new();
+
+// Set once by export before it writes anything.
+pub fn set_number_format(format: NumberFormat) {
+    let _ = NUMBER_FORMAT.set(format);
+}
+
+pub fn format_number(value: f64) -> String {
+    let format = NUMBER_FORMAT.get().copied().unwrap_or_default();
+
+    let magnitude = value.abs();
+    let scientific = value.is_finite()
+        && magnitude != 0.0
+        && (format
+            .scientific_above
+            .is_some_and(|above| magnitude >= above)
+            || format
+                .scientific_below
+                .is_some_and(|below| magnitude < below));
+
+    let text = match (scientific, format.precision) {
+        (false, None) => value.to_string(),
+        (false, Some(precision)) => format!("{:.*}", precision, value),
+        (true, None) => format!("{:e}", value),
+        (true, Some(precision)) => format!("{:.*e}", precision, value),
+    };
+
+    // Rounding a small negative value leaves "-0.00", which reads as a
+    // different number than "0.00" to some tools.
+    match text.strip_prefix('-') {
+        Some(rest) if !scientific && rest.bytes().all(|b| b == b'0' || b == b'.') => {
+            rest.to_string()
+        }
+        _ => text,
+    }
+}
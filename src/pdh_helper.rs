@@ -1,44 +1,326 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::OnceLock,
+};
+
+use serde::{Deserialize, Serialize};
+use time::{
+    macros::{datetime, format_description},
+    serde::rfc3339,
+    OffsetDateTime,
+};
 
-use time::{macros::datetime, PrimitiveDateTime};
 use windows::{
-    core::{HSTRING, PWSTR},
+    core::{HSTRING, PCWSTR, PWSTR},
+    Win32::Foundation::BOOLEAN,
     Win32::System::Performance::{
-        PdhAddCounterW, PdhBindInputDataSourceW, PdhCollectQueryDataWithTime, PdhEnumMachinesHW,
-        PdhEnumObjectItemsHW, PdhEnumObjectsHW, PdhGetDataSourceTimeRangeH,
-        PdhGetFormattedCounterValue, PdhOpenQueryH, PDH_CSTATUS_NO_OBJECT, PDH_FMT_COUNTERVALUE,
-        PDH_FMT_LARGE, PDH_INVALID_DATA, PDH_MORE_DATA, PDH_TIME_INFO, PERF_DETAIL_WIZARD,
+        PdhAddCounterW, PdhBindInputDataSourceW, PdhCalculateCounterFromRawValue, PdhCloseLog,
+        PdhCloseQuery, PdhCollectQueryDataWithTime, PdhEnumMachinesHW, PdhEnumObjectItemsHW,
+        PdhEnumObjectsHW, PdhExpandWildCardPathHW, PdhGetCounterInfoW, PdhGetDataSourceTimeRangeH,
+        PdhGetFormattedCounterArrayW, PdhGetFormattedCounterValue, PdhGetRawCounterValue,
+        PdhMakeCounterPathW, PdhOpenLogW, PdhOpenQueryH, PdhRemoveCounter, PdhSetQueryTimeRange,
+        PdhUpdateLogW, PdhValidatePathExW, PDH_CALC_NEGATIVE_DENOMINATOR,
+        PDH_CALC_NEGATIVE_TIMEBASE, PDH_CALC_NEGATIVE_VALUE, PDH_COUNTER_INFO_W,
+        PDH_COUNTER_PATH_ELEMENTS_W, PDH_CSTATUS_BAD_COUNTERNAME, PDH_CSTATUS_INVALID_DATA,
+        PDH_CSTATUS_NEW_DATA, PDH_CSTATUS_NO_COUNTER, PDH_CSTATUS_NO_COUNTERNAME,
+        PDH_CSTATUS_NO_INSTANCE, PDH_CSTATUS_NO_MACHINE, PDH_CSTATUS_NO_OBJECT,
+        PDH_CSTATUS_VALID_DATA, PDH_END_OF_LOG_FILE, PDH_FMT, PDH_FMT_COUNTERVALUE,
+        PDH_FMT_COUNTERVALUE_ITEM_W, PDH_FMT_DOUBLE, PDH_INVALID_DATA, PDH_LOG, PDH_LOG_TYPE,
+        PDH_LOG_WRITE_ACCESS, PDH_MORE_DATA, PDH_NO_DATA, PDH_NO_MORE_DATA, PDH_PATH_WBEM_NONE,
+        PDH_RAW_COUNTER, PDH_TIME_INFO, PERF_DETAIL, PERF_DETAIL_ADVANCED, PERF_DETAIL_EXPERT,
+        PERF_DETAIL_NOVICE, PERF_DETAIL_WIZARD,
     },
 };
 
+use crate::{
+    cli::DetailLevel,
+    counter_path::{CounterPath, CounterPathBuilder},
+    selection::wildcard_match,
+    series::{Series, SeriesBuilder},
+    status::{record, Outcome},
+    timespec::{display_offset, extend_log_range, format_duration},
+    units::Unit,
+};
+
+// Not exported by the windows crate.
+const PDH_LOG_CREATE_ALWAYS: u32 = 0x2;
+
+static DETAIL_LEVEL: OnceLock<PERF_DETAIL> = OnceLock::new();
+
+// Set once at startup from --detail.
+pub fn set_detail_level(level: DetailLevel) {
+    let _ = DETAIL_LEVEL.set(match level {
+        DetailLevel::Novice => PERF_DETAIL_NOVICE,
+        DetailLevel::Advanced => PERF_DETAIL_ADVANCED,
+        DetailLevel::Expert => PERF_DETAIL_EXPERT,
+        DetailLevel::Wizard => PERF_DETAIL_WIZARD,
+    });
+}
+
+pub fn detail_level() -> PERF_DETAIL {
+    *DETAIL_LEVEL.get().unwrap_or(&PERF_DETAIL_WIZARD)
+}
+
+static SALVAGE: OnceLock<bool> = OnceLock::new();
+
+// Set once at startup from --salvage.
+pub fn set_salvage(enabled: bool) {
+    let _ = SALVAGE.set(enabled);
+}
+
+fn salvage() -> bool {
+    *SALVAGE.get().unwrap_or(&false)
+}
+
+static EVERY: OnceLock<u64> = OnceLock::new();
+
+// Set once at startup from --every.
+pub fn set_every(every: u64) {
+    let _ = EVERY.set(every);
+}
+
+fn every() -> u64 {
+    *EVERY.get().unwrap_or(&1)
+}
+
+// The flags from pdh.h that change how values are formatted, which the
+// windows crate doesn't define.
+const PDH_FMT_NOSCALE: u32 = 0x1000;
+const PDH_FMT_1000: u32 = 0x2000;
+const PDH_FMT_NOCAP100: u32 = 0x8000;
+
+static FORMAT_FLAGS: OnceLock<u32> = OnceLock::new();
+
+// Set once at startup from --no-cap, --times-1000, and --no-scale.
+pub fn set_format_flags(no_cap: bool, times_1000: bool, no_scale: bool) {
+    let mut flags = 0;
+    if no_cap {
+        flags |= PDH_FMT_NOCAP100;
+    }
+    if times_1000 {
+        flags |= PDH_FMT_1000;
+    }
+    if no_scale {
+        flags |= PDH_FMT_NOSCALE;
+    }
+    let _ = FORMAT_FLAGS.set(flags);
+}
+
+pub fn format_flags() -> u32 {
+    *FORMAT_FLAGS.get().unwrap_or(&0)
+}
+
+// How every value is formatted: as a double, with the flags chosen.
+fn value_format() -> PDH_FMT {
+    PDH_FMT(PDH_FMT_DOUBLE.0 | format_flags())
+}
+
+static MACHINES: OnceLock<Vec<String>> = OnceLock::new();
+
+// Set once at startup from --machine.
+pub fn set_machines(patterns: Vec<String>) {
+    let _ = MACHINES.set(patterns);
+}
+
+pub fn machine_patterns() -> &'static [String] {
+    MACHINES.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+// Whether --machine leaves a machine in. Names are matched ignoring case and
+// the leading backslashes, so SQL01, \\SQL01, and sql* all match \\SQL01.
+fn machine_selected(machine: &str) -> bool {
+    let patterns = machine_patterns();
+    let machine = machine.trim_start_matches('\\').to_lowercase();
+    patterns.is_empty()
+        || patterns.iter().any(|pattern| {
+            wildcard_match(&pattern.trim_start_matches('\\').to_lowercase(), &machine)
+        })
+}
+
+static KEEP_INVALID: OnceLock<bool> = OnceLock::new();
+
+// Set by export's --keep-invalid.
+pub fn set_keep_invalid(enabled: bool) {
+    let _ = KEEP_INVALID.set(enabled);
+}
+
+pub fn keep_invalid() -> bool {
+    *KEEP_INVALID.get().unwrap_or(&false)
+}
+
+// With --keep-invalid, a sample PDH gave no value for is kept as a NaN with
+// the status in its payload, so it goes through the series like any other
+// value and the output can still say why there's no value.
+const QUIET_NAN: u64 = 0x7ff8_0000_0000_0000;
+
+pub fn invalid_value(status: u32) -> f64 {
+    f64::from_bits(QUIET_NAN | status as u64)
+}
+
+pub fn invalid_status(value: f64) -> Option<u32> {
+    let bits = value.to_bits();
+    match (bits & 0xffff_ffff) as u32 {
+        status if status != 0 && value.is_nan() && bits & QUIET_NAN == QUIET_NAN => Some(status),
+        _ => None,
+    }
+}
+
+// Times are serialized as RFC 3339, so saved summaries and values can be
+// read by other tools.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum CounterValueWithTime {
-    Long(PrimitiveDateTime, i32),
-    Double(PrimitiveDateTime, f64),
-    Large(PrimitiveDateTime, i64),
+    Long(#[serde(with = "rfc3339")] OffsetDateTime, i32),
+    Double(#[serde(with = "rfc3339")] OffsetDateTime, f64),
+    Large(#[serde(with = "rfc3339")] OffsetDateTime, i64),
+}
+
+impl CounterValueWithTime {
+    pub fn time(&self) -> OffsetDateTime {
+        match self {
+            CounterValueWithTime::Long(time, _) => *time,
+            CounterValueWithTime::Double(time, _) => *time,
+            CounterValueWithTime::Large(time, _) => *time,
+        }
+    }
+
+    pub fn value(&self) -> f64 {
+        match self {
+            CounterValueWithTime::Long(_, value) => *value as f64,
+            CounterValueWithTime::Double(_, value) => *value,
+            CounterValueWithTime::Large(_, value) => *value as f64,
+        }
+    }
+}
+
+pub struct RawCounterValue {
+    pub time: OffsetDateTime,
+    pub raw: PDH_RAW_COUNTER,
+    pub rate: Option<f64>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PerfLogSummary {
     pub machines: Vec<MachineSummary>,
-    pub start_time: time::PrimitiveDateTime,
-    pub end_time: time::PrimitiveDateTime,
+    #[serde(with = "rfc3339")]
+    pub start_time: time::OffsetDateTime,
+    #[serde(with = "rfc3339")]
+    pub end_time: time::OffsetDateTime,
+    // The number of collections in the logs. Each counter has at most one
+    // sample per collection.
+    pub sample_count: u32,
+    // The periods the data source reports separately. Usually there's one,
+    // covering the whole capture.
+    pub ranges: Vec<TimeRange>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TimeRange {
+    #[serde(with = "rfc3339")]
+    pub start_time: OffsetDateTime,
+    #[serde(with = "rfc3339")]
+    pub end_time: OffsetDateTime,
+    pub sample_count: u32,
 }
 
 impl PerfLogSummary {
-    pub fn print_hierarchy(&self) {
+    // The average time between collections. Gaps between bound logs make it
+    // longer than the interval they were collected at.
+    pub fn interval(&self) -> Option<time::Duration> {
+        if self.sample_count < 2 {
+            return None;
+        }
+
+        Some((self.end_time - self.start_time) / (self.sample_count - 1))
+    }
+
+    pub fn counter_paths(&self) -> usize {
+        self.machines.iter().map(|m| m.counter_paths()).sum()
+    }
+
+    pub fn write_samples(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        match self.interval() {
+            Some(interval) => writeln!(
+                writer,
+                "Samples: {} per counter, every {} ({} values across {} counter paths)",
+                self.sample_count,
+                format_duration(interval),
+                self.sample_count as u64 * self.counter_paths() as u64,
+                self.counter_paths()
+            ),
+            None => writeln!(writer, "Samples: {} per counter", self.sample_count),
+        }
+    }
+
+    pub fn print_hierarchy(&self, depth: u8, expand: &[String]) {
+        self.write_tree(&mut std::io::stdout(), depth, expand)
+            .expect("Failed to write hierarchy");
+    }
+
+    pub fn write_hierarchy(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        self.write_tree(writer, 3, &[])
+    }
+
+    // Depth 1 lists the machines, 2 adds their objects with counter and
+    // instance counts, and 3 lists every counter and instance. Objects
+    // matching an expand pattern are listed in full at any depth.
+    pub fn write_tree(
+        &self,
+        writer: &mut dyn std::io::Write,
+        depth: u8,
+        expand: &[String],
+    ) -> std::io::Result<()> {
         for machine in &self.machines {
-            println!("Machine: {}", machine.name);
+            writeln!(
+                writer,
+                "Machine: {} ({} objects, {} counter paths)",
+                machine.name,
+                machine.objects.len(),
+                machine.counter_paths()
+            )?;
 
             for object in &machine.objects {
-                println!("  {}", object.name);
-                println!("    Counters:");
-                for counter in &object.counters {
-                    println!("      {}", counter);
+                let expanded = depth >= 3
+                    || expand
+                        .iter()
+                        .any(|p| wildcard_match(&p.to_lowercase(), &object.name.to_lowercase()));
+
+                if expanded {
+                    writeln!(writer, "  {}", object.name)?;
+                    object.write_details(writer)?;
+                } else if depth >= 2 {
+                    writeln!(writer, "  {} ({})", object.name, object.counts())?;
                 }
+            }
+        }
 
-                println!("    Instances:");
-                for instance in &object.instances {
-                    println!("      {}", instance);
-                }
+        Ok(())
+    }
+
+    // Counter types are the same for every instance, so the first instance
+    // of each counter is enough.
+    pub fn load_counter_info(&mut self, hdatasource: isize) {
+        for machine in &mut self.machines {
+            for object in &mut machine.objects {
+                let paths = object
+                    .counters
+                    .iter()
+                    .map(|counter| match object.instances.first() {
+                        Some(instance) => format!(
+                            "{}\\{}({})\\{}",
+                            machine.name, object.name, instance, counter
+                        ),
+                        None => format!("{}\\{}\\{}", machine.name, object.name, counter),
+                    })
+                    .collect::<Vec<String>>();
+
+                let info = read_counter_info(hdatasource, &paths.iter().collect());
+
+                object.counter_info = object
+                    .counters
+                    .iter()
+                    .zip(&paths)
+                    .filter_map(|(counter, path)| Some((counter.clone(), *info.get(path)?)))
+                    .collect();
             }
         }
     }
@@ -47,12 +329,20 @@ impl PerfLogSummary {
         let mut all_counters = Vec::new();
         for machine in &self.machines {
             for object in &machine.objects {
+                let path = CounterPathBuilder::new()
+                    .machine(&machine.name)
+                    .object(&object.name);
+
+                if object.instances.is_empty() {
+                    for counter in &object.counters {
+                        all_counters.push(path.clone().counter(counter).build_or_panic());
+                    }
+                }
+
                 for instance in &object.instances {
+                    let path = path.clone().instance(instance);
                     for counter in &object.counters {
-                        all_counters.push(format!(
-                            "{}\\{}({})\\{}",
-                            machine.name, object.name, instance, counter
-                        ));
+                        all_counters.push(path.clone().counter(counter).build_or_panic());
                     }
                 }
             }
@@ -62,30 +352,94 @@ impl PerfLogSummary {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MachineSummary {
     pub name: String,
     pub objects: Vec<ObjectSummary>,
 }
 
+impl MachineSummary {
+    pub fn counter_paths(&self) -> usize {
+        self.objects
+            .iter()
+            .map(|o| o.counters.len() * o.instances.len().max(1))
+            .sum()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ObjectSummary {
     pub name: String,
     pub counters: Vec<String>,
     pub instances: Vec<String>,
+    // Keyed by counter name. Empty until load_counter_info is called.
+    pub counter_info: HashMap<String, CounterInfo>,
+}
+
+impl ObjectSummary {
+    fn counts(&self) -> String {
+        let counters = plural(self.counters.len(), "counter");
+        if self.instances.is_empty() {
+            counters
+        } else {
+            format!("{}, {}", counters, plural(self.instances.len(), "instance"))
+        }
+    }
+
+    fn write_details(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        writeln!(writer, "    Counters:")?;
+        for counter in &self.counters {
+            match self.counter_info.get(counter) {
+                Some(info) => writeln!(
+                    writer,
+                    "      {}  [{}, scale {}]",
+                    counter,
+                    Unit::from_info(info, counter).name(),
+                    info.default_scale
+                )?,
+                None => writeln!(writer, "      {}", counter)?,
+            }
+        }
+
+        writeln!(writer, "    Instances:")?;
+        for instance in &self.instances {
+            writeln!(writer, "      {}", instance)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CounterInfo {
+    pub counter_type: u32,
+    // The power of ten perfmon scales the counter by when graphing it. It
+    // isn't applied to values, which stay in the counter's own unit.
+    pub default_scale: i32,
 }
 
 pub fn get_perflog_summary(hdatasource: isize) -> PerfLogSummary {
     let mut machines = Vec::new();
 
     let machine_names = enum_machines(hdatasource);
+    let detail = detail_level();
 
     for machine in machine_names {
-        let object_names = enum_objects(&machine, hdatasource);
+        let object_names = enum_objects(&machine, hdatasource, detail);
 
         let mut objects = Vec::new();
 
         for object in object_names {
             let (counter_names, instance_names) =
-                match enum_object_items(&machine, &object, hdatasource) {
+                match enum_object_items(&machine, &object, hdatasource, detail) {
                     Some(value) => value,
                     None => continue,
                 };
@@ -94,6 +448,7 @@ pub fn get_perflog_summary(hdatasource: isize) -> PerfLogSummary {
                 name: object,
                 counters: counter_names,
                 instances: instance_names,
+                counter_info: HashMap::new(),
             };
 
             objects.push(object_summary);
@@ -105,53 +460,123 @@ pub fn get_perflog_summary(hdatasource: isize) -> PerfLogSummary {
         });
     }
 
-    let (start_time, end_time) = get_time_range(hdatasource);
-
-    let summary = PerfLogSummary {
+    let pinfo = get_time_info(hdatasource);
+    let ranges = get_time_ranges(hdatasource)
+        .iter()
+        .filter_map(|range| {
+            Some(TimeRange {
+                start_time: get_time_from_filetime(range.StartTime)?,
+                end_time: get_time_from_filetime(range.EndTime)?,
+                sample_count: range.SampleCount,
+            })
+        })
+        .collect();
+
+    // A log without samples has no times, and is shown as starting and
+    // ending at the FILETIME epoch like PDH reports it.
+    let start_time = get_time_from_filetime(pinfo.StartTime).unwrap_or(FILETIME_EPOCH);
+    let end_time = get_time_from_filetime(pinfo.EndTime).unwrap_or(FILETIME_EPOCH);
+    extend_log_range(start_time, end_time);
+
+    PerfLogSummary {
         machines,
         start_time,
         end_time,
-    };
+        sample_count: pinfo.SampleCount,
+        ranges,
+    }
+}
 
-    summary
+// The whole time the data source covers: from the start of its first range
+// to the end of its last, with the samples of all of them. Ranges with an
+// invalid time are left out, so one damaged log doesn't stretch the whole
+// data source back to 1601.
+pub fn get_time_info(hdatasource: isize) -> PDH_TIME_INFO {
+    let ranges = get_time_ranges(hdatasource)
+        .into_iter()
+        .filter(|r| {
+            get_time_from_filetime(r.StartTime).is_some()
+                && get_time_from_filetime(r.EndTime).is_some()
+        })
+        .collect::<Vec<PDH_TIME_INFO>>();
+
+    PDH_TIME_INFO {
+        StartTime: ranges.iter().map(|r| r.StartTime).min().unwrap_or(0),
+        EndTime: ranges.iter().map(|r| r.EndTime).max().unwrap_or(0),
+        SampleCount: ranges.iter().map(|r| r.SampleCount).sum(),
+    }
 }
 
-pub fn get_time_range(hdatasource: isize) -> (time::PrimitiveDateTime, time::PrimitiveDateTime) {
-    let mut pdwnumentries = 0;
-    let mut pinfo = PDH_TIME_INFO {
-        StartTime: 0,
-        EndTime: 0,
-        SampleCount: 0,
-    };
-    let mut pdwbuffersize = 24;
-    let pdhstatus = unsafe {
-        PdhGetDataSourceTimeRangeH(
-            hdatasource,
-            &mut pdwnumentries,
-            &mut pinfo,
-            &mut pdwbuffersize,
-        )
-    };
+// Every range the data source reports. A buffer for one is tried first, and
+// if there are more, PDH says how many to make room for.
+pub fn get_time_ranges(hdatasource: isize) -> Vec<PDH_TIME_INFO> {
+    let entry_size = std::mem::size_of::<PDH_TIME_INFO>() as u32;
+    let mut pdwnumentries = 1;
 
-    if pdhstatus != 0 {
-        panic!("Failed to get time range: {:#x}", pdhstatus);
+    loop {
+        let mut pinfo = vec![
+            PDH_TIME_INFO {
+                StartTime: 0,
+                EndTime: 0,
+                SampleCount: 0,
+            };
+            pdwnumentries.max(1) as usize
+        ];
+        let mut pdwbuffersize = entry_size * pinfo.len() as u32;
+        let pdhstatus = unsafe {
+            PdhGetDataSourceTimeRangeH(
+                hdatasource,
+                &mut pdwnumentries,
+                pinfo.as_mut_ptr(),
+                &mut pdwbuffersize,
+            )
+        };
+
+        match pdhstatus {
+            0 => {
+                pinfo.truncate(pdwnumentries as usize);
+                return pinfo;
+            }
+            PDH_MORE_DATA => {
+                // Older versions only report the size of the buffer they need.
+                pdwnumentries = pdwnumentries.max(pdwbuffersize.div_ceil(entry_size));
+                if pdwnumentries as usize <= pinfo.len() {
+                    pdwnumentries = pinfo.len() as u32 * 2;
+                }
+            }
+            _ => panic!("Failed to get time range: {:#x}", pdhstatus),
+        }
+    }
+}
+
+// FILETIMEs count 100ns intervals from here.
+const FILETIME_EPOCH: OffsetDateTime = datetime!(1601-01-01 00:00:00 UTC);
+
+const FILETIMES_PER_SECOND: i64 = 10_000_000;
+
+// The time of a FILETIME, or None if it's zero, negative, or past the year
+// 9999, which damaged logs have been seen with.
+pub fn get_time_from_filetime(filetime: i64) -> Option<OffsetDateTime> {
+    if filetime <= 0 {
+        return None;
     }
 
-    let start_time = get_time_from_filetime(pinfo.StartTime);
-    let end_time = get_time_from_filetime(pinfo.EndTime);
-    (start_time, end_time)
+    let since_epoch = time::Duration::new(
+        filetime / FILETIMES_PER_SECOND,
+        ((filetime % FILETIMES_PER_SECOND) * 100) as i32,
+    );
+    FILETIME_EPOCH.checked_add(since_epoch)
 }
 
-pub fn get_time_from_filetime(filetime: i64) -> time::PrimitiveDateTime {
-    let filetime_basedate = datetime!(1601-01-01 00:00:00);
-    let nanos = Duration::from_nanos(filetime as u64 * 100);
-    filetime_basedate + nanos
+pub fn get_filetime_from_time(time: time::OffsetDateTime) -> i64 {
+    ((time - FILETIME_EPOCH).whole_nanoseconds() / 100) as i64
 }
 
 pub fn enum_object_items(
     machine: &String,
     object: &String,
     hdatasource: isize,
+    detail: PERF_DETAIL,
 ) -> Option<(Vec<String>, Vec<String>)> {
     let szmachinename = HSTRING::from(machine);
     let szobjectname = HSTRING::from(object);
@@ -168,7 +593,7 @@ pub fn enum_object_items(
             &mut pcchcounterlistlength,
             mszinstancelist,
             &mut pcchinstancelistlength,
-            PERF_DETAIL_WIZARD,
+            detail,
             0,
         )
     };
@@ -198,7 +623,7 @@ pub fn enum_object_items(
             &mut pcchcounterlistlength,
             mszinstancelist,
             &mut pcchinstancelistlength,
-            PERF_DETAIL_WIZARD,
+            detail,
             0,
         )
     };
@@ -213,7 +638,7 @@ pub fn enum_object_items(
     Some((counter_names, instance_names))
 }
 
-pub fn enum_objects(machine: &String, hdatasource: isize) -> Vec<String> {
+pub fn enum_objects(machine: &String, hdatasource: isize, detail: PERF_DETAIL) -> Vec<String> {
     let szmachinename = HSTRING::from(machine);
 
     let mut cb_buffer = 0;
@@ -225,7 +650,7 @@ pub fn enum_objects(machine: &String, hdatasource: isize) -> Vec<String> {
             &szmachinename,
             lp_buffer,
             &mut cb_buffer,
-            PERF_DETAIL_WIZARD,
+            detail,
             false,
         )
     };
@@ -245,7 +670,7 @@ pub fn enum_objects(machine: &String, hdatasource: isize) -> Vec<String> {
             &szmachinename,
             lp_buffer,
             &mut cb_buffer,
-            PERF_DETAIL_WIZARD,
+            detail,
             false,
         )
     };
@@ -254,10 +679,71 @@ pub fn enum_objects(machine: &String, hdatasource: isize) -> Vec<String> {
         panic!("Failed to enum objects: {:#x}", pdhstatus);
     }
 
-    let object_names = get_strings_from_pwstr(&lp_buffer, cb_buffer);
-    object_names
+    get_strings_from_pwstr(&lp_buffer, cb_buffer)
+}
+
+// Puts a counter path together from its pieces. Machine names include the
+// leading backslashes.
+pub fn make_counter_path(
+    machine: Option<&str>,
+    object: &str,
+    instance: Option<&str>,
+    parent: Option<&str>,
+    index: u32,
+    counter: &str,
+) -> Result<String, u32> {
+    let wide = |text: &str| text.encode_utf16().chain([0]).collect::<Vec<u16>>();
+    let mut machine = machine.map(wide);
+    let mut object = wide(object);
+    let mut instance = instance.map(wide);
+    let mut parent = parent.map(wide);
+    let mut counter = wide(counter);
+
+    let pwstr = |text: &mut Option<Vec<u16>>| {
+        text.as_mut()
+            .map_or(PWSTR::null(), |text| PWSTR(text.as_mut_ptr()))
+    };
+    let elements = PDH_COUNTER_PATH_ELEMENTS_W {
+        szMachineName: pwstr(&mut machine),
+        szObjectName: PWSTR(object.as_mut_ptr()),
+        szInstanceName: pwstr(&mut instance),
+        szParentInstance: pwstr(&mut parent),
+        dwInstanceIndex: index,
+        szCounterName: PWSTR(counter.as_mut_ptr()),
+    };
+
+    let mut buffer_size = 0;
+    let pdhstatus = unsafe {
+        PdhMakeCounterPathW(
+            &elements,
+            PWSTR::null(),
+            &mut buffer_size,
+            PDH_PATH_WBEM_NONE,
+        )
+    };
+    if pdhstatus != PDH_MORE_DATA && pdhstatus != 0 {
+        return Err(pdhstatus);
+    }
+
+    let mut buffer = vec![0u16; buffer_size as usize];
+    let pdhstatus = unsafe {
+        PdhMakeCounterPathW(
+            &elements,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut buffer_size,
+            PDH_PATH_WBEM_NONE,
+        )
+    };
+    if pdhstatus != 0 {
+        return Err(pdhstatus);
+    }
+
+    let end = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+    Ok(String::from_utf16_lossy(&buffer[..end]))
 }
 
+// The machines in the logs that --machine leaves in. The objects and
+// counters of the others are never enumerated or read.
 pub fn enum_machines(hdatasource: isize) -> Vec<String> {
     let mut buffer_size = 0;
     let machine_list = PWSTR::null();
@@ -278,14 +764,121 @@ pub fn enum_machines(hdatasource: isize) -> Vec<String> {
         panic!("Failed to enum machines: {:#x}", pdhstatus);
     }
 
-    let machine_names = get_strings_from_pwstr(&lp_buffer, buffer_size);
-    machine_names
+    get_strings_from_pwstr(&lp_buffer, buffer_size)
+        .into_iter()
+        .filter(|machine| machine_selected(machine))
+        .collect()
+}
+
+// Expands a path with wildcards into the counters it matches. Pass 0 as the
+// data source to expand against this machine's live counters.
+pub fn expand_wildcard_path(hdatasource: isize, path: &str) -> Result<Vec<String>, u32> {
+    let wildcard_path = HSTRING::from(path);
+    let mut buffer_size = 0;
+    let pdhstatus = unsafe {
+        PdhExpandWildCardPathHW(
+            hdatasource,
+            &wildcard_path,
+            PWSTR::null(),
+            &mut buffer_size,
+            0,
+        )
+    };
+
+    if pdhstatus != PDH_MORE_DATA {
+        return Err(pdhstatus);
+    }
+
+    let mut path_list = vec![0u16; buffer_size as usize];
+    let lp_buffer: PWSTR = PWSTR(path_list.as_mut_ptr());
+    let pdhstatus = unsafe {
+        PdhExpandWildCardPathHW(hdatasource, &wildcard_path, lp_buffer, &mut buffer_size, 0)
+    };
+
+    if pdhstatus != 0 {
+        return Err(pdhstatus);
+    }
+
+    Ok(get_strings_from_pwstr(&lp_buffer, buffer_size))
+}
+
+// Checks that a full counter path, without wildcards, is in the data source.
+pub fn validate_counter_path(hdatasource: isize, path: &str) -> Result<(), u32> {
+    let counter_path = HSTRING::from(path);
+    match unsafe { PdhValidatePathExW(hdatasource, &counter_path) } {
+        0 => Ok(()),
+        pdhstatus => Err(pdhstatus),
+    }
 }
 
+// Binds the logs as one data source. When PDH rejects the list, the files it
+// won't bind are found by splitting the list in halves, reported, and left
+// out, so one corrupt log doesn't lose the rest.
 pub fn bind_input_logfiles(files: Vec<String>) -> isize {
+    let pdhstatus = match try_bind_input_logfiles(&files) {
+        Ok(hdatasource) => return hdatasource,
+        Err(pdhstatus) => pdhstatus,
+    };
+
+    if files.len() == 1 {
+        record(Outcome::BindFailed);
+        panic!(
+            "Failed to bind to log file {}: {}",
+            files[0],
+            status_name(pdhstatus)
+        );
+    }
+
+    let bindable = bindable_logfiles(&files);
+    if bindable.is_empty() {
+        record(Outcome::BindFailed);
+        panic!("Failed to bind to log files: {}", status_name(pdhstatus));
+    }
+
+    record(Outcome::PartialRead);
+    eprintln!(
+        "Continuing with the {} of {} log files that bind.",
+        bindable.len(),
+        files.len()
+    );
+    match try_bind_input_logfiles(&bindable) {
+        Ok(hdatasource) => hdatasource,
+        Err(pdhstatus) => {
+            record(Outcome::BindFailed);
+            panic!("Failed to bind to log files: {}", status_name(pdhstatus))
+        }
+    }
+}
+
+// The files that bind, trying each half of a list that doesn't until the
+// files that fail on their own are found.
+fn bindable_logfiles(files: &[String]) -> Vec<String> {
+    match try_bind_input_logfiles(files) {
+        Ok(hdatasource) => {
+            unsafe { PdhCloseLog(hdatasource, 0) };
+            files.to_vec()
+        }
+        Err(pdhstatus) if files.len() == 1 => {
+            eprintln!(
+                "Skipping {}: it can't be bound: {}",
+                files[0],
+                status_name(pdhstatus)
+            );
+            Vec::new()
+        }
+        Err(_) => {
+            let (first, second) = files.split_at(files.len() / 2);
+            let mut bindable = bindable_logfiles(first);
+            bindable.extend(bindable_logfiles(second));
+            bindable
+        }
+    }
+}
+
+pub fn try_bind_input_logfiles(files: &[String]) -> Result<isize, u32> {
     let mut file_list = String::new();
     for file in files {
-        file_list.push_str(&file);
+        file_list.push_str(file);
         file_list.push('\0');
     }
 
@@ -296,36 +889,52 @@ pub fn bind_input_logfiles(files: Vec<String>) -> isize {
     let mut hdatasource: isize = isize::default();
     let pdhstatus = unsafe { PdhBindInputDataSourceW(&mut hdatasource, &file) };
 
-    if pdhstatus != 0 {
-        panic!("Failed to bind to log files: {:#x}", pdhstatus);
+    match pdhstatus {
+        0 => Ok(hdatasource),
+        pdhstatus => Err(pdhstatus),
     }
-
-    hdatasource
 }
 
 fn get_strings_from_pwstr(object_list: &PWSTR, buffer_size: u32) -> Vec<String> {
     let object_list_ptr = object_list.as_ptr();
     let slice = unsafe { std::slice::from_raw_parts(object_list_ptr, buffer_size as usize) };
 
-    let mut strings = Vec::<String>::new();
-    let current_string = String::from_utf16(slice).unwrap();
-    let split = current_string.split('\0');
-
-    for s in split {
-        strings.push(s.to_string());
-    }
+    parse_multi_sz(slice)
+}
 
-    strings.pop();
-    strings.pop();
+// A MULTI_SZ list is strings that each end in a null, followed by one more
+// null. The list ends at the first empty string, whatever the buffer size
+// PDH reported says, and a last string missing its null is still kept.
+fn parse_multi_sz(buffer: &[u16]) -> Vec<String> {
+    buffer
+        .split(|c| *c == 0)
+        .take_while(|s| !s.is_empty())
+        .map(decode_utf16)
+        .collect()
+}
 
-    strings
+// Localized counter names have been seen with unpaired surrogates. They're
+// replaced rather than failing the whole list, since the rest of the names
+// are fine.
+fn decode_utf16(text: &[u16]) -> String {
+    match String::from_utf16(text) {
+        Ok(text) => text,
+        Err(_) => {
+            let text = String::from_utf16_lossy(text);
+            eprintln!(
+                "Warning: replaced characters that aren't valid UTF-16 in the name {}",
+                text
+            );
+            text
+        }
+    }
 }
 
-pub fn read_counter_values(
+pub fn read_counter_info(
     hdatasource: isize,
-    counters_to_read: &Vec<&String>,
-) -> HashMap<String, Vec<CounterValueWithTime>> {
-    let mut counter_data = HashMap::<String, Vec<CounterValueWithTime>>::new();
+    counters: &Vec<&String>,
+) -> HashMap<String, CounterInfo> {
+    let mut counter_info = HashMap::new();
 
     let mut phquery: isize = isize::default();
     let pdhstatus = unsafe { PdhOpenQueryH(hdatasource, 0, &mut phquery) };
@@ -334,61 +943,1063 @@ pub fn read_counter_values(
         panic!("Failed to open query: {:#x}", pdhstatus);
     }
 
-    let mut counter_handles = HashMap::<String, isize>::new();
-
-    for counter in counters_to_read {
+    for counter in counters {
         let counter_path = HSTRING::from(*counter);
         let mut phcounter: isize = isize::default();
         let pdhstatus = unsafe { PdhAddCounterW(phquery, &counter_path, 0, &mut phcounter) };
 
         if pdhstatus != 0 {
-            panic!("Failed to add counter: {:#x}", pdhstatus);
+            continue;
         }
 
-        counter_handles.insert(counter.to_string(), phcounter);
-        counter_data.insert(counter.to_string(), Vec::<CounterValueWithTime>::new());
+        if let Some(info) = get_counter_info(phcounter) {
+            counter_info.insert(counter.to_string(), info);
+        }
     }
 
-    loop {
-        let mut filetime: i64 = 0;
-        let pdhstatus = unsafe { PdhCollectQueryDataWithTime(phquery, &mut filetime) };
+    unsafe { PdhCloseQuery(phquery) };
 
-        if pdhstatus != 0 {
-            break;
+    counter_info
+}
+
+fn get_counter_info(hcounter: isize) -> Option<CounterInfo> {
+    let mut buffer_size = 0;
+    unsafe { PdhGetCounterInfoW(hcounter, BOOLEAN(0), &mut buffer_size, None) };
+
+    if buffer_size == 0 {
+        return None;
+    }
+
+    // The buffer holds the struct followed by its strings, so allocate it in
+    // u64s to keep the struct aligned.
+    let mut buffer = vec![0u64; (buffer_size as usize).div_ceil(8)];
+    let info_ptr = buffer.as_mut_ptr() as *mut PDH_COUNTER_INFO_W;
+    let pdhstatus =
+        unsafe { PdhGetCounterInfoW(hcounter, BOOLEAN(0), &mut buffer_size, Some(info_ptr)) };
+
+    if pdhstatus != 0 {
+        return None;
+    }
+
+    let info = unsafe { &*info_ptr };
+
+    Some(CounterInfo {
+        counter_type: info.dwType,
+        default_scale: info.lDefaultScale,
+    })
+}
+
+// The samples of a counter PDH gave no value for, counted by the status it
+// gave instead.
+#[derive(Clone)]
+pub struct CounterErrors {
+    pub statuses: BTreeMap<u32, u64>,
+    pub first: OffsetDateTime,
+    pub last: OffsetDateTime,
+}
+
+impl CounterErrors {
+    pub fn new(status: u32, time: OffsetDateTime) -> CounterErrors {
+        CounterErrors {
+            statuses: BTreeMap::from([(status, 1)]),
+            first: time,
+            last: time,
+        }
+    }
+
+    pub fn record(&mut self, status: u32, time: OffsetDateTime) {
+        *self.statuses.entry(status).or_default() += 1;
+        self.first = self.first.min(time);
+        self.last = self.last.max(time);
+    }
+
+    pub fn merge(&mut self, other: CounterErrors) {
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
         }
+        self.first = self.first.min(other.first);
+        self.last = self.last.max(other.last);
+    }
 
-        let time = get_time_from_filetime(filetime);
+    pub fn count(&self) -> u64 {
+        self.statuses.values().sum()
+    }
+}
 
-        for (counter_name, h_counter) in &counter_handles {
-            let mut pvalue = PDH_FMT_COUNTERVALUE::default();
-            let pdhstatus = unsafe {
-                PdhGetFormattedCounterValue(*h_counter, PDH_FMT_LARGE, None, &mut pvalue)
-            };
+pub fn status_name(status: u32) -> String {
+    let name = match status {
+        PDH_INVALID_DATA | PDH_CSTATUS_INVALID_DATA => "invalid data",
+        PDH_CSTATUS_NEW_DATA => "first sample",
+        PDH_CSTATUS_NO_MACHINE => "no machine",
+        PDH_CSTATUS_NO_OBJECT => "no object",
+        PDH_CSTATUS_NO_COUNTER => "no counter",
+        PDH_CSTATUS_NO_COUNTERNAME => "no counter name",
+        PDH_CSTATUS_BAD_COUNTERNAME => "bad counter path",
+        PDH_CSTATUS_NO_INSTANCE => "no instance",
+        PDH_NO_DATA => "no data",
+        PDH_CALC_NEGATIVE_DENOMINATOR => "negative denominator",
+        PDH_CALC_NEGATIVE_TIMEBASE => "negative time base",
+        PDH_CALC_NEGATIVE_VALUE => "negative value",
+        _ => return format!("status {:#x}", status),
+    };
 
-            match pdhstatus {
-                PDH_INVALID_DATA => println!("{} {}: {}", time, counter_name, "Invalid data"),
+    name.to_string()
+}
 
-                0 => match pvalue.CStatus {
-                    0 => unsafe {
-                        let cv = CounterValueWithTime::Large(time, pvalue.Anonymous.largeValue);
-                        counter_data
-                            .get_mut(counter_name)
-                            .expect("Key not found")
-                            .push(cv);
-                    },
-                    _ => {
-                        println!(
-                            "{} {}: Unexpected CStatus {}",
-                            time, counter_name, pvalue.CStatus
-                        );
-                    }
-                },
+// With since, only the samples from that time on are read.
+pub fn read_counter_values(
+    hdatasource: isize,
+    counters_to_read: &Vec<&String>,
+    since: Option<OffsetDateTime>,
+) -> (HashMap<String, Series>, HashMap<String, CounterErrors>) {
+    // Every counter has at most one sample per collection, so sizing the
+    // columns up front saves regrowing them on big reads.
+    let sample_count = get_time_info(hdatasource).SampleCount as usize;
+
+    if let [counter] = counters_to_read.as_slice() {
+        let (series, counter_errors) =
+            read_single_counter(hdatasource, counter, sample_count, since);
+        let errors = counter_errors
+            .map(|e| HashMap::from([(counter.to_string(), e)]))
+            .unwrap_or_default();
+        return (HashMap::from([(counter.to_string(), series)]), errors);
+    }
 
-                _ => {
-                    panic!("Failed to get counter value: {:#x}", pdhstatus);
-                }
+    let mut builder = SeriesBuilder::new(counters_to_read.len(), sample_count);
+
+    let errors = for_each_counter_value(hdatasource, counters_to_read, since, |index, cv| {
+        builder.push(index, cv);
+    });
+
+    let samples = counters_to_read
+        .iter()
+        .map(|counter| counter.to_string())
+        .zip(builder.finish())
+        .collect();
+
+    (samples, errors)
+}
+
+// Counters with at least this many instances of one counter selected, like
+// \Process(*)\% Processor Time, are read with one wildcard counter and a
+// PdhGetFormattedCounterArrayW call per collection rather than a counter per
+// instance.
+const MIN_ARRAY_INSTANCES: usize = 4;
+
+// The instances of a counter read with one wildcard counter, by the name
+// PdhGetFormattedCounterArrayW gives them, with their index in
+// counters_to_read.
+struct InstanceArray {
+    path: String,
+    instances: HashMap<String, usize>,
+}
+
+// Splits the counters into the ones read on their own, by index, and the
+// ones read as instance arrays. Instances with a parent, like
+// \Thread(svchost/0)\..., are always read on their own.
+fn group_instances(counters_to_read: &[&String]) -> (Vec<usize>, Vec<InstanceArray>) {
+    let mut groups = Vec::<(String, Vec<(String, usize)>)>::new();
+    let mut group_index = HashMap::<String, usize>::new();
+    let mut single = Vec::new();
+
+    for (index, counter) in counters_to_read.iter().enumerate() {
+        let mut path = match CounterPath::parse(counter) {
+            Some(path) => path,
+            None => {
+                single.push(index);
+                continue;
             }
-        }
+        };
+        let instance = match path.instance.take() {
+            Some(instance) if !instance.contains(['/', '*', '?']) => instance,
+            _ => {
+                single.push(index);
+                continue;
+            }
+        };
+        path.instance = Some("*".to_string());
+
+        let wildcard = path.to_string();
+        let at = *group_index.entry(wildcard.clone()).or_insert_with(|| {
+            groups.push((wildcard, Vec::new()));
+            groups.len() - 1
+        });
+        groups[at].1.push((instance, index));
+    }
+
+    let mut arrays = Vec::new();
+    for (path, members) in groups {
+        if members.len() < MIN_ARRAY_INSTANCES {
+            single.extend(members.iter().map(|(_, index)| *index));
+            continue;
+        }
+        arrays.push(InstanceArray {
+            path,
+            instances: members.into_iter().collect(),
+        });
+    }
+    single.sort_unstable();
+
+    (single, arrays)
+}
+
+// The values of every instance of a wildcard counter in the current
+// collection. The buffer is kept between calls and grown when PDH asks for
+// more, as u64s so the items in it are aligned.
+fn read_instance_array(
+    h_counter: isize,
+    buffer: &mut Vec<u64>,
+) -> Result<&[PDH_FMT_COUNTERVALUE_ITEM_W], u32> {
+    loop {
+        let mut buffer_size = (buffer.len() * std::mem::size_of::<u64>()) as u32;
+        let mut item_count = 0u32;
+        let pdhstatus = unsafe {
+            PdhGetFormattedCounterArrayW(
+                h_counter,
+                value_format(),
+                &mut buffer_size,
+                &mut item_count,
+                Some(buffer.as_mut_ptr() as *mut PDH_FMT_COUNTERVALUE_ITEM_W),
+            )
+        };
+
+        match pdhstatus {
+            0 => {
+                return Ok(unsafe {
+                    std::slice::from_raw_parts(
+                        buffer.as_ptr() as *const PDH_FMT_COUNTERVALUE_ITEM_W,
+                        item_count as usize,
+                    )
+                })
+            }
+            PDH_MORE_DATA => buffer.resize(
+                (buffer_size as usize).div_ceil(std::mem::size_of::<u64>()),
+                0,
+            ),
+            _ => return Err(pdhstatus),
+        }
+    }
+}
+
+// Records a sample PDH gave no value for, and with --keep-invalid, passes it
+// to f as the placeholder value.
+fn record_invalid(
+    errors: &mut HashMap<String, CounterErrors>,
+    counter: &str,
+    index: usize,
+    status: u32,
+    time: OffsetDateTime,
+    f: &mut impl FnMut(usize, CounterValueWithTime),
+) {
+    match errors.get_mut(counter) {
+        Some(counter_errors) => counter_errors.record(status, time),
+        None => {
+            errors.insert(counter.to_string(), CounterErrors::new(status, time));
+        }
+    }
+
+    if keep_invalid() {
+        f(
+            index,
+            CounterValueWithTime::Double(time, invalid_value(status)),
+        );
+    }
+}
+
+// Calls f with the index of the counter in counters_to_read and each sample
+// as it's read, instead of keeping them. Returns the samples that had no
+// value, by counter. Those are only passed to f with --keep-invalid.
+pub fn for_each_counter_value(
+    hdatasource: isize,
+    counters_to_read: &Vec<&String>,
+    since: Option<OffsetDateTime>,
+    mut f: impl FnMut(usize, CounterValueWithTime),
+) -> HashMap<String, CounterErrors> {
+    let mut query = CounterQuery::open(hdatasource);
+    let (single, arrays) = group_instances(counters_to_read);
+
+    // Paths that differ only in case, like from logs of \\HOST and \\host,
+    // share a counter in the query, so each gets the handle add returns
+    // rather than one by its position in the query.
+    let handles = single
+        .iter()
+        .map(|index| counters_to_read[*index].as_str())
+        .chain(arrays.iter().map(|array| array.path.as_str()))
+        .map(|path| match query.add(path) {
+            Ok(handle) => handle,
+            Err(pdhstatus) => panic!("Failed to add counter: {:#x}", pdhstatus),
+        })
+        .collect::<Vec<isize>>();
+    let (single_handles, array_handles) = handles.split_at(single.len());
+    if let Some(since) = since {
+        query.read_from(hdatasource, since);
+    }
+
+    // PdhGetFormattedCounterValue fills in every field it reports, so one
+    // value is reused for every counter and sample.
+    let mut pvalue = PDH_FMT_COUNTERVALUE::default();
+    let mut buffer = Vec::<u64>::new();
+    // Whether each counter had an instance in the array just read, by its
+    // position in counters_to_read.
+    let mut seen = vec![false; counters_to_read.len()];
+    let mut occurrences = HashMap::<String, u32>::new();
+    let mut errors = HashMap::<String, CounterErrors>::new();
+    let mut reader = LogReader::new(query.handle);
+
+    while let Some(time) = reader.next() {
+        for (&index, &h_counter) in single.iter().zip(single_handles) {
+            let pdhstatus = unsafe {
+                PdhGetFormattedCounterValue(h_counter, value_format(), None, &mut pvalue)
+            };
+
+            let status = match pdhstatus {
+                PDH_INVALID_DATA => pdhstatus,
+
+                0 => match pvalue.CStatus {
+                    0 => unsafe {
+                        let cv = CounterValueWithTime::Double(time, pvalue.Anonymous.doubleValue);
+                        f(index, cv);
+                        continue;
+                    },
+                    cstatus => cstatus,
+                },
+
+                _ => {
+                    panic!("Failed to get counter value: {:#x}", pdhstatus);
+                }
+            };
+
+            record_invalid(
+                &mut errors,
+                counters_to_read[index],
+                index,
+                status,
+                time,
+                &mut f,
+            );
+        }
+
+        for (array, &h_counter) in arrays.iter().zip(array_handles) {
+            let items = match read_instance_array(h_counter, &mut buffer) {
+                Ok(items) => items,
+                Err(status @ (PDH_INVALID_DATA | PDH_NO_DATA)) => {
+                    for &index in array.instances.values() {
+                        record_invalid(
+                            &mut errors,
+                            counters_to_read[index],
+                            index,
+                            status,
+                            time,
+                            &mut f,
+                        );
+                    }
+                    continue;
+                }
+                Err(pdhstatus) => panic!("Failed to get counter values: {:#x}", pdhstatus),
+            };
+
+            // Instances with the same name, like several svchost processes,
+            // come back with the same name in the order PDH numbers them.
+            let mut found = 0;
+            occurrences.clear();
+            for item in items {
+                let name = decode_utf16(unsafe { item.szName.as_wide() });
+                let occurrence = occurrences.entry(name.clone()).or_default();
+                let name = match *occurrence {
+                    0 => name,
+                    n => format!("{}#{}", name, n),
+                };
+                *occurrence += 1;
+
+                let index = match array.instances.get(&name) {
+                    Some(index) => *index,
+                    None => continue,
+                };
+                seen[index] = true;
+                found += 1;
+
+                match item.FmtValue.CStatus {
+                    0 => f(
+                        index,
+                        CounterValueWithTime::Double(time, unsafe {
+                            item.FmtValue.Anonymous.doubleValue
+                        }),
+                    ),
+                    cstatus => record_invalid(
+                        &mut errors,
+                        counters_to_read[index],
+                        index,
+                        cstatus,
+                        time,
+                        &mut f,
+                    ),
+                }
+            }
+
+            // An instance that isn't in this collection, like a process
+            // that exited, has no value, as it would have read on its own.
+            if found < array.instances.len() {
+                for &index in array.instances.values() {
+                    if !seen[index] {
+                        record_invalid(
+                            &mut errors,
+                            counters_to_read[index],
+                            index,
+                            PDH_CSTATUS_NO_INSTANCE,
+                            time,
+                            &mut f,
+                        );
+                    }
+                }
+            }
+            for &index in array.instances.values() {
+                seen[index] = false;
+            }
+        }
+    }
+
+    reader.report();
+    errors
+}
+
+// Reading one counter, like export --counter or plot, is the common case, so
+// it skips the per-sample closure and series builder and fills the times and
+// values directly.
+fn read_single_counter(
+    hdatasource: isize,
+    counter: &str,
+    sample_count: usize,
+    since: Option<OffsetDateTime>,
+) -> (Series, Option<CounterErrors>) {
+    let mut query = CounterQuery::open(hdatasource);
+    let h_counter = match query.add(counter) {
+        Ok(handle) => handle,
+        Err(pdhstatus) => panic!("Failed to add counter: {:#x}", pdhstatus),
+    };
+    if let Some(since) = since {
+        query.read_from(hdatasource, since);
+    }
+
+    let mut times = Vec::with_capacity(sample_count);
+    let mut values = Vec::with_capacity(sample_count);
+    let mut errors: Option<CounterErrors> = None;
+    let mut pvalue = PDH_FMT_COUNTERVALUE::default();
+    let keep_invalid = keep_invalid();
+    let mut reader = LogReader::new(query.handle);
+
+    while let Some(time) = reader.next() {
+        let pdhstatus =
+            unsafe { PdhGetFormattedCounterValue(h_counter, value_format(), None, &mut pvalue) };
+
+        let status = match (pdhstatus, pvalue.CStatus) {
+            (0, 0) => {
+                times.push(time);
+                values.push(unsafe { pvalue.Anonymous.doubleValue });
+                continue;
+            }
+            (0, cstatus) => cstatus,
+            (PDH_INVALID_DATA, _) => pdhstatus,
+            _ => panic!("Failed to get counter value: {:#x}", pdhstatus),
+        };
+
+        match &mut errors {
+            Some(errors) => errors.record(status, time),
+            None => errors = Some(CounterErrors::new(status, time)),
+        }
+
+        if keep_invalid {
+            times.push(time);
+            values.push(invalid_value(status));
+        }
+    }
+
+    reader.report();
+    (Series::from_doubles(times, values), errors)
+}
+
+// Damaged collections in a row that --salvage skips before giving up on the
+// rest of the log.
+const MAX_DAMAGED_SAMPLES: u32 = 100;
+
+// Reads the collections of a query on a log in order. A log cut off by a
+// crash makes PDH fail partway through with an error instead of
+// PDH_NO_MORE_DATA. That ends the read early, which is reported, or with
+// --salvage, the damaged collections are skipped until the log reads again.
+// With --every, only the first of each run of that many collections is
+// returned.
+struct LogReader {
+    handle: isize,
+    every: u64,
+    samples: u64,
+    last: Option<OffsetDateTime>,
+    damaged: BTreeMap<u32, u64>,
+    // Collections whose FILETIME was out of range, which are skipped.
+    bad_times: u64,
+    stopped: Option<u32>,
+}
+
+impl LogReader {
+    fn new(handle: isize) -> LogReader {
+        LogReader {
+            handle,
+            every: every(),
+            samples: 0,
+            last: None,
+            damaged: BTreeMap::new(),
+            bad_times: 0,
+            stopped: None,
+        }
+    }
+
+    fn next(&mut self) -> Option<OffsetDateTime> {
+        let mut failures = 0;
+
+        loop {
+            let mut filetime: i64 = 0;
+            match unsafe { PdhCollectQueryDataWithTime(self.handle, &mut filetime) } {
+                0 => {
+                    let time = match get_time_from_filetime(filetime) {
+                        Some(time) => time,
+                        None => {
+                            self.bad_times += 1;
+                            continue;
+                        }
+                    };
+                    self.samples += 1;
+                    self.last = Some(time);
+                    // The skipped collections still have to be made, so
+                    // rates are worked out from the one just before.
+                    if (self.samples - 1).is_multiple_of(self.every) {
+                        return Some(time);
+                    }
+                    failures = 0;
+                }
+                PDH_NO_MORE_DATA | PDH_END_OF_LOG_FILE => return None,
+                pdhstatus => {
+                    *self.damaged.entry(pdhstatus).or_default() += 1;
+                    failures += 1;
+                    if !salvage() || failures >= MAX_DAMAGED_SAMPLES {
+                        self.stopped = Some(pdhstatus);
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    fn report(&self) {
+        if self.stopped.is_some() || !self.damaged.is_empty() || self.bad_times > 0 {
+            record(Outcome::PartialRead);
+        }
+
+        if self.bad_times > 0 {
+            eprintln!(
+                "Skipped {} samples with an invalid timestamp.",
+                self.bad_times
+            );
+        }
+
+        let through = match self.last {
+            Some(last) => format!(
+                ", through {}",
+                last.to_offset(display_offset())
+                    .format(format_description!(
+                        "[year]-[month]-[day] [hour]:[minute]:[second]"
+                    ))
+                    .unwrap()
+            ),
+            None => String::new(),
+        };
+
+        if !salvage() {
+            if let Some(pdhstatus) = self.stopped {
+                eprintln!(
+                    "The log stopped reading with {} after {} samples{}. It may be cut off; --salvage tries to read past the damage.",
+                    status_name(pdhstatus),
+                    self.samples,
+                    through
+                );
+            }
+            return;
+        }
+
+        if self.damaged.is_empty() {
+            return;
+        }
+
+        let statuses = self
+            .damaged
+            .iter()
+            .map(|(status, count)| format!("{} {}", count, status_name(*status)))
+            .collect::<Vec<String>>()
+            .join(", ");
+        eprintln!(
+            "Skipped {} damaged samples ({}) and recovered {} samples{}.",
+            self.damaged.values().sum::<u64>(),
+            statuses,
+            self.samples,
+            through
+        );
+        if self.stopped.is_some() {
+            eprintln!(
+                "Gave up on the rest of the log after {} damaged samples in a row.",
+                MAX_DAMAGED_SAMPLES
+            );
+        }
+    }
+}
+
+// A query whose counters can be added and removed between collections.
+// Removing a counter leaves the others and their previous raw values alone,
+// so rate counters keep calculating without the gap that reopening the query
+// would cause. The query is closed when dropped.
+pub struct CounterQuery {
+    handle: isize,
+    counters: Vec<(String, isize)>,
+}
+
+impl CounterQuery {
+    // Pass 0 as the data source for live data from this machine.
+    pub fn open(hdatasource: isize) -> CounterQuery {
+        let mut handle: isize = isize::default();
+        let pdhstatus = unsafe { PdhOpenQueryH(hdatasource, 0, &mut handle) };
+
+        if pdhstatus != 0 {
+            panic!("Failed to open query: {:#x}", pdhstatus);
+        }
+
+        CounterQuery {
+            handle,
+            counters: Vec::new(),
+        }
+    }
+
+    // Returns the counter's handle. Adding a counter that's already in the
+    // query returns the handle it has.
+    pub fn add(&mut self, path: &str) -> Result<isize, u32> {
+        if let Some(index) = self.position(path) {
+            return Ok(self.counters[index].1);
+        }
+
+        let counter_path = HSTRING::from(path);
+        let mut phcounter: isize = isize::default();
+        let pdhstatus = unsafe { PdhAddCounterW(self.handle, &counter_path, 0, &mut phcounter) };
+
+        if pdhstatus != 0 {
+            return Err(pdhstatus);
+        }
+
+        self.counters.push((path.to_string(), phcounter));
+        Ok(phcounter)
+    }
+
+    // Returns whether the counter was in the query.
+    pub fn remove(&mut self, path: &str) -> bool {
+        match self.position(path) {
+            Some(index) => {
+                let (_, hcounter) = self.counters.remove(index);
+                unsafe { PdhRemoveCounter(hcounter) };
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Makes the query hold exactly these counters, removing the ones not
+    // listed and adding the new ones. Returns the counters that couldn't be
+    // added, with their status.
+    pub fn select(&mut self, paths: &[&String]) -> Vec<(String, u32)> {
+        let removed = self
+            .counters
+            .iter()
+            .map(|(path, _)| path.clone())
+            .filter(|path| !paths.iter().any(|p| p.eq_ignore_ascii_case(path)))
+            .collect::<Vec<String>>();
+        for path in removed {
+            self.remove(&path);
+        }
+
+        paths
+            .iter()
+            .filter_map(|path| self.add(path).err().map(|e| (path.to_string(), e)))
+            .collect()
+    }
+
+    // Limits reading the log to the samples from start through the end of
+    // the log. Like write_log_range, call it once the counters are added.
+    pub fn read_from(&self, hdatasource: isize, start: OffsetDateTime) {
+        let time_info = PDH_TIME_INFO {
+            StartTime: get_filetime_from_time(start),
+            EndTime: get_time_info(hdatasource).EndTime,
+            SampleCount: 0,
+        };
+        let pdhstatus = unsafe { PdhSetQueryTimeRange(self.handle, &time_info) };
+
+        if pdhstatus != 0 {
+            panic!("Failed to set query time range: {:#x}", pdhstatus);
+        }
+    }
+
+    pub fn counters(&self) -> impl Iterator<Item = &str> {
+        self.counters.iter().map(|(path, _)| path.as_str())
+    }
+
+    // Collects the next sample, or returns None at the end of a log or if
+    // the sample's time is invalid.
+    pub fn collect(&self) -> Option<OffsetDateTime> {
+        let mut filetime: i64 = 0;
+        let pdhstatus = unsafe { PdhCollectQueryDataWithTime(self.handle, &mut filetime) };
+
+        if pdhstatus != 0 {
+            return None;
+        }
+
+        get_time_from_filetime(filetime)
+    }
+
+    // The value in the last collection of the counter with the handle add
+    // returned, or the PDH status or CStatus that kept it from having one.
+    // Rate counters have no value until the second collection after they're
+    // added.
+    pub fn formatted_value(&self, hcounter: isize) -> Result<f64, u32> {
+        let mut pvalue = PDH_FMT_COUNTERVALUE::default();
+        let pdhstatus =
+            unsafe { PdhGetFormattedCounterValue(hcounter, value_format(), None, &mut pvalue) };
+
+        match (pdhstatus, pvalue.CStatus) {
+            (0, 0) => Ok(unsafe { pvalue.Anonymous.doubleValue }),
+            (0, cstatus) => Err(cstatus),
+            (pdhstatus, _) => Err(pdhstatus),
+        }
+    }
+
+    fn position(&self, path: &str) -> Option<usize> {
+        self.counters
+            .iter()
+            .position(|(p, _)| p.eq_ignore_ascii_case(path))
+    }
+}
+
+impl Drop for CounterQuery {
+    fn drop(&mut self) {
+        unsafe { PdhCloseQuery(self.handle) };
+    }
+}
+
+// A log written from a live query, the way logman writes one. Each update
+// collects the query's counters and appends them to the log. The log is
+// closed when dropped, which leaves the query open for the next log.
+pub struct LogWriter {
+    handle: isize,
+}
+
+impl LogWriter {
+    pub fn create(
+        path: &str,
+        log_type: PDH_LOG_TYPE,
+        query: &CounterQuery,
+    ) -> Result<LogWriter, u32> {
+        let szlogfilename = HSTRING::from(path);
+        let mut log_type = log_type;
+        let mut handle: isize = isize::default();
+        let pdhstatus = unsafe {
+            PdhOpenLogW(
+                &szlogfilename,
+                PDH_LOG(PDH_LOG_WRITE_ACCESS.0 | PDH_LOG_CREATE_ALWAYS),
+                &mut log_type,
+                query.handle,
+                0,
+                PCWSTR::null(),
+                &mut handle,
+            )
+        };
+
+        if pdhstatus != 0 {
+            return Err(pdhstatus);
+        }
+
+        Ok(LogWriter { handle })
+    }
+
+    pub fn update(&self) -> Result<(), u32> {
+        match unsafe { PdhUpdateLogW(self.handle, PCWSTR::null()) } {
+            0 => Ok(()),
+            pdhstatus => Err(pdhstatus),
+        }
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        unsafe { PdhCloseLog(self.handle, 0) };
+    }
+}
+
+// Like read_counter_values, but keeps the raw PDH values. When rate_window is
+// nonzero, each sample also gets the value calculated against the sample that
+// many collections earlier, which is what perfmon shows for a window of 1.
+pub fn read_raw_counter_values(
+    hdatasource: isize,
+    counters_to_read: &Vec<&String>,
+    rate_window: usize,
+) -> (
+    HashMap<String, Vec<RawCounterValue>>,
+    HashMap<String, CounterErrors>,
+) {
+    let sample_count = get_time_info(hdatasource).SampleCount as usize;
+
+    let mut phquery: isize = isize::default();
+    let pdhstatus = unsafe { PdhOpenQueryH(hdatasource, 0, &mut phquery) };
+
+    if pdhstatus != 0 {
+        panic!("Failed to open query: {:#x}", pdhstatus);
+    }
+
+    let mut counter_handles = Vec::<isize>::with_capacity(counters_to_read.len());
+    let mut counter_data = Vec::<Vec<RawCounterValue>>::with_capacity(counters_to_read.len());
+
+    for counter in counters_to_read {
+        let counter_path = HSTRING::from(*counter);
+        let mut phcounter: isize = isize::default();
+        let pdhstatus = unsafe { PdhAddCounterW(phquery, &counter_path, 0, &mut phcounter) };
+
+        if pdhstatus != 0 {
+            panic!("Failed to add counter: {:#x}", pdhstatus);
+        }
+
+        counter_handles.push(phcounter);
+        counter_data.push(Vec::with_capacity(sample_count));
+    }
+
+    let mut raw = PDH_RAW_COUNTER::default();
+    let mut errors = HashMap::<String, CounterErrors>::new();
+    let mut reader = LogReader::new(phquery);
+
+    while let Some(time) = reader.next() {
+        for (index, h_counter) in counter_handles.iter().enumerate() {
+            let pdhstatus = unsafe { PdhGetRawCounterValue(*h_counter, None, &mut raw) };
+
+            // Counted like the formatted values' errors, rather than a line
+            // for each sample.
+            let status = match (pdhstatus, raw.CStatus) {
+                (0, PDH_CSTATUS_VALID_DATA | PDH_CSTATUS_NEW_DATA) => None,
+                (0, cstatus) => Some(cstatus),
+                (pdhstatus, _) => Some(pdhstatus),
+            };
+            if let Some(status) = status {
+                match errors.get_mut(counters_to_read[index].as_str()) {
+                    Some(counter_errors) => counter_errors.record(status, time),
+                    None => {
+                        errors.insert(
+                            counters_to_read[index].to_string(),
+                            CounterErrors::new(status, time),
+                        );
+                    }
+                }
+                continue;
+            }
+
+            let series = &mut counter_data[index];
+
+            let rate = if rate_window > 0 && series.len() >= rate_window {
+                calculate_rate(*h_counter, &raw, &series[series.len() - rate_window].raw)
+            } else {
+                None
+            };
+
+            series.push(RawCounterValue { time, raw, rate });
+        }
+    }
+
+    reader.report();
+    unsafe { PdhCloseQuery(phquery) };
+
+    let values = counters_to_read
+        .iter()
+        .map(|counter| counter.to_string())
+        .zip(counter_data)
+        .collect();
+
+    (values, errors)
+}
+
+// Calculates the displayable value from two raw samples of the same counter
+// the way perfmon does. The counter handle supplies the counter type.
+pub fn calculate_rate(
+    hcounter: isize,
+    newer: &PDH_RAW_COUNTER,
+    older: &PDH_RAW_COUNTER,
+) -> Option<f64> {
+    let mut pvalue = PDH_FMT_COUNTERVALUE::default();
+    let pdhstatus = unsafe {
+        PdhCalculateCounterFromRawValue(hcounter, value_format(), newer, older, &mut pvalue)
+    };
+
+    if pdhstatus != 0 || pvalue.CStatus != PDH_CSTATUS_VALID_DATA {
+        return None;
+    }
+
+    Some(unsafe { pvalue.Anonymous.doubleValue })
+}
+
+pub fn get_filetime_from_raw(raw: &PDH_RAW_COUNTER) -> i64 {
+    ((raw.TimeStamp.dwHighDateTime as i64) << 32) | raw.TimeStamp.dwLowDateTime as i64
+}
+
+pub fn write_log_range(
+    hdatasource: isize,
+    counters: &Vec<&String>,
+    output_file: &str,
+    log_type: PDH_LOG_TYPE,
+    start_time: time::OffsetDateTime,
+    end_time: time::OffsetDateTime,
+) -> u32 {
+    let mut phquery: isize = isize::default();
+    let pdhstatus = unsafe { PdhOpenQueryH(hdatasource, 0, &mut phquery) };
+
+    if pdhstatus != 0 {
+        panic!("Failed to open query: {:#x}", pdhstatus);
+    }
+
+    for counter in counters {
+        let counter_path = HSTRING::from(*counter);
+        let mut phcounter: isize = isize::default();
+        let pdhstatus = unsafe { PdhAddCounterW(phquery, &counter_path, 0, &mut phcounter) };
+
+        if pdhstatus != 0 {
+            panic!("Failed to add counter: {:#x}", pdhstatus);
+        }
+    }
+
+    // The end of the range is inclusive, so stop one tick short of end_time
+    // to keep adjacent chunks from sharing a sample.
+    let time_info = PDH_TIME_INFO {
+        StartTime: get_filetime_from_time(start_time),
+        EndTime: get_filetime_from_time(end_time) - 1,
+        SampleCount: 0,
+    };
+    let pdhstatus = unsafe { PdhSetQueryTimeRange(phquery, &time_info) };
+
+    if pdhstatus != 0 {
+        panic!("Failed to set query time range: {:#x}", pdhstatus);
+    }
+
+    let szlogfilename = HSTRING::from(output_file);
+    let mut log_type = log_type;
+    let mut phlog: isize = isize::default();
+    let pdhstatus = unsafe {
+        PdhOpenLogW(
+            &szlogfilename,
+            PDH_LOG(PDH_LOG_WRITE_ACCESS.0 | PDH_LOG_CREATE_ALWAYS),
+            &mut log_type,
+            phquery,
+            0,
+            PCWSTR::null(),
+            &mut phlog,
+        )
+    };
+
+    if pdhstatus != 0 {
+        panic!(
+            "Failed to open output log {}: {:#x}",
+            output_file, pdhstatus
+        );
+    }
+
+    let mut samples_written = 0;
+    loop {
+        let pdhstatus = unsafe { PdhUpdateLogW(phlog, PCWSTR::null()) };
+
+        if pdhstatus != 0 {
+            break;
+        }
+
+        samples_written += 1;
+    }
+
+    unsafe {
+        PdhCloseLog(phlog, 0);
+        PdhCloseQuery(phquery);
+    }
+
+    samples_written
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::{get_filetime_from_time, get_time_from_filetime, parse_multi_sz};
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    #[test]
+    fn multi_sz_ends_at_double_null() {
+        assert_eq!(
+            parse_multi_sz(&wide("Processor\0Memory\0\0")),
+            ["Processor", "Memory"]
+        );
+    }
+
+    #[test]
+    fn multi_sz_ignores_what_follows_the_terminator() {
+        assert_eq!(
+            parse_multi_sz(&wide("Processor\0Memory\0\0\0\0")),
+            ["Processor", "Memory"]
+        );
+        assert_eq!(
+            parse_multi_sz(&wide("Processor\0\0Stale\0\0")),
+            ["Processor"]
+        );
+    }
+
+    #[test]
+    fn multi_sz_keeps_the_last_string_without_terminators() {
+        assert_eq!(
+            parse_multi_sz(&wide("Processor\0Memory\0")),
+            ["Processor", "Memory"]
+        );
+        assert_eq!(
+            parse_multi_sz(&wide("Processor\0Memory")),
+            ["Processor", "Memory"]
+        );
+    }
+
+    #[test]
+    fn multi_sz_empty_lists() {
+        assert!(parse_multi_sz(&[]).is_empty());
+        assert!(parse_multi_sz(&[0]).is_empty());
+        assert!(parse_multi_sz(&[0, 0]).is_empty());
+    }
+
+    #[test]
+    fn multi_sz_replaces_unpaired_surrogates() {
+        let mut buffer = wide("Caf");
+        buffer.push(0xD800);
+        buffer.extend(wide("\0Memory\0\0"));
+        assert_eq!(parse_multi_sz(&buffer), ["Caf\u{FFFD}", "Memory"]);
+    }
+
+    #[test]
+    fn multi_sz_single_string() {
+        assert_eq!(parse_multi_sz(&wide("_Total\0\0")), ["_Total"]);
+    }
+
+    #[test]
+    fn filetime_round_trips() {
+        let time = datetime!(2024-06-12 08:30:15.1234567 UTC);
+        assert_eq!(
+            get_time_from_filetime(get_filetime_from_time(time)),
+            Some(time)
+        );
+    }
+
+    #[test]
+    fn filetime_out_of_range_is_invalid() {
+        assert_eq!(get_time_from_filetime(0), None);
+        assert_eq!(get_time_from_filetime(-1), None);
+        assert_eq!(get_time_from_filetime(i64::MAX), None);
     }
-    counter_data
 }
@@ -0,0 +1,393 @@
+use std::io::IsTerminal;
+
+use time::{
+    format_description::BorrowedFormatItem, macros::format_description, OffsetDateTime, UtcOffset,
+};
+
+use crate::{
+    aggregate::aggregate_across,
+    chart::write_chart,
+    cli::PlotArgs,
+    derive::{evaluate_all, is_selected, read_selection},
+    reader::read_counters,
+    resample::rolling,
+    selection::CounterSelection,
+    status::progress,
+    timespec::zone_label,
+};
+
+// Room for the y axis labels to the left of the chart.
+const LABEL_WIDTH: usize = 11;
+
+const DEFAULT_WIDTH: usize = 100;
+
+// Minimum number of columns between the start of two time labels.
+const TICK_SPACING: usize = 16;
+
+const COLORS: [u8; 6] = [32, 33, 36, 35, 31, 34];
+
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(OffsetDateTime, f64)>,
+}
+
+pub fn plot(args: &PlotArgs) {
+    let selection = CounterSelection::new(&args.counter);
+    let mut counter_data =
+        match read_counters(&args.source, &read_selection(&selection, &args.derive)) {
+            Some(counter_data) => counter_data,
+            None => return,
+        };
+    let derived = match evaluate_all(&counter_data, &args.derive) {
+        Some(derived) => derived,
+        None => return,
+    };
+
+    let time_filter = args.time_filter.time_filter();
+
+    let counters = counter_data
+        .counters
+        .iter()
+        .filter(|c| is_selected(&selection, &args.derive, c))
+        .cloned()
+        .collect::<Vec<String>>();
+    let samples = counters
+        .iter()
+        .map(|c| counter_data.samples.remove(c).unwrap_or_default())
+        .collect();
+    let (counters, samples) = aggregate_across(counters, samples, &args.aggregate);
+
+    let series = counters
+        .into_iter()
+        .zip(samples)
+        .chain(args.derive.iter().map(|d| d.name.clone()).zip(derived))
+        .map(|(name, samples)| {
+            let samples = match args.rolling {
+                Some(window) => rolling(&samples, window, args.rolling_stat),
+                None => samples,
+            };
+            Series {
+                name,
+                points: samples
+                    .iter()
+                    .filter(|s| time_filter.matches(s.time()))
+                    .map(|s| (s.time(), s.value()))
+                    .collect(),
+            }
+        })
+        .filter(|s| !s.points.is_empty())
+        .collect::<Vec<Series>>();
+
+    if series.is_empty() {
+        eprintln!("No samples matched.");
+        return;
+    }
+
+    if let Some(output) = &args.output {
+        match write_chart(
+            output,
+            &series,
+            &args.threshold,
+            args.size,
+            time_filter.offset,
+        ) {
+            Ok(()) => progress!("Wrote {}", output),
+            Err(e) => eprintln!("Failed to write {}: {}", output, e),
+        }
+        return;
+    }
+
+    let width = args.width.unwrap_or_else(terminal_width);
+    let columns = width.saturating_sub(LABEL_WIDTH + 1).max(10);
+    let color = std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal();
+
+    print!(
+        "{}",
+        render_braille(
+            &series,
+            columns,
+            args.height.max(2),
+            &args.threshold,
+            time_filter.offset,
+            color
+        )
+    );
+}
+
+pub fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+// Each braille character is a 2x4 grid of dots, so the chart has twice the
+// horizontal and four times the vertical resolution of the character grid.
+struct BrailleCanvas {
+    columns: usize,
+    cells: Vec<u8>,
+    colors: Vec<Option<usize>>,
+}
+
+impl BrailleCanvas {
+    fn new(columns: usize, rows: usize) -> BrailleCanvas {
+        BrailleCanvas {
+            columns,
+            cells: vec![0; columns * rows],
+            colors: vec![None; columns * rows],
+        }
+    }
+
+    // Sets a dot without changing the cell's color.
+    fn dot(&mut self, x: usize, y: usize) -> usize {
+        const DOTS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+        let index = y / 4 * self.columns + x / 2;
+        self.cells[index] |= DOTS[x % 2][y % 4];
+        index
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: usize) {
+        let index = self.dot(x, y);
+        self.colors[index] = Some(color);
+    }
+
+    fn line(&mut self, from: (usize, usize), to: (usize, usize), color: usize) {
+        let (mut x, mut y) = (from.0 as i64, from.1 as i64);
+        let (x1, y1) = (to.0 as i64, to.1 as i64);
+        let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+        let (sx, sy) = (if x < x1 { 1 } else { -1 }, if y < y1 { 1 } else { -1 });
+        let mut error = dx + dy;
+
+        loop {
+            self.set(x as usize, y as usize, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += sx;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn row(&self, row: usize, color: bool) -> String {
+        let mut line = String::new();
+        let mut current = None;
+
+        for column in 0..self.columns {
+            let index = row * self.columns + column;
+            let cell_color = self.colors[index].filter(|_| color);
+            if cell_color != current {
+                match cell_color {
+                    Some(c) => line.push_str(&format!("\x1b[{}m", COLORS[c % COLORS.len()])),
+                    None => line.push_str("\x1b[0m"),
+                }
+                current = cell_color;
+            }
+            line.push(char::from_u32(0x2800 + self.cells[index] as u32).unwrap());
+        }
+
+        if current.is_some() {
+            line.push_str("\x1b[0m");
+        }
+
+        line
+    }
+}
+
+pub fn render_braille(
+    series: &[Series],
+    columns: usize,
+    rows: usize,
+    thresholds: &[f64],
+    offset: UtcOffset,
+    color: bool,
+) -> String {
+    let (start, end) = time_range(series);
+    let (min, max) = value_range(series, thresholds);
+
+    let (dots_x, dots_y) = (columns * 2 - 1, rows * 4 - 1);
+    let span = (end - start).as_seconds_f64();
+
+    let to_dot = |(time, value): (OffsetDateTime, f64)| {
+        let x = if span > 0.0 {
+            ((time - start).as_seconds_f64() / span * dots_x as f64).round() as usize
+        } else {
+            0
+        };
+        let y = ((max - value) / (max - min) * dots_y as f64).round() as usize;
+        (x.min(dots_x), y.min(dots_y))
+    };
+
+    let mut canvas = BrailleCanvas::new(columns, rows);
+
+    for threshold in thresholds {
+        let (_, y) = to_dot((start, *threshold));
+        for x in (0..=dots_x).step_by(3) {
+            canvas.dot(x, y);
+        }
+    }
+
+    for (index, s) in series.iter().enumerate() {
+        let mut previous = None;
+        for point in &s.points {
+            let dot = to_dot(*point);
+            match previous {
+                Some(previous) => canvas.line(previous, dot, index),
+                None => canvas.set(dot.0, dot.1, index),
+            }
+            previous = Some(dot);
+        }
+    }
+
+    let mut output = String::new();
+
+    for row in 0..rows {
+        let label = if row == 0 {
+            format_axis_value(max)
+        } else if row == rows - 1 {
+            format_axis_value(min)
+        } else if row == rows / 2 {
+            format_axis_value(max - (max - min) * (row as f64 + 0.5) / rows as f64)
+        } else {
+            String::new()
+        };
+        output.push_str(&format!(
+            "{:>width$} ┤{}\n",
+            label,
+            canvas.row(row, color),
+            width = LABEL_WIDTH - 1
+        ));
+    }
+
+    let (axis, labels) = time_axis(start, end, columns, offset);
+    output.push_str(&format!("{:>width$}└{}\n", "", axis, width = LABEL_WIDTH));
+    output.push_str(&format!(
+        "{:>width$}{}\n",
+        "",
+        labels,
+        width = LABEL_WIDTH + 1
+    ));
+
+    if offset != UtcOffset::UTC {
+        output.push_str(&format!(
+            "{:>width$}Times are {}\n",
+            "",
+            zone_label(offset),
+            width = LABEL_WIDTH + 1
+        ));
+    }
+
+    output.push('\n');
+
+    for (index, s) in series.iter().enumerate() {
+        let values = s.points.iter().map(|p| p.1);
+        let marker = if color {
+            format!("\x1b[{}m⣿\x1b[0m", COLORS[index % COLORS.len()])
+        } else {
+            "⣿".to_string()
+        };
+        output.push_str(&format!(
+            "{} {}  min {:.2}  avg {:.2}  max {:.2}\n",
+            marker,
+            s.name,
+            values.clone().fold(f64::MAX, f64::min),
+            values.clone().sum::<f64>() / s.points.len() as f64,
+            values.fold(f64::MIN, f64::max)
+        ));
+    }
+
+    output
+}
+
+pub fn format_axis_value(value: f64) -> String {
+    if value.abs() >= 1_000_000_000.0 {
+        format!("{:.3e}", value)
+    } else if value.abs() >= 1000.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+// Returns the axis line with tick marks and the line of time labels under it.
+pub fn time_axis(
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+    columns: usize,
+    offset: UtcOffset,
+) -> (String, String) {
+    let span = end - start;
+    let format = time_label_format(start, end, offset);
+
+    let mut axis = vec!['─'; columns];
+    let mut labels = vec![' '; columns];
+
+    let mut column = 0;
+    while column < columns {
+        let time = start + span * (column as f64 / (columns - 1).max(1) as f64);
+        let label = time.to_offset(offset).format(format).unwrap();
+        let length = label.chars().count();
+
+        if column + length > columns {
+            break;
+        }
+
+        axis[column] = '┬';
+        for (i, c) in label.chars().enumerate() {
+            labels[column + i] = c;
+        }
+
+        column += TICK_SPACING.max(length + 2);
+    }
+
+    (axis.into_iter().collect(), labels.into_iter().collect())
+}
+
+pub fn time_range(series: &[Series]) -> (OffsetDateTime, OffsetDateTime) {
+    let times = series.iter().flat_map(|s| &s.points).map(|p| p.0);
+    (times.clone().min().unwrap(), times.max().unwrap())
+}
+
+// Covers every value and threshold, widened when everything is the same so
+// the chart still has some height.
+pub fn value_range(series: &[Series], thresholds: &[f64]) -> (f64, f64) {
+    let values = series
+        .iter()
+        .flat_map(|s| &s.points)
+        .map(|p| p.1)
+        .chain(thresholds.iter().copied());
+    let min = values.clone().fold(f64::MAX, f64::min);
+    let max = values.fold(f64::MIN, f64::max);
+
+    if max == min {
+        (min - 1.0, max + 1.0)
+    } else {
+        (min, max)
+    }
+}
+
+// Shows the date only when the chart crosses midnight, and seconds only when
+// it covers a few minutes.
+pub fn time_label_format(
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+    offset: UtcOffset,
+) -> &'static [BorrowedFormatItem<'static>] {
+    let local = |time: OffsetDateTime| time.to_offset(offset);
+
+    if local(start).date() != local(end).date() {
+        format_description!("[month]-[day] [hour]:[minute]")
+    } else if end - start < time::Duration::minutes(10) {
+        format_description!("[hour]:[minute]:[second]")
+    } else {
+        format_description!("[hour]:[minute]")
+    }
+}
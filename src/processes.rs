@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    cli::ProcessesArgs,
+    counter_path::CounterPath,
+    export::format_time,
+    reader::read_counters,
+    selection::{wildcard_match, CounterSelection},
+    series_set::SeriesSet,
+    timespec::format_duration,
+};
+
+const ID_PROCESS: &str = "ID Process";
+const ELAPSED_TIME: &str = "Elapsed Time";
+
+// Elapsed Time going backwards by more than this means the PID belongs to a
+// new process with the same name.
+const ELAPSED_SLACK: f64 = 1.0;
+
+// A process as seen in one collection.
+struct Sighting {
+    machine: String,
+    name: String,
+    pid: u32,
+    elapsed: Option<f64>,
+}
+
+struct Lifetime {
+    name: String,
+    // Estimated from Elapsed Time, or the first collection it was in.
+    start: OffsetDateTime,
+    last_seen: OffsetDateTime,
+    elapsed: Option<f64>,
+}
+
+enum Event {
+    Start { reused: Option<String> },
+    Exit { ran: Duration },
+}
+
+struct ProcessEvent {
+    time: OffsetDateTime,
+    machine: String,
+    name: String,
+    pid: u32,
+    event: Event,
+}
+
+// Perfmon names processes by their image name, numbered like w3wp#1 when
+// there are several, and the numbers shift as processes come and go. The PID
+// in ID Process is what stays with a process, so lifetimes are followed by
+// PID, and Elapsed Time tells when a process that appears was started and
+// whether a PID was reused.
+pub fn processes(args: &ProcessesArgs) {
+    let patterns = [ID_PROCESS, ELAPSED_TIME]
+        .map(|counter| format!("\\Process(*)\\{}", counter))
+        .to_vec();
+    let counter_data = match read_counters(&args.source, &CounterSelection::new(&patterns)) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    let time_filter = args.time_filter.time_filter();
+    let names = args
+        .name
+        .iter()
+        .map(|n| n.to_lowercase())
+        .collect::<Vec<String>>();
+
+    let series = SeriesSet::from(counter_data);
+    let mut collections = BTreeMap::<OffsetDateTime, Vec<Sighting>>::new();
+    for pids in series.object("Process") {
+        let path = match &pids.path {
+            Some(path) if path.counter.eq_ignore_ascii_case(ID_PROCESS) => path,
+            _ => continue,
+        };
+        let instance = match &path.instance {
+            Some(instance) if instance != "_Total" => instance,
+            _ => continue,
+        };
+
+        let name = match instance.rsplit_once('#') {
+            Some((name, number)) if number.parse::<u32>().is_ok() => name.to_string(),
+            _ => instance.clone(),
+        };
+        if !names.is_empty()
+            && !names
+                .iter()
+                .any(|n| wildcard_match(n, &name.to_lowercase()))
+        {
+            continue;
+        }
+
+        let elapsed_path = CounterPath {
+            machine: path.machine.clone(),
+            object: path.object.clone(),
+            instance: Some(instance.clone()),
+            counter: ELAPSED_TIME.to_string(),
+        };
+        let elapsed = series
+            .get(&elapsed_path.to_string())
+            .map(|s| {
+                s.times()
+                    .zip(s.values())
+                    .collect::<HashMap<OffsetDateTime, f64>>()
+            })
+            .unwrap_or_default();
+
+        for (time, pid) in pids.times().zip(pids.values()) {
+            let pid = pid as u32;
+            // The Idle process is PID 0, and so are instances that went away.
+            if pid == 0 || !time_filter.matches(time) {
+                continue;
+            }
+            collections.entry(time).or_default().push(Sighting {
+                machine: path.machine.clone(),
+                name: name.clone(),
+                pid,
+                elapsed: elapsed.get(&time).copied(),
+            });
+        }
+    }
+
+    if collections.is_empty() {
+        eprintln!("No samples of \\Process(*)\\ID Process matched.");
+        return;
+    }
+
+    let (events, running) = follow_processes(&collections);
+
+    let machines = events
+        .iter()
+        .map(|e| e.machine.as_str())
+        .collect::<HashSet<&str>>();
+
+    for event in &events {
+        let process = if machines.len() > 1 {
+            format!("\\\\{} {} (PID {})", event.machine, event.name, event.pid)
+        } else {
+            format!("{} (PID {})", event.name, event.pid)
+        };
+        match &event.event {
+            Event::Start { reused: None } => {
+                println!("{}  START  {}", format_time(event.time), process)
+            }
+            Event::Start {
+                reused: Some(previous),
+            } => println!(
+                "{}  START  {}, reusing the PID of {}",
+                format_time(event.time),
+                process,
+                previous
+            ),
+            Event::Exit { ran } => println!(
+                "{}  EXIT   {} after {}",
+                format_time(event.time),
+                process,
+                format_duration(*ran)
+            ),
+        }
+    }
+
+    print_churn(&events);
+
+    let starts = events
+        .iter()
+        .filter(|e| matches!(e.event, Event::Start { .. }))
+        .count();
+    eprintln!(
+        "{} processes were running at the start, {} started and {} exited.",
+        running,
+        starts,
+        events.len() - starts
+    );
+}
+
+// Walks the collections in order, starting a lifetime for each PID that
+// appears and ending it when the PID goes away or turns out to be a new
+// process. Returns the events in time order and how many processes were
+// already running in the first collection.
+fn follow_processes(
+    collections: &BTreeMap<OffsetDateTime, Vec<Sighting>>,
+) -> (Vec<ProcessEvent>, usize) {
+    let first = *collections.keys().next().unwrap();
+    let mut active = HashMap::<(String, u32), Lifetime>::new();
+    // The last name each PID had, to spot PIDs being reused.
+    let mut previous = HashMap::<(String, u32), String>::new();
+    let mut events = Vec::new();
+    let mut running = 0;
+
+    let exit = |key: &(String, u32), lifetime: Lifetime, time: OffsetDateTime| ProcessEvent {
+        time,
+        machine: key.0.clone(),
+        name: lifetime.name,
+        pid: key.1,
+        event: Event::Exit {
+            ran: lifetime.last_seen - lifetime.start,
+        },
+    };
+
+    for (time, sightings) in collections {
+        let mut seen = HashSet::new();
+
+        for sighting in sightings {
+            let key = (sighting.machine.clone(), sighting.pid);
+            seen.insert(key.clone());
+
+            if let Some(lifetime) = active.get_mut(&key) {
+                let restarted = lifetime
+                    .elapsed
+                    .zip(sighting.elapsed)
+                    .is_some_and(|(before, now)| now < before - ELAPSED_SLACK);
+                if lifetime.name.eq_ignore_ascii_case(&sighting.name) && !restarted {
+                    lifetime.last_seen = *time;
+                    lifetime.elapsed = sighting.elapsed.or(lifetime.elapsed);
+                    continue;
+                }
+                let ended = active.remove(&key).unwrap();
+                previous.insert(key.clone(), ended.name.clone());
+                events.push(exit(&key, ended, *time));
+            }
+
+            let start = match sighting.elapsed {
+                Some(elapsed) if elapsed >= 0.0 => *time - Duration::seconds_f64(elapsed),
+                _ => *time,
+            };
+
+            if *time == first {
+                running += 1;
+            } else {
+                events.push(ProcessEvent {
+                    time: start,
+                    machine: sighting.machine.clone(),
+                    name: sighting.name.clone(),
+                    pid: sighting.pid,
+                    event: Event::Start {
+                        reused: previous.get(&key).cloned(),
+                    },
+                });
+            }
+
+            active.insert(
+                key,
+                Lifetime {
+                    name: sighting.name.clone(),
+                    start,
+                    last_seen: *time,
+                    elapsed: sighting.elapsed,
+                },
+            );
+        }
+
+        // A process missing from a collection exited since the one before.
+        let gone = active
+            .keys()
+            .filter(|key| !seen.contains(*key))
+            .cloned()
+            .collect::<Vec<(String, u32)>>();
+        for key in gone {
+            let ended = active.remove(&key).unwrap();
+            previous.insert(key.clone(), ended.name.clone());
+            events.push(exit(&key, ended, *time));
+        }
+    }
+
+    events.sort_by_key(|e| e.time);
+    (events, running)
+}
+
+// How many times each process name started and exited, most first, so the
+// ones that kept restarting stand out.
+fn print_churn(events: &[ProcessEvent]) {
+    let mut churn = BTreeMap::<String, (usize, usize)>::new();
+    for event in events {
+        let counts = churn.entry(event.name.clone()).or_default();
+        match event.event {
+            Event::Start { .. } => counts.0 += 1,
+            Event::Exit { .. } => counts.1 += 1,
+        }
+    }
+
+    if churn.is_empty() {
+        return;
+    }
+
+    let mut churn = churn.into_iter().collect::<Vec<(String, (usize, usize))>>();
+    churn.sort_by_key(|(_, (starts, exits))| std::cmp::Reverse(starts + exits));
+
+    println!();
+    println!("{:>6}  {:>6}  Process", "Starts", "Exits");
+    for (name, (starts, exits)) in churn {
+        println!("{:>6}  {:>6}  {}", starts, exits, name);
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use time::Weekday;
+
+use crate::{
+    cli::ProfileArgs,
+    reader::stream_counters,
+    report::CounterStats,
+    stats::{print_header, print_row, StreamingStats},
+    units::Unit,
+};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ProfileBy {
+    /// A row for each hour of the day
+    Hour,
+    /// A row for each day of the week
+    Day,
+}
+
+const DAYS: [Weekday; 7] = [
+    Weekday::Monday,
+    Weekday::Tuesday,
+    Weekday::Wednesday,
+    Weekday::Thursday,
+    Weekday::Friday,
+    Weekday::Saturday,
+    Weekday::Sunday,
+];
+
+// A counter's statistics for each hour of the day and each day of the week,
+// in --timezone, to show the daily and weekly load pattern of a log that
+// covers several days.
+struct Profile {
+    hours: Vec<StreamingStats>,
+    days: Vec<StreamingStats>,
+}
+
+impl Profile {
+    fn new() -> Profile {
+        Profile {
+            hours: (0..24).map(|_| StreamingStats::new()).collect(),
+            days: (0..7).map(|_| StreamingStats::new()).collect(),
+        }
+    }
+}
+
+pub fn profile(args: &ProfileArgs) {
+    let selection = match args.counters.selection() {
+        Some(selection) => selection,
+        None => return,
+    };
+
+    let time_filter = args.time_filter.time_filter();
+
+    let mut profiles = HashMap::<String, Profile>::new();
+    let (counters, info) = match stream_counters(&args.source, &selection, |counter, sample| {
+        if !time_filter.matches(sample.time()) {
+            return;
+        }
+
+        let local = sample.time().to_offset(time_filter.offset);
+        let profile = profiles
+            .entry(counter.to_string())
+            .or_insert_with(Profile::new);
+        profile.hours[local.hour() as usize].add(sample.value());
+        profile.days[local.weekday().number_days_from_monday() as usize].add(sample.value());
+    }) {
+        Some(result) => result,
+        None => return,
+    };
+
+    let by = if args.by.is_empty() {
+        vec![ProfileBy::Hour, ProfileBy::Day]
+    } else {
+        args.by.clone()
+    };
+
+    let mut printed = false;
+    for counter in &counters {
+        let profile = match profiles.get(counter) {
+            Some(profile) => profile,
+            None => continue,
+        };
+        let unit = if args.raw {
+            Unit::Count
+        } else {
+            Unit::lookup(&info, counter)
+        };
+
+        if printed {
+            println!();
+        }
+        printed = true;
+        println!("{}", counter);
+
+        if by.contains(&ProfileBy::Hour) {
+            let rows = profile
+                .hours
+                .iter()
+                .enumerate()
+                .filter_map(|(hour, s)| Some((format!("{:02}:00", hour), s.finish(counter, unit)?)))
+                .collect::<Vec<(String, CounterStats)>>();
+            print_table("Hour", &rows);
+        }
+
+        if by.contains(&ProfileBy::Day) {
+            let rows = profile
+                .days
+                .iter()
+                .zip(DAYS)
+                .filter_map(|(s, day)| Some((day.to_string(), s.finish(counter, unit)?)))
+                .collect::<Vec<(String, CounterStats)>>();
+            print_table("Day", &rows);
+        }
+    }
+
+    if !printed {
+        eprintln!("No samples matched.");
+    }
+}
+
+// The bucket with the highest average is the busy hour or day.
+fn print_table(label: &str, rows: &[(String, CounterStats)]) {
+    print_header(label);
+    for (name, s) in rows {
+        print_row(s, name);
+    }
+
+    if let Some((name, s)) = rows.iter().max_by(|a, b| a.1.avg.total_cmp(&b.1.avg)) {
+        println!(
+            "Busiest {}: {} (avg {})",
+            label.to_lowercase(),
+            name,
+            s.unit.format(s.avg)
+        );
+    }
+}
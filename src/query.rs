@@ -0,0 +1,919 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    io::{BufWriter, Write},
+};
+
+use time::OffsetDateTime;
+
+use crate::{
+    cli::QueryArgs,
+    export::format_time,
+    number_format::format_number,
+    reader::read_counters,
+    selection::{wildcard_match, CounterSelection},
+    series_set::{CounterSeries, SeriesSet},
+    status::progress,
+    timespec::{display_offset, parse_time_spec},
+};
+
+// A query like
+//   SELECT counter, avg(value) FROM samples WHERE counter LIKE '%Disk%'
+//   GROUP BY counter ORDER BY avg(value) DESC LIMIT 10
+// over a row per sample. Only the parts of SQL that make sense for samples
+// are here: no joins, subqueries, or expressions beyond comparisons.
+#[derive(Debug)]
+pub struct Query {
+    items: Vec<Item>,
+    filter: Option<Expr>,
+    group_by: Vec<Column>,
+    order_by: Vec<(String, bool)>,
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Column {
+    Time,
+    Counter,
+    Machine,
+    Object,
+    Instance,
+    Name,
+    Value,
+}
+
+const COLUMNS: [Column; 7] = [
+    Column::Time,
+    Column::Counter,
+    Column::Machine,
+    Column::Object,
+    Column::Instance,
+    Column::Name,
+    Column::Value,
+];
+
+impl Column {
+    fn name(&self) -> &'static str {
+        match self {
+            Column::Time => "time",
+            Column::Counter => "counter",
+            Column::Machine => "machine",
+            Column::Object => "object",
+            Column::Instance => "instance",
+            Column::Name => "name",
+            Column::Value => "value",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Column> {
+        COLUMNS
+            .into_iter()
+            .find(|c| c.name().eq_ignore_ascii_case(name))
+    }
+
+    fn is_text(&self) -> bool {
+        !matches!(self, Column::Time | Column::Value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Aggregate {
+    Count,
+    Avg,
+    Min,
+    Max,
+    Sum,
+}
+
+impl Aggregate {
+    fn parse(name: &str) -> Option<Aggregate> {
+        match name.to_lowercase().as_str() {
+            "count" => Some(Aggregate::Count),
+            "avg" => Some(Aggregate::Avg),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            "sum" => Some(Aggregate::Sum),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Item {
+    Column(Column, String),
+    // count(*) has no column.
+    Aggregate(Aggregate, Option<Column>, String),
+}
+
+impl Item {
+    fn label(&self) -> &str {
+        match self {
+            Item::Column(_, label) | Item::Aggregate(_, _, label) => label,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Column, Op, Literal),
+    // The pattern as a lowercase wildcard pattern.
+    Like(Column, String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Literal {
+    Number(f64),
+    Text(String),
+    // Times are written as text and resolved once the logs are read, since
+    // they can be relative to them, like 'end-30m'.
+    Time(OffsetDateTime),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f64),
+    Text(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 10] = ["<=", ">=", "<>", "!=", "=", "<", ">", ",", "(", ")"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\'' {
+            // Quotes inside text are doubled, like 'it''s'.
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '\'')) => {
+                        if rest[i + 2..].starts_with('\'') {
+                            value.push('\'');
+                            chars.next();
+                        } else {
+                            break i + 2;
+                        }
+                    }
+                    Some((_, c)) => value.push(c),
+                    None => return Err("Text is missing its closing quote.".to_string()),
+                }
+            };
+            tokens.push(Token::Text(value));
+            rest = &rest[end..];
+        } else if c == '*' {
+            tokens.push(Token::Symbol("*"));
+            rest = &rest[1..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if c.is_ascii_digit() || c == '.' || c == '-' {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+                .map_or(rest.len(), |i| i + 1);
+            let number = rest[..end]
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid number: {}", &rest[..end]))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(format!("Unexpected {} in the query.", c));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        if self.keyword(keyword) {
+            Ok(())
+        } else {
+            Err(format!("Expected {}{}.", keyword, self.found()))
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+        if self.symbol(symbol) {
+            Ok(())
+        } else {
+            Err(format!("Expected {}{}.", symbol, self.found()))
+        }
+    }
+
+    // What's in the way, for error messages.
+    fn found(&self) -> String {
+        match self.peek() {
+            Some(Token::Word(w)) => format!(" but found {}", w),
+            Some(Token::Number(n)) => format!(" but found {}", n),
+            Some(Token::Text(t)) => format!(" but found '{}'", t),
+            Some(Token::Symbol(s)) => format!(" but found {}", s),
+            None => " but the query ended".to_string(),
+        }
+    }
+
+    fn column(&mut self) -> Result<Column, String> {
+        match self.advance() {
+            Some(Token::Word(word)) => Column::parse(&word).ok_or_else(|| {
+                format!(
+                    "There's no column named {}. The columns are {}.",
+                    word,
+                    COLUMNS.map(|c| c.name()).join(", ")
+                )
+            }),
+            _ => {
+                self.next -= 1;
+                Err(format!("Expected a column{}.", self.found()))
+            }
+        }
+    }
+
+    fn items(&mut self) -> Result<Vec<Item>, String> {
+        if self.symbol("*") {
+            return Ok(COLUMNS
+                .into_iter()
+                .map(|c| Item::Column(c, c.name().to_string()))
+                .collect());
+        }
+
+        let mut items = Vec::new();
+        loop {
+            let aggregate = match (self.peek(), self.tokens.get(self.next + 1)) {
+                (Some(Token::Word(word)), Some(Token::Symbol("("))) => Aggregate::parse(word),
+                _ => None,
+            };
+
+            let item = match aggregate {
+                Some(aggregate) => {
+                    self.next += 2;
+                    let column = if aggregate == Aggregate::Count && self.symbol("*") {
+                        None
+                    } else {
+                        Some(self.column()?)
+                    };
+                    self.expect_symbol(")")?;
+                    if column.is_some_and(|c| aggregate != Aggregate::Count && c != Column::Value) {
+                        return Err(
+                            "Only value can be averaged, summed, or have a minimum or maximum."
+                                .to_string(),
+                        );
+                    }
+                    let label = format!(
+                        "{}({})",
+                        format!("{:?}", aggregate).to_lowercase(),
+                        column.map_or("*", |c| c.name())
+                    );
+                    Item::Aggregate(aggregate, column, label)
+                }
+                None => {
+                    let column = self.column()?;
+                    Item::Column(column, column.name().to_string())
+                }
+            };
+
+            items.push(match (item, self.keyword("as")) {
+                (item, false) => item,
+                (item, true) => {
+                    let alias = match self.advance() {
+                        Some(Token::Word(alias)) | Some(Token::Text(alias)) => alias,
+                        _ => return Err("Expected a name after AS.".to_string()),
+                    };
+                    match item {
+                        Item::Column(column, _) => Item::Column(column, alias),
+                        Item::Aggregate(aggregate, column, _) => {
+                            Item::Aggregate(aggregate, column, alias)
+                        }
+                    }
+                }
+            });
+
+            if !self.symbol(",") {
+                return Ok(items);
+            }
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.symbol("(") {
+            let expr = self.expr()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let column = self.column()?;
+
+        let negated = self.keyword("not");
+        if self.keyword("like") {
+            let pattern = match self.advance() {
+                Some(Token::Text(pattern)) => pattern,
+                _ => return Err("Expected text in quotes after LIKE.".to_string()),
+            };
+            if !column.is_text() {
+                return Err(format!("{} can't be matched with LIKE.", column.name()));
+            }
+            let like = Expr::Like(column, like_pattern(&pattern));
+            return Ok(if negated {
+                Expr::Not(Box::new(like))
+            } else {
+                like
+            });
+        }
+        if negated {
+            return Err(format!("Expected LIKE after NOT{}.", self.found()));
+        }
+
+        let op = match self.advance() {
+            Some(Token::Symbol("=")) => Op::Eq,
+            Some(Token::Symbol("<>")) | Some(Token::Symbol("!=")) => Op::Ne,
+            Some(Token::Symbol("<")) => Op::Lt,
+            Some(Token::Symbol("<=")) => Op::Le,
+            Some(Token::Symbol(">")) => Op::Gt,
+            Some(Token::Symbol(">=")) => Op::Ge,
+            _ => {
+                self.next -= 1;
+                return Err(format!(
+                    "Expected a comparison after {}{}.",
+                    column.name(),
+                    self.found()
+                ));
+            }
+        };
+
+        let literal = match (column, self.advance()) {
+            (Column::Value, Some(Token::Number(n))) => Literal::Number(n),
+            (Column::Value, _) => {
+                return Err("value can only be compared with a number.".to_string())
+            }
+            (_, Some(Token::Text(text))) => Literal::Text(text),
+            (column, _) => {
+                return Err(format!(
+                    "{} can only be compared with text in quotes.",
+                    column.name()
+                ))
+            }
+        };
+
+        Ok(Expr::Compare(column, op, literal))
+    }
+}
+
+// LIKE's % and _ are the wildcards the rest of the tool takes as * and ?.
+fn like_pattern(pattern: &str) -> String {
+    pattern
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            '%' => '*',
+            '_' => '?',
+            c => c,
+        })
+        .collect()
+}
+
+pub fn parse_query(text: &str) -> Result<Query, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        next: 0,
+    };
+
+    parser.expect_keyword("select")?;
+    let items = parser.items()?;
+    parser.expect_keyword("from")?;
+    if !parser.keyword("samples") {
+        return Err(format!("The only table is samples{}.", parser.found()));
+    }
+
+    let filter = if parser.keyword("where") {
+        Some(parser.expr()?)
+    } else {
+        None
+    };
+
+    let mut group_by = Vec::new();
+    if parser.keyword("group") {
+        parser.expect_keyword("by")?;
+        loop {
+            group_by.push(parser.column()?);
+            if !parser.symbol(",") {
+                break;
+            }
+        }
+    }
+
+    let mut order_by = Vec::new();
+    if parser.keyword("order") {
+        parser.expect_keyword("by")?;
+        loop {
+            // Sorted by an output column, named by its label.
+            let mut key = match parser.advance() {
+                Some(Token::Word(word)) => word,
+                Some(Token::Text(text)) => text,
+                _ => return Err("Expected a column after ORDER BY.".to_string()),
+            };
+            if parser.symbol("(") {
+                let inner = if parser.symbol("*") {
+                    "*".to_string()
+                } else {
+                    parser.column()?.name().to_string()
+                };
+                parser.expect_symbol(")")?;
+                key = format!("{}({})", key.to_lowercase(), inner);
+            }
+            let descending = parser.keyword("desc");
+            if !descending {
+                parser.keyword("asc");
+            }
+            order_by.push((key, descending));
+            if !parser.symbol(",") {
+                break;
+            }
+        }
+    }
+
+    let limit = if parser.keyword("limit") {
+        match parser.advance() {
+            Some(Token::Number(n)) if n >= 0.0 && n.fract() == 0.0 => Some(n as usize),
+            _ => return Err("Expected a whole number after LIMIT.".to_string()),
+        }
+    } else {
+        None
+    };
+
+    if parser.peek().is_some() {
+        return Err(format!("Expected the end of the query{}.", parser.found()));
+    }
+
+    let query = Query {
+        items,
+        filter,
+        group_by,
+        order_by,
+        limit,
+    };
+    query.check()?;
+    Ok(query)
+}
+
+impl Query {
+    fn is_aggregate(&self) -> bool {
+        !self.group_by.is_empty() || self.items.iter().any(|i| matches!(i, Item::Aggregate(..)))
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.is_aggregate() {
+            for item in &self.items {
+                if let Item::Column(column, _) = item {
+                    if !self.group_by.contains(column) {
+                        return Err(format!(
+                            "{} has to be in GROUP BY, or in an aggregate like avg({}).",
+                            column.name(),
+                            column.name()
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (key, _) in &self.order_by {
+            if self.column_index(key).is_none() {
+                return Err(format!(
+                    "ORDER BY {} has to be one of the selected columns.",
+                    key
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn column_index(&self, key: &str) -> Option<usize> {
+        self.items
+            .iter()
+            .position(|i| i.label().eq_ignore_ascii_case(key))
+    }
+
+    // The counters worth reading: those matched by a LIKE or = on counter,
+    // or an = on object, that the whole WHERE depends on. Every counter is
+    // read when there's none, and the WHERE is still applied to each sample.
+    pub fn selection(&self) -> CounterSelection {
+        let mut selection = CounterSelection::default();
+        let mut conjuncts = Vec::new();
+        if let Some(filter) = &self.filter {
+            collect_conjuncts(filter, &mut conjuncts);
+        }
+
+        for expr in conjuncts {
+            match expr {
+                Expr::Like(Column::Counter, pattern) if selection.include.is_empty() => {
+                    selection.include.push(pattern.clone())
+                }
+                Expr::Compare(Column::Counter, Op::Eq, Literal::Text(counter))
+                    if selection.include.is_empty() =>
+                {
+                    selection.include.push(counter.clone())
+                }
+                Expr::Compare(Column::Object, Op::Eq, Literal::Text(object))
+                    if selection.objects.is_empty() =>
+                {
+                    selection.objects.push(object.clone())
+                }
+                _ => {}
+            }
+        }
+
+        // Both narrow what's read, but a selection reads counters matching
+        // either, so only one is kept.
+        if !selection.include.is_empty() {
+            selection.objects.clear();
+        }
+        selection
+    }
+}
+
+fn collect_conjuncts<'a>(expr: &'a Expr, conjuncts: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::And(left, right) => {
+            collect_conjuncts(left, conjuncts);
+            collect_conjuncts(right, conjuncts);
+        }
+        expr => conjuncts.push(expr),
+    }
+}
+
+// Turns the times compared with into times, now that the logs they can be
+// relative to are read.
+fn resolve_times(expr: &mut Expr) -> Result<(), String> {
+    match expr {
+        Expr::And(left, right) | Expr::Or(left, right) => {
+            resolve_times(left)?;
+            resolve_times(right)
+        }
+        Expr::Not(inner) => resolve_times(inner),
+        Expr::Compare(Column::Time, _, literal) => {
+            if let Literal::Text(text) = literal {
+                let time = parse_time_spec(text)?
+                    .resolve(display_offset())
+                    .ok_or_else(|| format!("Invalid time: {}", text))?;
+                *literal = Literal::Time(time);
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Null,
+    Number(f64),
+    Text(String),
+    Time(OffsetDateTime),
+}
+
+impl Value {
+    fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a.total_cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+            (Value::Time(a), Value::Time(b)) => a.cmp(b),
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Less,
+            (_, Value::Null) => Ordering::Greater,
+            _ => Ordering::Equal,
+        }
+    }
+
+    fn format(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Number(n) => format_number(*n),
+            Value::Text(t) => format!("\"{}\"", t.replace('"', "\"\"")),
+            Value::Time(t) => format_time(*t),
+        }
+    }
+}
+
+// A sample as a row: the counter it's from and its time and value.
+struct Row<'a> {
+    series: &'a CounterSeries,
+    time: OffsetDateTime,
+    value: f64,
+}
+
+impl Row<'_> {
+    fn get(&self, column: Column) -> Value {
+        let path = self.series.path.as_ref();
+        let text = |t: Option<&str>| t.map_or(Value::Null, |t| Value::Text(t.to_string()));
+        match column {
+            Column::Time => Value::Time(self.time),
+            Column::Counter => Value::Text(self.series.counter.clone()),
+            Column::Machine => text(path.map(|p| p.machine.as_str())),
+            Column::Object => text(path.map(|p| p.object.as_str())),
+            Column::Instance => text(path.and_then(|p| p.instance.as_deref())),
+            Column::Name => text(path.map(|p| p.counter.as_str())),
+            Column::Value => Value::Number(self.value),
+        }
+    }
+
+    fn matches(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::And(left, right) => self.matches(left) && self.matches(right),
+            Expr::Or(left, right) => self.matches(left) || self.matches(right),
+            Expr::Not(inner) => !self.matches(inner),
+            Expr::Like(column, pattern) => match self.get(*column) {
+                Value::Text(text) => wildcard_match(pattern, &text.to_lowercase()),
+                _ => false,
+            },
+            Expr::Compare(column, op, literal) => {
+                let literal = match literal {
+                    Literal::Number(n) => Value::Number(*n),
+                    Literal::Text(t) => Value::Text(t.clone()),
+                    Literal::Time(t) => Value::Time(*t),
+                };
+                match self.get(*column) {
+                    Value::Null => false,
+                    value => op.holds(value.compare(&literal)),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Accumulator {
+    count: u64,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl Accumulator {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = Some(self.min.map_or(value, |m| m.min(value)));
+        self.max = Some(self.max.map_or(value, |m| m.max(value)));
+    }
+
+    fn result(&self, aggregate: Aggregate) -> Value {
+        let number = match aggregate {
+            Aggregate::Count => Some(self.count as f64),
+            Aggregate::Sum => Some(self.sum),
+            Aggregate::Avg => (self.count > 0).then(|| self.sum / self.count as f64),
+            Aggregate::Min => self.min,
+            Aggregate::Max => self.max,
+        };
+        number.map_or(Value::Null, Value::Number)
+    }
+}
+
+// Runs the query over every sample of the series, returning the column
+// labels and the rows.
+fn execute(
+    query: &mut Query,
+    series: &SeriesSet,
+) -> Result<(Vec<String>, Vec<Vec<Value>>), String> {
+    if let Some(filter) = &mut query.filter {
+        resolve_times(filter)?;
+    }
+
+    let samples = series.iter().flat_map(|s| {
+        s.times().zip(s.values()).map(move |(time, value)| Row {
+            series: s,
+            time,
+            value,
+        })
+    });
+    let matching = samples
+        .filter(|row| !row.value.is_nan() && query.filter.as_ref().is_none_or(|f| row.matches(f)));
+
+    let mut rows = if query.is_aggregate() {
+        // Groups in the order they're first seen, each with an accumulator.
+        let mut groups = Vec::<(Vec<Value>, Accumulator)>::new();
+        let mut index = HashMap::<Vec<String>, usize>::new();
+        for row in matching {
+            let key = query
+                .group_by
+                .iter()
+                .map(|c| row.get(*c))
+                .collect::<Vec<Value>>();
+            let hash_key = key.iter().map(Value::format).collect::<Vec<String>>();
+            let group = *index.entry(hash_key).or_insert_with(|| {
+                groups.push((key, Accumulator::default()));
+                groups.len() - 1
+            });
+            groups[group].1.add(row.value);
+        }
+
+        // Aggregates over no rows at all still give a row, like count(*) of
+        // 0.
+        if groups.is_empty() && query.group_by.is_empty() {
+            groups.push((Vec::new(), Accumulator::default()));
+        }
+
+        groups
+            .into_iter()
+            .map(|(key, accumulator)| {
+                query
+                    .items
+                    .iter()
+                    .map(|item| match item {
+                        Item::Column(column, _) => {
+                            let position = query.group_by.iter().position(|c| c == column);
+                            position.map_or(Value::Null, |p| key[p].clone())
+                        }
+                        Item::Aggregate(aggregate, _, _) => accumulator.result(*aggregate),
+                    })
+                    .collect::<Vec<Value>>()
+            })
+            .collect::<Vec<Vec<Value>>>()
+    } else {
+        matching
+            .map(|row| {
+                query
+                    .items
+                    .iter()
+                    .map(|item| match item {
+                        Item::Column(column, _) => row.get(*column),
+                        Item::Aggregate(..) => Value::Null,
+                    })
+                    .collect::<Vec<Value>>()
+            })
+            .collect()
+    };
+
+    let order = query
+        .order_by
+        .iter()
+        .filter_map(|(key, descending)| query.column_index(key).map(|i| (i, *descending)))
+        .collect::<Vec<(usize, bool)>>();
+    if !order.is_empty() {
+        rows.sort_by(|a, b| {
+            order
+                .iter()
+                .map(|(i, descending)| {
+                    let ordering = a[*i].compare(&b[*i]);
+                    if *descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                })
+                .find(|o| *o != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+    if let Some(limit) = query.limit {
+        rows.truncate(limit);
+    }
+
+    let labels = query.items.iter().map(|i| i.label().to_string()).collect();
+    Ok((labels, rows))
+}
+
+// Runs a query over the samples of the logs and writes the result as CSV, so
+// it can be piped to other tools.
+pub fn query(args: &QueryArgs) {
+    let mut query = match parse_query(&args.query) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let selection = query.selection();
+    if selection.selects_all() {
+        progress!(
+            "Reading every counter. A WHERE with counter LIKE or object = reads only the counters it matches."
+        );
+    }
+    let counter_data = match read_counters(&args.source, &selection) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    let (labels, rows) = match execute(&mut query, &SeriesSet::from(counter_data)) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let mut writer = BufWriter::new(std::io::stdout().lock());
+    let written = (|| {
+        let header = labels
+            .iter()
+            .map(|l| format!("\"{}\"", l.replace('"', "\"\"")))
+            .collect::<Vec<String>>();
+        writeln!(writer, "{}", header.join(","))?;
+        for row in &rows {
+            let fields = row.iter().map(Value::format).collect::<Vec<String>>();
+            writeln!(writer, "{}", fields.join(","))?;
+        }
+        writer.flush()
+    })();
+    if let Err(e) = written {
+        eprintln!("Failed to write the result: {}", e);
+        return;
+    }
+
+    progress!(
+        "{} row{}.",
+        rows.len(),
+        if rows.len() == 1 { "" } else { "s" }
+    );
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::OnceLock,
+};
+
+use time::OffsetDateTime;
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    cache::cached_summary,
+    cli::SourceArgs,
+    export::format_time,
+    log_files::{find_log_files, open_log_files},
+    pdh_helper::{
+        bind_input_logfiles, for_each_counter_value, read_counter_info, read_counter_values,
+        status_name, CounterErrors, CounterInfo, CounterValueWithTime, PerfLogSummary,
+    },
+    selection::{counter_matches, select_counters, CounterSelection},
+    series::{Duplicates, Series},
+    status::progress,
+    units::Unit,
+};
+
+static SHOW_ERRORS: OnceLock<bool> = OnceLock::new();
+static DUPLICATES: OnceLock<Duplicates> = OnceLock::new();
+
+// Set once at startup from --show-errors.
+pub fn set_show_errors(enabled: bool) {
+    let _ = SHOW_ERRORS.set(enabled);
+}
+
+// Set once at startup from --duplicates.
+pub fn set_duplicates(duplicates: Duplicates) {
+    let _ = DUPLICATES.set(duplicates);
+}
+
+// Logs that overlap, like a collector's file and a copy of part of it, give
+// two samples at the same time. Left in, they'd count that stretch twice.
+fn dedup(samples: HashMap<String, Series>) -> HashMap<String, Series> {
+    let duplicates = *DUPLICATES.get().unwrap_or(&Duplicates::First);
+    let mut removed = 0;
+
+    let samples = samples
+        .into_iter()
+        .map(|(counter, series)| {
+            let len = series.len();
+            let series = series.dedup(duplicates);
+            removed += len - series.len();
+            (counter, series)
+        })
+        .collect();
+
+    report_combined(removed);
+    samples
+}
+
+fn report_combined(removed: usize) {
+    if removed > 0 {
+        progress!(
+            "Combined {} samples at times already read, from logs that overlap.",
+            removed
+        );
+    }
+}
+
+// dedup for samples as they're streamed. Each counter's samples are held
+// back by time until no other can arrive at that time: when the files are
+// bound together they arrive in time order, so that's as soon as a later one
+// arrives, but with --separate an overlapping file can bring an earlier
+// time, so they're held until every file is read, like read_counters holds
+// them.
+struct StreamDedup {
+    duplicates: Duplicates,
+    in_order: bool,
+    // By time, the sample to pass on, with the sum and count of the samples
+    // at that time for --duplicates average.
+    held: HashMap<String, BTreeMap<OffsetDateTime, (CounterValueWithTime, f64, usize)>>,
+    removed: usize,
+}
+
+impl StreamDedup {
+    fn new(in_order: bool) -> StreamDedup {
+        StreamDedup {
+            duplicates: *DUPLICATES.get().unwrap_or(&Duplicates::First),
+            in_order,
+            held: HashMap::new(),
+            removed: 0,
+        }
+    }
+
+    fn push(
+        &mut self,
+        counter: &str,
+        sample: CounterValueWithTime,
+        f: &mut impl FnMut(&str, CounterValueWithTime),
+    ) {
+        if self.duplicates == Duplicates::All {
+            f(counter, sample);
+            return;
+        }
+
+        let held = match self.held.get_mut(counter) {
+            Some(held) => held,
+            None => self.held.entry(counter.to_string()).or_default(),
+        };
+
+        let time = sample.time();
+        if let Some((kept, sum, count)) = held.get_mut(&time) {
+            *sum += sample.value();
+            *count += 1;
+            if self.duplicates == Duplicates::Last {
+                *kept = sample;
+            }
+            self.removed += 1;
+            return;
+        }
+
+        if self.in_order {
+            while held.first_key_value().is_some_and(|(t, _)| *t < time) {
+                if let Some((_, earlier)) = held.pop_first() {
+                    f(counter, combine(self.duplicates, earlier));
+                }
+            }
+        }
+        held.insert(time, (sample, sample.value(), 1));
+    }
+
+    // Passes on the samples still held, in the order of the counters.
+    fn finish(mut self, counters: &[String], f: &mut impl FnMut(&str, CounterValueWithTime)) {
+        for counter in counters {
+            for held in self.held.remove(counter).unwrap_or_default().into_values() {
+                f(counter, combine(self.duplicates, held));
+            }
+        }
+        report_combined(self.removed);
+    }
+}
+
+fn combine(
+    duplicates: Duplicates,
+    (sample, sum, count): (CounterValueWithTime, f64, usize),
+) -> CounterValueWithTime {
+    match duplicates {
+        Duplicates::Average if count > 1 => {
+            CounterValueWithTime::Double(sample.time(), sum / count as f64)
+        }
+        _ => sample,
+    }
+}
+
+pub struct CounterData {
+    pub counters: Vec<String>,
+    pub samples: HashMap<String, Series>,
+    pub info: HashMap<String, CounterInfo>,
+    // The samples PDH had no value for, by counter.
+    pub errors: HashMap<String, CounterErrors>,
+}
+
+impl CounterData {
+    pub fn unit(&self, counter: &str) -> Unit {
+        Unit::lookup(&self.info, counter)
+    }
+
+    pub fn matching(&self, pattern: &str) -> Vec<(&String, &Series)> {
+        self.counters
+            .iter()
+            .filter(|c| counter_matches(pattern, c))
+            .filter_map(|c| self.samples.get(c).map(|s| (c, s)))
+            .collect()
+    }
+}
+
+pub fn read_counters(source: &SourceArgs, selection: &CounterSelection) -> Option<CounterData> {
+    if source.separate {
+        let files = find_log_files(&source.glob_pattern);
+
+        if files.is_empty() {
+            return None;
+        }
+
+        let counter_data = map_machines(
+            read_files_separately(&files, selection, source.parallel),
+            source,
+        );
+        report_errors(&counter_data.errors);
+        return Some(counter_data);
+    }
+
+    let (hdatasource, summary) = open_log_files(&source.glob_pattern)?;
+
+    let counter_data = read_selected_counters(hdatasource, &summary, selection, None);
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+
+    let counter_data = map_machines(counter_data, source);
+    report_errors(&counter_data.errors);
+    Some(counter_data)
+}
+
+// A counter often has samples without a value, like a process's counters
+// before it started, so rather than a line for each, say how many once and
+// leave the details to --show-errors.
+pub fn report_errors(errors: &HashMap<String, CounterErrors>) {
+    if errors.is_empty() {
+        return;
+    }
+
+    if !*SHOW_ERRORS.get().unwrap_or(&false) {
+        progress!(
+            "{} samples of {} counters had no value. Use --show-errors for details.",
+            errors.values().map(|e| e.count()).sum::<u64>(),
+            errors.len()
+        );
+        return;
+    }
+
+    let mut counters = errors.iter().collect::<Vec<(&String, &CounterErrors)>>();
+    counters.sort_by(|a, b| a.0.cmp(b.0));
+
+    eprintln!("Samples with no value:");
+    for (counter, counter_errors) in counters {
+        let statuses = counter_errors
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{} {}", count, status_name(*status)))
+            .collect::<Vec<String>>();
+        eprintln!("  {}", counter);
+        eprintln!(
+            "    {} from {} to {}",
+            statuses.join(", "),
+            format_time(counter_errors.first),
+            format_time(counter_errors.last)
+        );
+    }
+}
+
+fn merge_errors(
+    errors: &mut HashMap<String, CounterErrors>,
+    counter: String,
+    other: CounterErrors,
+) {
+    match errors.get_mut(&counter) {
+        Some(existing) => existing.merge(other),
+        None => {
+            errors.insert(counter, other);
+        }
+    }
+}
+
+// Like read_counters, but hands each sample to f as it's read instead of
+// keeping it, for work that needs only one pass. With --separate the files
+// are read one after another, so their samples arrive out of time order.
+// Samples of a counter at the same time are combined per --duplicates.
+// Returns the counters and their info, with the machines mapped like
+// read_counters does.
+pub fn stream_counters(
+    source: &SourceArgs,
+    selection: &CounterSelection,
+    mut f: impl FnMut(&str, CounterValueWithTime),
+) -> Option<(Vec<String>, HashMap<String, CounterInfo>)> {
+    let files = find_log_files(&source.glob_pattern);
+
+    if files.is_empty() {
+        return None;
+    }
+
+    let groups = if source.separate {
+        files.iter().map(|file| vec![file.clone()]).collect()
+    } else {
+        vec![files]
+    };
+
+    let mut counters = Vec::new();
+    let mut seen = HashSet::new();
+    let mut info = HashMap::new();
+    let mut errors = HashMap::new();
+    let mut dedup = StreamDedup::new(groups.len() == 1);
+
+    // A time filter works out times relative to the logs, like --last 2h,
+    // from the range of every log summarized by the first sample it checks,
+    // so every file is summarized before any is read.
+    if groups.len() > 1 {
+        for group in &groups {
+            let hdatasource = bind_input_logfiles(group.clone());
+            cached_summary(group, hdatasource);
+            unsafe { PdhCloseLog(hdatasource, 0) };
+        }
+    }
+
+    for group in groups {
+        let hdatasource = bind_input_logfiles(group.clone());
+        let summary = cached_summary(&group, hdatasource);
+
+        let all_counters = summary.get_all_counters();
+        let counters_to_read = select_counters(&all_counters, selection);
+
+        if !counters_to_read.is_empty() {
+            let paths = counters_to_read
+                .iter()
+                .map(|c| source.machine_path(c))
+                .collect::<Vec<String>>();
+
+            let counter_info = read_counter_info(hdatasource, &counters_to_read);
+            for (counter, path) in counters_to_read.iter().zip(&paths) {
+                if let Some(i) = counter_info.get(*counter) {
+                    info.insert(path.clone(), *i);
+                }
+                if seen.insert(path.clone()) {
+                    counters.push(path.clone());
+                }
+            }
+
+            let group_errors =
+                for_each_counter_value(hdatasource, &counters_to_read, None, |index, sample| {
+                    dedup.push(&paths[index], sample, &mut f)
+                });
+            for (counter, counter_errors) in group_errors {
+                merge_errors(&mut errors, source.machine_path(&counter), counter_errors);
+            }
+        }
+
+        unsafe { PdhCloseLog(hdatasource, 0) };
+    }
+
+    dedup.finish(&counters, &mut f);
+    report_errors(&errors);
+
+    Some((counters, info))
+}
+
+// Applies --strip-machine and --map-machine, merging the series of counters
+// that end up with the same path.
+pub fn map_machines(mut data: CounterData, source: &SourceArgs) -> CounterData {
+    if !source.maps_machines() {
+        return data;
+    }
+
+    let renamed = data
+        .counters
+        .into_iter()
+        .map(|counter| {
+            let path = source.machine_path(&counter);
+            let mut info = HashMap::new();
+            if let Some(i) = data.info.remove(&counter) {
+                info.insert(path.clone(), i);
+            }
+            let mut samples = HashMap::new();
+            samples.insert(
+                path.clone(),
+                data.samples.remove(&counter).unwrap_or_default(),
+            );
+            let mut errors = HashMap::new();
+            if let Some(e) = data.errors.remove(&counter) {
+                errors.insert(path.clone(), e);
+            }
+            CounterData {
+                counters: vec![path],
+                samples,
+                info,
+                errors,
+            }
+        })
+        .collect();
+
+    stitch(renamed)
+}
+
+pub fn read_selected_counters(
+    hdatasource: isize,
+    summary: &PerfLogSummary,
+    selection: &CounterSelection,
+    since: Option<OffsetDateTime>,
+) -> CounterData {
+    let counters = summary.get_all_counters();
+
+    let counters_to_read = select_counters(&counters, selection);
+
+    let ((samples, errors), info) = if counters_to_read.is_empty() {
+        ((HashMap::new(), HashMap::new()), HashMap::new())
+    } else {
+        (
+            read_counter_values(hdatasource, &counters_to_read, since),
+            read_counter_info(hdatasource, &counters_to_read),
+        )
+    };
+
+    CounterData {
+        counters: counters_to_read.into_iter().cloned().collect(),
+        samples: dedup(samples),
+        info,
+        errors,
+    }
+}
+
+// Binding every file into one data source fails when the files were captured
+// with different counter sets, so bind and read each file on its own and
+// stitch the series back together by timestamp.
+pub fn read_files_separately(
+    files: &[String],
+    selection: &CounterSelection,
+    parallel: bool,
+) -> CounterData {
+    let results = if parallel {
+        std::thread::scope(|scope| {
+            let handles = files
+                .iter()
+                .map(|file| scope.spawn(move || read_file(file, selection)))
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("Reader thread panicked"))
+                .collect::<Vec<CounterData>>()
+        })
+    } else {
+        files
+            .iter()
+            .map(|file| read_file(file, selection))
+            .collect::<Vec<CounterData>>()
+    };
+
+    stitch(results)
+}
+
+fn read_file(file: &str, selection: &CounterSelection) -> CounterData {
+    let hdatasource = bind_input_logfiles(vec![file.to_string()]);
+
+    let summary = cached_summary(&[file.to_string()], hdatasource);
+    let counter_data = read_selected_counters(hdatasource, &summary, selection, None);
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+
+    counter_data
+}
+
+fn stitch(results: Vec<CounterData>) -> CounterData {
+    let mut counters = Vec::new();
+    let mut parts = HashMap::<String, Vec<Series>>::new();
+    let mut info = HashMap::new();
+    let mut errors = HashMap::new();
+
+    for mut result in results {
+        info.extend(result.info);
+        for (counter, counter_errors) in result.errors {
+            merge_errors(&mut errors, counter, counter_errors);
+        }
+
+        for counter in result.counters {
+            let file_samples = result.samples.remove(&counter).unwrap_or_default();
+
+            match parts.get_mut(&counter) {
+                Some(existing) => existing.push(file_samples),
+                None => {
+                    counters.push(counter.clone());
+                    parts.insert(counter, vec![file_samples]);
+                }
+            }
+        }
+    }
+
+    let samples = dedup(
+        parts
+            .into_iter()
+            .map(|(counter, parts)| (counter, Series::merge(parts)))
+            .collect(),
+    );
+
+    CounterData {
+        counters,
+        samples,
+        info,
+        errors,
+    }
+}
@@ -0,0 +1,70 @@
+use std::{collections::HashSet, str::FromStr};
+
+use crate::selection::counter_matches;
+
+// An alias like "Processor(_Total)\% Processor Time=cpu_total". The left side
+// is matched like --counter, and the text after the last = is the new name.
+#[derive(Clone)]
+pub struct Rename {
+    pub pattern: String,
+    pub name: String,
+}
+
+impl FromStr for Rename {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('=') {
+            Some((pattern, name)) if !pattern.trim().is_empty() && !name.trim().is_empty() => {
+                Ok(Rename {
+                    pattern: pattern.trim().to_string(),
+                    name: name.trim().to_string(),
+                })
+            }
+            _ => Err(format!("Expected a rename like old=new: {}", s)),
+        }
+    }
+}
+
+// One rename per line. Blank lines and lines starting with # are skipped.
+pub fn read_rename_file(path: &str) -> Result<Vec<Rename>, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(number, line)| {
+            line.parse()
+                .map_err(|e| format!("{} line {}: {}", path, number + 1, e))
+        })
+        .collect()
+}
+
+// Returns the column name for each counter. The first matching rename wins,
+// and counters no rename matches keep their path. Two columns ending up with
+// the same name is an error, since a pattern that matches more than one
+// counter would otherwise silently merge them.
+pub fn rename_counters(counters: &[&String], renames: &[Rename]) -> Result<Vec<String>, String> {
+    let names = counters
+        .iter()
+        .map(|counter| {
+            renames
+                .iter()
+                .find(|r| counter_matches(&r.pattern, counter))
+                .map_or_else(|| counter.to_string(), |r| r.name.clone())
+        })
+        .collect::<Vec<String>>();
+
+    let mut seen = HashSet::new();
+    for (counter, name) in counters.iter().zip(&names) {
+        if !seen.insert(name.to_lowercase()) {
+            return Err(format!(
+                "More than one counter would be named {}, including {}",
+                name, counter
+            ));
+        }
+    }
+
+    Ok(names)
+}
@@ -0,0 +1,359 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::{GUID, HSTRING},
+    Win32::{
+        Foundation::{BOOL, HANDLE, TRUE},
+        System::{
+            Console::SetConsoleCtrlHandler,
+            Performance::{
+                PerfCreateInstance, PerfDeleteInstance, PerfProviderHandle, PerfSetCounterSetInfo,
+                PerfSetULongLongCounterValue, PerfStartProvider, PerfStopProvider,
+                PERF_COUNTERSET_INFO, PERF_COUNTERSET_INSTANCE, PERF_COUNTERSET_MULTI_INSTANCES,
+                PERF_COUNTER_INFO, PERF_DETAIL_NOVICE,
+            },
+        },
+    },
+};
+
+use crate::{
+    cli::ReplayArgs,
+    export::format_time,
+    reader::read_counters,
+    series::{in_time_order, Series},
+    status::progress,
+    xlsx::xml,
+};
+
+// The provider and counter set the manifest registers. They have to stay
+// the same, or a counter set registered by an older version won't match.
+const PROVIDER_GUID: GUID = GUID::from_u128(0x6c1f0d2e_8a57_4b43_9d2e_3f6a1b8c7e41);
+const COUNTERSET_GUID: GUID = GUID::from_u128(0xb2a4e9d3_5c71_4f0e_8e6b_91d2c3a4f5e6);
+
+const COUNTERSET_NAME: &str = "perflogtool Replay";
+
+// PerfLib counters are whole numbers, so each sample is set twice: rounded,
+// and times 1000 for the fractions of percentages and rates.
+const VALUE_ID: u32 = 1;
+const VALUE_X1000_ID: u32 = 2;
+
+// PERF_COUNTER_LARGE_RAWCOUNT from winperf.h, which the windows crate
+// doesn't define.
+const PERF_COUNTER_LARGE_RAWCOUNT: u32 = 0x0001_0100;
+
+// Stopping waits at most this long for the next sample.
+const STOP_CHECK: Duration = Duration::from_millis(250);
+
+// Set by Ctrl+C to stop replaying.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+// What PerfSetCounterSetInfo takes: the counter set, then its counters.
+#[repr(C)]
+struct CounterSetTemplate {
+    info: PERF_COUNTERSET_INFO,
+    counters: [PERF_COUNTER_INFO; 2],
+}
+
+// Plays the samples back as instances of a counter set of this process, one
+// per counter read, named by its path. Perfmon, dashboards, and anything
+// else reading live counters see them like any other, for as long as this
+// runs. The timing between samples is kept, divided by --speed.
+pub fn replay(args: &ReplayArgs) {
+    let manifest = match write_manifest() {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Failed to write the counter set manifest: {}", e);
+            return;
+        }
+    };
+    if args.register && !register(&manifest) {
+        return;
+    }
+
+    let selection = match args.counters.selection() {
+        Some(selection) => selection,
+        None => return,
+    };
+    let mut counter_data = match read_counters(&args.source, &selection) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    let time_filter = args.time_filter.time_filter();
+    let series = counter_data
+        .counters
+        .iter()
+        .map(|c| time_filter.apply(counter_data.samples.remove(c).unwrap_or_default()))
+        .collect::<Vec<Series>>();
+
+    let first = series
+        .iter()
+        .filter(|s| !s.is_empty())
+        .map(|s| s.time(0))
+        .min();
+    let last = series
+        .iter()
+        .filter(|s| !s.is_empty())
+        .map(|s| s.time(s.len() - 1))
+        .max();
+    let (first, last) = match first.zip(last) {
+        Some(range) => range,
+        None => {
+            eprintln!("No samples matched.");
+            return;
+        }
+    };
+
+    let provider = match Provider::start(&counter_data.counters) {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    unsafe { SetConsoleCtrlHandler(Some(console_handler), TRUE) };
+
+    progress!(
+        "Replaying {} counters from {} to {} at {}x as \\{}(*)\\Value. Press Ctrl+C to stop.",
+        counter_data.counters.len(),
+        format_time(first),
+        format_time(last),
+        args.speed,
+        COUNTERSET_NAME
+    );
+    if !args.register {
+        progress!(
+            "If they don't show up in perfmon, run once with --register from an elevated prompt."
+        );
+    }
+
+    loop {
+        let played = play(&provider, &series, first, args.speed);
+        progress!("Replayed {} samples.", played);
+        if !args.repeat || STOP.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+}
+
+// Sets each sample when its time comes, relative to the first, and returns
+// how many were set.
+fn play(provider: &Provider, series: &[Series], first: time::OffsetDateTime, speed: f64) -> usize {
+    let started = Instant::now();
+    let mut played = 0;
+
+    for (index, sample) in in_time_order(series) {
+        let due = started + (sample.time() - first).unsigned_abs().div_f64(speed);
+        loop {
+            if STOP.load(Ordering::Relaxed) {
+                return played;
+            }
+            let now = Instant::now();
+            if now >= due {
+                break;
+            }
+            std::thread::sleep((due - now).min(STOP_CHECK));
+        }
+
+        let value = sample.value();
+        if value.is_finite() {
+            provider.set(index, value);
+            played += 1;
+        }
+    }
+
+    played
+}
+
+struct Provider {
+    handle: PerfProviderHandle,
+    instances: Vec<*mut PERF_COUNTERSET_INSTANCE>,
+}
+
+impl Provider {
+    // Starts the provider and creates an instance for each counter. Counter
+    // paths have backslashes, which would split the instance's own path, so
+    // they become slashes, like SQL01/Processor(_Total)/% Processor Time.
+    fn start(counters: &[String]) -> Result<Provider, String> {
+        let mut handle = PerfProviderHandle::default();
+        let status = unsafe { PerfStartProvider(&PROVIDER_GUID, None, &mut handle) };
+        if status != 0 {
+            return Err(format!(
+                "Failed to start the counter provider: {:#x}",
+                status
+            ));
+        }
+
+        let counter = |id: u32| PERF_COUNTER_INFO {
+            CounterId: id,
+            Type: PERF_COUNTER_LARGE_RAWCOUNT,
+            Attrib: 0,
+            Size: std::mem::size_of::<u64>() as u32,
+            DetailLevel: PERF_DETAIL_NOVICE.0,
+            Scale: 0,
+            Offset: (id - 1) * std::mem::size_of::<u64>() as u32,
+        };
+        let mut template = CounterSetTemplate {
+            info: PERF_COUNTERSET_INFO {
+                CounterSetGuid: COUNTERSET_GUID,
+                ProviderGuid: PROVIDER_GUID,
+                NumCounters: 2,
+                InstanceType: PERF_COUNTERSET_MULTI_INSTANCES,
+            },
+            counters: [counter(VALUE_ID), counter(VALUE_X1000_ID)],
+        };
+
+        // From here the provider is stopped when dropped.
+        let mut provider = Provider {
+            handle,
+            instances: Vec::new(),
+        };
+
+        let status = unsafe {
+            // The setters take the provider handle as a plain HANDLE.
+            PerfSetCounterSetInfo(
+                HANDLE(handle.0),
+                &mut template.info,
+                std::mem::size_of::<CounterSetTemplate>() as u32,
+            )
+        };
+        if status != 0 {
+            return Err(format!("Failed to set up the counter set: {:#x}", status));
+        }
+
+        for (id, counter) in counters.iter().enumerate() {
+            let name = counter.trim_start_matches('\\').replace('\\', "/");
+            let instance = unsafe {
+                PerfCreateInstance(handle, &COUNTERSET_GUID, &HSTRING::from(&name), id as u32)
+            };
+            if instance.is_null() {
+                return Err(format!("Failed to create the instance for {}.", counter));
+            }
+            provider.instances.push(instance);
+        }
+
+        Ok(provider)
+    }
+
+    fn set(&self, index: usize, value: f64) {
+        let instance = self.instances[index];
+        let value = value.max(0.0);
+        unsafe {
+            PerfSetULongLongCounterValue(
+                HANDLE(self.handle.0),
+                instance,
+                VALUE_ID,
+                value.round() as u64,
+            );
+            PerfSetULongLongCounterValue(
+                HANDLE(self.handle.0),
+                instance,
+                VALUE_X1000_ID,
+                (value * 1000.0).round() as u64,
+            );
+        }
+    }
+}
+
+impl Drop for Provider {
+    fn drop(&mut self) {
+        for instance in &self.instances {
+            unsafe { PerfDeleteInstance(self.handle, *instance) };
+        }
+        unsafe { PerfStopProvider(self.handle) };
+    }
+}
+
+unsafe extern "system" fn console_handler(_ctrl_type: u32) -> BOOL {
+    STOP.store(true, Ordering::Relaxed);
+    TRUE
+}
+
+// The manifest lodctr registers the counter set from, under the user's local
+// app data. Its names are in the manifest itself, so it only needs this
+// executable's path as the provider's identity.
+fn write_manifest() -> std::io::Result<PathBuf> {
+    let dir = std::env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("perflogtool");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("replay.man");
+
+    let exe = std::env::current_exe()?;
+    let mut writer = BufWriter::new(File::create(&path)?);
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        writer,
+        "<instrumentationManifest xmlns=\"http://schemas.microsoft.com/win/2004/08/events\">"
+    )?;
+    writeln!(writer, "  <instrumentation>")?;
+    writeln!(
+        writer,
+        "    <counters xmlns=\"http://schemas.microsoft.com/win/2005/12/counters\" schemaVersion=\"2.0\">"
+    )?;
+    writeln!(
+        writer,
+        "      <provider providerName=\"perflogtool\" providerGuid=\"{{{:?}}}\" applicationIdentity=\"{}\" providerType=\"userMode\">",
+        PROVIDER_GUID,
+        xml(&exe.display().to_string())
+    )?;
+    writeln!(
+        writer,
+        "        <counterSet guid=\"{{{:?}}}\" uri=\"perflogtool.Replay\" name=\"{}\" description=\"Samples from logs played back by perflogtool replay, one instance per counter\" instances=\"multiple\">",
+        COUNTERSET_GUID, COUNTERSET_NAME
+    )?;
+    writeln!(
+        writer,
+        "          <counter id=\"{}\" uri=\"perflogtool.Replay.Value\" name=\"Value\" description=\"The sample, rounded to a whole number\" type=\"perf_counter_large_rawcount\" detailLevel=\"standard\"/>",
+        VALUE_ID
+    )?;
+    writeln!(
+        writer,
+        "          <counter id=\"{}\" uri=\"perflogtool.Replay.ValueX1000\" name=\"Value x1000\" description=\"The sample times 1000, for its fractions\" type=\"perf_counter_large_rawcount\" detailLevel=\"standard\"/>",
+        VALUE_X1000_ID
+    )?;
+    writeln!(writer, "        </counterSet>")?;
+    writeln!(writer, "      </provider>")?;
+    writeln!(writer, "    </counters>")?;
+    writeln!(writer, "  </instrumentation>")?;
+    writeln!(writer, "</instrumentationManifest>")?;
+    writer.flush()?;
+
+    Ok(path)
+}
+
+// lodctr does the registering, so the error messages are the ones
+// administrators already know.
+fn register(manifest: &Path) -> bool {
+    let status = process::Command::new("lodctr.exe")
+        .arg(format!("/m:{}", manifest.display()))
+        .status();
+
+    match status {
+        Ok(status) if status.success() => {
+            progress!(
+                "Registered the {} counter set. Remove it with: unlodctr /m:{}",
+                COUNTERSET_NAME,
+                manifest.display()
+            );
+            true
+        }
+        Ok(status) => {
+            eprintln!("lodctr failed: {}. It needs an elevated prompt.", status);
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to run lodctr: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::Write;
+
+use time::OffsetDateTime;
+
+use crate::{
+    analyze::Finding,
+    export::{format_time, format_time_with_offset},
+    resample::Aggregate,
+    stats::Welford,
+    timespec::{display_offset, format_duration, zone_label},
+    units::Unit,
+};
+
+#[derive(Clone)]
+pub struct CounterStats {
+    pub counter: String,
+    pub samples: usize,
+    pub min: f64,
+    pub avg: f64,
+    pub stddev: f64,
+    pub p95: f64,
+    pub max: f64,
+    pub unit: Unit,
+}
+
+impl CounterStats {
+    // Expects sorted values.
+    pub fn new(counter: &str, values: &[f64], unit: Unit) -> CounterStats {
+        let mut moments = Welford::default();
+        values.iter().for_each(|v| moments.add(*v));
+
+        CounterStats {
+            counter: counter.to_string(),
+            samples: values.len(),
+            min: Aggregate::Min.compute(values),
+            avg: Aggregate::Avg.compute(values),
+            stddev: moments.stddev(),
+            p95: Aggregate::P95.compute(values),
+            max: Aggregate::Max.compute(values),
+            unit,
+        }
+    }
+}
+
+pub struct KeyChart {
+    pub title: String,
+    pub path: String,
+    pub stats: Vec<CounterStats>,
+}
+
+pub struct Report<'a> {
+    pub title: String,
+    pub start: OffsetDateTime,
+    pub end: OffsetDateTime,
+    pub machines: Vec<String>,
+    pub counter_count: usize,
+    pub key_charts: Vec<KeyChart>,
+    pub findings: &'a [Finding],
+    pub finding_charts: &'a [Option<String>],
+    pub stats: &'a [CounterStats],
+}
+
+const STYLE: &str = "body { font-family: Segoe UI, sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }
+td.number { text-align: right; font-variant-numeric: tabular-nums; }
+.CRITICAL { color: #b00020; font-weight: bold; }
+.WARNING { color: #b26a00; font-weight: bold; }
+.INFO { color: #1565c0; }
+.finding { border-left: 4px solid #ccc; padding-left: 1em; margin-bottom: 2em; }
+img { max-width: 100%; }";
+
+pub fn write_report(writer: &mut dyn Write, report: &Report) -> std::io::Result<()> {
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>{}</title>", html(&report.title))?;
+    writeln!(writer, "<style>{}</style></head><body>", STYLE)?;
+
+    writeln!(writer, "<h1>{}</h1>", html(&report.title))?;
+    writeln!(writer, "<table>")?;
+    writeln!(
+        writer,
+        "<tr><th>Time range</th><td>{} - {} {} ({})</td></tr>",
+        format_time(report.start),
+        format_time(report.end),
+        zone_label(display_offset()),
+        format_duration(report.end - report.start)
+    )?;
+    writeln!(
+        writer,
+        "<tr><th>Machines</th><td>{}</td></tr>",
+        html(&report.machines.join(", "))
+    )?;
+    writeln!(
+        writer,
+        "<tr><th>Counters</th><td>{}</td></tr>",
+        report.counter_count
+    )?;
+    writeln!(
+        writer,
+        "<tr><th>Findings</th><td>{}</td></tr>",
+        report.findings.len()
+    )?;
+    writeln!(writer, "</table>")?;
+
+    writeln!(writer, "<h2>Findings</h2>")?;
+    if report.findings.is_empty() {
+        writeln!(writer, "<p>No analyzer found a problem.</p>")?;
+    }
+    for (finding, chart) in report.findings.iter().zip(report.finding_charts) {
+        writeln!(writer, "<div class=\"finding\">")?;
+        writeln!(
+            writer,
+            "<p><span class=\"{0}\">{0}</span> {1} &middot; {2} - {3}</p>",
+            finding.severity,
+            html(finding.analyzer),
+            format_time(finding.start),
+            format_time(finding.end)
+        )?;
+        writeln!(writer, "<p><code>{}</code></p>", html(&finding.counter))?;
+        writeln!(writer, "<p>{}</p>", html(&finding.message))?;
+        if let Some(chart) = chart {
+            writeln!(writer, "<img src=\"{}\" alt=\"\">", html(chart))?;
+        }
+        writeln!(writer, "</div>")?;
+    }
+
+    writeln!(writer, "<h2>Key counters</h2>")?;
+    for chart in &report.key_charts {
+        writeln!(writer, "<h3>{}</h3>", html(&chart.title))?;
+        writeln!(writer, "<img src=\"{}\" alt=\"\">", html(&chart.path))?;
+        write_stats_table(writer, &chart.stats)?;
+    }
+
+    writeln!(writer, "<h2>All counters</h2>")?;
+    write_stats_table(writer, report.stats)?;
+
+    writeln!(writer, "</body></html>")?;
+    writer.flush()
+}
+
+fn write_stats_table(writer: &mut dyn Write, stats: &[CounterStats]) -> std::io::Result<()> {
+    writeln!(writer, "<table>")?;
+    writeln!(
+        writer,
+        "<tr><th>Counter</th><th>Samples</th><th>Min</th><th>Avg</th><th>P95</th><th>Max</th></tr>"
+    )?;
+    for s in stats {
+        writeln!(
+            writer,
+            "<tr><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td>\
+             <td class=\"number\">{}</td><td class=\"number\">{}</td>\
+             <td class=\"number\">{}</td></tr>",
+            html(&s.counter),
+            s.samples,
+            s.unit.format(s.min),
+            s.unit.format(s.avg),
+            s.unit.format(s.p95),
+            s.unit.format(s.max)
+        )?;
+    }
+    writeln!(writer, "</table>")
+}
+
+pub fn write_findings_json(
+    writer: &mut dyn Write,
+    findings: &[Finding],
+    charts: &[Option<String>],
+) -> std::io::Result<()> {
+    writeln!(writer, "[")?;
+    for (index, (finding, chart)) in findings.iter().zip(charts).enumerate() {
+        let chart = match chart {
+            Some(chart) => json(chart),
+            None => "null".to_string(),
+        };
+        writeln!(
+            writer,
+            "  {{\"analyzer\": {}, \"severity\": {}, \"counter\": {}, \"start\": {}, \
+             \"end\": {}, \"message\": {}, \"chart\": {}}}{}",
+            json(finding.analyzer),
+            json(&finding.severity.to_string()),
+            json(&finding.counter),
+            json(&format_time_with_offset(finding.start)),
+            json(&format_time_with_offset(finding.end)),
+            json(&finding.message),
+            chart,
+            if index + 1 < findings.len() { "," } else { "" }
+        )?;
+    }
+    writeln!(writer, "]")?;
+    writer.flush()
+}
+
+fn html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn json(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
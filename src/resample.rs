@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    pdh_helper::CounterValueWithTime,
+    series::{Series, SeriesBuilder},
+    stats::{percentile, sort_values},
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Aggregate {
+    Avg,
+    Min,
+    Max,
+    P95,
+    Count,
+    Sum,
+}
+
+impl Aggregate {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aggregate::Avg => "avg",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::P95 => "p95",
+            Aggregate::Count => "count",
+            Aggregate::Sum => "sum",
+        }
+    }
+
+    // Expects sorted values.
+    pub fn compute(&self, values: &[f64]) -> f64 {
+        match self {
+            Aggregate::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregate::Min => values[0],
+            Aggregate::Max => values[values.len() - 1],
+            Aggregate::P95 => percentile(values, 95.0),
+            Aggregate::Count => values.len() as f64,
+            Aggregate::Sum => values.iter().sum(),
+        }
+    }
+}
+
+// Buckets are aligned to multiples of the interval since midnight so that
+// resampled series from different logs line up.
+pub fn bucket_start(time: OffsetDateTime, interval: Duration) -> OffsetDateTime {
+    let midnight = time.replace_time(time::Time::MIDNIGHT);
+    let since_midnight = (time - midnight).whole_nanoseconds();
+    let interval = interval.whole_nanoseconds().max(1);
+
+    midnight + Duration::nanoseconds((since_midnight - since_midnight % interval) as i64)
+}
+
+// Returns one resampled series per aggregate, in the order given.
+pub fn resample(samples: &Series, interval: Duration, aggregates: &[Aggregate]) -> Vec<Series> {
+    let mut buckets = BTreeMap::<OffsetDateTime, Vec<f64>>::new();
+    for sample in samples.iter() {
+        buckets
+            .entry(bucket_start(sample.time(), interval))
+            .or_default()
+            .push(sample.value());
+    }
+
+    // The aggregates of a bucket share its time.
+    let mut series = SeriesBuilder::new(aggregates.len(), buckets.len());
+    for (time, mut values) in buckets {
+        sort_values(&mut values);
+
+        for (index, aggregate) in aggregates.iter().enumerate() {
+            series.push(
+                index,
+                CounterValueWithTime::Double(time, aggregate.compute(&values)),
+            );
+        }
+    }
+
+    series.finish()
+}
+
+// Replaces each sample with the aggregate of the samples in the window
+// ending at it, which smooths a noisy counter without losing any of its
+// timestamps the way resampling does.
+pub fn rolling(samples: &Series, window: Duration, aggregate: Aggregate) -> Series {
+    // The values in the window, kept sorted for the aggregates that need it.
+    let mut values = Vec::<f64>::new();
+    let mut start = 0;
+
+    samples.map_values(|i| {
+        let value = samples.value(i);
+        let at = values.partition_point(|v| v.total_cmp(&value).is_lt());
+        values.insert(at, value);
+
+        while samples.time(i) - samples.time(start) >= window && start < i {
+            let old = samples.value(start);
+            let at = values.partition_point(|v| v.total_cmp(&old).is_lt());
+            values.remove(at);
+            start += 1;
+        }
+
+        aggregate.compute(&values)
+    })
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use time::{macros::format_description, Duration, OffsetDateTime};
+use windows::Win32::System::Performance::{PdhCloseLog, PDH_LOG_TYPE_BINARY, PDH_LOG_TYPE_TSV};
+
+use crate::{
+    cli::SplitFormat,
+    pdh_helper::{bind_input_logfiles, write_log_range},
+};
+
+// The last window of samples of each counter, so a problem can be saved
+// after it happens without writing everything to disk all the time.
+pub struct RingBuffer {
+    window: Duration,
+    counters: Vec<String>,
+    samples: VecDeque<(OffsetDateTime, Vec<Option<f64>>)>,
+    // While held, samples from a window before this time on are kept past
+    // the window, so a capture has what led up to it and what came after.
+    held: Option<OffsetDateTime>,
+}
+
+impl RingBuffer {
+    pub fn new(window: Duration, counters: Vec<String>) -> RingBuffer {
+        RingBuffer {
+            window,
+            counters,
+            samples: VecDeque::new(),
+            held: None,
+        }
+    }
+
+    // Adds one collection, with a value for each counter in order, and drops
+    // the samples that fell out of the window.
+    pub fn push(&mut self, time: OffsetDateTime, values: Vec<Option<f64>>) {
+        self.samples.push_back((time, values));
+
+        while let Some((oldest, _)) = self.samples.front() {
+            if time - *oldest <= self.window
+                || self.held.is_some_and(|held| held - *oldest <= self.window)
+            {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn hold(&mut self, time: OffsetDateTime) {
+        self.held = Some(time);
+    }
+
+    pub fn release(&mut self) {
+        self.held = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // Writes the samples in the CSV format perfmon writes, which PDH can
+    // read back. A .blg is made by relogging that CSV.
+    pub fn dump(&self, dir: &str, format: SplitFormat) -> Result<String, String> {
+        let (first, last) = match (self.samples.front(), self.samples.back()) {
+            (Some((first, _)), Some((last, _))) => (*first, *last),
+            _ => return Err("No samples collected yet.".to_string()),
+        };
+
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir, e))?;
+
+        let name = last
+            .format(format_description!(
+                "ring_[year][month][day]-[hour][minute][second]"
+            ))
+            .unwrap();
+        let csv_file = Path::new(dir).join(format!("{}.csv", name));
+        let csv_file = csv_file.display().to_string();
+        self.write_pdh_csv(&csv_file)
+            .map_err(|e| format!("Failed to write {}: {}", csv_file, e))?;
+
+        if let SplitFormat::Csv = format {
+            return Ok(csv_file);
+        }
+
+        let output_file = Path::new(dir)
+            .join(format!("{}.{}", name, format.extension()))
+            .display()
+            .to_string();
+        let log_type = match format {
+            SplitFormat::Blg => PDH_LOG_TYPE_BINARY,
+            _ => PDH_LOG_TYPE_TSV,
+        };
+
+        let hdatasource = bind_input_logfiles(vec![csv_file.clone()]);
+        let counters = self.counters.iter().collect::<Vec<&String>>();
+        let samples = write_log_range(
+            hdatasource,
+            &counters,
+            &output_file,
+            log_type,
+            first,
+            last + Duration::seconds(1),
+        );
+        unsafe { PdhCloseLog(hdatasource, 0) };
+        let _ = std::fs::remove_file(&csv_file);
+
+        if samples == 0 {
+            let _ = std::fs::remove_file(&output_file);
+            return Err(format!("Failed to relog the samples to {}", output_file));
+        }
+
+        Ok(output_file)
+    }
+
+    fn write_pdh_csv(&self, path: &str) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        write!(writer, "\"(PDH-CSV 4.0) (Coordinated Universal Time)(0)\"")?;
+        for counter in &self.counters {
+            write!(writer, ",\"{}\"", counter.replace('"', "\"\""))?;
+        }
+        writeln!(writer)?;
+
+        for (time, values) in &self.samples {
+            let time = time
+                .format(format_description!(
+                    "[month]/[day]/[year] [hour]:[minute]:[second].[subsecond digits:3]"
+                ))
+                .unwrap();
+            write!(writer, "\"{}\"", time)?;
+            for value in values {
+                match value {
+                    Some(value) => write!(writer, ",\"{}\"", value)?,
+                    // perfmon writes a blank in quotes for a missing value.
+                    None => write!(writer, ",\" \"")?,
+                }
+            }
+            writeln!(writer)?;
+        }
+
+        writer.flush()
+    }
+}
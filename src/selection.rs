@@ -0,0 +1,125 @@
+use std::io::Read;
+
+use crate::counter_path::CounterPath;
+
+// The counters matching any include pattern or under any of the objects, or
+// every counter when there are neither, less those matching an exclude
+// pattern.
+#[derive(Clone, Default)]
+pub struct CounterSelection {
+    pub include: Vec<String>,
+    pub objects: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl CounterSelection {
+    pub fn new(include: &[String]) -> CounterSelection {
+        CounterSelection {
+            include: include.to_vec(),
+            objects: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    pub fn matches(&self, counter: &str) -> bool {
+        (self.selects_all()
+            || self.include.iter().any(|p| counter_matches(p, counter))
+            || self.in_objects(counter))
+            && !self.exclude.iter().any(|p| counter_matches(p, counter))
+    }
+
+    pub fn selects_all(&self) -> bool {
+        self.include.is_empty() && self.objects.is_empty()
+    }
+
+    // Every counter and instance of an object, on any machine. Object names
+    // are matched whole, so --object Process doesn't pick up Processor.
+    fn in_objects(&self, counter: &str) -> bool {
+        if self.objects.is_empty() {
+            return false;
+        }
+
+        CounterPath::parse(counter).is_some_and(|path| {
+            self.objects
+                .iter()
+                .any(|object| object.eq_ignore_ascii_case(&path.object))
+        })
+    }
+}
+
+// Patterns without wildcards match anywhere in the full counter path. Patterns
+// with * or ? must match the whole path, except that a pattern without a
+// leading \\machine matches any machine. Matching ignores case.
+pub fn select_counters<'a>(
+    counters: &'a [String],
+    selection: &CounterSelection,
+) -> Vec<&'a String> {
+    counters
+        .iter()
+        .filter(|counter| selection.matches(counter))
+        .collect()
+}
+
+// Reads a counter list like the ones relog -cf and logman -cf take: one path
+// or pattern per line. Blank lines and lines starting with # are skipped. A
+// path of - reads stdin, so the output of find can be piped in.
+pub fn read_counter_list(path: &str) -> Result<Vec<String>, String> {
+    let text = if path == "-" {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("Failed to read counters from stdin: {}", e))?;
+        text
+    } else {
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?
+    };
+
+    Ok(text
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.trim_matches('"').to_string())
+        .collect())
+}
+
+pub fn counter_matches(pattern: &str, counter: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let counter = counter.to_lowercase();
+
+    if !pattern.contains(['*', '?']) {
+        return counter.contains(&pattern);
+    }
+
+    if pattern.starts_with("\\\\") {
+        wildcard_match(&pattern, &counter)
+    } else {
+        wildcard_match(&format!("*{}", pattern), &counter)
+    }
+}
+
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -0,0 +1,333 @@
+use std::{cmp::Reverse, collections::BinaryHeap, sync::Arc};
+
+use clap::ValueEnum;
+use time::OffsetDateTime;
+
+use crate::pdh_helper::CounterValueWithTime;
+
+// The samples of one counter stored as columns: the times, shared by the
+// counters read together, and the values in the type PDH returned them in.
+// A sample per CounterValueWithTime takes 32 bytes; here it's the size of the
+// value plus a share of the time.
+#[derive(Clone, Default)]
+pub struct Series {
+    times: Arc<Vec<OffsetDateTime>>,
+    // The position in times of each value, or None when the counter has a
+    // value at every time.
+    rows: Option<Vec<u32>>,
+    values: Values,
+}
+
+#[derive(Clone)]
+enum Values {
+    Long(Vec<i32>),
+    Double(Vec<f64>),
+    Large(Vec<i64>),
+}
+
+impl Default for Values {
+    fn default() -> Self {
+        Values::Double(Vec::new())
+    }
+}
+
+impl Values {
+    fn with_capacity(sample: &CounterValueWithTime, capacity: usize) -> Values {
+        match sample {
+            CounterValueWithTime::Long(..) => Values::Long(Vec::with_capacity(capacity)),
+            CounterValueWithTime::Double(..) => Values::Double(Vec::with_capacity(capacity)),
+            CounterValueWithTime::Large(..) => Values::Large(Vec::with_capacity(capacity)),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Values::Long(values) => values.len(),
+            Values::Double(values) => values.len(),
+            Values::Large(values) => values.len(),
+        }
+    }
+
+    fn push(&mut self, sample: &CounterValueWithTime) {
+        match (&mut *self, sample) {
+            (Values::Long(values), CounterValueWithTime::Long(_, v)) => values.push(*v),
+            (Values::Double(values), CounterValueWithTime::Double(_, v)) => values.push(*v),
+            (Values::Large(values), CounterValueWithTime::Large(_, v)) => values.push(*v),
+            // A counter doesn't change type, but if one did, keep everything
+            // as doubles.
+            _ => {
+                let mut values = (0..self.len()).map(|i| self.value(i)).collect::<Vec<f64>>();
+                values.push(sample.value());
+                *self = Values::Double(values);
+            }
+        }
+    }
+
+    fn value(&self, index: usize) -> f64 {
+        match self {
+            Values::Long(values) => values[index] as f64,
+            Values::Double(values) => values[index],
+            Values::Large(values) => values[index] as f64,
+        }
+    }
+
+    fn select(&self, indexes: &[usize]) -> Values {
+        match self {
+            Values::Long(values) => Values::Long(indexes.iter().map(|i| values[*i]).collect()),
+            Values::Double(values) => Values::Double(indexes.iter().map(|i| values[*i]).collect()),
+            Values::Large(values) => Values::Large(indexes.iter().map(|i| values[*i]).collect()),
+        }
+    }
+
+    fn get(&self, time: OffsetDateTime, index: usize) -> CounterValueWithTime {
+        match self {
+            Values::Long(values) => CounterValueWithTime::Long(time, values[index]),
+            Values::Double(values) => CounterValueWithTime::Double(time, values[index]),
+            Values::Large(values) => CounterValueWithTime::Large(time, values[index]),
+        }
+    }
+}
+
+impl Series {
+    // A counter read on its own, with a value at every time.
+    pub fn from_doubles(times: Vec<OffsetDateTime>, values: Vec<f64>) -> Series {
+        Series {
+            times: Arc::new(times),
+            rows: None,
+            values: Values::Double(values),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn time(&self, index: usize) -> OffsetDateTime {
+        self.times[self.row(index)]
+    }
+
+    fn row(&self, index: usize) -> usize {
+        match &self.rows {
+            Some(rows) => rows[index] as usize,
+            None => index,
+        }
+    }
+
+    pub fn value(&self, index: usize) -> f64 {
+        self.values.value(index)
+    }
+
+    pub fn get(&self, index: usize) -> CounterValueWithTime {
+        self.values.get(self.time(index), index)
+    }
+
+    pub fn first(&self) -> Option<CounterValueWithTime> {
+        (!self.is_empty()).then(|| self.get(0))
+    }
+
+    pub fn last(&self) -> Option<CounterValueWithTime> {
+        (!self.is_empty()).then(|| self.get(self.len() - 1))
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = CounterValueWithTime> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.len()).map(|i| self.value(i))
+    }
+
+    // For code that works on slices of samples, one counter at a time.
+    pub fn to_vec(&self) -> Vec<CounterValueWithTime> {
+        self.iter().collect()
+    }
+
+    // The same samples with new values, still sharing the times.
+    pub fn map_values(&self, f: impl FnMut(usize) -> f64) -> Series {
+        Series {
+            times: self.times.clone(),
+            rows: self.rows.clone(),
+            values: Values::Double((0..self.len()).map(f).collect()),
+        }
+    }
+
+    // The samples for which keep returns true, still sharing the times.
+    pub fn select(&self, mut keep: impl FnMut(usize) -> bool) -> Series {
+        let indexes = (0..self.len()).filter(|i| keep(*i)).collect::<Vec<usize>>();
+        if indexes.len() == self.len() {
+            return self.clone();
+        }
+
+        let rows = indexes
+            .iter()
+            .map(|i| self.row(*i) as u32)
+            .collect::<Vec<u32>>();
+        let dense = rows.iter().enumerate().all(|(i, row)| i == *row as usize);
+
+        Series {
+            times: self.times.clone(),
+            rows: (!dense).then_some(rows),
+            values: self.values.select(&indexes),
+        }
+    }
+
+    // Combines the series of the same counter from different reads, in time
+    // order.
+    pub fn merge(series: Vec<Series>) -> Series {
+        if series.len() == 1 {
+            return series.into_iter().next().unwrap();
+        }
+
+        let mut samples = series
+            .iter()
+            .flat_map(|s| s.iter())
+            .collect::<Vec<CounterValueWithTime>>();
+        samples.sort_by_key(|s| s.time());
+        samples.into_iter().collect()
+    }
+}
+
+// What to keep of the samples of a counter that share a time, which logs
+// that overlap give after they're bound together.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Duplicates {
+    /// The first sample read
+    First,
+    /// The last sample read
+    Last,
+    /// The average of the samples
+    Average,
+    /// Every sample
+    All,
+}
+
+impl Series {
+    // Combines each run of samples at the same time into one. Samples arrive
+    // in time order, so the ones that share a time are next to each other.
+    pub fn dedup(self, duplicates: Duplicates) -> Series {
+        let repeated = (1..self.len()).any(|i| self.time(i) == self.time(i - 1));
+        if duplicates == Duplicates::All || !repeated {
+            return self;
+        }
+
+        let mut builder = SeriesBuilder::new(1, self.len());
+        let mut start = 0;
+        while start < self.len() {
+            let time = self.time(start);
+            let mut end = start + 1;
+            while end < self.len() && self.time(end) == time {
+                end += 1;
+            }
+
+            let sample = match duplicates {
+                Duplicates::Last => self.get(end - 1),
+                Duplicates::Average => CounterValueWithTime::Double(
+                    time,
+                    (start..end).map(|i| self.value(i)).sum::<f64>() / (end - start) as f64,
+                ),
+                _ => self.get(start),
+            };
+            builder.push(0, sample);
+            start = end;
+        }
+        builder.finish().pop().unwrap()
+    }
+}
+
+// The samples of every series with the index of their series, in time order,
+// and in series order at the same time.
+pub fn in_time_order(
+    series: &[Series],
+) -> impl Iterator<Item = (usize, CounterValueWithTime)> + '_ {
+    let mut next = series
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| !s.is_empty())
+        .map(|(column, s)| Reverse((s.time(0), column, 0)))
+        .collect::<BinaryHeap<_>>();
+
+    std::iter::from_fn(move || {
+        let Reverse((_, column, index)) = next.pop()?;
+        let samples = &series[column];
+        if index + 1 < samples.len() {
+            next.push(Reverse((samples.time(index + 1), column, index + 1)));
+        }
+        Some((column, samples.get(index)))
+    })
+}
+
+impl FromIterator<CounterValueWithTime> for Series {
+    fn from_iter<I: IntoIterator<Item = CounterValueWithTime>>(iter: I) -> Self {
+        let mut builder = SeriesBuilder::new(1, 0);
+        for sample in iter {
+            builder.push_own_time(0, sample);
+        }
+        builder.finish().pop().unwrap()
+    }
+}
+
+// Builds the series of counters read together, which share one copy of the
+// times they were collected at.
+pub struct SeriesBuilder {
+    times: Vec<OffsetDateTime>,
+    columns: Vec<(Option<Vec<u32>>, Option<Values>)>,
+    capacity: usize,
+}
+
+impl SeriesBuilder {
+    pub fn new(counters: usize, capacity: usize) -> SeriesBuilder {
+        SeriesBuilder {
+            times: Vec::with_capacity(capacity),
+            columns: (0..counters).map(|_| (None, None)).collect(),
+            capacity,
+        }
+    }
+
+    // Samples must arrive in time order, as they do from a query, with the
+    // counters of one collection all stamped with its time.
+    pub fn push(&mut self, index: usize, sample: CounterValueWithTime) {
+        if self.times.last() != Some(&sample.time()) {
+            self.times.push(sample.time());
+        }
+        self.push_row(index, self.times.len() - 1, &sample);
+    }
+
+    // For a series built on its own, where every sample has a time of its
+    // own, even when two share a timestamp.
+    fn push_own_time(&mut self, index: usize, sample: CounterValueWithTime) {
+        self.times.push(sample.time());
+        self.push_row(index, self.times.len() - 1, &sample);
+    }
+
+    fn push_row(&mut self, index: usize, row: usize, sample: &CounterValueWithTime) {
+        let capacity = self.capacity;
+        let (rows, values) = &mut self.columns[index];
+        let values = values.get_or_insert_with(|| Values::with_capacity(sample, capacity));
+
+        // Rows are only kept once the counter misses a time.
+        if rows.is_none() && values.len() != row {
+            *rows = Some((0..values.len() as u32).collect());
+        }
+        if let Some(rows) = rows {
+            rows.push(row as u32);
+        }
+
+        values.push(sample);
+    }
+
+    pub fn finish(self) -> Vec<Series> {
+        let times = Arc::new(self.times);
+        self.columns
+            .into_iter()
+            .map(|(rows, values)| Series {
+                times: times.clone(),
+                rows,
+                values: values.unwrap_or_default(),
+            })
+            .collect()
+    }
+}
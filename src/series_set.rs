@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use time::OffsetDateTime;
+
+use crate::{
+    counter_path::CounterPath, pdh_helper::CounterErrors, reader::CounterData, series::Series,
+    units::Unit,
+};
+
+// A counter's samples with what's known about it: the pieces of its path,
+// its unit and counter type, and the samples it had no value for.
+pub struct CounterSeries {
+    pub counter: String,
+    // None for counters that aren't full paths, like the columns of a CSV
+    // log that wasn't written by perfmon.
+    pub path: Option<CounterPath>,
+    pub unit: Unit,
+    // None when the counter's info couldn't be read.
+    pub counter_type: Option<u32>,
+    pub series: Series,
+    pub errors: Option<CounterErrors>,
+}
+
+impl CounterSeries {
+    pub fn object(&self) -> Option<&str> {
+        self.path.as_ref().map(|p| p.object.as_str())
+    }
+
+    pub fn instance(&self) -> Option<&str> {
+        self.path.as_ref().and_then(|p| p.instance.as_deref())
+    }
+
+    pub fn times(&self) -> impl Iterator<Item = OffsetDateTime> + '_ {
+        (0..self.series.len()).map(|i| self.series.time(i))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = f64> + '_ {
+        self.series.values()
+    }
+}
+
+// The counters read together, in the order they were read, looked up by
+// path or by the object they belong to. Paths compare without case, like PDH
+// compares them.
+pub struct SeriesSet {
+    series: Vec<CounterSeries>,
+    index: HashMap<String, usize>,
+}
+
+impl SeriesSet {
+    pub fn iter(&self) -> impl Iterator<Item = &CounterSeries> {
+        self.series.iter()
+    }
+
+    pub fn get(&self, counter: &str) -> Option<&CounterSeries> {
+        self.index
+            .get(&counter.to_lowercase())
+            .map(|&i| &self.series[i])
+    }
+
+    pub fn object<'a>(&'a self, object: &'a str) -> impl Iterator<Item = &'a CounterSeries> {
+        self.series
+            .iter()
+            .filter(move |s| s.object().is_some_and(|o| o.eq_ignore_ascii_case(object)))
+    }
+}
+
+impl IntoIterator for SeriesSet {
+    type Item = CounterSeries;
+    type IntoIter = std::vec::IntoIter<CounterSeries>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.series.into_iter()
+    }
+}
+
+impl From<CounterData> for SeriesSet {
+    fn from(mut data: CounterData) -> SeriesSet {
+        let series = std::mem::take(&mut data.counters)
+            .into_iter()
+            .map(|counter| CounterSeries {
+                path: CounterPath::parse(&counter),
+                unit: data.unit(&counter),
+                counter_type: data.info.get(&counter).map(|i| i.counter_type),
+                series: data.samples.remove(&counter).unwrap_or_default(),
+                errors: data.errors.remove(&counter),
+                counter,
+            })
+            .collect::<Vec<CounterSeries>>();
+
+        let index = series
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.counter.to_lowercase(), i))
+            .collect();
+
+        SeriesSet { series, index }
+    }
+}
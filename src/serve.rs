@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    net::{TcpListener, TcpStream},
+};
+
+use time::OffsetDateTime;
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    cli::ServeArgs,
+    export::format_time_with_offset,
+    http::{read_request, write_response},
+    log_files::open_log_files,
+    pdh_helper::{read_counter_values, PerfLogSummary},
+    report::json,
+    selection::counter_matches,
+    series::Series,
+    status::progress,
+    timespec::{display_offset, parse_time_spec},
+};
+
+// Points a series is averaged down to when the request doesn't say, which is
+// about as many as a chart on a wide screen can show.
+const DEFAULT_POINTS: usize = 2000;
+
+// Serves the logs to a browser on this machine until interrupted: a page that
+// charts the counters, and the JSON API it reads them from.
+//
+//   GET /                  the chart page
+//   GET /api/summary       time range, samples, machines, and counter count
+//   GET /api/counters      counter paths, with ?match= text or a wildcard
+//                          pattern like --counter takes
+//   GET /api/series        samples of ?counter=, between ?start= and ?end=,
+//                          averaged into at most ?points= points
+//
+// start and end are times like --start takes, or milliseconds since 1970.
+// Requests are answered one at a time, since PDH reads one query at a time
+// from a data source anyway.
+pub fn serve(args: &ServeArgs) {
+    let (hdatasource, summary) = match open_log_files(&args.glob_pattern) {
+        Some(opened) => opened,
+        None => return,
+    };
+
+    let listener = match TcpListener::bind(("127.0.0.1", args.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen on port {}: {}", args.port, e);
+            unsafe { PdhCloseLog(hdatasource, 0) };
+            return;
+        }
+    };
+
+    let mut viewer = Viewer {
+        hdatasource,
+        counters: summary.get_all_counters(),
+        summary,
+        series: HashMap::new(),
+    };
+
+    progress!(
+        "Serving {} counters at http://127.0.0.1:{}/ until interrupted.",
+        viewer.counters.len(),
+        args.port
+    );
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = viewer.handle(stream) {
+            eprintln!("Failed to answer a request: {}", e);
+        }
+    }
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+}
+
+struct Viewer {
+    hdatasource: isize,
+    summary: PerfLogSummary,
+    counters: Vec<String>,
+    // Counters already read, so zooming in on one doesn't read the logs
+    // again.
+    series: HashMap<String, Series>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(body: String) -> Response {
+        Response {
+            status: 200,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: format!("{{\"error\":{}}}", json(message)),
+        }
+    }
+}
+
+impl Viewer {
+    fn handle(&mut self, mut stream: TcpStream) -> std::io::Result<()> {
+        let request = read_request(&stream)?;
+        let response = match request.method.as_str() {
+            "GET" => self.route(&request.target),
+            _ => Response::error(405, "Only GET is supported."),
+        };
+        write_response(
+            &mut stream,
+            response.status,
+            response.content_type,
+            response.body.as_bytes(),
+        )
+    }
+
+    fn route(&mut self, target: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = parse_query(query);
+
+        match path {
+            "/" | "/index.html" => Response {
+                status: 200,
+                content_type: "text/html",
+                body: INDEX.to_string(),
+            },
+            "/api/summary" => Response::json(self.summary_json()),
+            "/api/counters" => Response::json(self.counters_json(query.get("match"))),
+            "/api/series" => self.series_json(&query),
+            _ => Response::error(404, "Not found."),
+        }
+    }
+
+    fn summary_json(&self) -> String {
+        let machines = self
+            .summary
+            .machines
+            .iter()
+            .map(|m| json(&m.name))
+            .collect::<Vec<String>>();
+        format!(
+            "{{\"start\":{},\"end\":{},\"samples\":{},\"machines\":[{}],\"counters\":{},\"offset_minutes\":{}}}",
+            json(&format_time_with_offset(self.summary.start_time)),
+            json(&format_time_with_offset(self.summary.end_time)),
+            self.summary.sample_count,
+            machines.join(","),
+            self.counters.len(),
+            display_offset().whole_minutes()
+        )
+    }
+
+    fn counters_json(&self, pattern: Option<&String>) -> String {
+        let counters = self
+            .counters
+            .iter()
+            .filter(|c| pattern.is_none_or(|p| counter_matches(p, c)))
+            .map(|c| json(c))
+            .collect::<Vec<String>>();
+        format!("[{}]", counters.join(","))
+    }
+
+    fn series_json(&mut self, query: &HashMap<String, String>) -> Response {
+        let counter = match query.get("counter") {
+            Some(counter) => counter,
+            None => return Response::error(400, "counter is missing."),
+        };
+        let counter = match self
+            .counters
+            .iter()
+            .find(|c| c.eq_ignore_ascii_case(counter))
+        {
+            Some(counter) => counter.clone(),
+            None => return Response::error(404, &format!("{} isn't in the logs.", counter)),
+        };
+
+        let mut bounds = [None, None];
+        for (bound, name) in bounds.iter_mut().zip(["start", "end"]) {
+            if let Some(text) = query.get(name) {
+                *bound = match parse_time(text) {
+                    Ok(time) => time,
+                    Err(e) => return Response::error(400, &format!("Bad {}: {}", name, e)),
+                };
+            }
+        }
+        let points = match query.get("points").map(|p| p.parse::<usize>()) {
+            None => DEFAULT_POINTS,
+            Some(Ok(points)) if points > 0 => points,
+            Some(_) => return Response::error(400, "points must be a number above 0."),
+        };
+
+        let hdatasource = self.hdatasource;
+        let series = self.series.entry(counter.clone()).or_insert_with(|| {
+            let (mut samples, _) = read_counter_values(hdatasource, &vec![&counter], None);
+            samples.remove(&counter).unwrap_or_default()
+        });
+
+        let in_range = (0..series.len())
+            .filter(|i| {
+                let time = series.time(*i);
+                bounds[0].is_none_or(|start| time >= start)
+                    && bounds[1].is_none_or(|end| time <= end)
+            })
+            .collect::<Vec<usize>>();
+
+        let samples = downsample(series, &in_range, points)
+            .into_iter()
+            .map(|(time, value)| {
+                let value = if value.is_finite() {
+                    value.to_string()
+                } else {
+                    "null".to_string()
+                };
+                format!("[{},{}]", time, value)
+            })
+            .collect::<Vec<String>>();
+
+        Response::json(format!(
+            "{{\"counter\":{},\"samples\":{},\"points\":[{}]}}",
+            json(&counter),
+            in_range.len(),
+            samples.join(",")
+        ))
+    }
+}
+
+// Averages runs of samples into at most points points, each at the average
+// of their times, in milliseconds since 1970 for JavaScript.
+fn downsample(series: &Series, indexes: &[usize], points: usize) -> Vec<(i64, f64)> {
+    let per_point = indexes.len().div_ceil(points).max(1);
+
+    indexes
+        .chunks(per_point)
+        .map(|chunk| {
+            let times = chunk.iter().map(|i| unix_millis(series.time(*i)));
+            let values = chunk.iter().map(|i| series.value(*i));
+            let n = chunk.len() as f64;
+            (
+                (times.map(|t| t as f64).sum::<f64>() / n) as i64,
+                values.sum::<f64>() / n,
+            )
+        })
+        .collect()
+}
+
+fn unix_millis(time: OffsetDateTime) -> i64 {
+    (time.unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+fn parse_time(text: &str) -> Result<Option<OffsetDateTime>, String> {
+    if let Ok(millis) = text.parse::<i64>() {
+        return OffsetDateTime::from_unix_timestamp_nanos(millis as i128 * 1_000_000)
+            .map(Some)
+            .map_err(|e| e.to_string());
+    }
+    Ok(parse_time_spec(text)?.resolve(display_offset()))
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect()
+}
+
+// Counter paths are full of characters that get escaped, like \ and %.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// The chart page. It lists the counters to pick from, charts the ones
+// picked, and reads them again at more detail when a range is dragged across
+// the chart. Double-click to zoom back out.
+const INDEX: &str = r##"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>perflogtool</title>
+<style>
+body { font-family: Segoe UI, sans-serif; margin: 0; display: flex; height: 100vh; color: #222; }
+#side { width: 360px; display: flex; flex-direction: column; border-right: 1px solid #ccc; }
+#side input { margin: 8px; padding: 4px; }
+#list { overflow-y: auto; flex: 1; font-size: 12px; }
+#list div { padding: 2px 8px; cursor: pointer; white-space: nowrap; }
+#list div:hover { background: #eef; }
+#list div.picked { background: #dde; font-weight: bold; }
+#main { flex: 1; display: flex; flex-direction: column; }
+#info { padding: 8px; font-size: 13px; }
+#legend { padding: 0 8px; font-size: 12px; }
+canvas { flex: 1; width: 100%; }
+</style></head>
+<body>
+<div id="side"><input id="filter" placeholder="Filter, like \Processor(*)\*"><div id="list"></div></div>
+<div id="main"><div id="info"></div><div id="legend"></div><canvas id="chart"></canvas></div>
+<script>
+const colors = ["#1565c0", "#b00020", "#2e7d32", "#ef6c00", "#6a1b9a", "#00838f", "#5d4037", "#c2185b"];
+let summary = null, counters = [], picked = [], data = {}, range = null, drag = null;
+
+function fmt(ms) {
+  return new Date(ms + summary.offset_minutes * 60000).toISOString().slice(0, 19).replace("T", " ");
+}
+
+async function get(url) {
+  const response = await fetch(url);
+  return response.json();
+}
+
+async function load() {
+  summary = await get("/api/summary");
+  document.getElementById("info").textContent =
+    `${summary.counters} counters, ${summary.samples} samples, ${summary.start} to ${summary.end}`;
+  counters = await get("/api/counters");
+  showList();
+}
+
+function showList() {
+  const text = document.getElementById("filter").value.toLowerCase();
+  const list = document.getElementById("list");
+  list.innerHTML = "";
+  for (const counter of counters.filter(c => c.toLowerCase().includes(text)).slice(0, 2000)) {
+    const div = document.createElement("div");
+    div.textContent = counter;
+    div.className = picked.includes(counter) ? "picked" : "";
+    div.onclick = () => toggle(counter);
+    list.appendChild(div);
+  }
+}
+
+async function toggle(counter) {
+  if (picked.includes(counter)) {
+    picked = picked.filter(c => c !== counter);
+    delete data[counter];
+  } else {
+    picked.push(counter);
+    await fetchSeries(counter);
+  }
+  showList();
+  draw();
+}
+
+async function fetchSeries(counter) {
+  const canvas = document.getElementById("chart");
+  let url = `/api/series?counter=${encodeURIComponent(counter)}&points=${canvas.clientWidth}`;
+  if (range) url += `&start=${Math.floor(range[0])}&end=${Math.ceil(range[1])}`;
+  data[counter] = (await get(url)).points || [];
+}
+
+async function zoom(newRange) {
+  range = newRange;
+  await Promise.all(picked.map(fetchSeries));
+  draw();
+}
+
+function draw() {
+  const canvas = document.getElementById("chart");
+  canvas.width = canvas.clientWidth;
+  canvas.height = canvas.clientHeight;
+  const ctx = canvas.getContext("2d");
+  ctx.clearRect(0, 0, canvas.width, canvas.height);
+
+  const legend = document.getElementById("legend");
+  legend.innerHTML = "";
+  const points = picked.flatMap(c => data[c] || []).filter(p => p[1] !== null);
+  if (points.length === 0) return;
+
+  const left = 70, bottom = 30, width = canvas.width - left - 10, height = canvas.height - bottom - 10;
+  const t0 = Math.min(...points.map(p => p[0])), t1 = Math.max(...points.map(p => p[0]));
+  const v0 = Math.min(0, ...points.map(p => p[1])), v1 = Math.max(...points.map(p => p[1])) || 1;
+  const x = t => left + (t1 === t0 ? 0 : (t - t0) / (t1 - t0) * width);
+  const y = v => 10 + height - (v - v0) / (v1 - v0) * height;
+  canvas.toTime = px => t0 + (px - left) / width * (t1 - t0);
+
+  ctx.strokeStyle = "#ccc";
+  ctx.fillStyle = "#444";
+  ctx.font = "11px Segoe UI";
+  for (let i = 0; i <= 4; i++) {
+    const v = v0 + (v1 - v0) * i / 4;
+    ctx.beginPath(); ctx.moveTo(left, y(v)); ctx.lineTo(left + width, y(v)); ctx.stroke();
+    ctx.fillText(v.toPrecision(4), 4, y(v) + 4);
+    const t = t0 + (t1 - t0) * i / 4;
+    ctx.fillText(fmt(t), Math.min(x(t), left + width - 110), canvas.height - 10);
+  }
+
+  picked.forEach((counter, i) => {
+    const color = colors[i % colors.length];
+    ctx.strokeStyle = color;
+    ctx.beginPath();
+    let drawing = false;
+    for (const [t, v] of data[counter] || []) {
+      if (v === null) { drawing = false; continue; }
+      drawing ? ctx.lineTo(x(t), y(v)) : ctx.moveTo(x(t), y(v));
+      drawing = true;
+    }
+    ctx.stroke();
+    const item = document.createElement("div");
+    item.style.color = color;
+    item.textContent = counter;
+    legend.appendChild(item);
+  });
+
+  if (drag) {
+    ctx.fillStyle = "rgba(21, 101, 192, 0.15)";
+    ctx.fillRect(Math.min(drag[0], drag[1]), 10, Math.abs(drag[1] - drag[0]), height);
+  }
+}
+
+const canvas = document.getElementById("chart");
+canvas.onmousedown = e => { drag = [e.offsetX, e.offsetX]; };
+canvas.onmousemove = e => { if (drag) { drag[1] = e.offsetX; draw(); } };
+canvas.onmouseup = () => {
+  const [a, b] = drag || [0, 0];
+  drag = null;
+  if (Math.abs(b - a) > 5 && canvas.toTime) zoom([canvas.toTime(Math.min(a, b)), canvas.toTime(Math.max(a, b))]);
+  else draw();
+};
+canvas.ondblclick = () => zoom(null);
+document.getElementById("filter").oninput = showList;
+window.onresize = draw;
+load();
+</script>
+</body></html>
+"##;
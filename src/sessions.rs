@@ -0,0 +1,141 @@
+use std::collections::BTreeSet;
+
+use time::Duration;
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    cache::cached_summary,
+    cli::SummaryArgs,
+    export::format_time,
+    log_files::find_log_files,
+    pdh_helper::{bind_input_logfiles, PerfLogSummary},
+    timespec::{display_offset, zone_label},
+};
+
+// A gap longer than this many intervals between one file and the next means
+// the collector was stopped and started again.
+const GAP_INTERVALS: i32 = 3;
+
+// Gaps shorter than this are never a new session, for logs with a single
+// sample or a very short interval.
+const MIN_GAP: Duration = Duration::minutes(1);
+
+struct LogFile {
+    path: String,
+    summary: PerfLogSummary,
+}
+
+// Prints the summary of each capture session in the logs on its own. Each
+// file is read on its own to find where one session ends and the next
+// begins, then the files of each session are bound together.
+pub fn summarize_sessions(args: &SummaryArgs) {
+    let files = find_log_files(&args.glob_pattern);
+
+    if files.is_empty() {
+        return;
+    }
+
+    let mut logs = files
+        .into_iter()
+        .map(|path| {
+            let hdatasource = bind_input_logfiles(vec![path.clone()]);
+            let summary = cached_summary(std::slice::from_ref(&path), hdatasource);
+            unsafe { PdhCloseLog(hdatasource, 0) };
+            LogFile { path, summary }
+        })
+        .collect::<Vec<LogFile>>();
+    logs.sort_by_key(|log| log.summary.start_time);
+
+    let sessions = split_sessions(logs);
+    println!(
+        "{} capture session{}",
+        sessions.len(),
+        if sessions.len() == 1 { "" } else { "s" }
+    );
+
+    for (index, session) in sessions.iter().enumerate() {
+        println!();
+        println!(
+            "Session {}: {} file{}",
+            index + 1,
+            session.len(),
+            if session.len() == 1 { "" } else { "s" }
+        );
+        for log in session {
+            println!("  {}", log.path);
+        }
+
+        let paths = session
+            .iter()
+            .map(|log| log.path.clone())
+            .collect::<Vec<String>>();
+        let hdatasource = bind_input_logfiles(paths.clone());
+        let mut summary = cached_summary(&paths, hdatasource);
+
+        if args.depth >= 3 || !args.expand.is_empty() {
+            summary.load_counter_info(hdatasource);
+        }
+
+        println!(
+            "Time range: {} - {} ({})",
+            format_time(summary.start_time),
+            format_time(summary.end_time),
+            zone_label(display_offset())
+        );
+        summary
+            .write_samples(&mut std::io::stdout())
+            .expect("Failed to write summary");
+        summary.print_hierarchy(args.depth, &args.expand);
+
+        unsafe { PdhCloseLog(hdatasource, 0) };
+    }
+}
+
+// Files in start order go in the same session until one starts well after
+// the one before ended, or has a different set of counters. Instances come
+// and go within a session, like processes, so only the counters count.
+fn split_sessions(logs: Vec<LogFile>) -> Vec<Vec<LogFile>> {
+    let mut sessions = Vec::<Vec<LogFile>>::new();
+
+    for log in logs {
+        let continues = sessions
+            .last()
+            .and_then(|session| session.last())
+            .is_some_and(|last| {
+                let gap = log.summary.start_time - last.summary.end_time;
+                let interval = [&last.summary, &log.summary]
+                    .iter()
+                    .filter_map(|s| s.interval())
+                    .max()
+                    .unwrap_or(Duration::ZERO);
+                gap <= (interval * GAP_INTERVALS).max(MIN_GAP)
+                    && counter_set(&last.summary) == counter_set(&log.summary)
+            });
+
+        if continues {
+            sessions.last_mut().unwrap().push(log);
+        } else {
+            sessions.push(vec![log]);
+        }
+    }
+
+    sessions
+}
+
+fn counter_set(summary: &PerfLogSummary) -> BTreeSet<(String, String, String)> {
+    summary
+        .machines
+        .iter()
+        .flat_map(|machine| {
+            machine.objects.iter().flat_map(|object| {
+                object.counters.iter().map(|counter| {
+                    (
+                        machine.name.to_lowercase(),
+                        object.name.to_lowercase(),
+                        counter.to_lowercase(),
+                    )
+                })
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    cli::{SpikeMethod, SpikesArgs},
+    export::format_time,
+    pdh_helper::CounterValueWithTime,
+    reader::read_counters,
+    selection::CounterSelection,
+    stats::{percentile, sort_values, Welford},
+};
+
+// Too few samples in the window make for a meaningless baseline.
+const MIN_BASELINE_SAMPLES: usize = 10;
+
+// Scales the MAD so it estimates the standard deviation of normal data.
+const MAD_SCALE: f64 = 1.4826;
+
+pub struct Spike {
+    pub time: OffsetDateTime,
+    pub value: f64,
+    pub baseline: f64,
+    pub deviations: f64,
+}
+
+pub fn spikes(args: &SpikesArgs) {
+    let counter_data = match read_counters(&args.source, &CounterSelection::new(&args.counter)) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    if counter_data.counters.is_empty() {
+        eprintln!("No counters matched.");
+        return;
+    }
+
+    let time_filter = args.time_filter.time_filter();
+
+    let mut total = 0;
+    for counter in &counter_data.counters {
+        let samples = counter_data.samples[counter]
+            .iter()
+            .filter(|s| time_filter.matches(s.time()))
+            .collect::<Vec<CounterValueWithTime>>();
+
+        let spikes = find_spikes(&samples, args.window, args.threshold, args.method);
+
+        if spikes.is_empty() {
+            continue;
+        }
+
+        total += spikes.len();
+
+        println!("{}", counter);
+        for spike in spikes {
+            println!(
+                "  {}  value {:>14.2}  baseline {:>14.2}  {:>6.1} deviations",
+                format_time(spike.time),
+                spike.value,
+                spike.baseline,
+                spike.deviations
+            );
+        }
+        println!();
+    }
+
+    println!(
+        "{} spikes in {} counters.",
+        total,
+        counter_data.counters.len()
+    );
+}
+
+// Compares each sample to the samples in the window before it. Windows with
+// no variation at all are skipped, since any change would be infinitely many
+// deviations away.
+pub fn find_spikes(
+    samples: &[CounterValueWithTime],
+    window: Duration,
+    threshold: f64,
+    method: SpikeMethod,
+) -> Vec<Spike> {
+    let mut spikes = Vec::new();
+    let mut baseline_window = VecDeque::<(OffsetDateTime, f64)>::new();
+    let mut moments = Welford::default();
+
+    for sample in samples {
+        let (time, value) = (sample.time(), sample.value());
+
+        while let Some((oldest_time, oldest_value)) = baseline_window.front().copied() {
+            if time - oldest_time <= window {
+                break;
+            }
+            baseline_window.pop_front();
+            moments.remove(oldest_value);
+        }
+
+        if baseline_window.len() >= MIN_BASELINE_SAMPLES {
+            let (baseline, scale) = match method {
+                SpikeMethod::Stddev => (moments.mean(), moments.stddev()),
+                SpikeMethod::Mad => median_and_mad(&baseline_window),
+            };
+
+            if scale > 0.0 {
+                let deviations = (value - baseline).abs() / scale;
+                if deviations > threshold {
+                    spikes.push(Spike {
+                        time,
+                        value,
+                        baseline,
+                        deviations,
+                    });
+                }
+            }
+        }
+
+        baseline_window.push_back((time, value));
+        moments.add(value);
+    }
+
+    spikes
+}
+
+fn median_and_mad(window: &VecDeque<(OffsetDateTime, f64)>) -> (f64, f64) {
+    let mut values = window.iter().map(|(_, v)| *v).collect::<Vec<f64>>();
+    sort_values(&mut values);
+    let median = percentile(&values, 50.0);
+
+    let mut deviations = values
+        .iter()
+        .map(|v| (v - median).abs())
+        .collect::<Vec<f64>>();
+    sort_values(&mut deviations);
+
+    (median, percentile(&deviations, 50.0) * MAD_SCALE)
+}
@@ -0,0 +1,131 @@
+use std::path::Path;
+
+use time::{macros::format_description, Duration, OffsetDateTime, Time};
+use windows::Win32::System::Performance::{
+    PdhCloseLog, PDH_LOG_TYPE, PDH_LOG_TYPE_BINARY, PDH_LOG_TYPE_CSV, PDH_LOG_TYPE_TSV,
+};
+
+use crate::{
+    cli::{SplitArgs, SplitFormat},
+    export::format_time,
+    log_files::open_log_files,
+    pdh_helper::write_log_range,
+    selection::select_counters,
+    timespec::{display_offset, zone_label},
+};
+
+pub fn split(args: &SplitArgs) {
+    let selection = match args.counters.selection() {
+        Some(selection) => selection,
+        None => return,
+    };
+
+    let (hdatasource, summary) = match open_log_files(&args.glob_pattern) {
+        Some(opened) => opened,
+        None => return,
+    };
+
+    println!(
+        "Time range: {} - {} ({})",
+        format_time(summary.start_time),
+        format_time(summary.end_time),
+        zone_label(display_offset())
+    );
+
+    let counters = summary.get_all_counters();
+
+    let counters_to_write = select_counters(&counters, &selection);
+
+    if counters_to_write.is_empty() {
+        println!("No counters matched.");
+        unsafe { PdhCloseLog(hdatasource, 0) };
+        return;
+    }
+
+    let chunks = get_chunks(summary.start_time, summary.end_time, args.hours);
+
+    std::fs::create_dir_all(&args.out).expect("Failed to create output directory");
+
+    let log_type = match args.format {
+        SplitFormat::Blg => PDH_LOG_TYPE_BINARY,
+        SplitFormat::Csv => PDH_LOG_TYPE_CSV,
+        SplitFormat::Tsv => PDH_LOG_TYPE_TSV,
+    };
+
+    for (chunk_start, chunk_end) in chunks {
+        write_chunk(
+            hdatasource,
+            &counters_to_write,
+            args,
+            log_type,
+            chunk_start,
+            chunk_end,
+        );
+    }
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+}
+
+fn write_chunk(
+    hdatasource: isize,
+    counters: &Vec<&String>,
+    args: &SplitArgs,
+    log_type: PDH_LOG_TYPE,
+    chunk_start: OffsetDateTime,
+    chunk_end: OffsetDateTime,
+) {
+    let file_time = chunk_start
+        .to_offset(display_offset())
+        .format(format_description!("[year][month][day]-[hour][minute]"))
+        .unwrap();
+    let file_name = format!("{}_{}.{}", args.prefix, file_time, args.format.extension());
+    let output_file = Path::new(&args.out).join(file_name).display().to_string();
+
+    let samples = write_log_range(
+        hdatasource,
+        counters,
+        &output_file,
+        log_type,
+        chunk_start,
+        chunk_end,
+    );
+
+    if samples == 0 {
+        // PdhOpenLogW creates the file up front, so remove empty chunks.
+        let _ = std::fs::remove_file(&output_file);
+        println!(
+            "  {} - {}: no samples",
+            format_time(chunk_start),
+            format_time(chunk_end)
+        );
+        return;
+    }
+
+    println!("  {}: {} samples", output_file, samples);
+}
+
+// Chunks start on an hour (or midnight for daily chunks) in the --timezone
+// offset so the file names line up between runs over the same data.
+fn get_chunks(
+    start_time: OffsetDateTime,
+    end_time: OffsetDateTime,
+    hours: Option<u32>,
+) -> Vec<(OffsetDateTime, OffsetDateTime)> {
+    let start_time = start_time.to_offset(display_offset());
+    let (mut chunk_start, length) = match hours {
+        Some(hours) => (
+            start_time.replace_time(Time::from_hms(start_time.hour(), 0, 0).unwrap()),
+            Duration::hours(hours as i64),
+        ),
+        None => (start_time.replace_time(Time::MIDNIGHT), Duration::days(1)),
+    };
+
+    let mut chunks = Vec::new();
+    while chunk_start <= end_time {
+        let chunk_end = chunk_start + length;
+        chunks.push((chunk_start, chunk_end));
+        chunk_start = chunk_end;
+    }
+
+    chunks
+}
@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+
+use crate::{
+    cli::StatsArgs,
+    counter_path::CounterPath,
+    reader::{read_counters, stream_counters},
+    report::CounterStats,
+    selection::CounterSelection,
+    units::Unit,
+};
+
+pub fn stats(args: &StatsArgs) {
+    let selection = match args.counters.selection() {
+        Some(selection) => selection,
+        None => return,
+    };
+
+    let stats = if args.exact {
+        exact_stats(args, &selection)
+    } else {
+        streamed_stats(args, &selection)
+    };
+
+    let stats = match stats {
+        Some(stats) => stats,
+        None => return,
+    };
+
+    if stats.is_empty() {
+        eprintln!("No samples matched.");
+        return;
+    }
+
+    match args.group_by {
+        Some(GroupBy::Instance) => print_by_instance(stats, args.sort_by.unwrap_or(StatsSort::Avg)),
+        None => {
+            let mut stats = stats;
+            if let Some(sort_by) = args.sort_by {
+                sort_stats(&mut stats, sort_by);
+            }
+            print_header("Counter");
+            for s in &stats {
+                print_row(s, &s.counter);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum GroupBy {
+    /// One table per counter, with a row per instance
+    Instance,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum StatsSort {
+    Samples,
+    Min,
+    Avg,
+    Stddev,
+    P95,
+    Max,
+}
+
+impl StatsSort {
+    fn value(&self, stats: &CounterStats) -> f64 {
+        match self {
+            StatsSort::Samples => stats.samples as f64,
+            StatsSort::Min => stats.min,
+            StatsSort::Avg => stats.avg,
+            StatsSort::Stddev => stats.stddev,
+            StatsSort::P95 => stats.p95,
+            StatsSort::Max => stats.max,
+        }
+    }
+}
+
+// Highest first.
+fn sort_stats(stats: &mut [CounterStats], sort_by: StatsSort) {
+    stats.sort_by(|a, b| sort_by.value(b).total_cmp(&sort_by.value(a)));
+}
+
+// Groups the instances of each counter, like every disk of
+// \LogicalDisk(*)\Avg. Disk sec/Read, under the counter's wildcard path.
+fn print_by_instance(stats: Vec<CounterStats>, sort_by: StatsSort) {
+    let mut groups = Vec::<(String, Vec<(String, CounterStats)>)>::new();
+    for s in stats {
+        let (group, instance) = match CounterPath::parse(&s.counter) {
+            Some(mut path) => match path.instance.replace("*".to_string()) {
+                Some(instance) => (path.to_string(), instance),
+                None => (s.counter.clone(), "-".to_string()),
+            },
+            None => (s.counter.clone(), "-".to_string()),
+        };
+
+        match groups.iter_mut().find(|(g, _)| *g == group) {
+            Some((_, rows)) => rows.push((instance, s)),
+            None => groups.push((group, vec![(instance, s)])),
+        }
+    }
+
+    for (i, (group, mut rows)) in groups.into_iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", group);
+        rows.sort_by(|a, b| sort_by.value(&b.1).total_cmp(&sort_by.value(&a.1)));
+        print_header("Instance");
+        for (instance, s) in &rows {
+            print_row(s, instance);
+        }
+    }
+}
+
+pub fn print_header(label: &str) {
+    println!(
+        "{:>8}  {:>12}  {:>12}  {:>12}  {:>12}  {:>12}  {}",
+        "Samples", "Min", "Avg", "StdDev", "P95", "Max", label
+    );
+}
+
+pub fn print_row(s: &CounterStats, label: &str) {
+    println!(
+        "{:>8}  {:>12}  {:>12}  {:>12}  {:>12}  {:>12}  {}",
+        s.samples,
+        s.unit.format(s.min),
+        s.unit.format(s.avg),
+        s.unit.format(s.stddev),
+        s.unit.format(s.p95),
+        s.unit.format(s.max),
+        label
+    );
+}
+
+fn unit(args: &StatsArgs, unit: Unit) -> Unit {
+    if args.raw {
+        Unit::Count
+    } else {
+        unit
+    }
+}
+
+// Sorts every value of a counter, so it needs them all in memory.
+fn exact_stats(args: &StatsArgs, selection: &CounterSelection) -> Option<Vec<CounterStats>> {
+    let counter_data = read_counters(&args.source, selection)?;
+
+    let time_filter = args.time_filter.time_filter();
+
+    let stats = counter_data
+        .counters
+        .iter()
+        .filter_map(|counter| {
+            let mut values = counter_data.samples[counter]
+                .iter()
+                .filter(|s| time_filter.matches(s.time()))
+                .map(|s| s.value())
+                .collect::<Vec<f64>>();
+
+            if values.is_empty() {
+                return None;
+            }
+
+            sort_values(&mut values);
+            Some(CounterStats::new(
+                counter,
+                &values,
+                unit(args, counter_data.unit(counter)),
+            ))
+        })
+        .collect();
+
+    Some(stats)
+}
+
+// Updates each counter's statistics as its samples are read, so memory
+// depends on the number of counters rather than the number of samples.
+fn streamed_stats(args: &StatsArgs, selection: &CounterSelection) -> Option<Vec<CounterStats>> {
+    let time_filter = args.time_filter.time_filter();
+
+    let mut streams = HashMap::<String, StreamingStats>::new();
+    let (counters, info) = stream_counters(&args.source, selection, |counter, sample| {
+        if time_filter.matches(sample.time()) {
+            streams
+                .entry(counter.to_string())
+                .or_default()
+                .add(sample.value());
+        }
+    })?;
+
+    let stats = counters
+        .iter()
+        .filter_map(|counter| {
+            streams
+                .get(counter)?
+                .finish(counter, unit(args, Unit::lookup(&info, counter)))
+        })
+        .collect();
+
+    Some(stats)
+}
+
+// Linear interpolation between closest ranks, matching Excel's PERCENTILE.INC.
+// The values must already be sorted.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+
+    let rank = (p / 100.0).clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+pub fn sort_values(values: &mut [f64]) {
+    values.sort_by(|a, b| a.total_cmp(b));
+}
+
+// Mean and variance in one pass with Welford's algorithm, which doesn't lose
+// precision the way subtracting a running sum of squares does when the
+// values are large and close together. Values can be removed again for a
+// rolling window.
+#[derive(Clone, Copy, Default)]
+pub struct Welford {
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl Welford {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn remove(&mut self, value: f64) {
+        if self.count <= 1 {
+            *self = Welford::default();
+            return;
+        }
+
+        self.count -= 1;
+        let delta = value - self.mean;
+        self.mean -= delta / self.count as f64;
+        self.m2 = (self.m2 - delta * (value - self.mean)).max(0.0);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    // Population standard deviation, since the samples are all there is.
+    pub fn stddev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        (self.m2 / self.count as f64).sqrt()
+    }
+}
+
+// Min, average, standard deviation, and max are exact. The 95th percentile is
+// a P² estimate, so memory stays the same however many samples go through.
+pub struct StreamingStats {
+    moments: Welford,
+    min: f64,
+    max: f64,
+    p95: P2Quantile,
+}
+
+impl StreamingStats {
+    pub fn new() -> StreamingStats {
+        StreamingStats {
+            moments: Welford::default(),
+            min: f64::MAX,
+            max: f64::MIN,
+            p95: P2Quantile::new(95.0),
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.moments.add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.p95.add(value);
+    }
+
+    pub fn finish(&self, counter: &str, unit: Unit) -> Option<CounterStats> {
+        if self.moments.count() == 0 {
+            return None;
+        }
+
+        Some(CounterStats {
+            counter: counter.to_string(),
+            samples: self.moments.count(),
+            min: self.min,
+            avg: self.moments.mean(),
+            stddev: self.moments.stddev(),
+            p95: self.p95.estimate(),
+            max: self.max,
+            unit,
+        })
+    }
+}
+
+impl Default for StreamingStats {
+    fn default() -> Self {
+        StreamingStats::new()
+    }
+}
+
+// The P² algorithm (Jain and Chlamtac, 1985) tracks five markers whose heights
+// approximate the minimum, the maximum, the percentile, and the points halfway
+// to it on either side. Each new value moves the markers' positions, and a
+// marker that drifts from its desired position has its height adjusted with a
+// parabolic fit through its neighbors.
+pub struct P2Quantile {
+    p: f64,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+    count: usize,
+}
+
+impl P2Quantile {
+    pub fn new(percentile: f64) -> P2Quantile {
+        let p = (percentile / 100.0).clamp(0.0, 1.0);
+        P2Quantile {
+            p,
+            heights: [0.0; 5],
+            positions: [0.0, 1.0, 2.0, 3.0, 4.0],
+            desired: [0.0, 2.0 * p, 4.0 * p, 2.0 + 2.0 * p, 4.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+            count: 0,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = value;
+            self.count += 1;
+            if self.count == 5 {
+                sort_values(&mut self.heights);
+            }
+            return;
+        }
+        self.count += 1;
+
+        let q = &mut self.heights;
+        let k = if value < q[0] {
+            q[0] = value;
+            0
+        } else if value >= q[4] {
+            q[4] = value;
+            3
+        } else {
+            (0..4).find(|&i| value < q[i + 1]).unwrap()
+        };
+
+        for i in k + 1..5 {
+            self.positions[i] += 1.0;
+        }
+        for i in 0..5 {
+            self.desired[i] += self.increments[i];
+        }
+
+        for i in 1..4 {
+            let n = &mut self.positions;
+            let d = self.desired[i] - n[i];
+            if (d >= 1.0 && n[i + 1] - n[i] > 1.0) || (d <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let d = d.signum();
+                let parabolic = q[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]));
+
+                q[i] = if q[i - 1] < parabolic && parabolic < q[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+                };
+                n[i] += d;
+            }
+        }
+    }
+
+    // Exact until there are enough values to place the markers.
+    pub fn estimate(&self) -> f64 {
+        if self.count < 5 {
+            let mut values = self.heights[..self.count].to_vec();
+            sort_values(&mut values);
+            percentile(&values, self.p * 100.0)
+        } else {
+            self.heights[2]
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex, OnceLock,
+};
+
+static QUIET: OnceLock<bool> = OnceLock::new();
+static OUTCOME: Mutex<Outcome> = Mutex::new(Outcome::Success);
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+// Set once at startup from --quiet.
+pub fn set_quiet(enabled: bool) {
+    let _ = QUIET.set(enabled);
+}
+
+// Progress and informational messages are left out with --quiet. Errors and
+// warnings are still written.
+pub fn quiet() -> bool {
+    *QUIET.get().unwrap_or(&false)
+}
+
+// What went wrong during the run, from least to most serious. The most
+// serious one seen decides the exit code, so scheduled tasks and health
+// checks can tell what happened without reading the output.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Success,
+    // analyze or triage found a threshold crossed.
+    Warning,
+    Critical,
+    // Some files were skipped, or a log stopped reading partway through.
+    PartialRead,
+    NoFiles,
+    BindFailed,
+}
+
+impl Outcome {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Success => 0,
+            Outcome::NoFiles => 3,
+            Outcome::BindFailed => 4,
+            Outcome::PartialRead => 5,
+            Outcome::Warning => 6,
+            Outcome::Critical => 7,
+        }
+    }
+}
+
+pub fn record(outcome: Outcome) {
+    let mut current = OUTCOME.lock().unwrap_or_else(|e| e.into_inner());
+    *current = (*current).max(outcome);
+}
+
+pub fn record_panic() {
+    PANICKED.store(true, Ordering::Relaxed);
+}
+
+// The code to exit with. Failures without a code of their own, which panic,
+// exit with 1, and clap exits with 2 for bad arguments before anything runs.
+pub fn exit_code() -> i32 {
+    let outcome = *OUTCOME.lock().unwrap_or_else(|e| e.into_inner());
+    if PANICKED.load(Ordering::Relaxed) && outcome != Outcome::BindFailed {
+        return 1;
+    }
+    outcome.exit_code()
+}
+
+// eprintln for progress and informational messages, which --quiet leaves
+// out.
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if !$crate::status::quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use progress;
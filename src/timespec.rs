@@ -0,0 +1,337 @@
+use std::{
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+use time::{
+    format_description::BorrowedFormatItem, macros::format_description, Date, Duration,
+    OffsetDateTime, PrimitiveDateTime, Time, UtcOffset, Weekday,
+};
+
+// Parses durations like "90s", "5m", "2h", or "1d". A bare number is seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => text.split_at(index),
+        None => (text, "s"),
+    };
+
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", text))?;
+
+    let seconds = match unit.trim().to_lowercase().as_str() {
+        "s" | "sec" | "secs" => number,
+        "m" | "min" | "mins" => number * 60.0,
+        "h" | "hr" | "hrs" => number * 3600.0,
+        "d" | "day" | "days" => number * 86400.0,
+        _ => return Err(format!("Invalid duration unit: {}", text)),
+    };
+
+    if seconds < 0.0 {
+        return Err(format!("Duration must not be negative: {}", text));
+    }
+
+    Duration::checked_seconds_f64(seconds).ok_or_else(|| format!("Duration is too long: {}", text))
+}
+
+// A duration things are done every so often, or over, like --interval or
+// --resample, where zero would collect or re-read as fast as possible.
+pub fn parse_interval(text: &str) -> Result<Duration, String> {
+    let duration = parse_duration(text)?;
+    if duration.is_zero() {
+        return Err(format!("Duration must be more than zero: {}", text));
+    }
+    Ok(duration)
+}
+
+// A time-of-day window like "08:00-18:00". The end is exclusive, and a window
+// that ends before it starts wraps past midnight.
+#[derive(Clone, Copy, Debug)]
+pub struct HoursRange {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl HoursRange {
+    pub fn contains(&self, time: Time) -> bool {
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for HoursRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Expected hours like 08:00-18:00: {}", s))?;
+
+        Ok(HoursRange {
+            start: parse_time_of_day(start)?,
+            end: parse_time_of_day(end)?,
+        })
+    }
+}
+
+fn parse_time_of_day(text: &str) -> Result<Time, String> {
+    let text = text.trim();
+    let (hour, minute) = text.split_once(':').unwrap_or((text, "0"));
+
+    let hour: u8 = hour
+        .parse()
+        .map_err(|_| format!("Invalid time of day: {}", text))?;
+    let minute: u8 = minute
+        .parse()
+        .map_err(|_| format!("Invalid time of day: {}", text))?;
+
+    // Allow "24:00" as the end of the day.
+    if hour == 24 && minute == 0 {
+        return Ok(Time::MIDNIGHT);
+    }
+
+    Time::from_hms(hour, minute, 0).map_err(|_| format!("Invalid time of day: {}", text))
+}
+
+// A set of weekdays like "Mon-Fri", "Sat,Sun", or "Mon-Wed,Fri".
+#[derive(Clone, Debug)]
+pub struct DaySet {
+    pub days: Vec<Weekday>,
+}
+
+impl DaySet {
+    pub fn contains(&self, day: Weekday) -> bool {
+        self.days.contains(&day)
+    }
+}
+
+impl FromStr for DaySet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut days = Vec::new();
+
+        for part in s.split(',') {
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let mut day = parse_weekday(first)?;
+                    let last = parse_weekday(last)?;
+                    days.push(day);
+                    while day != last {
+                        day = day.next();
+                        days.push(day);
+                    }
+                }
+                None => days.push(parse_weekday(part)?),
+            }
+        }
+
+        Ok(DaySet { days })
+    }
+}
+
+fn parse_weekday(text: &str) -> Result<Weekday, String> {
+    let text = text.trim().to_lowercase();
+    let day = match text.get(..3).unwrap_or_default() {
+        "mon" => Weekday::Monday,
+        "tue" => Weekday::Tuesday,
+        "wed" => Weekday::Wednesday,
+        "thu" => Weekday::Thursday,
+        "fri" => Weekday::Friday,
+        "sat" => Weekday::Saturday,
+        "sun" => Weekday::Sunday,
+        _ => return Err(format!("Invalid day: {}", text)),
+    };
+
+    Ok(day)
+}
+
+static DISPLAY_OFFSET: OnceLock<UtcOffset> = OnceLock::new();
+
+// Sample times are kept in UTC and only converted to the --timezone offset
+// when they're printed or exported. Set once at startup.
+pub fn set_display_offset(offset: UtcOffset) {
+    let _ = DISPLAY_OFFSET.set(offset);
+}
+
+pub fn display_offset() -> UtcOffset {
+    DISPLAY_OFFSET.get().copied().unwrap_or(UtcOffset::UTC)
+}
+
+// "UTC" or "UTC+02:00", for labeling columns and axes.
+pub fn zone_label(offset: UtcOffset) -> String {
+    if offset == UtcOffset::UTC {
+        return "UTC".to_string();
+    }
+
+    let offset = offset
+        .format(format_description!(
+            "[offset_hour sign:mandatory]:[offset_minute]"
+        ))
+        .unwrap();
+    format!("UTC{}", offset)
+}
+
+// Accepts "UTC", "local", or an offset like "+02:00" or "-0500".
+pub fn parse_utc_offset(text: &str) -> Result<UtcOffset, String> {
+    let text = text.trim();
+
+    if text.eq_ignore_ascii_case("utc") || text.eq_ignore_ascii_case("z") {
+        return Ok(UtcOffset::UTC);
+    }
+
+    if text.eq_ignore_ascii_case("local") {
+        return UtcOffset::current_local_offset()
+            .map_err(|_| "Unable to determine the local UTC offset".to_string());
+    }
+
+    let (sign, rest) = match text.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() > 2 => rest.split_at(rest.len() - 2),
+        None => (rest, "0"),
+    };
+
+    let hours: i8 = hours
+        .parse()
+        .map_err(|_| format!("Invalid UTC offset: {}", text))?;
+    let minutes: i8 = minutes
+        .parse()
+        .map_err(|_| format!("Invalid UTC offset: {}", text))?;
+
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0)
+        .map_err(|_| format!("Invalid UTC offset: {}", text))
+}
+
+// Formats a duration like "1h05m" or "45s" for reports.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.whole_seconds().max(0);
+    let (days, hours, minutes, seconds) = (
+        seconds / 86400,
+        seconds % 86400 / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+    );
+
+    if days > 0 {
+        format!("{}d{:02}h{:02}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h{:02}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+// The first and last sample of every log read so far, for times relative to
+// them.
+static LOG_RANGE: Mutex<Option<(OffsetDateTime, OffsetDateTime)>> = Mutex::new(None);
+
+// Widens the range to cover a set of logs as their summary is read.
+pub fn extend_log_range(start: OffsetDateTime, end: OffsetDateTime) {
+    let mut range = LOG_RANGE.lock().unwrap_or_else(|e| e.into_inner());
+    *range = Some(match *range {
+        Some((first, last)) => (first.min(start), last.max(end)),
+        None => (start, end),
+    });
+}
+
+pub fn log_range() -> Option<(OffsetDateTime, OffsetDateTime)> {
+    *LOG_RANGE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// A time given on the command line: a date and time, or a time relative to
+// the start or end of the logs, since those are rarely known up front.
+#[derive(Clone, Copy, Debug)]
+pub enum TimeSpec {
+    At(PrimitiveDateTime),
+    FromStart(Duration),
+    FromEnd(Duration),
+}
+
+impl TimeSpec {
+    // Times relative to the logs are None until a log has been read.
+    pub fn resolve(&self, offset: UtcOffset) -> Option<OffsetDateTime> {
+        match self {
+            TimeSpec::At(time) => Some(time.assume_offset(offset)),
+            TimeSpec::FromStart(duration) => log_range().map(|(start, _)| start + *duration),
+            TimeSpec::FromEnd(duration) => log_range().map(|(_, end)| end + *duration),
+        }
+    }
+}
+
+// Accepts what parse_datetime does, or start or end of the logs with an
+// optional duration added or taken away, like "end-30m" or "start+1h".
+pub fn parse_time_spec(text: &str) -> Result<TimeSpec, String> {
+    let trimmed = text.trim().to_lowercase();
+    let (anchor, rest): (fn(Duration) -> TimeSpec, &str) =
+        if let Some(rest) = trimmed.strip_prefix("start") {
+            (TimeSpec::FromStart, rest)
+        } else if let Some(rest) = trimmed.strip_prefix("end") {
+            (TimeSpec::FromEnd, rest)
+        } else {
+            return parse_datetime(text).map(TimeSpec::At);
+        };
+
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return Ok(anchor(Duration::ZERO));
+    }
+
+    let duration = match rest.split_at(1) {
+        ("+", duration) => parse_duration(duration)?,
+        ("-", duration) => -parse_duration(duration)?,
+        _ => return Err(format!("Invalid time: {}", text)),
+    };
+    Ok(anchor(duration))
+}
+
+// Accepts "2023-06-12 08:00", "2023-06-12 08:00:00", or the same with a T
+// between the date and time. A bare date means midnight.
+pub fn parse_datetime(text: &str) -> Result<PrimitiveDateTime, String> {
+    const DATE: &[BorrowedFormatItem] = format_description!("[year]-[month]-[day]");
+
+    let text = text.trim();
+    let (date, time) = match text.split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+
+    let date = Date::parse(date, DATE).map_err(|_| format!("Invalid date: {}", text))?;
+
+    let time = match time {
+        Some(time) => parse_clock_time(time.trim()).ok_or(format!("Invalid time: {}", text))?,
+        None => Time::MIDNIGHT,
+    };
+
+    Ok(PrimitiveDateTime::new(date, time))
+}
+
+fn parse_clock_time(text: &str) -> Option<Time> {
+    let mut parts = text.split(':');
+    let hour = parts.next()?.parse().ok()?;
+    let minute = parts.next().unwrap_or("0").parse().ok()?;
+    let second: f64 = parts.next().unwrap_or("0").parse().ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Time::from_hms_milli(
+        hour,
+        minute,
+        second.trunc() as u8,
+        (second.fract() * 1000.0).round() as u16,
+    )
+    .ok()
+}
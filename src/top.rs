@@ -0,0 +1,75 @@
+use crate::{
+    cli::TopArgs, reader::read_counters, selection::CounterSelection, series_set::SeriesSet,
+    stats::sort_values,
+};
+
+pub fn top(args: &TopArgs) {
+    let counter_data = match read_counters(
+        &args.source,
+        &CounterSelection::new(std::slice::from_ref(&args.counter)),
+    ) {
+        Some(counter_data) => counter_data,
+        None => return,
+    };
+
+    let time_filter = args.time_filter.time_filter();
+
+    let mut ranked = Vec::new();
+    for series in SeriesSet::from(counter_data) {
+        let instance = series.instance().unwrap_or_default();
+        if !args.include_total && (instance == "_Total" || instance == "Idle") {
+            continue;
+        }
+
+        let mut values = series
+            .times()
+            .zip(series.values())
+            .filter(|(time, _)| time_filter.matches(*time))
+            .map(|(_, value)| value)
+            .collect::<Vec<f64>>();
+
+        if values.is_empty() {
+            continue;
+        }
+
+        let path = match series.path {
+            Some(path) => path,
+            None => continue,
+        };
+
+        sort_values(&mut values);
+        ranked.push((args.by.compute(&values), path));
+    }
+
+    if ranked.is_empty() {
+        eprintln!("No samples matched.");
+        return;
+    }
+
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let multiple_machines = ranked.iter().any(|(_, p)| p.machine != ranked[0].1.machine);
+
+    println!(
+        "Top {} of {} instances by {} of {}",
+        args.count.min(ranked.len()),
+        ranked.len(),
+        args.by.name(),
+        args.counter
+    );
+
+    for (rank, (value, path)) in ranked.iter().take(args.count).enumerate() {
+        let instance = path.instance.as_deref().unwrap_or_default();
+        if multiple_machines {
+            println!(
+                "{:>4}  {:>16.2}  \\\\{}  {}",
+                rank + 1,
+                value,
+                path.machine,
+                instance
+            );
+        } else {
+            println!("{:>4}  {:>16.2}  {}", rank + 1, value, instance);
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use time::UtcOffset;
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    analyze::{all_analyzers, charts::write_finding_charts, record_outcome, Finding},
+    cache::{cached_summary, fingerprint, read_cache, write_cache, CachedRead},
+    chart::write_chart,
+    cli::TriageArgs,
+    counter_path::map_machine_name,
+    counter_path::CounterPath,
+    export::{format_time, write_csv},
+    log_files::find_log_files,
+    pdh_helper::bind_input_logfiles,
+    plot::Series,
+    reader::{
+        map_machines, read_files_separately, read_selected_counters, report_errors, CounterData,
+    },
+    report::{write_findings_json, write_report, CounterStats, KeyChart, Report},
+    selection::{counter_matches, CounterSelection},
+    stats::sort_values,
+    status::progress,
+    timespec::{display_offset, zone_label},
+};
+
+// The counters everyone looks at first, charted and summarized at the top of
+// the report.
+const KEY_COUNTERS: [(&str, &str); 6] = [
+    ("Processor time", "\\Processor(_Total)\\% Processor Time"),
+    ("Available memory", "\\Memory\\Available MBytes"),
+    ("Disk latency", "\\PhysicalDisk(*)\\Avg. Disk sec/Transfer"),
+    ("Disk queue", "\\PhysicalDisk(*)\\Avg. Disk Queue Length"),
+    (
+        "Network throughput",
+        "\\Network Interface(*)\\Bytes Total/sec",
+    ),
+    ("Context switches", "\\System\\Context Switches/sec"),
+];
+
+const CHART_SIZE: (u32, u32) = (1280, 480);
+
+const KEY_CHART_PREFIX: &str = "key-";
+
+const CACHE_FILE: &str = "samples.cache";
+
+const STAMP_FILE: &str = "derived.stamp";
+
+// Runs everything against the logs and writes it all under one directory:
+//
+//   report.html    findings, key counters, and counter statistics
+//   findings.json  the findings for other tools
+//   data/          summary.txt, stats.csv, key-counters.csv, and the cache
+//   charts/        key counter charts and one chart per finding
+//
+// Reading the logs is by far the slowest phase, so its result is cached and
+// reused by later runs over the same logs. The data files and key charts are
+// only redone when the logs or the time filter change. Analysis and the
+// report always run.
+pub fn triage(args: &TriageArgs) {
+    let out = Path::new(&args.out);
+    let (data_dir, charts_dir) = (out.join("data"), out.join("charts"));
+    for dir in [&data_dir, &charts_dir] {
+        std::fs::create_dir_all(dir).expect("Failed to create output directory");
+    }
+
+    let files = find_log_files(&args.source.glob_pattern);
+    if files.is_empty() {
+        return;
+    }
+
+    let fingerprint = fingerprint(&files, args.source.separate);
+    let cache_path = data_dir.join(CACHE_FILE);
+
+    let cached = if args.fresh {
+        None
+    } else {
+        read_cache(&cache_path, &fingerprint)
+    };
+
+    let reused = cached.is_some();
+    let read = match cached {
+        Some(read) => {
+            progress!(
+                "Reusing the counters read by an earlier run. Use --fresh to read them again."
+            );
+            read
+        }
+        None => {
+            let read = read_phase(
+                &files,
+                args.source.parallel,
+                args.source.separate,
+                &data_dir,
+            );
+            if let Err(e) = write_cache(&cache_path, &fingerprint, &read) {
+                eprintln!("Failed to write {}: {}", cache_path.display(), e);
+            }
+            read
+        }
+    };
+
+    let CachedRead {
+        start,
+        end,
+        machines,
+        data: counter_data,
+    } = read;
+
+    // The cache holds the paths as logged, so the same cache serves any
+    // --strip-machine or --map-machine.
+    let mut counter_data = map_machines(counter_data, &args.source);
+    let mut machines = machines
+        .iter()
+        .map(|m| map_machine_name(m, &args.source.map_machine))
+        .collect::<Vec<String>>();
+    machines.sort();
+    machines.dedup();
+
+    let time_filter = args.time_filter.time_filter();
+    for samples in counter_data.samples.values_mut() {
+        *samples = time_filter.apply(std::mem::take(samples));
+    }
+
+    progress!("Calculating statistics...");
+    let stats = counter_stats(&counter_data);
+    let key_counters = key_counters(&counter_data);
+
+    // The data files and key charts depend only on the logs and the time
+    // filter, so a stamp of both says whether the last run's are still good.
+    let stamp = format!(
+        "{}{:?}{} {:?}\n",
+        fingerprint, time_filter, args.source.strip_machine, args.source.map_machine
+    );
+    let stamp_path = data_dir.join(STAMP_FILE);
+    let current = reused && std::fs::read_to_string(&stamp_path).is_ok_and(|s| s == stamp);
+
+    if !current {
+        data_phase(&data_dir, &counter_data, &stats, &key_counters);
+    }
+
+    let key_charts = key_chart_phase(
+        out,
+        &charts_dir,
+        &counter_data,
+        &stats,
+        &key_counters,
+        time_filter.offset,
+        !current,
+    );
+
+    if !current {
+        std::fs::write(&stamp_path, stamp).expect("Failed to write stamp");
+    }
+
+    progress!("Running analyzers...");
+    let analyzers = all_analyzers();
+    let mut findings = analyzers
+        .iter()
+        .flat_map(|a| a.analyze(&counter_data))
+        .collect::<Vec<Finding>>();
+    findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.start.cmp(&b.start)));
+
+    remove_finding_charts(&charts_dir);
+    let finding_charts = write_finding_charts(
+        &charts_dir.display().to_string(),
+        &findings,
+        &analyzers,
+        &counter_data,
+        time_filter.offset,
+    )
+    .into_iter()
+    .map(|c| c.map(|c| relative(out, Path::new(&c))))
+    .collect::<Vec<Option<String>>>();
+
+    write_findings_json(
+        &mut create(&out.join("findings.json")),
+        &findings,
+        &finding_charts,
+    )
+    .expect("Failed to write findings");
+
+    let report = Report {
+        title: format!("Triage of {}", args.source.glob_pattern),
+        start,
+        end,
+        machines,
+        counter_count: counter_data.counters.len(),
+        key_charts,
+        findings: &findings,
+        finding_charts: &finding_charts,
+        stats: &stats,
+    };
+
+    let report_path = out.join("report.html");
+    write_report(&mut create(&report_path), &report).expect("Failed to write report");
+
+    progress!("{} findings.", findings.len());
+    record_outcome(&findings);
+    println!("{}", report_path.display());
+}
+
+// Binds the logs for the summary, then reads every counter.
+fn read_phase(files: &[String], parallel: bool, separate: bool, data_dir: &Path) -> CachedRead {
+    let hdatasource = bind_input_logfiles(files.to_vec());
+
+    let summary = cached_summary(files, hdatasource);
+
+    let mut summary_file = create(&data_dir.join("summary.txt"));
+    writeln!(
+        summary_file,
+        "Time range: {} - {} ({})",
+        format_time(summary.start_time),
+        format_time(summary.end_time),
+        zone_label(display_offset())
+    )
+    .and_then(|_| summary.write_samples(&mut summary_file))
+    .and_then(|_| summary.write_hierarchy(&mut summary_file))
+    .and_then(|_| summary_file.flush())
+    .expect("Failed to write summary");
+
+    progress!("Reading every counter...");
+    let data = if separate {
+        unsafe { PdhCloseLog(hdatasource, 0) };
+        read_files_separately(files, &CounterSelection::default(), parallel)
+    } else {
+        let data =
+            read_selected_counters(hdatasource, &summary, &CounterSelection::default(), None);
+        unsafe { PdhCloseLog(hdatasource, 0) };
+        data
+    };
+    report_errors(&data.errors);
+
+    CachedRead {
+        start: summary.start_time,
+        end: summary.end_time,
+        machines: summary.machines.iter().map(|m| m.name.clone()).collect(),
+        data,
+    }
+}
+
+fn data_phase(
+    data_dir: &Path,
+    counter_data: &CounterData,
+    stats: &[CounterStats],
+    key_counters: &[(&'static str, Vec<&String>)],
+) {
+    write_stats_csv(&data_dir.join("stats.csv"), stats);
+
+    let key_names = key_counters
+        .iter()
+        .flat_map(|(_, counters)| counters)
+        .map(|c| c.to_string())
+        .collect::<Vec<String>>();
+    let key_series = key_names
+        .iter()
+        .map(|c| counter_data.samples[c].clone())
+        .collect::<Vec<_>>();
+
+    write_csv(
+        &mut create(&data_dir.join("key-counters.csv")),
+        &key_names,
+        &key_series,
+        ',',
+    )
+    .expect("Failed to write CSV");
+}
+
+// Draws the key counter charts, or with draw false, picks up the ones an
+// earlier run drew.
+fn key_chart_phase(
+    out: &Path,
+    charts_dir: &Path,
+    counter_data: &CounterData,
+    stats: &[CounterStats],
+    key_counters: &[(&'static str, Vec<&String>)],
+    offset: UtcOffset,
+    draw: bool,
+) -> Vec<KeyChart> {
+    if draw {
+        progress!("Drawing key counter charts...");
+    }
+
+    key_counters
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (title, counters))| {
+            let path = charts_dir.join(format!("{}{}.png", KEY_CHART_PREFIX, index + 1));
+
+            if draw || !path.exists() {
+                let series = counters
+                    .iter()
+                    .map(|c| Series {
+                        name: c.to_string(),
+                        points: counter_data.samples[*c]
+                            .iter()
+                            .map(|s| (s.time(), s.value()))
+                            .collect(),
+                    })
+                    .filter(|s| !s.points.is_empty())
+                    .collect::<Vec<Series>>();
+
+                if series.is_empty() {
+                    return None;
+                }
+
+                let result = write_chart(
+                    &path.display().to_string(),
+                    &series,
+                    &[],
+                    CHART_SIZE,
+                    offset,
+                );
+                if let Err(e) = result {
+                    eprintln!("Failed to write {}: {}", path.display(), e);
+                    return None;
+                }
+            }
+
+            Some(KeyChart {
+                title: title.to_string(),
+                path: relative(out, &path),
+                stats: stats
+                    .iter()
+                    .filter(|s| counters.contains(&&s.counter))
+                    .cloned()
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+// Finding charts are numbered by their order in the report, so old ones
+// would be mislabeled after the findings change.
+fn remove_finding_charts(charts_dir: &Path) {
+    let entries = match std::fs::read_dir(charts_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.ends_with(".png") && !name.starts_with(KEY_CHART_PREFIX) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn create(path: &Path) -> BufWriter<File> {
+    BufWriter::new(File::create(path).expect("Failed to create output file"))
+}
+
+// Paths in the report are relative so the directory can be zipped and sent.
+fn relative(base: &Path, path: &Path) -> String {
+    path.strip_prefix(base)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+        .replace('\\', "/")
+}
+
+fn key_counters(data: &CounterData) -> Vec<(&'static str, Vec<&String>)> {
+    KEY_COUNTERS
+        .iter()
+        .map(|(title, pattern)| {
+            let counters = data
+                .counters
+                .iter()
+                .filter(|c| counter_matches(pattern, c))
+                .filter(|c| {
+                    // _Total just repeats the instances on a per-instance chart.
+                    pattern.contains("_Total")
+                        || CounterPath::parse(c).and_then(|p| p.instance).as_deref()
+                            != Some("_Total")
+                })
+                .collect::<Vec<&String>>();
+            (*title, counters)
+        })
+        .filter(|(_, counters)| !counters.is_empty())
+        .collect()
+}
+
+fn counter_stats(data: &CounterData) -> Vec<CounterStats> {
+    data.counters
+        .iter()
+        .filter_map(|counter| {
+            let mut values = data.samples[counter]
+                .iter()
+                .map(|s| s.value())
+                .collect::<Vec<f64>>();
+
+            if values.is_empty() {
+                return None;
+            }
+
+            sort_values(&mut values);
+            Some(CounterStats::new(counter, &values, data.unit(counter)))
+        })
+        .collect()
+}
+
+fn write_stats_csv(path: &Path, stats: &[CounterStats]) {
+    let mut writer = create(path);
+
+    let result = (|| {
+        writeln!(
+            writer,
+            "\"Counter\",\"Samples\",\"Min\",\"Avg\",\"P95\",\"Max\""
+        )?;
+        for s in stats {
+            writeln!(
+                writer,
+                "\"{}\",{},{},{},{},{}",
+                s.counter.replace('"', "\"\""),
+                s.samples,
+                s.min,
+                s.avg,
+                s.p95,
+                s.max
+            )?;
+        }
+        writer.flush()
+    })();
+
+    result.expect("Failed to write statistics");
+}
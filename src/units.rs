@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use crate::pdh_helper::CounterInfo;
+
+// The display suffix bits of a counter type, from winperf.h.
+const PERF_DISPLAY_MASK: u32 = 0xF000_0000;
+const PERF_DISPLAY_PER_SEC: u32 = 0x1000_0000;
+const PERF_DISPLAY_PERCENT: u32 = 0x2000_0000;
+const PERF_DISPLAY_SECONDS: u32 = 0x3000_0000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Unit {
+    // Bytes times the multiplier, which is 1024 * 1024 for counters like
+    // Available MBytes.
+    Bytes(f64),
+    BytesPerSec,
+    Percent,
+    PerSec,
+    Seconds,
+    Count,
+}
+
+impl Unit {
+    // The counter type says whether a value is a rate, a percentage, or a
+    // time. Whether it counts bytes is only in the counter name.
+    pub fn from_info(info: &CounterInfo, counter: &str) -> Unit {
+        let name = counter.rsplit('\\').next().unwrap_or(counter);
+        let bytes = if name.contains("MBytes") {
+            Some(1024.0 * 1024.0)
+        } else if name.contains("KBytes") {
+            Some(1024.0)
+        } else if name.contains("Bytes") {
+            Some(1.0)
+        } else {
+            None
+        };
+
+        match (info.counter_type & PERF_DISPLAY_MASK, bytes) {
+            (PERF_DISPLAY_PER_SEC, Some(_)) => Unit::BytesPerSec,
+            (PERF_DISPLAY_PER_SEC, None) => Unit::PerSec,
+            (PERF_DISPLAY_PERCENT, _) => Unit::Percent,
+            (PERF_DISPLAY_SECONDS, _) => Unit::Seconds,
+            (_, Some(multiplier)) => Unit::Bytes(multiplier),
+            _ => Unit::Count,
+        }
+    }
+
+    // Count for counters whose info couldn't be read.
+    pub fn lookup(info: &HashMap<String, CounterInfo>, counter: &str) -> Unit {
+        match info.get(counter) {
+            Some(info) => Unit::from_info(info, counter),
+            None => Unit::Count,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Unit::Bytes(_) => "bytes",
+            Unit::BytesPerSec => "bytes/sec",
+            Unit::Percent => "percent",
+            Unit::PerSec => "per sec",
+            Unit::Seconds => "seconds",
+            Unit::Count => "count",
+        }
+    }
+
+    pub fn format(&self, value: f64) -> String {
+        if !value.is_finite() {
+            return value.to_string();
+        }
+
+        match self {
+            Unit::Bytes(multiplier) => format_bytes(value * multiplier),
+            Unit::BytesPerSec => format!("{}/s", format_bytes(value)),
+            Unit::Percent => format!("{:.1}%", value),
+            Unit::PerSec => format!("{}/s", format_number(value)),
+            Unit::Seconds if value.abs() < 1.0 => format!("{:.1} ms", value * 1000.0),
+            Unit::Seconds => format!("{:.2} s", value),
+            Unit::Count => format_number(value),
+        }
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut value = bytes;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+// Whole numbers print without decimals, everything else with two.
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
@@ -0,0 +1,103 @@
+use windows::Win32::System::Performance::PdhCloseLog;
+
+use crate::{
+    cli::ValidateArgs,
+    log_files::find_log_files,
+    pdh_helper::{
+        bind_input_logfiles, enum_machines, expand_wildcard_path, status_name,
+        validate_counter_path,
+    },
+    selection::read_counter_list,
+};
+
+// Checks a list of counter paths against the logs before a long export, and
+// says what's wrong with each one that isn't there. Paths without a machine
+// are checked on every machine in the logs.
+pub fn validate(args: &ValidateArgs) {
+    let mut paths = args.paths.clone();
+    if let Some(path) = &args.counters_from {
+        match read_counter_list(path) {
+            Ok(from_file) => paths.extend(from_file),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    }
+
+    if paths.is_empty() {
+        eprintln!("No counter paths to check.");
+        return;
+    }
+
+    let files = find_log_files(&args.glob_pattern);
+    if files.is_empty() {
+        return;
+    }
+
+    let hdatasource = bind_input_logfiles(files);
+    let machines = enum_machines(hdatasource);
+
+    let mut found = 0;
+    for path in &paths {
+        let result = match check(hdatasource, &machines, path) {
+            Ok(result) => {
+                found += 1;
+                result
+            }
+            Err(e) => e,
+        };
+        println!("{:<20}  {}", result, path);
+    }
+
+    unsafe { PdhCloseLog(hdatasource, 0) };
+
+    eprintln!("{} of {} paths are in the logs.", found, paths.len());
+}
+
+// Says how the path matched, or why it didn't.
+fn check(hdatasource: isize, machines: &[String], path: &str) -> Result<String, String> {
+    let candidates = if path.starts_with("\\\\") {
+        vec![path.to_string()]
+    } else {
+        let path = format!("\\{}", path.trim_start_matches('\\'));
+        machines
+            .iter()
+            .map(|machine| format!("{}{}", machine, path))
+            .collect()
+    };
+
+    let mut matched = 0;
+    let mut counters = 0;
+    let mut error = None;
+    for candidate in &candidates {
+        let result = if candidate.contains('*') {
+            match expand_wildcard_path(hdatasource, candidate) {
+                Ok(expanded) if expanded.is_empty() => Err("no matches".to_string()),
+                Ok(expanded) => {
+                    counters += expanded.len();
+                    Ok(())
+                }
+                Err(status) => Err(status_name(status)),
+            }
+        } else {
+            validate_counter_path(hdatasource, candidate).map_err(status_name)
+        };
+
+        match result {
+            Ok(()) => matched += 1,
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+
+    match (matched, counters, error) {
+        (0, _, error) => Err(error.unwrap_or("no machines".to_string())),
+        (_, 0, _) if candidates.len() == 1 => Ok("OK".to_string()),
+        (_, 0, _) => Ok(format!(
+            "OK on {} of {} machines",
+            matched,
+            candidates.len()
+        )),
+        (_, counters, _) => Ok(format!("{} counters", counters)),
+    }
+}
@@ -0,0 +1,561 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use time::{macros::datetime, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use zip::{
+    write::{SimpleFileOptions, ZipWriter},
+    CompressionMethod,
+};
+
+use crate::{
+    pdh_helper::CounterValueWithTime,
+    report::CounterStats,
+    series::Series,
+    stats::sort_values,
+    timespec::{display_offset, zone_label},
+    units::Unit,
+};
+
+// The most counters charted. Each chart gets its own space on the Charts
+// sheet, so more than this is a long scroll.
+const MAX_CHARTS: usize = 10;
+
+// Rows of the Charts sheet each chart takes.
+const CHART_ROWS: usize = 20;
+
+// Excel's limits on a sheet. The first row and column are the header and
+// the time.
+const MAX_ROWS: usize = 1_048_576 - 1;
+const MAX_COLUMNS: usize = 16_384 - 1;
+
+// Excel counts days from the end of 1899, in the time zone it's shown in.
+const EXCEL_EPOCH: PrimitiveDateTime = datetime!(1899-12-30 0:00);
+
+// The cell formats in styles.xml, by their index.
+const STYLE_TIME: u32 = 1;
+const STYLE_HEADER: u32 = 2;
+
+const MAIN_NS: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const REL_NS: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const PACKAGE_REL_NS: &str = "http://schemas.openxmlformats.org/package/2006/relationships";
+
+// Writes a workbook with the samples on a Data sheet, like the wide CSV, the
+// statistics of each counter on a Statistics sheet, and charts of the
+// counters that vary the most on a Charts sheet. Returns the number of
+// charts.
+pub fn write_xlsx(path: &Path, counters: &[String], series: &[Series]) -> std::io::Result<usize> {
+    let rows = rows(counters.len(), series);
+
+    if counters.len() > MAX_COLUMNS {
+        return Err(std::io::Error::other(format!(
+            "Excel sheets hold at most {} counters; export them as csv with --max-columns instead",
+            MAX_COLUMNS
+        )));
+    }
+    if rows.len() > MAX_ROWS {
+        return Err(std::io::Error::other(format!(
+            "Excel sheets hold at most {} rows; thin out the samples with --resample or --every",
+            MAX_ROWS
+        )));
+    }
+
+    let stats = counters
+        .iter()
+        .zip(series)
+        .filter_map(|(counter, samples)| {
+            let mut values = samples
+                .iter()
+                .map(|s| s.value())
+                .filter(|v| !v.is_nan())
+                .collect::<Vec<f64>>();
+            if values.is_empty() {
+                return None;
+            }
+            sort_values(&mut values);
+            Some(CounterStats::new(counter, &values, Unit::Count))
+        })
+        .collect::<Vec<CounterStats>>();
+    let charted = top_counters(counters, &stats);
+
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+
+    zip.start_file("[Content_Types].xml", options)?;
+    write_content_types(&mut zip, charted.len())?;
+
+    zip.start_file("_rels/.rels", options)?;
+    write_relationships(
+        &mut zip,
+        &[("officeDocument", "xl/workbook.xml".to_string())],
+    )?;
+
+    zip.start_file("xl/workbook.xml", options)?;
+    write_workbook(&mut zip)?;
+
+    zip.start_file("xl/_rels/workbook.xml.rels", options)?;
+    write_relationships(
+        &mut zip,
+        &[
+            ("worksheet", "worksheets/sheet1.xml".to_string()),
+            ("worksheet", "worksheets/sheet2.xml".to_string()),
+            ("worksheet", "worksheets/sheet3.xml".to_string()),
+            ("styles", "styles.xml".to_string()),
+        ],
+    )?;
+
+    zip.start_file("xl/styles.xml", options)?;
+    zip.write_all(STYLES.as_bytes())?;
+
+    zip.start_file("xl/worksheets/sheet1.xml", options)?;
+    write_data_sheet(&mut zip, counters, &rows)?;
+
+    zip.start_file("xl/worksheets/sheet2.xml", options)?;
+    write_stats_sheet(&mut zip, &stats)?;
+
+    zip.start_file("xl/worksheets/sheet3.xml", options)?;
+    write_charts_sheet(&mut zip, !charted.is_empty())?;
+
+    if !charted.is_empty() {
+        zip.start_file("xl/worksheets/_rels/sheet3.xml.rels", options)?;
+        write_relationships(
+            &mut zip,
+            &[("drawing", "../drawings/drawing1.xml".to_string())],
+        )?;
+
+        zip.start_file("xl/drawings/drawing1.xml", options)?;
+        write_drawing(&mut zip, charted.len())?;
+
+        let charts = (1..=charted.len())
+            .map(|n| ("chart", format!("../charts/chart{}.xml", n)))
+            .collect::<Vec<(&str, String)>>();
+        zip.start_file("xl/drawings/_rels/drawing1.xml.rels", options)?;
+        write_relationships(&mut zip, &charts)?;
+
+        for (n, column) in charted.iter().enumerate() {
+            zip.start_file(format!("xl/charts/chart{}.xml", n + 1), options)?;
+            write_chart(&mut zip, &counters[*column], *column + 1, rows.len())?;
+        }
+    }
+
+    zip.finish()?.flush()?;
+    Ok(charted.len())
+}
+
+// The samples by time, with a cell per counter, like the rows of the wide
+// CSV.
+fn rows(columns: usize, series: &[Series]) -> Vec<(OffsetDateTime, Vec<Option<f64>>)> {
+    let mut rows = BTreeMap::<OffsetDateTime, Vec<Option<f64>>>::new();
+    for (column, samples) in series.iter().enumerate() {
+        for sample in samples.iter() {
+            rows.entry(sample.time())
+                .or_insert_with(|| vec![None; columns])[column] = Some(cell_value(&sample));
+        }
+    }
+    rows.into_iter().collect()
+}
+
+fn cell_value(sample: &CounterValueWithTime) -> f64 {
+    match sample {
+        CounterValueWithTime::Long(_, value) => *value as f64,
+        CounterValueWithTime::Large(_, value) => *value as f64,
+        CounterValueWithTime::Double(_, value) => *value,
+    }
+}
+
+// The columns of the counters to chart: the ones that vary the most for
+// their size, by the ratio of their standard deviation to their average. A
+// counter that holds steady, however big, makes a flat line that says
+// nothing.
+fn top_counters(counters: &[String], stats: &[CounterStats]) -> Vec<usize> {
+    let mut ranked = stats
+        .iter()
+        .filter(|s| s.samples > 1 && s.avg != 0.0 && s.stddev > 0.0)
+        .map(|s| (s.stddev / s.avg.abs(), s.counter.as_str()))
+        .collect::<Vec<(f64, &str)>>();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    ranked
+        .into_iter()
+        .take(MAX_CHARTS)
+        .filter_map(|(_, counter)| counters.iter().position(|c| c == counter))
+        .collect()
+}
+
+// A time as Excel stores it: days since its epoch, shown in the --timezone
+// offset.
+fn excel_time(time: OffsetDateTime) -> f64 {
+    let local = time.to_offset(display_offset());
+    let local = PrimitiveDateTime::new(local.date(), local.time());
+    (local - EXCEL_EPOCH).as_seconds_f64() / 86_400.0
+}
+
+// The letters of a column, from 0 for A.
+fn column_name(mut column: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (column % 26) as u8);
+        if column < 26 {
+            break;
+        }
+        column = column / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+pub fn xml(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_content_types(writer: &mut dyn Write, charts: usize) -> std::io::Result<()> {
+    const SHEET: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml";
+
+    writeln!(
+        writer,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>"
+    )?;
+    write!(
+        writer,
+        "<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">"
+    )?;
+    write!(
+        writer,
+        "<Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>"
+    )?;
+    write!(
+        writer,
+        "<Default Extension=\"xml\" ContentType=\"application/xml\"/>"
+    )?;
+    write!(
+        writer,
+        "<Override PartName=\"/xl/workbook.xml\" ContentType=\"{}.sheet.main+xml\"/>",
+        SHEET
+    )?;
+    write!(
+        writer,
+        "<Override PartName=\"/xl/styles.xml\" ContentType=\"{}.styles+xml\"/>",
+        SHEET
+    )?;
+    for sheet in 1..=3 {
+        write!(
+            writer,
+            "<Override PartName=\"/xl/worksheets/sheet{}.xml\" ContentType=\"{}.worksheet+xml\"/>",
+            sheet, SHEET
+        )?;
+    }
+    if charts > 0 {
+        write!(
+            writer,
+            "<Override PartName=\"/xl/drawings/drawing1.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.drawing+xml\"/>"
+        )?;
+    }
+    for chart in 1..=charts {
+        write!(
+            writer,
+            "<Override PartName=\"/xl/charts/chart{}.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.drawingml.chart+xml\"/>",
+            chart
+        )?;
+    }
+    write!(writer, "</Types>")
+}
+
+// Relationships are numbered rId1, rId2, and so on in the order given.
+fn write_relationships(writer: &mut dyn Write, targets: &[(&str, String)]) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>"
+    )?;
+    write!(writer, "<Relationships xmlns=\"{}\">", PACKAGE_REL_NS)?;
+    for (index, (kind, target)) in targets.iter().enumerate() {
+        write!(
+            writer,
+            "<Relationship Id=\"rId{}\" Type=\"{}/{}\" Target=\"{}\"/>",
+            index + 1,
+            REL_NS,
+            kind,
+            target
+        )?;
+    }
+    write!(writer, "</Relationships>")
+}
+
+fn write_workbook(writer: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>"
+    )?;
+    write!(
+        writer,
+        "<workbook xmlns=\"{}\" xmlns:r=\"{}\"><sheets>",
+        MAIN_NS, REL_NS
+    )?;
+    for (index, name) in ["Data", "Statistics", "Charts"].iter().enumerate() {
+        write!(
+            writer,
+            "<sheet name=\"{}\" sheetId=\"{}\" r:id=\"rId{}\"/>",
+            name,
+            index + 1,
+            index + 1
+        )?;
+    }
+    write!(writer, "</sheets></workbook>")
+}
+
+// Normal cells, times, and bold headers.
+const STYLES: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>
+<styleSheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\">\
+<numFmts count=\"1\"><numFmt numFmtId=\"164\" formatCode=\"yyyy-mm-dd hh:mm:ss.000\"/></numFmts>\
+<fonts count=\"2\"><font><sz val=\"11\"/><name val=\"Calibri\"/></font>\
+<font><b/><sz val=\"11\"/><name val=\"Calibri\"/></font></fonts>\
+<fills count=\"2\"><fill><patternFill patternType=\"none\"/></fill>\
+<fill><patternFill patternType=\"gray125\"/></fill></fills>\
+<borders count=\"1\"><border><left/><right/><top/><bottom/><diagonal/></border></borders>\
+<cellStyleXfs count=\"1\"><xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\"/></cellStyleXfs>\
+<cellXfs count=\"3\"><xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\"/>\
+<xf numFmtId=\"164\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\" applyNumberFormat=\"1\"/>\
+<xf numFmtId=\"0\" fontId=\"1\" fillId=\"0\" borderId=\"0\" xfId=\"0\" applyFont=\"1\"/></cellXfs>\
+<cellStyles count=\"1\"><cellStyle name=\"Normal\" xfId=\"0\" builtinId=\"0\"/></cellStyles>\
+</styleSheet>";
+
+// The start of a sheet with its header row frozen and the first column as
+// wide as first_width.
+fn write_sheet_start(writer: &mut dyn Write, first_width: u32) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>"
+    )?;
+    write!(
+        writer,
+        "<worksheet xmlns=\"{}\" xmlns:r=\"{}\">",
+        MAIN_NS, REL_NS
+    )?;
+    write!(
+        writer,
+        "<sheetViews><sheetView workbookViewId=\"0\"><pane ySplit=\"1\" topLeftCell=\"A2\" activePane=\"bottomLeft\" state=\"frozen\"/></sheetView></sheetViews>"
+    )?;
+    write!(
+        writer,
+        "<cols><col min=\"1\" max=\"1\" width=\"{}\" customWidth=\"1\"/></cols>",
+        first_width
+    )?;
+    write!(writer, "<sheetData>")
+}
+
+fn write_header(writer: &mut dyn Write, names: &[&str]) -> std::io::Result<()> {
+    write!(writer, "<row r=\"1\">")?;
+    for (column, name) in names.iter().enumerate() {
+        write_text(writer, column, 1, name, Some(STYLE_HEADER))?;
+    }
+    write!(writer, "</row>")
+}
+
+fn write_text(
+    writer: &mut dyn Write,
+    column: usize,
+    row: usize,
+    text: &str,
+    style: Option<u32>,
+) -> std::io::Result<()> {
+    let style = style.map(|s| format!(" s=\"{}\"", s)).unwrap_or_default();
+    write!(
+        writer,
+        "<c r=\"{}{}\" t=\"inlineStr\"{}><is><t>{}</t></is></c>",
+        column_name(column),
+        row,
+        style,
+        xml(text)
+    )
+}
+
+// Values that aren't numbers, like the samples --keep-invalid keeps, are
+// #N/A, which charts leave a gap for.
+fn write_number(
+    writer: &mut dyn Write,
+    column: usize,
+    row: usize,
+    value: f64,
+    style: Option<u32>,
+) -> std::io::Result<()> {
+    let style = style.map(|s| format!(" s=\"{}\"", s)).unwrap_or_default();
+    if value.is_finite() {
+        write!(
+            writer,
+            "<c r=\"{}{}\"{}><v>{}</v></c>",
+            column_name(column),
+            row,
+            style,
+            value
+        )
+    } else {
+        write!(
+            writer,
+            "<c r=\"{}{}\" t=\"e\"{}><v>#N/A</v></c>",
+            column_name(column),
+            row,
+            style
+        )
+    }
+}
+
+fn write_data_sheet(
+    writer: &mut dyn Write,
+    counters: &[String],
+    rows: &[(OffsetDateTime, Vec<Option<f64>>)],
+) -> std::io::Result<()> {
+    write_sheet_start(writer, 24)?;
+
+    let offset = display_offset();
+    let time = if offset == UtcOffset::UTC {
+        "Time".to_string()
+    } else {
+        format!("Time ({})", zone_label(offset))
+    };
+    let names = std::iter::once(time.as_str())
+        .chain(counters.iter().map(String::as_str))
+        .collect::<Vec<&str>>();
+    write_header(writer, &names)?;
+
+    for (index, (time, values)) in rows.iter().enumerate() {
+        let row = index + 2;
+        write!(writer, "<row r=\"{}\">", row)?;
+        write_number(writer, 0, row, excel_time(*time), Some(STYLE_TIME))?;
+        for (column, value) in values.iter().enumerate() {
+            if let Some(value) = value {
+                write_number(writer, column + 1, row, *value, None)?;
+            }
+        }
+        write!(writer, "</row>")?;
+    }
+
+    write!(writer, "</sheetData></worksheet>")
+}
+
+fn write_stats_sheet(writer: &mut dyn Write, stats: &[CounterStats]) -> std::io::Result<()> {
+    write_sheet_start(writer, 60)?;
+    write_header(
+        writer,
+        &["Counter", "Samples", "Min", "Avg", "StdDev", "P95", "Max"],
+    )?;
+
+    for (index, s) in stats.iter().enumerate() {
+        let row = index + 2;
+        write!(writer, "<row r=\"{}\">", row)?;
+        write_text(writer, 0, row, &s.counter, None)?;
+        let values = [s.samples as f64, s.min, s.avg, s.stddev, s.p95, s.max];
+        for (column, value) in values.into_iter().enumerate() {
+            write_number(writer, column + 1, row, value, None)?;
+        }
+        write!(writer, "</row>")?;
+    }
+
+    write!(writer, "</sheetData></worksheet>")
+}
+
+fn write_charts_sheet(writer: &mut dyn Write, has_charts: bool) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>"
+    )?;
+    write!(
+        writer,
+        "<worksheet xmlns=\"{}\" xmlns:r=\"{}\"><sheetData/>",
+        MAIN_NS, REL_NS
+    )?;
+    if has_charts {
+        write!(writer, "<drawing r:id=\"rId1\"/>")?;
+    }
+    write!(writer, "</worksheet>")
+}
+
+// Places the charts one under another, each CHART_ROWS rows tall.
+fn write_drawing(writer: &mut dyn Write, charts: usize) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>"
+    )?;
+    write!(
+        writer,
+        "<xdr:wsDr xmlns:xdr=\"http://schemas.openxmlformats.org/drawingml/2006/spreadsheetDrawing\" xmlns:a=\"http://schemas.openxmlformats.org/drawingml/2006/main\" xmlns:r=\"{}\" xmlns:c=\"http://schemas.openxmlformats.org/drawingml/2006/chart\">",
+        REL_NS
+    )?;
+    for chart in 0..charts {
+        let top = chart * CHART_ROWS;
+        write!(
+            writer,
+            "<xdr:twoCellAnchor>\
+<xdr:from><xdr:col>0</xdr:col><xdr:colOff>0</xdr:colOff><xdr:row>{}</xdr:row><xdr:rowOff>0</xdr:rowOff></xdr:from>\
+<xdr:to><xdr:col>14</xdr:col><xdr:colOff>0</xdr:colOff><xdr:row>{}</xdr:row><xdr:rowOff>0</xdr:rowOff></xdr:to>\
+<xdr:graphicFrame macro=\"\"><xdr:nvGraphicFramePr><xdr:cNvPr id=\"{}\" name=\"Chart {}\"/><xdr:cNvGraphicFramePr/></xdr:nvGraphicFramePr>\
+<xdr:xfrm><a:off x=\"0\" y=\"0\"/><a:ext cx=\"0\" cy=\"0\"/></xdr:xfrm>\
+<a:graphic><a:graphicData uri=\"http://schemas.openxmlformats.org/drawingml/2006/chart\"><c:chart r:id=\"rId{}\"/></a:graphicData></a:graphic>\
+</xdr:graphicFrame><xdr:clientData/></xdr:twoCellAnchor>",
+            top,
+            top + CHART_ROWS - 1,
+            chart + 2,
+            chart + 1,
+            chart + 1
+        )?;
+    }
+    write!(writer, "</xdr:wsDr>")
+}
+
+// A line of the counter in column over time, read from the Data sheet so it
+// follows any edits there. It's a scatter chart so samples are spaced by
+// their times rather than evenly.
+fn write_chart(
+    writer: &mut dyn Write,
+    counter: &str,
+    column: usize,
+    rows: usize,
+) -> std::io::Result<()> {
+    let last = rows + 1;
+    let column = column_name(column);
+
+    writeln!(
+        writer,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>"
+    )?;
+    write!(
+        writer,
+        "<c:chartSpace xmlns:c=\"http://schemas.openxmlformats.org/drawingml/2006/chart\" xmlns:a=\"http://schemas.openxmlformats.org/drawingml/2006/main\" xmlns:r=\"{}\"><c:chart>",
+        REL_NS
+    )?;
+    write!(
+        writer,
+        "<c:title><c:tx><c:rich><a:bodyPr/><a:p><a:pPr><a:defRPr sz=\"1200\" b=\"1\"/></a:pPr><a:r><a:t>{}</a:t></a:r></a:p></c:rich></c:tx><c:overlay val=\"0\"/></c:title>",
+        xml(counter)
+    )?;
+    write!(
+        writer,
+        "<c:autoTitleDeleted val=\"0\"/><c:plotArea><c:layout/>\
+<c:scatterChart><c:scatterStyle val=\"lineMarker\"/><c:varyColors val=\"0\"/>\
+<c:ser><c:idx val=\"0\"/><c:order val=\"0\"/><c:tx><c:strRef><c:f>Data!${col}$1</c:f></c:strRef></c:tx>\
+<c:marker><c:symbol val=\"none\"/></c:marker>\
+<c:xVal><c:numRef><c:f>Data!$A$2:$A${last}</c:f></c:numRef></c:xVal>\
+<c:yVal><c:numRef><c:f>Data!${col}$2:${col}${last}</c:f></c:numRef></c:yVal>\
+<c:smooth val=\"0\"/></c:ser><c:axId val=\"1\"/><c:axId val=\"2\"/></c:scatterChart>",
+        col = column,
+        last = last
+    )?;
+    write!(
+        writer,
+        "<c:valAx><c:axId val=\"1\"/><c:scaling><c:orientation val=\"minMax\"/></c:scaling><c:delete val=\"0\"/><c:axPos val=\"b\"/>\
+<c:numFmt formatCode=\"m/d hh:mm\" sourceLinked=\"0\"/><c:tickLblPos val=\"low\"/><c:crossAx val=\"2\"/><c:crosses val=\"autoZero\"/><c:crossBetween val=\"midCat\"/></c:valAx>\
+<c:valAx><c:axId val=\"2\"/><c:scaling><c:orientation val=\"minMax\"/></c:scaling><c:delete val=\"0\"/><c:axPos val=\"l\"/><c:majorGridlines/>\
+<c:numFmt formatCode=\"General\" sourceLinked=\"1\"/><c:tickLblPos val=\"nextTo\"/><c:crossAx val=\"1\"/><c:crosses val=\"autoZero\"/><c:crossBetween val=\"midCat\"/></c:valAx>"
+    )?;
+    write!(
+        writer,
+        "</c:plotArea><c:plotVisOnly val=\"1\"/><c:dispBlanksAs val=\"gap\"/></c:chart></c:chartSpace>"
+    )
+}
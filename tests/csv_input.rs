@@ -0,0 +1,84 @@
+// These drive the real binary, which needs pdh.dll, so they only run on Windows.
+#![cfg(windows)]
+
+use std::process::Command;
+
+const SAMPLE_CSV: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/sample.csv");
+
+fn run(args: &[&str]) -> (String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_perflogtool"))
+        .args(args)
+        .output()
+        .expect("Failed to run perflogtool");
+
+    assert!(output.status.success(), "perflogtool {:?} failed", args);
+
+    (
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn csv_log_is_detected() {
+    let (_, stderr) = run(&["summary", SAMPLE_CSV]);
+
+    assert!(stderr.contains("Found 1 files."));
+    assert!(stderr.contains("(CSV)"));
+}
+
+#[test]
+fn csv_summary_lists_objects() {
+    let (stdout, _) = run(&["summary", SAMPLE_CSV]);
+
+    assert!(stdout.contains("Time range:"));
+    assert!(stdout.contains("Machine: \\\\TESTSERVER"));
+    assert!(stdout.contains("  Processor"));
+    assert!(stdout.contains("  Memory"));
+    assert!(stdout.contains("_Total"));
+}
+
+#[test]
+fn csv_find_returns_full_paths() {
+    let (stdout, _) = run(&["find", SAMPLE_CSV, "Processor"]);
+
+    let paths = stdout.lines().collect::<Vec<&str>>();
+    assert_eq!(paths.len(), 2);
+    assert!(paths.contains(&"\\\\TESTSERVER\\Processor(_Total)\\% Processor Time"));
+    assert!(paths.contains(&"\\\\TESTSERVER\\Processor(0)\\% Processor Time"));
+}
+
+#[test]
+fn csv_export_reads_samples() {
+    let (stdout, _) = run(&[
+        "export",
+        SAMPLE_CSV,
+        "--counter",
+        "Memory\\Available MBytes",
+    ]);
+
+    let lines = stdout.lines().collect::<Vec<&str>>();
+    assert_eq!(
+        lines[0],
+        "\"Time\",\"\\\\TESTSERVER\\Memory\\Available MBytes\""
+    );
+    assert!(lines.len() >= 6);
+    assert!(lines.iter().any(|l| l.ends_with(",3990")));
+}
+
+#[test]
+fn csv_export_applies_where_filter() {
+    let (stdout, _) = run(&[
+        "export",
+        SAMPLE_CSV,
+        "--counter",
+        "Processor(_Total)",
+        "--where",
+        "value > 90",
+    ]);
+
+    let rows = stdout.lines().skip(1).collect::<Vec<&str>>();
+    assert_eq!(rows.len(), 2);
+    assert!(rows[0].ends_with(",95.5"));
+    assert!(rows[1].ends_with(",97"));
+}